@@ -1,21 +1,30 @@
 // build.rs - The build script.
 // This file is created by Fan Jinhao.
 
+#[cfg(feature = "c_port")]
 extern crate bindgen;
+#[cfg(feature = "c_port")]
 extern crate cc;
 
 // use std::env;
+#[cfg(feature = "c_port")]
 use std::path::PathBuf;
 
 fn main() {
-    // NOTE: Running bindgen in every build is quite time-consuming.
-    // The solution is to comment out the following line after the first time you've generated the bindings.
-    run_bindgen();
+    // Only the C port needs bindgen and a C compiler,
+    // there is nothing to build for the pure Rust port (see src/rust_port.rs).
+    #[cfg(feature = "c_port")]
+    {
+        // NOTE: Running bindgen in every build is quite time-consuming.
+        // The solution is to comment out the following line after the first time you've generated the bindings.
+        run_bindgen();
 
-    run_cc();
+        run_cc();
+    }
 }
 
 // Run bindgen to genernate C bindings in portable.h to Rust.
+#[cfg(feature = "c_port")]
 fn run_bindgen() {
     let bindings = bindgen::Builder::default()
         .header("portable/portable.h")
@@ -32,6 +41,7 @@ fn run_bindgen() {
 }
 
 // Run cc to compile portable files.
+#[cfg(feature = "c_port")]
 fn run_cc() {
     cc::Build::new()
         .file("portable/port.c")
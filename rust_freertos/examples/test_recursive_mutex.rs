@@ -10,12 +10,12 @@ fn main() {
 
     let _mutex_holder = move || {
         for i in 1..11 {
-            recursive_mutex.down_recursive(0);
+            recursive_mutex.down_recursive(0).unwrap();
             assert!(recursive_mutex.get_recursive_count() == i);
         }
 
         for j in 1..11 {
-            recursive_mutex.up_recursive();
+            recursive_mutex.up_recursive().unwrap();
             assert!(recursive_mutex.get_recursive_count() == 10-j);
         }
         kernel::task_end_scheduler();
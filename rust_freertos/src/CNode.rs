@@ -9,18 +9,15 @@ use crate::task_control_cap::*;
 use crate::task_ipc::*;
 use crate::types::*;
 use crate::CSpace::*;
+use crate::task_global::*;
+use crate::kernel::CriticalSection;
 use crate::*;
 use std::sync::{Arc, RwLock};
 use std::ptr::*;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct CTable {
-    pub caps: [cte_t; MAX_CSlots],
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct slot_range_t {
-    pub cnode: Arc<RwLock<cte_t>>,
+    pub cnode: *mut cte_t,
     pub offset: u64,
     pub length: u64,
 }
@@ -33,19 +30,83 @@ pub struct finaliseSlot_ret_t {
     pub cleanupInfo: cap_t,
 }
 
-extern "C" {
-    pub static mut current_syscall_error: syscall_error_t;
-    pub static mut current_lookup_fault: lookup_fault_t;
-    fn preemptionPoint() -> u64;
-    fn finaliseCap(cap: cap_t, final_: bool_t, exposed: bool_t) -> finaliseCap_ret_t;
-    fn sameRegionAs(cap_a: cap_t, cap_b: cap_t) -> bool_t;
-    fn sameObjectAs(cap_a: cap_t, cap_b: cap_t) -> bool_t;
-    fn cancelBadgedSends(epptr: *mut endpoint_t, badge: u64);
-    fn maskCapRights(cap_rights: seL4_CapRights_t, cap: cap_t) -> cap_t;
-    fn kprintf(format: *const u8, ...) -> u64;
-    fn puts(str: *const u8) -> u64;
-    fn deletedIRQHandler(irq: u8); //  where to place? TODO
-    fn Arch_postCapDeletion(cap: cap_t); //  where to place? TODO
+/// # Description
+/// Create a CNode of 2^radix empty slots.  A cptr is resolved by the CNode
+/// if it starts with the guard, and the `radix` bits that follow are the
+/// index of its slot.  The slots are allocated from the heap, and never
+/// freed, as there may be caps to the CNode in other CSpaces.
+///
+/// * C implementation: objecttype.c createObject(), seL4_CapTableObject
+///
+/// # Arguments:
+/// * `radix` - the number of bits of the index of the slots.
+/// * `guard` - the bits the cptrs resolved by the CNode start with.
+/// * `guard_size` - the number of bits of the guard.
+///
+/// # Return:
+/// The cap to the CNode, seL4_RangeError if the guard and the index don't
+/// fit in a cptr or the number of slots doesn't fit in a usize, and seL4_NotEnoughMemory if the slots can't be allocated.
+pub fn cnode_cap_new(radix: u64, guard: u64, guard_size: u64) -> Result<cap_t, seL4_Error> {
+    /* The number of slots, 1 << radix, must fit in a usize. */
+    if radix < 1 || radix >= usize::BITS as u64 || guard_size > wordBits - radix {
+        return Err(seL4_Error::seL4_RangeError);
+    }
+    let layout = match std::alloc::Layout::array::<cte_t>(1usize << radix) {
+        Ok(layout) => layout,
+        Err(_) => return Err(seL4_Error::seL4_NotEnoughMemory),
+    };
+    /* A slot of zeroes holds a null cap and is in no mapping database. */
+    let slots = unsafe { std::alloc::alloc_zeroed(layout) };
+    if slots.is_null() {
+        return Err(seL4_Error::seL4_NotEnoughMemory);
+    }
+    let mask = MASK!(guard_size);
+    Ok(cap_cnode_cap_new(guard & mask, guard_size, radix, slots as u64))
+}
+
+/// # Description
+/// Put an original cap in an empty slot of a CNode, as the kernel does with
+/// the caps to the objects it creates.  The cap is the root of a derivation
+/// tree in the mapping database: the caps copied and minted from it, in any
+/// CSpace, are deleted when it is revoked.
+///
+/// * C implementation: boot.c write_slot()
+///
+/// # Arguments:
+/// * `cnode` - the cap to the CNode.
+/// * `index` - the index of the slot in the CNode.
+/// * `cap` - the original cap.
+///
+/// # Return:
+/// Ok, or seL4_InvalidCapability if `cnode` isn't a CNode cap,
+/// seL4_RangeError if it has no slot `index` and seL4_DeleteFirst if the
+/// slot isn't empty.
+///
+/// # Safety
+///
+/// The slots of `cnode` must be alive and not moved, as the ones of a CNode
+/// made by cnode_cap_new() are: a CNode cap made by cap_cnode_cap_new()
+/// must hold a pointer to at least 1 << radix slots.
+pub unsafe fn provide_cap(cnode: cap_t, index: u64, cap: cap_t) -> Result<(), seL4_Error> {
+    if cap_get_capType(cnode) != cap_tag_t::cap_cnode_cap as u64 {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    if index >> cap_cnode_cap_get_capCNodeRadix(cnode) != 0 {
+        return Err(seL4_Error::seL4_RangeError);
+    }
+    let _critical = CriticalSection::enter();
+    let slot = (cap_cnode_cap_get_capCNodePtr(cnode) as *mut cte_t).offset(index as isize);
+    if cap_get_capType((*slot).cap) != cap_tag_t::cap_null_cap as u64 {
+        return Err(seL4_Error::seL4_DeleteFirst);
+    }
+    (*slot).cap = cap;
+    (*slot).cteMDBNode = mdb_node_new(0, 1u64, 1u64, 0);
+    Ok(())
+}
+
+/* The kernel operations of the port are never preempted. */
+fn preemptionPoint() -> u64 {
+    0u64
 }
 
 pub unsafe fn decodeCNodeInvocation(
@@ -76,11 +137,10 @@ pub unsafe fn decodeCNodeInvocation(
         return lu_ret.status;
     }
     let destSlot = lu_ret.slot;
-    let dest_arc_lock = Arc::new(RwLock::new(*destSlot.clone()));
     if invLabel >= invocation_label::CNodeCopy as u64
         && invLabel <= invocation_label::CNodeMutate as u64
     {
-        if length < 4 || Box::into_raw(excaps.excaprefs[0].clone()) as u64 == 0u64 {
+        if length < 4 || excaps.excaprefs[0] as u64 == 0u64 {
             userError!("CNode Copy/Mint/Move/Mutate: Truncated message.");
             current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
             return exception::EXCEPTION_SYSCALL_ERROR as u64;
@@ -88,7 +148,7 @@ pub unsafe fn decodeCNodeInvocation(
         let srcIndex = getSyscallArg(2, buffer);
         let srcDepth = getSyscallArg(3, buffer);
         let srcRoot = (*excaps.excaprefs[0]).cap;
-        let status = ensureEmptySlot(Arc::new(RwLock::new(*destSlot)));
+        let status = ensureEmptySlot(destSlot);
         let newCap: cap_t;
         let isMove: bool;
         if status != 0u64 {
@@ -101,7 +161,6 @@ pub unsafe fn decodeCNodeInvocation(
             return lu_ret.status;
         }
         let srcSlot = lu_ret.slot;
-        let src_arc_lock = Arc::new(RwLock::new(*srcSlot));
         if cap_get_capType((*srcSlot).cap) == cap_tag_t::cap_null_cap as u64 {
             userError!("CNode Copy/Mint/Move/Mutate: Source slot invalid or empty.");
             current_syscall_error.type_ = seL4_Error::seL4_FailedLookup as u64;
@@ -167,24 +226,24 @@ pub unsafe fn decodeCNodeInvocation(
         //    );
         let current_task = get_current_task_handle!();
         current_task.set_state(_thread_state::Restart);
-        if isMove { // Arc::new(RwLock::new(srcSlot))
-            invokeCNodeMove(newCap, src_arc_lock.clone(), dest_arc_lock.clone());
+        if isMove {
+            return invokeCNodeMove(newCap, srcSlot, destSlot);
         } else {
-            invokeCNodeInsert(newCap, src_arc_lock, dest_arc_lock.clone());
+            return invokeCNodeInsert(newCap, srcSlot, destSlot);
         }
     }
     if invLabel == invocation_label::CNodeRevoke as u64 {
         // setThreadState(node_state!(get_ptr_from_handle!(get_current_task_handle!())), _thread_state::Restart);
         let current_task = get_current_task_handle!();
         current_task.set_state(_thread_state::Restart);
-        return invokeCNodeRevoke(dest_arc_lock.clone());
+        return invokeCNodeRevoke(destSlot);
     } else if invLabel == invocation_label::CNodeDelete as u64 {
         // setThreadState(node_state!(get_ptr_from_handle!(get_current_task_handle!())), _thread_state::Restart);
         let current_task = get_current_task_handle!();
         current_task.set_state(_thread_state::Restart);
-        return invokeCNodeDelete(dest_arc_lock);
+        return invokeCNodeDelete(destSlot);
     } else if invLabel == invocation_label::CNodeSaveCaller as u64 {
-        let status = ensureEmptySlot(dest_arc_lock.clone());
+        let status = ensureEmptySlot(destSlot);
         if status != 0u64 {
             userError!("CNode SaveCaller: Destination slot not empty.");
             return status;
@@ -192,7 +251,7 @@ pub unsafe fn decodeCNodeInvocation(
         // setThreadState(node_state!(get_ptr_from_handle!(get_current_task_handle!())), _thread_state::Restart);
         let current_task = get_current_task_handle!();
         current_task.set_state(_thread_state::Restart);
-        return invokeCNodeSaveCaller(dest_arc_lock.clone());
+        return invokeCNodeSaveCaller(destSlot);
     } else if invLabel == invocation_label::CNodeCancelBadgedSends as u64 {
         let destCap = (*destSlot).cap;
         if hasCancelSendRights(destCap) == 0u64 {
@@ -205,7 +264,7 @@ pub unsafe fn decodeCNodeInvocation(
         current_task.set_state(_thread_state::Restart);
         return invokeCNodeCancelBadgedSends(destCap);
     } else if invLabel == invocation_label::CNodeRotate as u64 {
-        if length < 8 || Box::into_raw(excaps.excaprefs[0].clone()) as u64 == 0u64 || Box::into_raw(excaps.excaprefs[1].clone()) as u64 == 0u64 {
+        if length < 8 || excaps.excaprefs[0] as u64 == 0u64 || excaps.excaprefs[1] as u64 == 0u64 {
             current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
             return exception::EXCEPTION_SYSCALL_ERROR as u64;
         }
@@ -222,7 +281,6 @@ pub unsafe fn decodeCNodeInvocation(
             return lu_ret.status;
         }
         let srcSlot = lu_ret.slot;
-        let src_arc_lock = Arc::new(RwLock::new(*srcSlot));
         lu_ret = lookupPivotSlot(thread, pivotRoot, pivotIndex, pivotDepth);
         if lu_ret.status != 0u64 {
             return lu_ret.status;
@@ -234,7 +292,7 @@ pub unsafe fn decodeCNodeInvocation(
             return exception::EXCEPTION_SYSCALL_ERROR as u64;
         }
         if srcSlot != destSlot {
-            let status = ensureEmptySlot(dest_arc_lock.clone());
+            let status = ensureEmptySlot(destSlot);
             if status != 0u64 {
                 return status;
             }
@@ -266,18 +324,18 @@ pub unsafe fn decodeCNodeInvocation(
         // setThreadState(node_state!(get_ptr_from_handle!(get_current_task_handle!())), _thread_state::Restart);
         let current_task = get_current_task_handle!();
         current_task.set_state(_thread_state::Restart);
-        return invokeCNodeRotate(newSrcCap, newPivotCap, src_arc_lock, Arc::new(RwLock::new(*pivotSlot.clone())), dest_arc_lock.clone());
+        return invokeCNodeRotate(newSrcCap, newPivotCap, srcSlot, pivotSlot, destSlot);
     }
     0u64
 }
 
 #[no_mangle]
-pub unsafe fn invokeCNodeRevoke(destSlot: Arc<RwLock<cte_t>>) -> u64 {
+pub unsafe fn invokeCNodeRevoke(destSlot: *mut cte_t) -> u64 {
     cteRevoke(destSlot)
 }
 
 #[no_mangle]
-pub unsafe fn invokeCNodeDelete(destSlot: Arc<RwLock<cte_t>>) -> u64 {
+pub unsafe fn invokeCNodeDelete(destSlot: *mut cte_t) -> u64 {
     cteDelete(destSlot, 1u64)
 }
 
@@ -285,8 +343,7 @@ pub unsafe fn invokeCNodeDelete(destSlot: Arc<RwLock<cte_t>>) -> u64 {
 pub unsafe fn invokeCNodeCancelBadgedSends(cap: cap_t) -> u64 {
     let badge = cap_endpoint_cap_get_capEPBadge(cap);
     if badge != 0u64 {
-        // let ep = Arc::from_raw(cap_endpoint_cap_get_capEPPtr(cap) as *mut endpoint_t);
-        cancelBadgedSends(cap_endpoint_cap_get_capEPPtr(cap) as *mut endpoint_t, badge);
+        crate::endpoint::cancelBadgedSends(crate::untyped::object_of(cap_endpoint_cap_get_capEPPtr(cap)), badge);
     }
     0u64
 }
@@ -294,8 +351,8 @@ pub unsafe fn invokeCNodeCancelBadgedSends(cap: cap_t) -> u64 {
 #[no_mangle]
 pub unsafe fn invokeCNodeInsert(
     cap: cap_t,
-    srcSlot: Arc<RwLock<cte_t>>,
-    destSlot: Arc<RwLock<cte_t>>,
+    srcSlot: *mut cte_t,
+    destSlot: *mut cte_t,
 ) -> u64 {
     cteInsert(cap, srcSlot, destSlot);
     0u64
@@ -304,8 +361,8 @@ pub unsafe fn invokeCNodeInsert(
 #[no_mangle]
 pub unsafe fn invokeCNodeMove(
     cap: cap_t,
-    srcSlot: Arc<RwLock<cte_t>>,
-    destSlot: Arc<RwLock<cte_t>>,
+    srcSlot: *mut cte_t,
+    destSlot: *mut cte_t,
 ) -> u64 {
     cteMove(cap, srcSlot, destSlot);
     0u64
@@ -315,30 +372,29 @@ pub unsafe fn invokeCNodeMove(
 pub unsafe fn invokeCNodeRotate(
     cap1: cap_t,
     cap2: cap_t,
-    slot1: Arc<RwLock<cte_t>>,
-    slot2: Arc<RwLock<cte_t>>,
-    slot3: Arc<RwLock<cte_t>>,
+    slot1: *mut cte_t,
+    slot2: *mut cte_t,
+    slot3: *mut cte_t,
 ) -> u64 {
-    if Arc::ptr_eq(&slot1, &slot3) {
-        cteSwap(cap1, slot1.clone(), cap2, slot2.clone());
+    if slot1 == slot3 {
+        cteSwap(cap1, slot1, cap2, slot2);
     } else {
-        cteMove(cap2, slot2.clone(), slot3.clone());
-        cteMove(cap1, slot1.clone(), slot2.clone());
+        cteMove(cap2, slot2, slot3);
+        cteMove(cap1, slot1, slot2);
     }
     0u64
 }
 
 #[no_mangle]
-pub unsafe fn invokeCNodeSaveCaller(destSlot: Arc<RwLock<cte_t>>) -> u64 {
+pub unsafe fn invokeCNodeSaveCaller(destSlot: *mut cte_t) -> u64 {
     let srcSlot = tcb_ptr_cte_ptr(get_ptr_from_handle!(get_current_task_handle!()), tcb_cnode_index::tcbCaller as u64);
     let cap = (*srcSlot).cap;
-    let srcSlot = Arc::from_raw(srcSlot);
     let cap_type = cap_get_capType(cap);
     if cap_type == cap_tag_t::cap_null_cap as u64 {
         //userError!("CNode SaveCaller: Reply cap not present.")
     } else if cap_type == cap_tag_t::cap_reply_cap as u64 {
         if cap_reply_cap_get_capReplyMaster(cap) == 0u64 {
-            cteMove(cap, Arc::new(RwLock::new(*srcSlot.clone())), destSlot.clone());
+            cteMove(cap, srcSlot, destSlot);
         }
     } else {
         panic!("caller capability must be null or reply");
@@ -346,7 +402,7 @@ pub unsafe fn invokeCNodeSaveCaller(destSlot: Arc<RwLock<cte_t>>) -> u64 {
     0u64
 }
 
-unsafe fn setUntypedCapAsFull(srcCap: cap_t, newCap: cap_t, srcSlot: Arc<RwLock<cte_t>>) {
+unsafe fn setUntypedCapAsFull(srcCap: cap_t, newCap: cap_t, srcSlot: *mut cte_t) {
     if cap_get_capType(srcCap) == cap_tag_t::cap_untyped_cap as u64
         && cap_get_capType(newCap) == cap_tag_t::cap_untyped_cap as u64
     {
@@ -354,7 +410,7 @@ unsafe fn setUntypedCapAsFull(srcCap: cap_t, newCap: cap_t, srcSlot: Arc<RwLock<
             && cap_untyped_cap_get_capBlockSize(newCap) == cap_untyped_cap_get_capBlockSize(srcCap)
         {
             cap_untyped_cap_ptr_set_capFreeIndex(
-                &mut srcSlot.write().unwrap().cap,
+                &mut (*srcSlot).cap,
                 (1 << cap_untyped_cap_get_capBlockSize(srcCap)) - 4,
             );
         }
@@ -362,88 +418,83 @@ unsafe fn setUntypedCapAsFull(srcCap: cap_t, newCap: cap_t, srcSlot: Arc<RwLock<
 }
 
 #[no_mangle]
-pub unsafe fn cteInsert(newCap: cap_t, srcSlot: Arc<RwLock<cte_t>>, destSlot: Arc<RwLock<cte_t>>) {
-    // owned
-    let srcMDB: mdb_node_t = srcSlot.clone().read().unwrap().cteMDBNode; // (*srcSlot).cteMDBNode;
-    let srcCap: cap_t = srcSlot.clone().read().unwrap().cap; // (*srcSlot).cap;
+pub unsafe fn cteInsert(newCap: cap_t, srcSlot: *mut cte_t, destSlot: *mut cte_t) {
+    let srcMDB: mdb_node_t = (*srcSlot).cteMDBNode;
+    let srcCap: cap_t = (*srcSlot).cap;
     let newCapIsRevocable: u64 = isCapRevocable(newCap, srcCap);
-    let mut newMDB = mdb_node_set_mdbPrev(srcMDB, Arc::as_ptr(&srcSlot) as u64);
+    let mut newMDB = mdb_node_set_mdbPrev(srcMDB, srcSlot as u64);
     newMDB = mdb_node_set_mdbRevocable(newMDB, newCapIsRevocable);
     newMDB = mdb_node_set_mdbFirstBadged(newMDB, newCapIsRevocable);
-    setUntypedCapAsFull(srcCap, newCap, srcSlot.clone());
-    // (*destSlot).cap = newCap;
-    destSlot.write().unwrap().cap = newCap;
-    // (*destSlot) = newMDB;
-    destSlot.write().unwrap().cteMDBNode = newMDB;
-    mdb_node_ptr_set_mdbNext(&mut srcSlot.write().unwrap().cteMDBNode, Arc::as_ptr(&destSlot) as u64);
+    setUntypedCapAsFull(srcCap, newCap, srcSlot);
+    (*destSlot).cap = newCap;
+    (*destSlot).cteMDBNode = newMDB;
+    mdb_node_ptr_set_mdbNext(&mut (*srcSlot).cteMDBNode, destSlot as u64);
     if mdb_node_get_mdbNext(newMDB) != 0u64 {
         mdb_node_ptr_set_mdbPrev(
             &mut (*(mdb_node_get_mdbNext(newMDB) as *mut cte_t)).cteMDBNode,
-            Arc::as_ptr(&destSlot) as u64
+            destSlot as u64,
         );
     }
 }
 
 #[no_mangle]
-pub unsafe fn cteMove(newCap: cap_t, srcSlot: Arc<RwLock<cte_t>>, destSlot: Arc<RwLock<cte_t>>) {
-    let mdb: mdb_node_t = srcSlot.clone().read().unwrap().cteMDBNode; // (*srcSlot).cteMDBNode;
-    destSlot.write().unwrap().cap = newCap;
-    srcSlot.write().unwrap().cap = cap_null_cap_new();
-    destSlot.write().unwrap().cteMDBNode = mdb;
-    srcSlot.write().unwrap().cteMDBNode = mdb_node_new(0, 0, 0, 0);
+pub unsafe fn cteMove(newCap: cap_t, srcSlot: *mut cte_t, destSlot: *mut cte_t) {
+    let mdb: mdb_node_t = (*srcSlot).cteMDBNode;
+    (*destSlot).cap = newCap;
+    (*srcSlot).cap = cap_null_cap_new();
+    (*destSlot).cteMDBNode = mdb;
+    (*srcSlot).cteMDBNode = mdb_node_new(0, 0, 0, 0);
     let prev_ptr: u64 = mdb_node_get_mdbPrev(mdb);
     if prev_ptr != 0u64 {
-        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&destSlot) as u64);
+        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, destSlot as u64);
     }
     let next_ptr: u64 = mdb_node_get_mdbNext(mdb);
     if next_ptr != 0u64 {
-        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&destSlot) as u64);
+        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, destSlot as u64);
     }
 }
 
 #[no_mangle]
-pub unsafe fn capSwapForDelete(slot1: Arc<RwLock<cte_t>>, slot2: Arc<RwLock<cte_t>>) {
-    if Arc::ptr_eq(&slot1, &slot2) {
+pub unsafe fn capSwapForDelete(slot1: *mut cte_t, slot2: *mut cte_t) {
+    if slot1 == slot2 {
         return;
     }
-    let cap1 = slot1.clone().read().unwrap().cap;
-    let cap2 = slot2.clone().read().unwrap().cap;
+    let cap1 = (*slot1).cap;
+    let cap2 = (*slot2).cap;
     cteSwap(cap1, slot1, cap2, slot2);
 }
 
 #[no_mangle]
-pub unsafe fn cteSwap(cap1: cap_t, slot1: Arc<RwLock<cte_t>>, cap2: cap_t, slot2: Arc<RwLock<cte_t>>) {
-    slot1.write().unwrap().cap = cap2;
-    slot2.write().unwrap().cap = cap1;
-    let mdb1: mdb_node_t = slot1.read().unwrap().cteMDBNode;
+pub unsafe fn cteSwap(cap1: cap_t, slot1: *mut cte_t, cap2: cap_t, slot2: *mut cte_t) {
+    (*slot1).cap = cap2;
+    (*slot2).cap = cap1;
+    let mdb1: mdb_node_t = (*slot1).cteMDBNode;
     let mut prev_ptr: u64 = mdb_node_get_mdbPrev(mdb1);
     if prev_ptr != 0u64 {
-        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&slot2) as u64);
+        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, slot2 as u64);
     }
     let mut next_ptr: u64 = mdb_node_get_mdbNext(mdb1);
     if next_ptr != 0u64 {
-        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&slot2) as u64);
+        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, slot2 as u64);
     }
-    let mdb2: mdb_node_t = slot2.read().unwrap().cteMDBNode;
-    slot1.write().unwrap().cteMDBNode = mdb2;
-    slot2.write().unwrap().cteMDBNode = mdb1;
-
+    let mdb2: mdb_node_t = (*slot2).cteMDBNode;
+    (*slot1).cteMDBNode = mdb2;
+    (*slot2).cteMDBNode = mdb1;
     prev_ptr = mdb_node_get_mdbPrev(mdb2);
     if prev_ptr != 0u64 {
-        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&slot1) as u64);
+        mdb_node_ptr_set_mdbNext(&mut (*(prev_ptr as *mut cte_t)).cteMDBNode, slot1 as u64);
     }
     next_ptr = mdb_node_get_mdbNext(mdb2);
     if next_ptr != 0u64 {
-        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, Arc::as_ptr(&slot1) as u64);
+        mdb_node_ptr_set_mdbPrev(&mut (*(next_ptr as *mut cte_t)).cteMDBNode, slot1 as u64);
     }
 }
 
 #[no_mangle]
-pub unsafe fn cteRevoke(slot: Arc<RwLock<cte_t>>) -> u64 {
-    let mut ret = mdb_node_get_mdbNext(slot.read().unwrap().cteMDBNode) as *mut cte_t;
-    let mut nextPtr: Arc<RwLock<cte_t>> = Arc::new(RwLock::new(*ret));
-    while Arc::as_ptr(&nextPtr.clone()) as u64 != 0u64 && isMDBParentOf(slot.clone(), nextPtr.clone()) != 0u64 {
-        let mut status: u64 = cteDelete(nextPtr.clone(), true as u64);
+pub unsafe fn cteRevoke(slot: *mut cte_t) -> u64 {
+    let mut nextPtr: *mut cte_t = mdb_node_get_mdbNext((*slot).cteMDBNode) as *mut cte_t;
+    while nextPtr as u64 != 0u64 && isMDBParentOf(slot, nextPtr) != 0u64 {
+        let mut status: u64 = cteDelete(nextPtr, true as u64);
         if status != 0u64 {
             return status;
         }
@@ -451,92 +502,87 @@ pub unsafe fn cteRevoke(slot: Arc<RwLock<cte_t>>) -> u64 {
         if status != 0u64 {
             return status;
         }
-        ret = mdb_node_get_mdbNext(slot.write().unwrap().cteMDBNode) as *mut cte_t;
-        // nextPtr = Arc::from_raw();
-        *nextPtr.write().unwrap() = *ret;
+        nextPtr = mdb_node_get_mdbNext((*slot).cteMDBNode) as *mut cte_t;
     }
     0u64
 }
 
 #[no_mangle]
-pub unsafe fn cteDelete(slot: Arc<RwLock<cte_t>>, exposed: bool_t) -> u64 {
-    let fs_ret: finaliseSlot_ret_t = finaliseSlot(slot.clone(), exposed);
+pub unsafe fn cteDelete(slot: *mut cte_t, exposed: bool_t) -> u64 {
+    let fs_ret: finaliseSlot_ret_t = finaliseSlot(slot, exposed);
     if fs_ret.status != 0u64 {
         return fs_ret.status;
     }
     if exposed != 0u64 || fs_ret.success != 0u64 {
-        emptySlot(slot.clone(), fs_ret.cleanupInfo);
+        emptySlot(slot, fs_ret.cleanupInfo);
     }
     0u64
 }
 
 #[no_mangle]
-pub unsafe fn emptySlot(slot: Arc<RwLock<cte_t>>, cleanupInfo: cap_t) {
-    if cap_get_capType(slot.read().unwrap().cap) != cap_tag_t::cap_null_cap as u64 {
-        let mdbNode: mdb_node_t = slot.read().unwrap().cteMDBNode; // (*slot).cteMDBNode;
-        let prev = Arc::new(RwLock::new(*(mdb_node_get_mdbPrev(mdbNode) as *mut cte_t)));
-        let next = Arc::new(RwLock::new(*(mdb_node_get_mdbNext(mdbNode) as *mut cte_t)));
-        if Arc::as_ptr(&prev) as u64 != 0u64 {
-            mdb_node_ptr_set_mdbNext(&mut prev.write().unwrap().cteMDBNode, Arc::as_ptr(&next) as u64);
-        }
-        if Arc::as_ptr(&next) as u64 != 0u64 {
-            mdb_node_ptr_set_mdbPrev(&mut next.write().unwrap().cteMDBNode, Arc::as_ptr(&prev) as u64);
-        }
-        if Arc::as_ptr(&next) as u64 != 0u64 {
+pub unsafe fn emptySlot(slot: *mut cte_t, cleanupInfo: cap_t) {
+    if cap_get_capType((*slot).cap) != cap_tag_t::cap_null_cap as u64 {
+        let mdbNode: mdb_node_t = (*slot).cteMDBNode;
+        let prev = mdb_node_get_mdbPrev(mdbNode) as *mut cte_t;
+        let next = mdb_node_get_mdbNext(mdbNode) as *mut cte_t;
+        if prev as u64 != 0u64 {
+            mdb_node_ptr_set_mdbNext(&mut (*prev).cteMDBNode, next as u64);
+        }
+        if next as u64 != 0u64 {
+            mdb_node_ptr_set_mdbPrev(&mut (*next).cteMDBNode, prev as u64);
+        }
+        if next as u64 != 0u64 {
             mdb_node_ptr_set_mdbFirstBadged(
-                &mut next.write().unwrap().cteMDBNode,
-                mdb_node_get_mdbFirstBadged(next.write().unwrap().cteMDBNode)
+                &mut (*next).cteMDBNode,
+                mdb_node_get_mdbFirstBadged((*next).cteMDBNode)
                     | mdb_node_get_mdbFirstBadged(mdbNode),
             );
         }
-        slot.write().unwrap().cteMDBNode = mdb_node_new(0, 0, 0, 0);
-        slot.write().unwrap().cap = cap_null_cap_new();
+        (*slot).cap = cap_null_cap_new();
+        (*slot).cteMDBNode = mdb_node_new(0, 0, 0, 0);
         postCapDeletion(cleanupInfo);
     }
 }
 
 #[inline]
-unsafe fn capRemovable(cap: cap_t, slot: Arc<RwLock<cte_t>>) -> bool {
+unsafe fn capRemovable(cap: cap_t, slot: *mut cte_t) -> bool {
     let cap_type = cap_get_capType(cap);
     if cap_type == cap_tag_t::cap_null_cap as u64 {
         return true;
     } else if cap_type == cap_tag_t::cap_zombie_cap as u64 {
         let n = cap_zombie_cap_get_capZombieNumber(cap);
-        let ret = cap_zombie_cap_get_capZombiePtr(cap) as *mut cte_t;
-        let z_slot = Arc::new(RwLock::new(*ret));
-        return n == 0 || (n == 1 && Arc::ptr_eq(&slot, &z_slot));
+        let z_slot = cap_zombie_cap_get_capZombiePtr(cap) as *mut cte_t;
+        return n == 0 || (n == 1 && slot == z_slot);
     }
     panic!("finaliseCap should only return Zombie or NullCap")
 }
 
 #[inline]
-unsafe fn capCyclicZombie(cap: cap_t, slot: Arc<RwLock<cte_t>>) -> bool {
-    let ret = cap_zombie_cap_get_capZombiePtr(cap) as *mut cte_t;
-
+unsafe fn capCyclicZombie(cap: cap_t, slot: *mut cte_t) -> bool {
     cap_get_capType(cap) == cap_tag_t::cap_zombie_cap as u64
-        && Arc::ptr_eq(&Arc::new(RwLock::new(*ret)), &slot)
+        && cap_zombie_cap_get_capZombiePtr(cap) as *mut cte_t == slot
 }
 
-unsafe fn finaliseSlot(slot: Arc<RwLock<cte_t>>, immediate: bool_t) -> finaliseSlot_ret_t {
-    while cap_get_capType(slot.clone().read().unwrap().cap) != cap_tag_t::cap_null_cap as u64 {
-        let final_: u64 = isFinalCapability(slot.clone());
-        let fc_ret = finaliseCap(slot.clone().read().unwrap().cap, final_, 0u64);
-        if capRemovable(fc_ret.remainder, slot.clone()) {
+unsafe fn finaliseSlot(slot: *mut cte_t, immediate: bool_t) -> finaliseSlot_ret_t {
+    while cap_get_capType((*slot).cap) != cap_tag_t::cap_null_cap as u64 {
+        let final_: u64 = isFinalCapability(slot);
+        let fc_ret = finaliseCap((*slot).cap, final_, 0u64);
+        if capRemovable(fc_ret.remainder, slot) {
             return finaliseSlot_ret_t {
                 status: 0u64,
                 success: 1u64,
                 cleanupInfo: fc_ret.cleanupInfo,
             };
         }
-        slot.clone().write().unwrap().cap = fc_ret.remainder;
-        if immediate == 0u64 && capCyclicZombie(fc_ret.remainder, slot.clone()) {
+        (*slot).cap = fc_ret.remainder;
+        if immediate == 0u64 && capCyclicZombie(fc_ret.remainder, slot) {
             return finaliseSlot_ret_t {
                 status: 0u64,
                 success: 0u64,
                 cleanupInfo: fc_ret.cleanupInfo,
             };
         }
-        let mut status = reduceZombie(slot.clone(), immediate);
+        let mut status = reduceZombie(slot, immediate);
         if status != 0u64 {
             return finaliseSlot_ret_t {
                 status: status,
@@ -560,55 +606,55 @@ unsafe fn finaliseSlot(slot: Arc<RwLock<cte_t>>, immediate: bool_t) -> finaliseS
     }
 }
 
-unsafe fn reduceZombie(slot: Arc<RwLock<cte_t>>, immediate: bool_t) -> u64 {
-    let ptr = Arc::from_raw(cap_zombie_cap_get_capZombiePtr(slot.read().unwrap().cap) as *mut cte_t);
-    let n = cap_zombie_cap_get_capZombieNumber(slot.read().unwrap().cap);
-    let type_ = cap_zombie_cap_get_capZombieType(slot.read().unwrap().cap);
+unsafe fn reduceZombie(slot: *mut cte_t, immediate: bool_t) -> u64 {
+    let ptr = cap_zombie_cap_get_capZombiePtr((*slot).cap) as *mut cte_t;
+    let n = cap_zombie_cap_get_capZombieNumber((*slot).cap);
+    let type_ = cap_zombie_cap_get_capZombieType((*slot).cap);
     if immediate == 1u64 {
-        let endSlot = Arc::from_raw(Arc::as_ptr(&ptr).offset((n - 1) as isize));
-        let status = cteDelete(Arc::new(RwLock::new(*endSlot)), 0u64);
+        let endSlot = ptr.offset((n - 1) as isize);
+        let status = cteDelete(endSlot, 0u64);
         if status != 0u64 {
             return status;
         }
-        let cap_type = cap_get_capType(slot.read().unwrap().cap);
+        let cap_type = cap_get_capType((*slot).cap);
         if cap_type == cap_tag_t::cap_null_cap as u64 {
         } else if cap_type == cap_tag_t::cap_zombie_cap as u64 {
-            let ptr2 = Arc::from_raw(cap_zombie_cap_get_capZombiePtr(slot.read().unwrap().cap) as *mut cte_t);
+            let ptr2 = cap_zombie_cap_get_capZombiePtr((*slot).cap) as *mut cte_t;
             if ptr == ptr2
-                && cap_zombie_cap_get_capZombieNumber(slot.read().unwrap().cap) == n
-                && cap_zombie_cap_get_capZombieType(slot.read().unwrap().cap) == type_
+                && cap_zombie_cap_get_capZombieNumber((*slot).cap) == n
+                && cap_zombie_cap_get_capZombieType((*slot).cap) == type_
             {
-                slot.write().unwrap().cap = cap_zombie_cap_set_capZombieNumber(slot.read().unwrap().cap, n - 1);
+                (*slot).cap = cap_zombie_cap_set_capZombieNumber((*slot).cap, n - 1);
             }
         } else {
             panic!("Expected recursion to result in Zombie.");
         }
     } else {
-        capSwapForDelete(Arc::new(RwLock::new(*ptr)), slot);
+        capSwapForDelete(ptr, slot);
     }
     0u64
 }
 
 // #[allow(unused_variables)]
 #[no_mangle]
-pub unsafe fn cteDeleteOne(slot: Arc<RwLock<cte_t>>) {
-    let cap_type = cap_get_capType(slot.clone().read().unwrap().cap);
+pub unsafe fn cteDeleteOne(slot: *mut cte_t) {
+    let cap_type = cap_get_capType((*slot).cap);
     if cap_type != cap_tag_t::cap_null_cap as u64 {
-        let final_ = isFinalCapability(slot.clone());
-        let fc_ret = finaliseCap(slot.clone().read().unwrap().cap, final_, 1u64);
+        let final_ = isFinalCapability(slot);
+        let fc_ret = finaliseCap((*slot).cap, final_, 1u64);
         emptySlot(slot, cap_null_cap_new());
     }
 }
 
 #[no_mangle]
-pub unsafe fn insertNewCap(parent: Arc<RwLock<cte_t>>, slot: Arc<RwLock<cte_t>>, cap: cap_t) {
-    let next = Arc::new(RwLock::new(*(mdb_node_get_mdbNext(parent.read().unwrap().cteMDBNode) as *mut cte_t)));
-    slot.clone().write().unwrap().cap = cap;
-    slot.clone().write().unwrap().cteMDBNode = mdb_node_new(Arc::as_ptr(&next) as u64, 1u64, 1u64, Arc::as_ptr(&parent) as u64);
-    if Arc::as_ptr(&next) as u64 != 0u64 {
-        mdb_node_ptr_set_mdbPrev(&mut next.write().unwrap().cteMDBNode, Arc::as_ptr(&slot) as u64);
+pub unsafe fn insertNewCap(parent: *mut cte_t, slot: *mut cte_t, cap: cap_t) {
+    let next = mdb_node_get_mdbNext((*parent).cteMDBNode) as *mut cte_t;
+    (*slot).cap = cap;
+    (*slot).cteMDBNode = mdb_node_new(next as u64, 1u64, 1u64, parent as u64);
+    if next as u64 != 0u64 {
+        mdb_node_ptr_set_mdbPrev(&mut (*next).cteMDBNode, slot as u64);
     }
-    mdb_node_ptr_set_mdbNext(&mut parent.write().unwrap().cteMDBNode, Arc::as_ptr(&slot) as u64);
+    mdb_node_ptr_set_mdbNext(&mut (*parent).cteMDBNode, slot as u64);
 }
 
 #[no_mangle]
@@ -624,37 +670,37 @@ pub unsafe fn setupReplyMaster(thread: &mut TaskHandle) {
 }
 
 #[no_mangle]
-pub unsafe fn isMDBParentOf(cte_a: Arc<RwLock<cte_t>>, cte_b: Arc<RwLock<cte_t>>) -> bool_t {
-    if mdb_node_get_mdbRevocable(cte_a.read().unwrap().cteMDBNode) == 0u64 {
+pub unsafe fn isMDBParentOf(cte_a: *mut cte_t, cte_b: *mut cte_t) -> bool_t {
+    if mdb_node_get_mdbRevocable((*cte_a).cteMDBNode) == 0u64 {
         return 0u64;
     }
-    if sameRegionAs(cte_a.read().unwrap().cap, cte_b.read().unwrap().cap) == 0u64 {
+    if sameRegionAs((*cte_a).cap, (*cte_b).cap) == 0u64 {
         return 0u64;
     }
-    let cap_type = cap_get_capType(cte_a.read().unwrap().cap);
+    let cap_type = cap_get_capType((*cte_a).cap);
     if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
-        let badge = cap_endpoint_cap_get_capEPBadge(cte_a.read().unwrap().cap);
+        let badge = cap_endpoint_cap_get_capEPBadge((*cte_a).cap);
         if badge == 0u64 {
             return 1u64;
         }
-        return ((badge == cap_endpoint_cap_get_capEPBadge(cte_a.read().unwrap().cap))
-            && mdb_node_get_mdbFirstBadged(cte_b.read().unwrap().cteMDBNode) == 0u64) as u64;
+        return ((badge == cap_endpoint_cap_get_capEPBadge((*cte_b).cap))
+            && mdb_node_get_mdbFirstBadged((*cte_b).cteMDBNode) == 0u64) as u64;
     } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
-        let badge = cap_notification_cap_get_capNtfnBadge(cte_a.read().unwrap().cap);
+        let badge = cap_notification_cap_get_capNtfnBadge((*cte_a).cap);
         if badge == 0u64 {
             return 1u64;
         }
-        return ((badge == cap_notification_cap_get_capNtfnBadge(cte_b.read().unwrap().cap))
-            && mdb_node_get_mdbFirstBadged(cte_b.read().unwrap().cteMDBNode) == 0u64) as u64;
+        return ((badge == cap_notification_cap_get_capNtfnBadge((*cte_b).cap))
+            && mdb_node_get_mdbFirstBadged((*cte_b).cteMDBNode) == 0u64) as u64;
     }
     1u64
 }
 
 #[no_mangle]
-pub unsafe fn ensureNoChildren(slot: Arc<RwLock<cte_t>>) -> u64 {
-    if mdb_node_get_mdbNext(slot.read().unwrap().cteMDBNode) != 0u64 {
-        let next = Arc::from_raw(mdb_node_get_mdbNext(slot.read().unwrap().cteMDBNode) as *mut cte_t);
-        if isMDBParentOf(slot, Arc::new(RwLock::new(*next))) != 0u64 {
+pub unsafe fn ensureNoChildren(slot: *mut cte_t) -> u64 {
+    if mdb_node_get_mdbNext((*slot).cteMDBNode) != 0u64 {
+        let next = mdb_node_get_mdbNext((*slot).cteMDBNode) as *mut cte_t;
+        if isMDBParentOf(slot, next) != 0u64 {
             current_syscall_error.type_ = seL4_Error::seL4_RevokeFirst as u64;
             return exception::EXCEPTION_SYSCALL_ERROR as u64;
         }
@@ -663,8 +709,8 @@ pub unsafe fn ensureNoChildren(slot: Arc<RwLock<cte_t>>) -> u64 {
 }
 
 #[no_mangle]
-pub unsafe fn ensureEmptySlot(slot: Arc<RwLock<cte_t>>) -> u64 {
-    if cap_get_capType(slot.read().unwrap().cap) != cap_tag_t::cap_null_cap as u64 {
+pub unsafe fn ensureEmptySlot(slot: *mut cte_t) -> u64 {
+    if cap_get_capType((*slot).cap) != cap_tag_t::cap_null_cap as u64 {
         current_syscall_error.type_ = seL4_Error::seL4_DeleteFirst as u64;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
@@ -672,29 +718,27 @@ pub unsafe fn ensureEmptySlot(slot: Arc<RwLock<cte_t>>) -> u64 {
 }
 
 #[no_mangle]
-pub unsafe fn isFinalCapability(cte: Arc<RwLock<cte_t>>) -> bool_t {
-    let mdb = cte.read().unwrap().cteMDBNode;
+pub unsafe fn isFinalCapability(cte: *mut cte_t) -> bool_t {
+    let mdb = (*cte).cteMDBNode;
     let prevIsSameObject: bool = if mdb_node_get_mdbPrev(mdb) == 0u64 {
         false
     } else {
-        let prev = Arc::from_raw(mdb_node_get_mdbPrev(mdb) as *mut cte_t);
-        sameObjectAs((*prev).cap, cte.read().unwrap().cap) == 1u64 // 奇行种呜呜呜
+        let prev = mdb_node_get_mdbPrev(mdb) as *mut cte_t;
+        sameObjectAs((*prev).cap, (*cte).cap) == 1u64
     };
     if prevIsSameObject {
-        return 0u64;
+        0u64
+    } else if mdb_node_get_mdbNext(mdb) == 0u64 {
+        1u64
     } else {
-        if mdb_node_get_mdbNext(mdb) == 0u64 {
-            return 1u64;
-        } else {
-            let next = Arc::from_raw(mdb_node_get_mdbNext(mdb) as *mut cte_t);
-            return sameObjectAs(cte.read().unwrap().cap, (*next).cap);
-        }
+        let next = mdb_node_get_mdbNext(mdb) as *mut cte_t;
+        (sameObjectAs((*cte).cap, (*next).cap) == 0u64) as u64
     }
 }
 
 #[no_mangle]
-pub unsafe fn slotCapLongRunningDelete(slot: Arc<RwLock<cte_t>>) -> bool_t {
-    let cap_type = cap_get_capType(slot.read().unwrap().cap);
+pub unsafe fn slotCapLongRunningDelete(slot: *mut cte_t) -> bool_t {
+    let cap_type = cap_get_capType((*slot).cap);
     if cap_type == cap_tag_t::cap_null_cap as u64 || isFinalCapability(slot) == 0u64 {
         return 0u64;
     }
@@ -708,7 +752,7 @@ pub unsafe fn slotCapLongRunningDelete(slot: Arc<RwLock<cte_t>>) -> bool_t {
 }
 
 #[no_mangle]
-pub unsafe fn getReceiveSlots(thread: &mut TaskHandle, buffer: *mut u64) -> Result<Arc<RwLock<cte_t>>, FreeRtosError> {
+pub unsafe fn getReceiveSlots(thread: &mut TaskHandle, buffer: *mut u64) -> Result<*mut cte_t, FreeRtosError> {
     let thread_ptr = get_ptr_from_handle!(thread);
     if buffer as u64 == 0u64 {
         return Err(FreeRtosError::Ajkaierdja);
@@ -728,7 +772,7 @@ pub unsafe fn getReceiveSlots(thread: &mut TaskHandle, buffer: *mut u64) -> Resu
     if cap_get_capType((*slot).cap) != cap_tag_t::cap_null_cap as u64 {
         return Err(FreeRtosError::Ajkaierdja);
     }
-    Ok(Arc::new(RwLock::new(*slot)))
+    Ok(slot)
 }
 
 #[no_mangle]
@@ -740,10 +784,7 @@ pub unsafe fn loadCapTransfer(buffer: *mut u64) -> cap_transfer_t {
 // objecttype.rs
 #[inline]
 pub unsafe fn postCapDeletion(cap: cap_t) {
-    if cap_get_capType(cap) == cap_tag_t::cap_irq_handler_cap as u64 {
-        let irq: u8 = cap_irq_handler_cap_get_capIRQ(cap) as u8;
-        deletedIRQHandler(irq);
-    } else if isArchCap(cap) != 0u64 {
-        Arch_postCapDeletion(cap);
-    }
+    /* There are no IRQ handlers nor arch caps in the port, so nothing is
+    left to clean up once the cap is deleted. */
+    let _ = cap;
 }
@@ -9,6 +9,7 @@
 use crate::arch_structures_TCB::*;
 use crate::task_control_cap::*;
 use crate::types::*;
+use crate::task_global::*;
 use std::io::Take;
 use std::sync::{ Arc, RwLock };
 use std::ptr::*;
@@ -22,31 +23,31 @@ pub struct lookupCap_ret_t {
 }
 
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub struct lookupCapAndSlot_ret_t {
     pub status: u64,
     pub cap: cap_t,
-    pub slot: Box<cte_t>,
+    pub slot: *mut cte_t,
 }
 
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub struct lookupSlot_raw_ret_t {
     pub status: u64,
-    pub slot: Box<cte_t>,
+    pub slot: *mut cte_t,
 }
 
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub struct lookupSlot_ret_t {
     pub status: u64,
-    pub slot: Box<cte_t>,
+    pub slot: *mut cte_t,
 }
 
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 pub struct resolveAddressBits_ret_t {
     pub status: u64,
-    pub slot: Box<cte_t>,
+    pub slot: *mut cte_t,
     pub bitsRemaining: u64,
 }
 
@@ -66,9 +67,7 @@ pub unsafe fn lookupCap(thread: &mut TaskHandle, capptr: u64) -> lookupCap_ret_t
 }
 
 pub unsafe fn lookupSlot(thread: &mut TaskHandle, capptr: u64) -> lookupSlot_raw_ret_t {
-    // let thread_ptr = get_ptr_from_handle!(thread);
-    let threadRoot : cap_t = thread.0.clone().read().unwrap().ctable.caps[0].cap;
-    // (*tcb_ptr_cte_ptr(thread_ptr, tcb_cnode_index::tcbCTable as u64)).cap;
+    let threadRoot: cap_t = thread.get_cspace_root();
     let res_ret = resolveAddressBits(thread, threadRoot, capptr, wordBits);
     lookupSlot_raw_ret_t {
         status: res_ret.status,
@@ -83,7 +82,7 @@ pub unsafe fn lookupCapAndSlot(thread: &mut TaskHandle, capptr: u64) -> lookupCa
         return lookupCapAndSlot_ret_t {
             status: lu_ret.status,
             cap: cap_null_cap_new(),
-            slot: Box::from_raw(null_mut()) // Arc::from_raw(null_mut())
+            slot: null_mut()
         };
     }
     lookupCapAndSlot_ret_t {
@@ -108,7 +107,7 @@ pub unsafe fn lookupSlotForCNodeOp(
         current_lookup_fault = lookup_fault_invalid_root_new();
         return lookupSlot_ret_t {
             status: exception::EXCEPTION_SYSCALL_ERROR as u64,
-            slot: Box::from_raw(null_mut())
+            slot: null_mut()
         };
     }
 
@@ -118,7 +117,7 @@ pub unsafe fn lookupSlotForCNodeOp(
         current_syscall_error.rangeErrorMax = wordBits;
         return lookupSlot_ret_t {
             status: exception::EXCEPTION_SYSCALL_ERROR as u64,
-            slot: Box::from_raw(null_mut())
+            slot: null_mut()
         };
     }
 
@@ -128,7 +127,7 @@ pub unsafe fn lookupSlotForCNodeOp(
         current_syscall_error.failedLookupWasSource = isSource;
         return lookupSlot_ret_t {
             status: exception::EXCEPTION_SYSCALL_ERROR as u64,
-            slot: Box::from_raw(null_mut())
+            slot: null_mut()
         };
     }
     if res_ret.bitsRemaining != 0 {
@@ -137,7 +136,7 @@ pub unsafe fn lookupSlotForCNodeOp(
         current_lookup_fault = lookup_fault_depth_mismatch_new(0, res_ret.bitsRemaining);
         return lookupSlot_ret_t {
             status: exception::EXCEPTION_SYSCALL_ERROR as u64,
-            slot: Box::from_raw(null_mut())
+            slot: null_mut()
         };
     }
     lookupSlot_ret_t {
@@ -186,7 +185,7 @@ pub unsafe fn resolveAddressBits(
 ) -> resolveAddressBits_ret_t { // i literally think this is not idiomatic rust (should use Result and propagate errors)
     let mut ret = resolveAddressBits_ret_t {
         status: 0u64,
-        slot: Box::from_raw(null_mut()),
+        slot: null_mut(),
         bitsRemaining: n_bits,
     };
     if cap_get_capType(nodeCap) != cap_tag_t::cap_cnode_cap as u64 {
@@ -221,7 +220,7 @@ pub unsafe fn resolveAddressBits(
         let slot : *mut cte_t = (cap_cnode_cap_get_capCNodePtr(nodeCap) as *mut cte_t).offset(offset as isize);
         if n_bits <= levelBits {
             ret.status = 0u64;
-            ret.slot = Box::from_raw(slot);
+            ret.slot = slot;
             ret.bitsRemaining = 0;
             return ret;
         }
@@ -229,7 +228,7 @@ pub unsafe fn resolveAddressBits(
         nodeCap = (*slot).cap;
         if cap_get_capType(nodeCap) != cap_tag_t::cap_cnode_cap as u64 {
             ret.status = exception::EXCEPTION_NONE as u64;
-            ret.slot = Box::from_raw(slot);
+            ret.slot = slot;
             ret.bitsRemaining = n_bits;
             return ret;
         }
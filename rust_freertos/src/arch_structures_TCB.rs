@@ -195,6 +195,24 @@ pub fn isArchCap(cap: cap_t) -> word_t {
     cap_get_capType(cap) % 2
 }
 
+#[inline]
+pub fn cap_cnode_cap_new(
+    capCNodeGuard: u64,
+    capCNodeGuardSize: u64,
+    capCNodeRadix: u64,
+    capCNodePtr: u64,
+) -> cap_t {
+    cap_t {
+        words: [
+            ((cap_tag_t::cap_cnode_cap as u64 & 0x1fu64) << 59)
+                | ((capCNodeGuardSize & 0x3fu64) << 53)
+                | ((capCNodeRadix & 0x3fu64) << 47)
+                | ((capCNodePtr & 0xfffffffffffeu64) >> 1),
+            capCNodeGuard,
+        ],
+    }
+}
+
 #[inline]
 pub fn cap_cnode_cap_get_capCNodeRadix(cap: cap_t) -> u64 {
     (cap.words[0] & 0x1f800000000000u64) >> 47
@@ -224,6 +242,26 @@ pub fn cap_cnode_cap_set_capCNodeGuardSize(mut cap: cap_t, v64: u64) -> cap_t {
     cap
 }
 
+#[inline]
+pub fn cap_endpoint_cap_new(
+    capEPBadge: u64,
+    capCanGrant: u64,
+    capCanReceive: u64,
+    capCanSend: u64,
+    capEPPtr: u64,
+) -> cap_t {
+    cap_t {
+        words: [
+            ((cap_tag_t::cap_endpoint_cap as u64 & 0x1fu64) << 59)
+                | ((capCanGrant & 0x1u64) << 58)
+                | ((capCanReceive & 0x1u64) << 57)
+                | ((capCanSend & 0x1u64) << 56)
+                | (capEPPtr & 0xffffffffffffu64),
+            capEPBadge,
+        ],
+    }
+}
+
 #[inline]
 pub fn cap_endpoint_cap_get_capEPBadge(cap: cap_t) -> u64 {
     cap.words[1] & 0xffffffffffffffffu64
@@ -257,6 +295,24 @@ pub fn cap_endpoint_cap_set_capCanGrant(mut cap: cap_t, v64: u64) -> cap_t {
     cap
 }
 
+#[inline]
+pub fn cap_notification_cap_new(
+    capNtfnBadge: u64,
+    capNtfnCanReceive: u64,
+    capNtfnCanSend: u64,
+    capNtfnPtr: u64,
+) -> cap_t {
+    cap_t {
+        words: [
+            ((cap_tag_t::cap_notification_cap as u64 & 0x1fu64) << 59)
+                | ((capNtfnCanReceive & 0x1u64) << 58)
+                | ((capNtfnCanSend & 0x1u64) << 57)
+                | (capNtfnPtr & 0xffffffffffffu64),
+            capNtfnBadge,
+        ],
+    }
+}
+
 #[inline]
 pub fn cap_notification_cap_get_capNtfnBadge(cap: cap_t) -> u64 {
     cap.words[1] & 0xffffffffffffffffu64
@@ -293,6 +349,16 @@ pub fn cap_notification_cap_set_capNtfnCanReceive(mut cap: cap_t, v64: u64) -> c
     cap
 }
 
+#[inline]
+pub fn cap_untyped_cap_new(capFreeIndex: u64, capIsDevice: u64, capBlockSize: u64, capPtr: u64) -> cap_t {
+    cap_t {
+        words: [
+            ((cap_tag_t::cap_untyped_cap as u64 & 0x1fu64) << 59) | (capPtr & 0xffffffffffffu64),
+            ((capFreeIndex & 0xffffffffffffu64) << 16) | ((capIsDevice & 0x1u64) << 6) | (capBlockSize & 0x3fu64),
+        ],
+    }
+}
+
 #[inline]
 pub fn cap_untyped_cap_get_capPtr(cap: cap_t) -> u64 {
     let mut ret = cap.words[0] & 0xffffffffffffu64;
@@ -532,8 +598,9 @@ pub fn endpoint_ptr_get_state(endpoint_ptr: &endpoint_t) -> u64 {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone, Debug)]
 pub struct seL4_Fault {
-    words: [u64; 2],
+    pub words: [u64; 2],
 }
 pub type seL4_Fault_t = seL4_Fault;
 
@@ -555,18 +622,64 @@ pub fn seL4_Fault_NullFault_new() -> seL4_Fault_t {
     seL4_Fault_t { words: [0, 0] }
 }
 
-#[allow(unused_variables)]
 #[inline]
 pub fn seL4_Fault_CapFault_new(address: u64, inReceivePhase: u64) -> seL4_Fault_t {
     seL4_Fault_t {
         words: [
             ((inReceivePhase & 0x1u64) << 63)
                 | ((seL4_Fault_tag_t::seL4_Fault_CapFault as u64) & 0x7u64),
+            address,
+        ],
+    }
+}
+
+#[inline]
+pub fn seL4_Fault_CapFault_get_address(seL4_Fault: &seL4_Fault_t) -> u64 {
+    seL4_Fault.words[1]
+}
+
+#[inline]
+pub fn seL4_Fault_CapFault_get_inReceivePhase(seL4_Fault: &seL4_Fault_t) -> u64 {
+    (seL4_Fault.words[0] & 0x8000000000000000u64) >> 63
+}
+
+#[inline]
+pub fn seL4_Fault_UnknownSyscall_new(syscallNumber: u64) -> seL4_Fault_t {
+    seL4_Fault_t {
+        words: [
+            (seL4_Fault_tag_t::seL4_Fault_UnknownSyscall as u64) & 0x7u64,
+            syscallNumber,
+        ],
+    }
+}
+
+#[inline]
+pub fn seL4_Fault_UnknownSyscall_get_syscallNumber(seL4_Fault: &seL4_Fault_t) -> u64 {
+    seL4_Fault.words[1]
+}
+
+#[inline]
+pub fn seL4_Fault_UserException_new(number: u64, code: u64) -> seL4_Fault_t {
+    seL4_Fault_t {
+        words: [
+            ((number & 0xffffffffu64) << 32)
+                | ((code & 0xfffffffu64) << 3)
+                | ((seL4_Fault_tag_t::seL4_Fault_UserException as u64) & 0x7u64),
             0,
         ],
     }
 }
 
+#[inline]
+pub fn seL4_Fault_UserException_get_number(seL4_Fault: &seL4_Fault_t) -> u64 {
+    (seL4_Fault.words[0] & 0xffffffff00000000u64) >> 32
+}
+
+#[inline]
+pub fn seL4_Fault_UserException_get_code(seL4_Fault: &seL4_Fault_t) -> u64 {
+    (seL4_Fault.words[0] & 0xfffffff8u64) >> 3
+}
+
 #[inline]
 pub fn seL4_Fault_ptr_get_seL4_FaultType(seL4_Fault: &seL4_Fault_t) -> u64 {
     seL4_Fault.words[0] & 0x7u64
@@ -851,6 +964,11 @@ pub fn lookup_fault_guard_mismatch_get_bitsFound(lookup_fault: lookup_fault_t) -
     (lookup_fault.words[0] & 0x1fcu64) >> 2
 }
 
+#[inline]
+pub fn lookup_fault_guard_mismatch_get_guardFound(lookup_fault: lookup_fault_t) -> u64 {
+    lookup_fault.words[1]
+}
+
 #[inline]
 pub fn lookup_fault_guard_mismatch_get_bitsLeft(lookup_fault: lookup_fault_t) -> u64 {
     (lookup_fault.words[0] & 0xfe00u64) >> 9
@@ -945,6 +1063,13 @@ pub struct seL4_CNode_CapData_t {
     pub words: [u64; 1],
 }
 
+#[inline]
+pub fn seL4_CNode_CapData_new(guard: u64, guardSize: u64) -> seL4_CNode_CapData_t {
+    seL4_CNode_CapData_t {
+        words: [(guard & 0x3ffffffffffffffu64) << 6 | (guardSize & 0x3fu64)],
+    }
+}
+
 #[inline]
 pub fn seL4_CNode_CapData_get_guard(seL4_CNode_CapData: seL4_CNode_CapData_t) -> u64 {
     (seL4_CNode_CapData.words[0] & 0xffffffffffffffc0u64) >> 6
@@ -0,0 +1,183 @@
+// block_queue.rs, queues of fixed-size memory blocks
+// The blocks are allocated up front and passed between tasks by ownership,
+// so filling a block and sending it never copies its contents.
+
+use crate::port::*;
+use crate::queue_api::MoveQueue;
+use crate::queue_h::QueueError;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// * Description:
+///
+/// A pool of fixed-size memory blocks.  All blocks are allocated when the pool
+/// is created.  A task allocating a block when the pool is exhausted blocks
+/// until another task drops one.  Clones of the pool share the same blocks.
+#[derive(Clone)]
+pub struct BlockPool {
+    free_blocks: Arc<MoveQueue<Box<[u8]>>>,
+    block_size: usize,
+}
+
+/// A block of a `BlockPool`, which goes back to the pool when it is dropped.
+pub struct Block {
+    data: Option<Box<[u8]>>,
+    pool: BlockPool,
+}
+
+impl BlockPool {
+    /// # Description
+    /// Create a pool and allocate its blocks.
+    ///
+    /// # Arguments:
+    /// * `block_count` - the number of blocks.
+    /// * `block_size` - the size of each block in bytes.
+    ///
+    /// # Return:
+    /// The created pool.
+    pub fn new(block_count: UBaseType, block_size: usize) -> Self {
+        let free_blocks = MoveQueue::new(block_count);
+        for _ in 0..block_count {
+            /* The queue has room for every block, so this can't fail. */
+            let _ = free_blocks.send(vec![0; block_size].into_boxed_slice(), 0);
+        }
+        BlockPool {
+            free_blocks: Arc::new(free_blocks),
+            block_size,
+        }
+    }
+
+    /// # Description
+    /// Get the size of the blocks of the pool.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// `usize` - the size of each block in bytes.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// # Description
+    /// Take a block from the pool.  Its contents are left over from its
+    /// previous user.
+    ///
+    /// # Arguments:
+    /// * `xTicksToWait` - The maximum amount of time the task should block waiting for a block
+    ///   to be dropped, should the pool be exhausted.
+    ///
+    /// # Return:
+    /// Ok(Block) if a block was allocated, otherwise QueueError::QueueEmpty.
+    pub fn allocate(&self, xTicksToWait: TickType) -> Result<Block, QueueError> {
+        let data = self.free_blocks.receive(xTicksToWait)?;
+        Ok(Block {
+            data: Some(data),
+            pool: self.clone(),
+        })
+    }
+}
+
+impl Deref for Block {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Block {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.data.as_mut().unwrap()
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if let Some(data) = self.data.take() {
+            /* Only the blocks of the pool are ever returned to it, so there
+            is always room for this one. */
+            let _ = self.pool.free_blocks.send(data, 0);
+        }
+    }
+}
+
+/// * Description:
+///
+/// A queue of blocks from its own pool.  A producer allocates a block, fills
+/// it and sends it; the consumer receives it and drops it when done, which
+/// returns the block to the pool.
+pub struct BlockQueue {
+    pool: BlockPool,
+    queue: MoveQueue<Block>,
+}
+
+impl BlockQueue {
+    /// # Description
+    /// Create a queue together with its pool.
+    ///
+    /// # Arguments:
+    /// * `length` - The maximum number of blocks that the queue can contain.
+    /// * `block_count` - the number of blocks in the pool, including the blocks
+    ///   being filled by producers and processed by consumers.
+    /// * `block_size` - the size of each block in bytes.
+    ///
+    /// # Return:
+    /// The created queue.
+    pub fn new(length: UBaseType, block_count: UBaseType, block_size: usize) -> Self {
+        BlockQueue {
+            pool: BlockPool::new(block_count, block_size),
+            queue: MoveQueue::new(length),
+        }
+    }
+
+    /// # Description
+    /// Get the pool the blocks of this queue come from.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// The pool.
+    pub fn pool(&self) -> &BlockPool {
+        &self.pool
+    }
+
+    /// # Description
+    /// Take a block from the pool of this queue.
+    ///
+    /// # Arguments:
+    /// * `xTicksToWait` - The maximum amount of time the task should block waiting for a block.
+    ///
+    /// # Return:
+    /// Ok(Block) if a block was allocated, otherwise QueueError::QueueEmpty.
+    pub fn allocate(&self, xTicksToWait: TickType) -> Result<Block, QueueError> {
+        self.pool.allocate(xTicksToWait)
+    }
+
+    /// # Description
+    /// Post a block to the back of the queue.
+    ///
+    /// # Arguments:
+    /// * `block` - the block to send.
+    /// * `xTicksToWait` - The maximum amount of time the task should block waiting for space to
+    ///   become available on the queue, should it already be full.
+    ///
+    /// # Return:
+    /// Ok() if the block was successfully posted, otherwise the error and the block.
+    pub fn send(&self, block: Block, xTicksToWait: TickType) -> Result<(), (QueueError, Block)> {
+        self.queue.send(block, xTicksToWait)
+    }
+
+    /// # Description
+    /// Receive a block from the queue.
+    ///
+    /// # Arguments:
+    /// * `xTicksToWait` - The maximum amount of time the task should block waiting for a block.
+    ///
+    /// # Return:
+    /// Ok(Block) if a block was received, otherwise QueueError::QueueEmpty.
+    pub fn receive(&self, xTicksToWait: TickType) -> Result<Block, QueueError> {
+        self.queue.receive(xTicksToWait)
+    }
+}
@@ -0,0 +1,663 @@
+// capability.rs, a safe interface to the caps of the running task
+// A cap is named by its CPtr, the address of its slot in the CSpace of the
+// running task, and the typed caps check the type of the cap they name when
+// they are made.  Their operations are those of the CNode invocations of
+// seL4: they derive, move and delete the caps in the slots of the CSpace, and
+// keep the mapping database up to date, so that revoking a cap deletes the
+// caps copied and minted from it.  The untyped caps are retyped into new
+// objects, see untyped.rs, and the tasks retyped are configured and started
+// through their thread caps.  The errors are reported as seL4_Error.
+
+#![allow(non_snake_case)]
+
+use crate::arch_structures_TCB::*;
+use crate::endpoint::{receive_through, reply_receive_through, send_through};
+use crate::kernel::CriticalSection;
+use crate::notification::{seL4_Poll, seL4_Signal, seL4_Wait};
+use crate::task_global::*;
+use crate::types::*;
+use crate::CNode::{cteDelete, cteInsert, cteMove, cteRevoke, ensureEmptySlot};
+use crate::CSpace::{lookupSlot, lookupTargetSlot};
+use crate::task_control_cap::{decodeSetPriority, decodeTCBConfigure, decodeTCBInvocation, thread_control_flag, TaskHandle, TaskState};
+use crate::untyped::{decodeUntypedRetype, seL4_MinUntypedBits, seL4_ObjectType};
+use crate::*;
+
+/// The address of a slot in the CSpace of the running task: the slot
+/// reached by resolving the `depth` lowest bits of `cptr`, see
+/// CSpace::resolveAddressBits().  The slots of the caps to CNodes are
+/// resolved through at full depth, so they are named with a lower depth.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CPtr {
+    pub cptr: u64,
+    pub depth: u64,
+}
+
+impl CPtr {
+    /// The slot of `cptr`, resolved at full depth.
+    pub const fn new(cptr: u64) -> Self {
+        CPtr {
+            cptr,
+            depth: wordBits,
+        }
+    }
+
+    /// The slot of the `depth` lowest bits of `cptr`.
+    pub const fn with_depth(cptr: u64, depth: u64) -> Self {
+        CPtr { cptr, depth }
+    }
+}
+
+/// The rights a cap is copied or minted with.  `read` is the right to
+/// receive, `write` the right to send and `grant` the right to call.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CapRights {
+    pub read: bool,
+    pub write: bool,
+    pub grant: bool,
+}
+
+impl CapRights {
+    /// All the rights of the cap copied.
+    pub const ALL: CapRights = CapRights {
+        read: true,
+        write: true,
+        grant: true,
+    };
+    /// The right to receive only.
+    pub const READ: CapRights = CapRights {
+        read: true,
+        write: false,
+        grant: false,
+    };
+    /// The right to send only.
+    pub const WRITE: CapRights = CapRights {
+        read: false,
+        write: true,
+        grant: false,
+    };
+
+    fn to_rights(self) -> seL4_CapRights_t {
+        rightsFromWord(self.write as u64 | (self.read as u64) << 1 | (self.grant as u64) << 2)
+    }
+}
+
+/* Get the error set by the kernel functions in current_syscall_error. */
+fn current_error() -> seL4_Error {
+    match unsafe { current_syscall_error.type_ } {
+        1 => seL4_Error::seL4_InvalidArgument,
+        2 => seL4_Error::seL4_InvalidCapability,
+        3 => seL4_Error::seL4_IllegalOperation,
+        4 => seL4_Error::seL4_RangeError,
+        5 => seL4_Error::seL4_AlignmentError,
+        6 => seL4_Error::seL4_FailedLookup,
+        7 => seL4_Error::seL4_TruncatedMessage,
+        8 => seL4_Error::seL4_DeleteFirst,
+        9 => seL4_Error::seL4_RevokeFirst,
+        10 => seL4_Error::seL4_NotEnoughMemory,
+        _ => seL4_Error::seL4_InvalidArgument,
+    }
+}
+
+fn check_status(status: u64) -> Result<(), seL4_Error> {
+    if status == exception::EXCEPTION_NONE as u64 {
+        Ok(())
+    } else if status == exception::EXCEPTION_LOOKUP_FAULT as u64 {
+        Err(seL4_Error::seL4_FailedLookup)
+    } else {
+        Err(current_error())
+    }
+}
+
+/* Look up the slot of `cptr` in the CSpace of the running task.  At full
+depth the lookup stops at the first cap that isn't a CNode, as the lookup of
+the caps invoked; at a lower depth all the bits must be resolved, as the
+lookup of the slots of the CNode invocations.  Must be called from a
+critical section. */
+fn lookup_slot(cptr: CPtr) -> Result<*mut cte_t, seL4_Error> {
+    let mut current = get_current_task_handle!();
+    if cptr.depth == wordBits {
+        let lu_ret = unsafe { lookupSlot(&mut current, cptr.cptr) };
+        check_status(lu_ret.status)?;
+        Ok(lu_ret.slot)
+    } else {
+        let root = current.get_cspace_root();
+        let lu_ret = unsafe { lookupTargetSlot(&mut current, root, cptr.cptr, cptr.depth) };
+        check_status(lu_ret.status)?;
+        Ok(lu_ret.slot)
+    }
+}
+
+/* Look up the slot of `cptr`, which must hold a cap of type `tag`.  Must be
+called from a critical section. */
+fn lookup_cap(cptr: CPtr, tag: u64) -> Result<*mut cte_t, seL4_Error> {
+    let slot = lookup_slot(cptr)?;
+    if cap_get_capType(unsafe { (*slot).cap }) != tag {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    Ok(slot)
+}
+
+/* Read the cap of `cptr`, which must be of type `tag`, to invoke it. */
+fn read_cap(cptr: CPtr, tag: u64) -> Result<cap_t, seL4_Error> {
+    let _critical = CriticalSection::enter();
+    let slot = lookup_cap(cptr, tag)?;
+    Ok(unsafe { (*slot).cap })
+}
+
+/* Look up the empty slot of `cptr`.  Must be called from a critical
+section. */
+fn lookup_empty_slot(cptr: CPtr) -> Result<*mut cte_t, seL4_Error> {
+    let slot = lookup_slot(cptr)?;
+    check_status(unsafe { ensureEmptySlot(slot) })?;
+    Ok(slot)
+}
+
+/* Derive the cap in `src`, with the rights in `rights` and, if given, the
+badge in `badge`, into the empty slot `dest`.
+* C implementation: cnode.c decodeCNodeInvocation(), CNodeCopy and CNodeMint */
+fn derive_to(src: CPtr, tag: u64, dest: CPtr, rights: CapRights, badge: Option<u64>) -> Result<(), seL4_Error> {
+    let _critical = CriticalSection::enter();
+    let srcSlot = lookup_cap(src, tag)?;
+    let destSlot = lookup_empty_slot(dest)?;
+    unsafe {
+        let mut newCap = maskCapRights(rights.to_rights(), (*srcSlot).cap);
+        if let Some(badge) = badge {
+            newCap = updateCapData(0u64, badge, newCap);
+        }
+        let dc_ret = deriveCap(srcSlot, newCap);
+        check_status(dc_ret.status)?;
+        if cap_get_capType(dc_ret.cap) == cap_tag_t::cap_null_cap as u64 {
+            return Err(seL4_Error::seL4_IllegalOperation);
+        }
+        cteInsert(dc_ret.cap, srcSlot, destSlot);
+    }
+    Ok(())
+}
+
+/// The operations of the typed caps.  The slot of a typed cap is checked to
+/// hold a cap of its type when it is made, but the cap may be moved or
+/// deleted afterwards by another copy of the CPtr, so every operation looks
+/// up the slot again.
+pub trait Capability: Sized {
+    /// The type of the caps, see cap_tag_t.
+    const TAG: cap_tag_t;
+
+    /// The slot of the cap.
+    fn cptr(&self) -> CPtr;
+
+    #[doc(hidden)]
+    fn with_cptr(cptr: CPtr) -> Self;
+
+    /// # Description
+    /// Name the cap in the slot `cptr` of the running task.
+    ///
+    /// # Arguments:
+    /// * `cptr` - the slot of the cap.
+    ///
+    /// # Return:
+    /// The typed cap, or seL4_FailedLookup if there is no such slot, and
+    /// seL4_InvalidCapability if the cap in the slot isn't of the type.
+    fn from_cptr(cptr: CPtr) -> Result<Self, seL4_Error> {
+        let _critical = CriticalSection::enter();
+        lookup_cap(cptr, Self::TAG as u64)?;
+        Ok(Self::with_cptr(cptr))
+    }
+
+    /// # Description
+    /// Copy the cap to an empty slot, with some of its rights.  The copy
+    /// is a child of the cap, and is deleted when the cap is revoked.
+    ///
+    /// * C implementation: cnode.c decodeCNodeInvocation(), CNodeCopy
+    ///
+    /// # Arguments:
+    /// * `dest` - the empty slot of the copy.
+    /// * `rights` - the rights kept by the copy.
+    ///
+    /// # Return:
+    /// The copy, or seL4_DeleteFirst if `dest` isn't empty.
+    fn copy(&self, dest: CPtr, rights: CapRights) -> Result<Self, seL4_Error> {
+        derive_to(self.cptr(), Self::TAG as u64, dest, rights, None)?;
+        Ok(Self::with_cptr(dest))
+    }
+
+    /// # Description
+    /// Move the cap to an empty slot, leaving its slot empty.
+    ///
+    /// * C implementation: cnode.c decodeCNodeInvocation(), CNodeMove
+    ///
+    /// # Arguments:
+    /// * `dest` - the empty slot the cap is moved to.
+    ///
+    /// # Return:
+    /// The cap in its new slot, or seL4_DeleteFirst if `dest` isn't empty.
+    fn move_to(self, dest: CPtr) -> Result<Self, seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let srcSlot = lookup_cap(self.cptr(), Self::TAG as u64)?;
+        let destSlot = lookup_empty_slot(dest)?;
+        if srcSlot != destSlot {
+            unsafe { cteMove((*srcSlot).cap, srcSlot, destSlot) };
+        }
+        Ok(Self::with_cptr(dest))
+    }
+
+    /// # Description
+    /// Delete the caps derived from the cap, in any CSpace.
+    ///
+    /// * C implementation: cnode.c invokeCNodeRevoke()
+    ///
+    /// # Return:
+    /// Ok if the caps are deleted.
+    fn revoke(&self) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.cptr(), Self::TAG as u64)?;
+        check_status(unsafe { cteRevoke(slot) })
+    }
+
+    /// # Description
+    /// Delete the cap, leaving its slot empty.  Deleting the last cap to an
+    /// endpoint or a notification wakes the tasks waiting on it.
+    ///
+    /// * C implementation: cnode.c invokeCNodeDelete()
+    ///
+    /// # Return:
+    /// Ok if the cap is deleted.
+    fn delete(self) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.cptr(), Self::TAG as u64)?;
+        check_status(unsafe { cteDelete(slot, 1u64) })
+    }
+}
+
+macro_rules! typed_cap {
+    ($(#[$doc:meta])* $name:ident, $tag:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, PartialEq, Eq)]
+        pub struct $name(CPtr);
+
+        impl Capability for $name {
+            const TAG: cap_tag_t = cap_tag_t::$tag;
+
+            fn cptr(&self) -> CPtr {
+                self.0
+            }
+
+            fn with_cptr(cptr: CPtr) -> Self {
+                $name(cptr)
+            }
+        }
+    };
+}
+
+typed_cap!(
+    /// A cap to an endpoint, see endpoint.rs.
+    EndpointCap,
+    cap_endpoint_cap
+);
+typed_cap!(
+    /// A cap to a notification, see notification.rs.
+    NotificationCap,
+    cap_notification_cap
+);
+typed_cap!(
+    /// A cap to a CNode.
+    CNodeCap,
+    cap_cnode_cap
+);
+typed_cap!(
+    /// A cap to a task, see TaskHandle::thread_cap(), or to a task retyped
+    /// from untyped memory, which is started by TcbCap::resume().
+    TcbCap,
+    cap_thread_cap
+);
+typed_cap!(
+    /// A cap to untyped memory, see untyped.rs.
+    UntypedCap,
+    cap_untyped_cap
+);
+
+impl EndpointCap {
+    /// # Description
+    /// Copy the cap to an empty slot, with some of its rights and a badge.
+    /// The receivers of the messages sent through the copy get the badge.
+    ///
+    /// * C implementation: cnode.c decodeCNodeInvocation(), CNodeMint
+    ///
+    /// # Arguments:
+    /// * `dest` - the empty slot of the copy.
+    /// * `rights` - the rights kept by the copy.
+    /// * `badge` - the badge of the copy.
+    ///
+    /// # Return:
+    /// The copy, or seL4_IllegalOperation if the cap already has a badge.
+    pub fn mint(&self, dest: CPtr, rights: CapRights, badge: u64) -> Result<Self, seL4_Error> {
+        derive_to(self.0, Self::TAG as u64, dest, rights, Some(badge))?;
+        Ok(EndpointCap(dest))
+    }
+
+    /// The badge of the cap, 0 if it has none.
+    pub fn badge(&self) -> Result<u64, seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        Ok(cap_endpoint_cap_get_capEPBadge(unsafe { (*slot).cap }))
+    }
+
+    /// # Description
+    /// Send a message through the cap, blocking until a receiver takes it.
+    ///
+    /// * C implementation: seL4_Send()
+    ///
+    /// # Arguments:
+    /// * `info` - the label and length of the message in the message
+    ///   registers of the running task.
+    ///
+    /// # Return:
+    /// seL4_InvalidCapability if the cap lacks the send right.
+    pub fn send(&self, info: seL4_MessageInfo_t) -> Result<(), seL4_Error> {
+        send_through(read_cap(self.0, Self::TAG as u64)?, info, true, false)?;
+        Ok(())
+    }
+
+    /// # Description
+    /// Send a message through the cap and wait for the reply.  A cap
+    /// without the grant right leaves the running task inactive instead.
+    ///
+    /// * C implementation: seL4_Call()
+    ///
+    /// # Arguments:
+    /// * `info` - the label and length of the message.
+    ///
+    /// # Return:
+    /// The label and length of the reply.
+    pub fn call(&self, info: seL4_MessageInfo_t) -> Result<seL4_MessageInfo_t, seL4_Error> {
+        send_through(read_cap(self.0, Self::TAG as u64)?, info, true, true)
+    }
+
+    /// # Description
+    /// Receive a message through the cap, blocking until a sender comes.
+    ///
+    /// * C implementation: seL4_Recv()
+    ///
+    /// # Return:
+    /// The label and length of the message and the badge of the sender,
+    /// or seL4_InvalidCapability if the cap lacks the receive right.
+    pub fn recv(&self) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+        receive_through(read_cap(self.0, Self::TAG as u64)?, true)
+    }
+
+    /// # Description
+    /// Reply to the last caller, then receive through the cap.
+    ///
+    /// * C implementation: seL4_ReplyRecv()
+    ///
+    /// # Arguments:
+    /// * `info` - the label and length of the reply.
+    ///
+    /// # Return:
+    /// The label and length of the next message and the badge of its sender.
+    pub fn reply_recv(&self, info: seL4_MessageInfo_t) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+        reply_receive_through(read_cap(self.0, Self::TAG as u64)?, info)
+    }
+}
+
+impl NotificationCap {
+    /// # Description
+    /// Copy the cap to an empty slot, with some of its rights and a badge.
+    /// The bits of the badge are set in the notification when it is
+    /// signalled through the copy.
+    ///
+    /// * C implementation: cnode.c decodeCNodeInvocation(), CNodeMint
+    ///
+    /// # Arguments:
+    /// * `dest` - the empty slot of the copy.
+    /// * `rights` - the rights kept by the copy.
+    /// * `badge` - the badge of the copy.
+    ///
+    /// # Return:
+    /// The copy, or seL4_IllegalOperation if the cap already has a badge.
+    pub fn mint(&self, dest: CPtr, rights: CapRights, badge: u64) -> Result<Self, seL4_Error> {
+        derive_to(self.0, Self::TAG as u64, dest, rights, Some(badge))?;
+        Ok(NotificationCap(dest))
+    }
+
+    /// The badge of the cap, 0 if it has none.
+    pub fn badge(&self) -> Result<u64, seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        Ok(cap_notification_cap_get_capNtfnBadge(unsafe { (*slot).cap }))
+    }
+
+    /// # Description
+    /// Signal the notification through the cap, setting the bits of its
+    /// badge.
+    ///
+    /// * C implementation: seL4_Signal()
+    ///
+    /// # Return:
+    /// seL4_InvalidCapability if the cap lacks the send right.
+    pub fn signal(&self) -> Result<(), seL4_Error> {
+        seL4_Signal(read_cap(self.0, Self::TAG as u64)?)
+    }
+
+    /// # Description
+    /// Wait on the notification through the cap until it is signalled.
+    ///
+    /// * C implementation: seL4_Wait()
+    ///
+    /// # Return:
+    /// The badge bits the notification was signalled with.
+    pub fn wait(&self) -> Result<word_t, seL4_Error> {
+        seL4_Wait(read_cap(self.0, Self::TAG as u64)?)
+    }
+
+    /// # Description
+    /// Take the badge bits of the notification through the cap, without
+    /// blocking.
+    ///
+    /// * C implementation: seL4_Poll()
+    ///
+    /// # Return:
+    /// The badge bits, 0 if the notification wasn't signalled.
+    pub fn poll(&self) -> Result<word_t, seL4_Error> {
+        seL4_Poll(read_cap(self.0, Self::TAG as u64)?)
+    }
+}
+
+impl CNodeCap {
+    /// # Description
+    /// Copy the cap to an empty slot, with a new guard.  A CNode is shared
+    /// by the CSpaces that hold caps to it, each with its own guard.
+    ///
+    /// * C implementation: cnode.c decodeCNodeInvocation(), CNodeMint
+    ///
+    /// # Arguments:
+    /// * `dest` - the empty slot of the copy.
+    /// * `guard` - the bits the cptrs resolved by the copy start with.
+    /// * `guard_size` - the number of bits of the guard.
+    ///
+    /// # Return:
+    /// The copy, or seL4_IllegalOperation if the guard and the index of the
+    /// slots don't fit in a cptr.
+    pub fn mint(&self, dest: CPtr, guard: u64, guard_size: u64) -> Result<Self, seL4_Error> {
+        let data = seL4_CNode_CapData_new(guard, guard_size).words[0];
+        derive_to(self.0, Self::TAG as u64, dest, CapRights::ALL, Some(data))?;
+        Ok(CNodeCap(dest))
+    }
+}
+
+impl TcbCap {
+    /* Perform an invocation of the cap that takes no argument. */
+    fn invoke(&self, label: invocation_label) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        let excaps = extra_caps_t {
+            excaprefs: [std::ptr::null_mut(); seL4_MsgMaxExtraCaps],
+        };
+        check_status(unsafe {
+            decodeTCBInvocation(label as u64, 0, slot, (*slot).cap, excaps, std::ptr::null_mut())
+        })
+    }
+
+    /// # Description
+    /// Set the fault endpoint, the CSpace root and the IPC buffer of the
+    /// task.  The CSpace root is copied into the tcbCTable slot of the task.
+    ///
+    /// * C implementation: tcb.c decodeTCBConfigure()
+    ///
+    /// # Arguments:
+    /// * `fault_ep` - the cptr of the fault endpoint, in the CSpace of the task, or 0 for none, see fault.rs.
+    /// * `cspace_root` - the slot of a CNode cap, the root of the CSpace of the task.
+    /// * `ipc_buffer` - the address of the IPC buffer of the task.
+    ///
+    /// # Return:
+    /// Ok, or seL4_IllegalOperation if `cspace_root` isn't a CNode cap.
+    pub fn configure(&self, fault_ep: u64, cspace_root: CPtr, ipc_buffer: u64) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        let rootSlot = lookup_slot(cspace_root)?;
+        check_status(unsafe {
+            let target = TaskHandle::from_thread_cap((*slot).cap);
+            decodeTCBConfigure(&target, slot, fault_ep, 0, ipc_buffer, rootSlot)
+        })
+    }
+
+    /* Set the priority or the maximum controlled priority of the task. */
+    fn set_prio(&self, authority: &TcbCap, prio: u64, flag: thread_control_flag) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        let authSlot = lookup_slot(authority.0)?;
+        check_status(unsafe {
+            let target = TaskHandle::from_thread_cap((*slot).cap);
+            decodeSetPriority(&target, slot, prio, (*authSlot).cap, flag)
+        })
+    }
+
+    /// # Description
+    /// Set the priority of the task.
+    ///
+    /// * C implementation: tcb.c decodeSetPriority()
+    ///
+    /// # Arguments:
+    /// * `authority` - the cap of the task whose authority the priority is set with.
+    /// * `priority` - the priority.
+    ///
+    /// # Return:
+    /// Ok, or seL4_RangeError if the priority is higher than the maximum
+    /// controlled priority of `authority`.
+    pub fn set_priority(&self, authority: &TcbCap, priority: u64) -> Result<(), seL4_Error> {
+        self.set_prio(authority, priority, thread_control_flag::thread_control_update_priority)
+    }
+
+    /// # Description
+    /// Set the maximum controlled priority of the task, the highest
+    /// priority it can give to a task.
+    ///
+    /// * C implementation: tcb.c decodeSetMCPriority()
+    ///
+    /// # Arguments:
+    /// * `authority` - the cap of the task whose authority the priority is set with.
+    /// * `mcp` - the maximum controlled priority.
+    ///
+    /// # Return:
+    /// Ok, or seL4_RangeError if the priority is higher than the maximum
+    /// controlled priority of `authority`.
+    pub fn set_mc_priority(&self, authority: &TcbCap, mcp: u64) -> Result<(), seL4_Error> {
+        self.set_prio(authority, mcp, thread_control_flag::thread_control_update_mcp)
+    }
+
+    /// # Description
+    /// Set the closure run by a task retyped from untyped memory once it is
+    /// resumed, as seL4 sets the instruction pointer of a new thread with
+    /// seL4_TCB_WriteRegisters().  A task left inactive after its closure
+    /// panicked is given another closure the same way, see fault.rs.
+    ///
+    /// # Arguments:
+    /// * `func` - the closure of the task.
+    ///
+    /// # Return:
+    /// Ok, or seL4_IllegalOperation if the task has already been started and
+    /// isn't inactive.
+    pub fn set_entry<F>(&self, func: F) -> Result<(), seL4_Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        let target = unsafe { TaskHandle::from_thread_cap((*slot).cap) };
+        if target.is_started() && !matches!(target.get_state(), TaskState::InActive) {
+            return Err(seL4_Error::seL4_IllegalOperation);
+        }
+        target.set_entry(func);
+        Ok(())
+    }
+
+    /// # Description
+    /// Resume the task.  A task retyped from untyped memory is started by
+    /// its first resume, and runs the closure set by set_entry().
+    ///
+    /// * C implementation: tcb.c decodeTCBInvocation(), TCBResume
+    ///
+    /// # Return:
+    /// Ok, or seL4_IllegalOperation if the task has no closure to run.
+    pub fn resume(&self) -> Result<(), seL4_Error> {
+        self.invoke(invocation_label::TCBResume)
+    }
+
+    /// # Description
+    /// Suspend the task until it is resumed.
+    ///
+    /// * C implementation: tcb.c decodeTCBInvocation(), TCBSuspend
+    ///
+    /// # Return:
+    /// Ok if the task is suspended.
+    pub fn suspend(&self) -> Result<(), seL4_Error> {
+        self.invoke(invocation_label::TCBSuspend)
+    }
+}
+
+impl UntypedCap {
+    /// # Description
+    /// Retype the memory of the cap into objects, and put the caps to them
+    /// in a range of empty slots of a CNode.  The caps are children of the
+    /// untyped cap, and are deleted when it is revoked; the memory is then
+    /// cleared and retyped from the start.
+    ///
+    /// * C implementation: untyped.c decodeUntypedInvocation()
+    ///
+    /// # Arguments:
+    /// * `object_type` - the type of the objects.
+    /// * `size_bits` - the size of the untyped objects, and the radix of the CNodes.
+    /// * `node` - the CNode cap of the slots, or the CSpace root at depth 0.
+    /// * `offset` - the index of the first slot in the CNode.
+    /// * `count` - the number of objects.
+    ///
+    /// # Return:
+    /// Ok, or seL4_NotEnoughMemory if the objects don't fit in the memory
+    /// left, and seL4_DeleteFirst if a slot isn't empty.
+    pub fn retype(
+        &self,
+        object_type: seL4_ObjectType,
+        size_bits: u64,
+        node: CPtr,
+        offset: u64,
+        count: u64,
+    ) -> Result<(), seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        let nodeCap = if node.depth == 0 {
+            get_current_task_handle!().get_cspace_root()
+        } else {
+            unsafe { (*lookup_slot(node)?).cap }
+        };
+        check_status(unsafe {
+            decodeUntypedRetype(slot, (*slot).cap, object_type as u64, size_bits, nodeCap, node.depth, offset, count)
+        })
+    }
+
+    /// The number of bytes of the memory retyped since it was last
+    /// cleared, up to the free index of the cap.
+    pub fn watermark(&self) -> Result<u64, seL4_Error> {
+        let _critical = CriticalSection::enter();
+        let slot = lookup_cap(self.0, Self::TAG as u64)?;
+        Ok(cap_untyped_cap_get_capFreeIndex(unsafe { (*slot).cap }) << seL4_MinUntypedBits)
+    }
+}
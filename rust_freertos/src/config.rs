@@ -51,6 +51,13 @@ macro_rules! configMAX_ENDPOINTS {
     };
 }
 
+#[macro_export]
+macro_rules! configMAX_NOTIFICATIONS {
+    () => {
+        10
+    };
+}
+
 #[macro_export]
 macro_rules! configTIMER_TASK_PRIORITY {
     () => {
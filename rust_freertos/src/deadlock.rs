@@ -0,0 +1,167 @@
+//! Deadlock detection on the wait-for graph of blocked tasks.
+//!
+//! A task blocked on a mutex waits for the holder of the mutex, and a task
+//! that called through an endpoint waits for the receiver to reply.  Only
+//! these waits are tracked: a task blocked sending or receiving on an
+//! endpoint may be served by any task with a cap to it, so it has no edge.
+//! These edges are kept in the TCBs of the blocked tasks and are updated
+//! whenever a task blocks or wakes up.  Each time a task blocks, the edges are
+//! followed from it.  If they lead back to the task, none of the tasks on the
+//! way can ever wake up again, and the deadlock hook is called with the cycle.
+
+#[cfg(not(feature = "configUSE_CAPS"))]
+use crate::task_control::*;
+#[cfg(feature = "configUSE_CAPS")]
+use crate::task_control_cap::*;
+use crate::*;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// An object a task is blocked on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WaitObject {
+    /// A mutex, identified by its address.
+    Mutex(usize),
+    /// An endpoint, identified by its index in `ENDPOINT_LIST`.
+    Endpoint(u64),
+}
+
+impl fmt::Display for WaitObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaitObject::Mutex(address) => write!(f, "mutex {:#x}", address),
+            WaitObject::Endpoint(index) => write!(f, "endpoint {}", index),
+        }
+    }
+}
+
+/// An edge of the wait-for graph: `task` is blocked on `object`.
+#[derive(Clone)]
+pub struct WaitForEdge {
+    pub task: TaskHandle,
+    pub object: WaitObject,
+}
+
+/// Called with the edges of a cycle, starting at the task that closed it.
+/// The hook is called from a critical section, so it MUST NOT block.
+pub type DeadlockHook = fn(&[WaitForEdge]);
+
+lazy_static! {
+    static ref DEADLOCK_HOOK: RwLock<Option<DeadlockHook>> = RwLock::new(None);
+}
+
+/// # Description
+/// Set the function to be called when a deadlock is detected.  Without a
+/// hook, the cycle is logged as an error.
+///
+/// # Arguments
+/// `hook` - the hook, or None to log deadlocks again.
+///
+/// # Return
+/// Nothing
+pub fn set_deadlock_hook(hook: Option<DeadlockHook>) {
+    *DEADLOCK_HOOK.write().unwrap() = hook;
+}
+
+/// # Description
+/// Describe a cycle of the wait-for graph, e.g. "Task1 is blocked on mutex
+/// 0x7f00 held by Task2, Task2 is blocked on endpoint 0 served by Task1".
+///
+/// # Arguments
+/// `cycle` - the edges of the cycle, as passed to the deadlock hook.
+///
+/// # Return
+/// The description.
+pub fn describe_cycle(cycle: &[WaitForEdge]) -> String {
+    let mut description = Vec::new();
+    for (i, edge) in cycle.iter().enumerate() {
+        let next = &cycle[(i + 1) % cycle.len()];
+        let relation = match edge.object {
+            WaitObject::Mutex(_) => "held by",
+            WaitObject::Endpoint(_) => "served by",
+        };
+        description.push(format!(
+            "{} is blocked on {} {} {}",
+            edge.task.get_name(),
+            edge.object,
+            relation,
+            next.task.get_name()
+        ));
+    }
+    description.join(", ")
+}
+
+/* Get the object `task` is blocked on, and the task it waits for. */
+fn get_wait_for_edge(task: &TaskHandle) -> Option<(WaitObject, TaskHandle)> {
+    #[cfg(feature = "configUSE_MUTEXES")]
+    {
+        if let Some(blocked_on) = task.get_mutex_blocked_on() {
+            if let Some(holder) = blocked_on.holder.read().unwrap().clone() {
+                let address = Arc::as_ptr(&blocked_on.holder) as usize;
+                return Some((WaitObject::Mutex(address), holder));
+            }
+        }
+    }
+    #[cfg(feature = "configUSE_CAPS")]
+    {
+        if let Some(blocked_on) = task.get_endpoint_blocked_on() {
+            if let Some(partner) = blocked_on.partner {
+                return Some((WaitObject::Endpoint(blocked_on.index), partner));
+            }
+        }
+    }
+    None
+}
+
+/// # Description
+/// Follow the wait-for graph from a blocked task.
+///
+/// # Arguments
+/// `task` - the task that is blocked.
+///
+/// # Return
+/// The edges of the cycle starting at `task`, or None if its waits don't
+/// lead back to it.
+pub fn find_deadlock(task: &TaskHandle) -> Option<Vec<WaitForEdge>> {
+    let mut cycle = Vec::new();
+    let mut next_task = task.clone();
+    while let Some((object, waits_for)) = get_wait_for_edge(&next_task) {
+        cycle.push(WaitForEdge {
+            task: next_task,
+            object,
+        });
+        if waits_for == *task {
+            return Some(cycle);
+        }
+        /* A cycle that doesn't pass through `task` was reported when the
+        last of its tasks blocked. */
+        if cycle.iter().any(|edge| edge.task == waits_for) {
+            return None;
+        }
+        next_task = waits_for;
+    }
+    None
+}
+
+/// # Description
+/// Called each time a task blocks on a mutex or an endpoint.  Calls the
+/// deadlock hook if the task closed a cycle of the wait-for graph.
+///
+/// # Arguments
+/// `task` - the task that is blocking.
+///
+/// # Return
+/// `bool` - true if a deadlock was detected.
+pub fn check_for_deadlock(task: &TaskHandle) -> bool {
+    match find_deadlock(task) {
+        Some(cycle) => {
+            traceDEADLOCK_DETECTED!(&cycle);
+            match *DEADLOCK_HOOK.read().unwrap() {
+                Some(hook) => hook(&cycle),
+                None => error!("Deadlock detected: {}", describe_cycle(&cycle)),
+            }
+            true
+        }
+        None => false,
+    }
+}
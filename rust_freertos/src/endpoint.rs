@@ -0,0 +1,653 @@
+// endpoint.rs, synchronous IPC on endpoints
+// An endpoint is one of the queues of ENDPOINT_LIST, and the EPPtr of an
+// endpoint cap is the index of its queue, or the object retyped from untyped
+// memory holding the index, see untyped::object_of().  The queue holds the
+// tasks blocked sending or receiving on the endpoint; senders and receivers
+// are never queued at the same time, so the task at the head tells the
+// endpoint state.
+// The message is passed in the message registers of the tasks, followed by
+// their IPC buffers, as in seL4.
+
+#![allow(non_snake_case)]
+
+use crate::arch_structures_TCB::*;
+use crate::kernel::CriticalSection;
+use crate::list;
+use crate::port::*;
+use crate::regs::*;
+use crate::task_control_cap::*;
+use crate::task_global;
+use crate::task_ipc::getSyscallArg;
+use crate::types::*;
+use crate::CSpace::lookupCap;
+use crate::*;
+
+/// # Description
+/// Create a cap to an endpoint.
+///
+/// # Arguments:
+/// * `index` - the index of the endpoint in ENDPOINT_LIST.
+/// * `badge` - the badge a receiver gets with the messages sent through the cap.
+/// * `can_send` - whether the cap can send and call.
+/// * `can_receive` - whether the cap can receive.
+/// * `can_grant` - whether a call through the cap can be replied to.
+///
+/// # Return:
+/// The endpoint cap.
+pub(crate) fn endpoint_cap_new(
+    index: u64,
+    badge: word_t,
+    can_send: bool,
+    can_receive: bool,
+    can_grant: bool,
+) -> cap_t {
+    cap_endpoint_cap_new(
+        badge,
+        can_grant as u64,
+        can_receive as u64,
+        can_send as u64,
+        index,
+    )
+}
+
+/* Get the index of the endpoint designated by `cap`. */
+fn endpoint_index(cap: cap_t) -> Result<u64, seL4_Error> {
+    if cap_get_capType(cap) != cap_tag_t::cap_endpoint_cap as u64 {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    let index = unsafe { crate::untyped::object_of(cap_endpoint_cap_get_capEPPtr(cap)) };
+    if index >= configMAX_ENDPOINTS!() {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    Ok(index)
+}
+
+/* The state of an endpoint follows from the tasks queued on it. */
+fn endpoint_get_state(index: u64) -> endpoint_state {
+    let queue = &task_global::ENDPOINT_LIST[index as usize];
+    if list::list_is_empty(queue) {
+        return endpoint_state::EPState_Idle;
+    }
+    match list::get_owner_of_head_entry(queue).get_state() {
+        TaskState::BlockedOnSend => endpoint_state::EPState_Send,
+        _ => endpoint_state::EPState_Recv,
+    }
+}
+
+/* Move `thread` from the ready list to the queue of an endpoint.  Must be
+called from a critical section. */
+fn block_on_endpoint(thread: &TaskHandle, index: u64, state: TaskState, blocking_ipc: BlockingIPC) {
+    thread.set_state(state);
+    thread.set_blocking_ipc(blocking_ipc);
+    if list::list_remove(thread.get_state_list_item()) == 0 {
+        taskRESET_READY_PRIORITY!(thread.get_priority());
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+    thread.append_task_to_endpoint_list(index).unwrap();
+}
+
+/* Take `thread` off the list it is on, if any.  Must be called from a
+critical section. */
+pub(crate) fn remove_from_state_list(thread: &TaskHandle) {
+    if list::get_list_item_container(&thread.get_state_list_item()).is_some() {
+        list::list_remove(thread.get_state_list_item());
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
+
+/* Block `caller` until `receiver` replies.  The caller waits on the receiver,
+so the wait-for edge of the call is kept until the reply.  Must be called
+from a critical section. */
+fn block_on_reply(caller: &TaskHandle, receiver: &TaskHandle, index: u64) {
+    caller.set_state(TaskState::BlockedOnReply);
+    remove_from_state_list(caller);
+    list::list_insert_end(&task_global::SUSPENDED_TASK_LIST, caller.get_state_list_item());
+    caller.set_endpoint_blocked_on(Some(EndpointWait {
+        index,
+        partner: Some(receiver.clone()),
+    }));
+    receiver.set_caller(Some(caller.clone()));
+    #[cfg(feature = "configCHECK_FOR_DEADLOCKS")]
+    deadlock::check_for_deadlock(caller);
+}
+
+/* A caller that can't be replied to is never scheduled again, as an inactive
+seL4 thread.  Must be called from a critical section. */
+pub(crate) fn set_inactive(thread: &TaskHandle) {
+    thread.set_state(TaskState::InActive);
+    remove_from_state_list(thread);
+    list::list_insert_end(&task_global::SUSPENDED_TASK_LIST, thread.get_state_list_item());
+}
+
+/* Put a task that was dequeued from an endpoint, or waited for a reply, back
+on the ready list.  Must be called from a critical section. */
+pub(crate) fn wake(thread: &TaskHandle) {
+    thread.set_state(TaskState::running);
+    remove_from_state_list(thread);
+    thread.append_task_to_ready_list().unwrap();
+}
+
+/* The running task keeps running unless it woke a task of a higher
+priority. */
+pub(crate) fn yield_to(thread: &TaskHandle) {
+    if thread.get_priority() > get_current_task_priority!() {
+        taskYIELD_IF_USING_PREEMPTION!();
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
+
+/// # Description
+/// Copy the message of `sender` to `receiver`: the message registers, then
+/// the rest of the message from IPC buffer to IPC buffer.  The message is
+/// truncated to the message registers if either task has no IPC buffer.
+/// A faulting sender sends its fault instead, see fault::doFaultTransfer().
+///
+/// * C implementation: ipc.c doIPCTransfer()
+///
+/// # Arguments:
+/// * `sender` - the task sending the message, described by its msgInfoRegister.
+/// * `badge` - the badge of the cap the message was sent through.
+/// * `receiver` - the task receiving the message.
+///
+/// # Return:
+/// Nothing
+pub fn doIPCTransfer(sender: &TaskHandle, badge: word_t, receiver: &TaskHandle) {
+    if seL4_Fault_get_seL4_FaultType(&sender.get_fault()) != seL4_Fault_tag_t::seL4_Fault_NullFault as u64 {
+        fault::doFaultTransfer(badge, sender, receiver);
+        return;
+    }
+    let send_buffer = sender.get_ipc_buffer() as *mut u64;
+    let receive_buffer = receiver.get_ipc_buffer() as *mut u64;
+    let tag = messageInfoFromWord(get_tcb_from_handle!(sender).registers[msgInfoRegister]);
+    unsafe {
+        let sender_ptr = get_ptr_from_handle!(sender);
+        let receiver_ptr = get_ptr_from_handle!(receiver);
+        let length = TaskHandle::copyMRs(
+            sender_ptr,
+            send_buffer,
+            receiver_ptr,
+            receive_buffer,
+            seL4_MessageInfo_get_length(tag),
+        );
+        let tag = seL4_MessageInfo_new(seL4_MessageInfo_get_label(tag), 0, 0, length);
+        setRegister(receiver_ptr, msgInfoRegister, wordFromMessageInfo(tag));
+        setRegister(receiver_ptr, badgeRegister, badge);
+    }
+}
+
+/// # Description
+/// Send the message of `thread` on an endpoint.  If a task is waiting to
+/// receive, the message is transferred to it and it is woken; otherwise
+/// `thread` blocks on the endpoint until a receiver takes the message.
+///
+/// * C implementation: endpoint.c sendIPC()
+///
+/// # Arguments:
+/// * `blocking` - whether to block if no receiver is waiting, else the message is dropped.
+/// * `do_call` - whether `thread` waits for a reply once the message is received.
+/// * `badge` - the badge of the cap the message is sent through.
+/// * `can_grant` - whether the cap has the grant right.  A call through a cap without it
+///   can't be replied to, and leaves `thread` inactive.
+/// * `thread` - the running task.
+/// * `index` - the index of the endpoint in ENDPOINT_LIST.
+///
+/// # Return:
+/// Nothing
+pub fn sendIPC(
+    blocking: bool,
+    do_call: bool,
+    badge: word_t,
+    can_grant: bool,
+    thread: &TaskHandle,
+    index: u64,
+) {
+    let _critical = CriticalSection::enter();
+    match endpoint_get_state(index) {
+        endpoint_state::EPState_Idle | endpoint_state::EPState_Send => {
+            if blocking {
+                block_on_endpoint(
+                    thread,
+                    index,
+                    TaskState::BlockedOnSend,
+                    BlockingIPC {
+                        badge,
+                        can_grant,
+                        is_call: do_call,
+                    },
+                );
+                portYIELD_WITHIN_API!();
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
+            }
+        }
+        endpoint_state::EPState_Recv => {
+            let dest = list::get_owner_of_head_entry(&task_global::ENDPOINT_LIST[index as usize]);
+            dest.delete_task_from_endpoint_list(index).unwrap();
+            doIPCTransfer(thread, badge, &dest);
+            /* The caller must be waiting for the reply before the receiver
+            gets a chance to run. */
+            if do_call {
+                if can_grant {
+                    block_on_reply(thread, &dest, index);
+                } else {
+                    set_inactive(thread);
+                }
+            }
+            wake(&dest);
+            if do_call {
+                portYIELD_WITHIN_API!();
+            } else {
+                yield_to(&dest);
+            }
+        }
+    }
+}
+
+/// # Description
+/// Take `thread` off the endpoint it is blocked sending or receiving on.
+/// It is left inactive.  Must be called from a critical section.
+///
+/// * C implementation: endpoint.c cancelIPC()
+///
+/// # Arguments:
+/// * `thread` - the blocked task.
+///
+/// # Return:
+/// Nothing
+pub fn cancelIPC(thread: &TaskHandle) {
+    match thread.get_state() {
+        TaskState::BlockedOnSend | TaskState::BlockedOnReceive => {
+            if let Some(blocked_on) = thread.get_endpoint_blocked_on() {
+                thread.delete_task_from_endpoint_list(blocked_on.index).unwrap();
+            }
+            thread.set_state(TaskState::InActive);
+        }
+        _ => {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+    }
+}
+
+/* Wake the tasks queued on an endpoint that `cancel` selects, without
+transferring a message, and keep the others queued in order.  Must be called
+from a critical section. */
+fn cancel_queued<F: Fn(&TaskHandle) -> bool>(index: u64, cancel: F) {
+    let queue = &task_global::ENDPOINT_LIST[index as usize];
+    let mut kept = Vec::new();
+    while !list::list_is_empty(queue) {
+        let thread = list::get_owner_of_head_entry(queue);
+        thread.delete_task_from_endpoint_list(index).unwrap();
+        if cancel(&thread) {
+            {
+                let mut tcb = get_tcb_from_handle_mut!(thread);
+                tcb.registers[badgeRegister] = 0;
+                tcb.registers[msgInfoRegister] = 0;
+            }
+            wake(&thread);
+        } else {
+            kept.push(thread);
+        }
+    }
+    for thread in kept {
+        thread.append_task_to_endpoint_list(index).unwrap();
+    }
+}
+
+/// # Description
+/// Wake all the tasks queued on an endpoint, as when the last cap to it is
+/// deleted.  Their messages are dropped, and the receivers get an empty
+/// message with badge 0.
+///
+/// * C implementation: endpoint.c cancelAllIPC()
+///
+/// # Arguments:
+/// * `index` - the index of the endpoint in ENDPOINT_LIST.
+///
+/// # Return:
+/// Nothing
+pub fn cancelAllIPC(index: u64) {
+    let _critical = CriticalSection::enter();
+    cancel_queued(index, |_| true);
+    taskYIELD_IF_USING_PREEMPTION!();
+}
+
+/// # Description
+/// Wake the tasks blocked sending on an endpoint through caps with a badge,
+/// dropping their messages, as before the caps with the badge are revoked.
+///
+/// * C implementation: endpoint.c cancelBadgedSends()
+///
+/// # Arguments:
+/// * `index` - the index of the endpoint in ENDPOINT_LIST.
+/// * `badge` - the badge.
+///
+/// # Return:
+/// Nothing
+pub fn cancelBadgedSends(index: u64, badge: word_t) {
+    let _critical = CriticalSection::enter();
+    cancel_queued(index, |thread| {
+        matches!(thread.get_state(), TaskState::BlockedOnSend) && thread.get_blocking_ipc().badge == badge
+    });
+    taskYIELD_IF_USING_PREEMPTION!();
+}
+
+/// # Description
+/// Receive a message on an endpoint.  If a task is waiting to send, its
+/// message is transferred to `thread` and the sender is woken, or left
+/// waiting for the reply if it called; otherwise `thread` blocks on the
+/// endpoint until a sender comes.  A signal pending on the notification
+/// bound to `thread` is received first, as an empty message with the
+/// notification badge; it wakes `thread` as well while it is blocked.
+///
+/// * C implementation: endpoint.c receiveIPC()
+///
+/// # Arguments:
+/// * `thread` - the running task.
+/// * `index` - the index of the endpoint in ENDPOINT_LIST.
+/// * `blocking` - whether to block if no sender is waiting, else an empty message with
+///   badge 0 is received.
+///
+/// # Return:
+/// Nothing
+pub fn receiveIPC(thread: &TaskHandle, index: u64, blocking: bool) {
+    let _critical = CriticalSection::enter();
+    if let Some(ntfn) = thread.get_bound_notification() {
+        if notification::completeSignal(ntfn, thread) {
+            return;
+        }
+    }
+    match endpoint_get_state(index) {
+        endpoint_state::EPState_Idle | endpoint_state::EPState_Recv => {
+            if blocking {
+                block_on_endpoint(thread, index, TaskState::BlockedOnReceive, Default::default());
+                portYIELD_WITHIN_API!();
+            } else {
+                /* doNBRecvFailedTransfer() */
+                let mut tcb = get_tcb_from_handle_mut!(thread);
+                tcb.registers[badgeRegister] = 0;
+                tcb.registers[msgInfoRegister] = 0;
+            }
+        }
+        endpoint_state::EPState_Send => {
+            let sender = list::get_owner_of_head_entry(&task_global::ENDPOINT_LIST[index as usize]);
+            sender.delete_task_from_endpoint_list(index).unwrap();
+            let blocking_ipc = sender.get_blocking_ipc();
+            doIPCTransfer(&sender, blocking_ipc.badge, thread);
+            if blocking_ipc.is_call {
+                if blocking_ipc.can_grant {
+                    block_on_reply(&sender, thread, index);
+                } else {
+                    set_inactive(&sender);
+                }
+            } else {
+                wake(&sender);
+                yield_to(&sender);
+            }
+        }
+    }
+}
+
+/// # Description
+/// Send the message of `sender` as the reply to `receiver`, and wake
+/// `receiver`.  Nothing happens if `receiver` no longer waits for a reply.
+/// The reply to a fault restarts `receiver` or leaves it inactive, see
+/// fault::handleFaultReply().
+///
+/// * C implementation: tcb.c doReplyTransfer()
+///
+/// # Arguments:
+/// * `sender` - the running task, which received the call.
+/// * `receiver` - the task that called.
+///
+/// # Return:
+/// Nothing
+pub fn doReplyTransfer(sender: &TaskHandle, receiver: &TaskHandle) {
+    let _critical = CriticalSection::enter();
+    if let TaskState::BlockedOnReply = receiver.get_state() {
+        receiver.set_endpoint_blocked_on(None);
+        if seL4_Fault_get_seL4_FaultType(&receiver.get_fault()) == seL4_Fault_tag_t::seL4_Fault_NullFault as u64 {
+            doIPCTransfer(sender, 0, receiver);
+        } else {
+            let restart = fault::handleFaultReply(receiver, sender);
+            receiver.set_fault(seL4_Fault_NullFault_new());
+            if !restart {
+                set_inactive(receiver);
+                return;
+            }
+        }
+        wake(receiver);
+        yield_to(receiver);
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
+
+/* Check the rights of an endpoint cap and get the index of its endpoint. */
+pub(crate) fn endpoint_with_rights(ep: cap_t, send: bool, receive: bool, grant: bool) -> Result<u64, seL4_Error> {
+    let index = endpoint_index(ep)?;
+    if (send && cap_endpoint_cap_get_capCanSend(ep) == 0)
+        || (receive && cap_endpoint_cap_get_capCanReceive(ep) == 0)
+        || (grant && cap_endpoint_cap_get_capCanGrant(ep) == 0)
+    {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    Ok(index)
+}
+
+/* Look up the cap of `ep` in the CSpace of the running task. */
+fn lookup_endpoint(ep: u64) -> Result<cap_t, seL4_Error> {
+    let _critical = CriticalSection::enter();
+    let mut current = get_current_task_handle!();
+    let lu_ret = unsafe { lookupCap(&mut current, ep) };
+    if lu_ret.status != 0u64 {
+        return Err(seL4_Error::seL4_FailedLookup);
+    }
+    Ok(lu_ret.cap)
+}
+
+fn set_message_info(thread: &TaskHandle, info: seL4_MessageInfo_t) {
+    get_tcb_from_handle_mut!(thread).registers[msgInfoRegister] = wordFromMessageInfo(info);
+}
+
+fn get_message_info(thread: &TaskHandle) -> (seL4_MessageInfo_t, word_t) {
+    let tcb = get_tcb_from_handle!(thread);
+    (
+        messageInfoFromWord(tcb.registers[msgInfoRegister]),
+        tcb.registers[badgeRegister],
+    )
+}
+
+/// # Description
+/// Set a word of the message of the running task.  The first words are
+/// kept in the message registers, the others in the IPC buffer, which is an
+/// array of at least seL4_MsgMaxLength + 1 words with the message from the
+/// second word on.  Words beyond the message registers are dropped if the
+/// task has no IPC buffer.
+///
+/// # Arguments:
+/// * `i` - the index of the word in the message.
+/// * `value` - the word.
+///
+/// # Return:
+/// Nothing
+pub fn seL4_SetMR(i: usize, value: word_t) {
+    let current = get_current_task_handle!();
+    let buffer = current.get_ipc_buffer() as *mut u64;
+    unsafe {
+        setMR(get_ptr_from_handle!(current), buffer, i as u32, value);
+    }
+}
+
+/// # Description
+/// Get a word of the message of the running task, see seL4_SetMR().
+///
+/// # Arguments:
+/// * `i` - the index of the word in the message.
+///
+/// # Return:
+/// The word, or 0 if it is beyond the message registers and the task has no
+/// IPC buffer.
+pub fn seL4_GetMR(i: usize) -> word_t {
+    let buffer = get_current_task_handle!().get_ipc_buffer() as *mut u64;
+    if i >= n_msgRegisters && buffer.is_null() {
+        return 0;
+    }
+    unsafe { getSyscallArg(i as u64, buffer) }
+}
+
+/* Send the message of the running task through the endpoint cap `ep`, as
+seL4_Send(), seL4_NBSend() and seL4_Call() do, and get the label and length
+of the message of the task afterwards, the reply of a call. */
+pub(crate) fn send_through(
+    ep: cap_t,
+    info: seL4_MessageInfo_t,
+    blocking: bool,
+    do_call: bool,
+) -> Result<seL4_MessageInfo_t, seL4_Error> {
+    let index = endpoint_with_rights(ep, true, false, false)?;
+    let current = get_current_task_handle!();
+    set_message_info(&current, info);
+    sendIPC(
+        blocking,
+        do_call,
+        cap_endpoint_cap_get_capEPBadge(ep),
+        cap_endpoint_cap_get_capCanGrant(ep) != 0,
+        &current,
+        index,
+    );
+    Ok(get_message_info(&current).0)
+}
+
+/* Receive a message through the endpoint cap `ep`, as seL4_Recv() and
+seL4_NBRecv() do. */
+pub(crate) fn receive_through(ep: cap_t, blocking: bool) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+    let index = endpoint_with_rights(ep, false, true, false)?;
+    let current = get_current_task_handle!();
+    current.set_caller(None);
+    receiveIPC(&current, index, blocking);
+    Ok(get_message_info(&current))
+}
+
+/* Reply, then receive through the endpoint cap `ep`, as seL4_ReplyRecv()
+does. */
+pub(crate) fn reply_receive_through(
+    ep: cap_t,
+    info: seL4_MessageInfo_t,
+) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+    endpoint_with_rights(ep, false, true, false)?;
+    seL4_Reply(info);
+    receive_through(ep, true)
+}
+
+/// # Description
+/// Send a message on an endpoint, blocking until a task receives it.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the send right, in the CSpace of the running task.
+/// * `info` - the label and length of the message set with seL4_SetMR().
+///
+/// # Return:
+/// Ok(()) once the message was received, otherwise seL4_FailedLookup or
+/// seL4_InvalidCapability.
+pub fn seL4_Send(ep: u64, info: seL4_MessageInfo_t) -> Result<(), seL4_Error> {
+    send_through(lookup_endpoint(ep)?, info, true, false)?;
+    Ok(())
+}
+
+/// # Description
+/// Send a message on an endpoint if a task is waiting to receive it, else
+/// drop the message.  Never blocks.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the send right, in the CSpace of the running task.
+/// * `info` - the label and length of the message set with seL4_SetMR().
+///
+/// # Return:
+/// Ok(()) whether or not the message was received, otherwise
+/// seL4_FailedLookup or seL4_InvalidCapability.
+pub fn seL4_NBSend(ep: u64, info: seL4_MessageInfo_t) -> Result<(), seL4_Error> {
+    send_through(lookup_endpoint(ep)?, info, false, false)?;
+    Ok(())
+}
+
+/// # Description
+/// Send a message on an endpoint and block until the receiver replies.  The
+/// reply replaces the message of the running task.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the send right, in the CSpace of the running task.
+///   Without the grant right the receiver can't reply, and the running task is left inactive
+///   once the message is received.
+/// * `info` - the label and length of the message set with seL4_SetMR().
+///
+/// # Return:
+/// The label and length of the reply, otherwise seL4_FailedLookup or
+/// seL4_InvalidCapability.
+pub fn seL4_Call(ep: u64, info: seL4_MessageInfo_t) -> Result<seL4_MessageInfo_t, seL4_Error> {
+    send_through(lookup_endpoint(ep)?, info, true, true)
+}
+
+/// # Description
+/// Block until a message is received on an endpoint.  A call that was
+/// received before and not replied to can't be replied to anymore.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the receive right, in the CSpace of the running task.
+///
+/// # Return:
+/// The label and length of the message, and the badge of the cap it was
+/// sent through, otherwise seL4_FailedLookup or seL4_InvalidCapability.
+pub fn seL4_Recv(ep: u64) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+    receive_through(lookup_endpoint(ep)?, true)
+}
+
+/// # Description
+/// Receive a message on an endpoint if a task is waiting to send one.
+/// Never blocks.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the receive right, in the CSpace of the running task.
+///
+/// # Return:
+/// As seL4_Recv(); an empty message with badge 0 if no task was waiting.
+pub fn seL4_NBRecv(ep: u64) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+    receive_through(lookup_endpoint(ep)?, false)
+}
+
+/// # Description
+/// Reply to the last call received by the running task.  Nothing happens if
+/// there is no call to reply to.
+///
+/// # Arguments:
+/// * `info` - the label and length of the reply set with seL4_SetMR().
+///
+/// # Return:
+/// Nothing
+pub fn seL4_Reply(info: seL4_MessageInfo_t) {
+    let current = get_current_task_handle!();
+    set_message_info(&current, info);
+    if let Some(caller) = current.get_caller() {
+        current.set_caller(None);
+        doReplyTransfer(&current, &caller);
+    }
+}
+
+/// # Description
+/// Reply to the last call received by the running task, then block until
+/// the next message is received on an endpoint, as a server loop does.
+///
+/// # Arguments:
+/// * `ep` - the cptr of an endpoint cap with the receive right, in the CSpace of the running task.
+/// * `info` - the label and length of the reply set with seL4_SetMR().
+///
+/// # Return:
+/// As seL4_Recv().  Nothing is replied if the cap is invalid.
+pub fn seL4_ReplyRecv(
+    ep: u64,
+    info: seL4_MessageInfo_t,
+) -> Result<(seL4_MessageInfo_t, word_t), seL4_Error> {
+    reply_receive_through(lookup_endpoint(ep)?, info)
+}
@@ -0,0 +1,188 @@
+// executor.rs, async/await support
+// Runs futures inside a single task.  While no future can make progress,
+// the task waits for its notification, which the wakers of the futures give.
+
+use crate::port::*;
+#[cfg(not(feature = "configUSE_CAPS"))]
+use crate::task_control::*;
+#[cfg(feature = "configUSE_CAPS")]
+use crate::task_control_cap::*;
+use crate::task_notify::{task_notify_give, task_notify_give_from_isr, task_notify_take};
+use crate::*;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/* Wakes the task running a future by notifying it. */
+struct TaskWaker {
+    task: TaskHandle,
+    woken: AtomicBool,
+}
+
+/* The wakers are built on a vtable of their own rather than std::task::Wake,
+so that wake_from_isr() can tell them from other wakers. */
+static TASK_WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_task_waker, wake_task_waker, wake_task_waker_by_ref, drop_task_waker);
+
+impl TaskWaker {
+    fn into_waker(self: Arc<Self>) -> Waker {
+        let data = Arc::into_raw(self) as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(data, &TASK_WAKER_VTABLE)) }
+    }
+}
+
+unsafe fn clone_task_waker(data: *const ()) -> RawWaker {
+    Arc::increment_strong_count(data as *const TaskWaker);
+    RawWaker::new(data, &TASK_WAKER_VTABLE)
+}
+
+unsafe fn wake_task_waker(data: *const ()) {
+    wake_task_waker_by_ref(data);
+    drop_task_waker(data);
+}
+
+unsafe fn wake_task_waker_by_ref(data: *const ()) {
+    let waker = &*(data as *const TaskWaker);
+    waker.woken.store(true, Ordering::SeqCst);
+    task_notify_give(&waker.task);
+}
+
+unsafe fn drop_task_waker(data: *const ()) {
+    drop(Arc::from_raw(data as *const TaskWaker));
+}
+
+/// # Description
+/// The interrupt safe version of `Waker::wake()`.  The wakers of `block_on()`
+/// and `Executor` notify their task with task_notify_give_from_isr(); any
+/// other waker is woken as it is, so it must be safe to wake from an
+/// interrupt.
+///
+/// # Arguments:
+/// `waker` - the waker to wake.
+///
+/// # Return:
+/// true if the woken task has a priority above the running task, so a
+/// context switch should be requested before the interrupt exits.
+pub fn wake_from_isr(waker: Waker) -> bool {
+    if !ptr::eq(waker.vtable(), &TASK_WAKER_VTABLE) {
+        waker.wake();
+        return false;
+    }
+    let task_waker = unsafe { &*(waker.data() as *const TaskWaker) };
+    task_waker.woken.store(true, Ordering::SeqCst);
+    task_notify_give_from_isr(&task_waker.task)
+}
+
+/// # Description
+/// Run a future to completion in the calling task, which waits for its
+/// notification while the future can't make progress.  The notification of
+/// the task must not be used for anything else meanwhile.
+///
+/// # Arguments:
+/// `future` - the future to run.
+///
+/// # Return:
+/// The output of the future.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Arc::new(TaskWaker {
+        task: get_current_task_handle!(),
+        woken: AtomicBool::new(true),
+    })
+    .into_waker();
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        task_notify_take(true, portMAX_DELAY);
+    }
+}
+
+/// * Description:
+///
+/// Runs many futures inside the calling task, e.g. cooperative state
+/// machines that would otherwise need a task each.  Only the futures whose
+/// wakers were called are polled again.  The notification of the task must
+/// not be used for anything else while the executor runs.
+pub struct Executor<'a> {
+    futures: Vec<Pin<Box<dyn Future<Output = ()> + 'a>>>,
+}
+
+impl<'a> Executor<'a> {
+    /// # Description
+    /// Create an executor without futures.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// The created executor.
+    pub fn new() -> Self {
+        Executor {
+            futures: Vec::new(),
+        }
+    }
+
+    /// # Description
+    /// Add a future to be run by `run()`.
+    ///
+    /// # Arguments:
+    /// `future` - the future to run.
+    ///
+    /// # Return:
+    /// Nothing
+    pub fn spawn<F: Future<Output = ()> + 'a>(&mut self, future: F) {
+        self.futures.push(Box::pin(future));
+    }
+
+    /// # Description
+    /// Run the futures in the calling task until all of them have completed.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// Nothing
+    pub fn run(&mut self) {
+        let task = get_current_task_handle!();
+        let mut futures: Vec<_> = self
+            .futures
+            .drain(..)
+            .map(|future| {
+                let waker = Arc::new(TaskWaker {
+                    task: task.clone(),
+                    woken: AtomicBool::new(true),
+                });
+                (future, waker)
+            })
+            .collect();
+
+        while !futures.is_empty() {
+            let mut polled = false;
+            futures.retain_mut(|(future, waker)| {
+                if !waker.woken.swap(false, Ordering::SeqCst) {
+                    return true;
+                }
+                polled = true;
+                let task_waker = Arc::clone(waker).into_waker();
+                let mut context = Context::from_waker(&task_waker);
+                future.as_mut().poll(&mut context).is_pending()
+            });
+
+            /* No future was woken, so wait until one is. */
+            if !polled {
+                task_notify_take(true, portMAX_DELAY);
+            }
+        }
+    }
+}
+
+impl<'a> Default for Executor<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
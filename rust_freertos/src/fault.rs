@@ -0,0 +1,211 @@
+// fault.rs, faults of tasks sent to their fault endpoints
+// A task faults when it invokes a cptr that doesn't designate a cap, makes a
+// system call unknown to the kernel, or when its closure panics.  As in seL4,
+// the fault is sent as a call on the fault endpoint, named by the fault
+// handler cptr of the task in its own CSpace, and the label of the message is
+// the type of the fault:
+//   seL4_Fault_CapFault        IP, Addr, InRecvPhase, LookupFailureType, and the lookup failure
+//   seL4_Fault_UnknownSyscall  Syscall
+//   seL4_Fault_UserException   FaultIP, SP, FLAGS, Number, Code
+// Tasks have no instruction or stack pointer of their own, so those words
+// are 0, and a panic is the user exception 0 with code 0.
+// A reply labelled 0 restarts the task: the system call is made again, the
+// unknown system call returns with the reply as its message, and a task
+// whose closure panicked runs the closure given since by
+// TcbCap::set_entry().  Any other label leaves the task inactive, until it
+// is resumed or deleted through its thread cap.
+// Faults of a task without a fault handler are not sent, and are reported as
+// before: an error of the system call, or the panic left to abort the task.
+
+#![allow(non_snake_case)]
+
+use crate::arch_structures_TCB::*;
+use crate::endpoint::{doIPCTransfer, endpoint_with_rights, sendIPC, set_inactive};
+use crate::kernel::CriticalSection;
+use crate::port::*;
+use crate::regs::*;
+use crate::task_control_cap::*;
+use crate::task_global::*;
+use crate::types::*;
+use crate::CSpace::lookupCap;
+use crate::*;
+use std::any::Any;
+use std::panic;
+
+/* Leave the running task inactive until it is resumed through its thread
+cap. */
+fn park(thread: &TaskHandle) {
+    {
+        let _critical = CriticalSection::enter();
+        set_inactive(thread);
+        portYIELD_WITHIN_API!();
+    }
+    thread.set_state(TaskState::running);
+}
+
+/* Call the fault endpoint of `thread` with the fault set in current_fault.
+If the fault handler isn't an endpoint cap with the send and grant rights,
+current_fault is set to the cap fault of the handler, and EXCEPTION_FAULT is
+returned. */
+unsafe fn sendFaultIPC(thread: &TaskHandle) -> u64 {
+    let handlerCPtr = thread.get_fault_handler();
+    let original_lookup_fault = current_lookup_fault;
+    let lu_ret = lookupCap(&mut thread.clone(), handlerCPtr);
+    if lu_ret.status != 0u64 {
+        current_fault = seL4_Fault_CapFault_new(handlerCPtr, 0);
+        return exception::EXCEPTION_FAULT as u64;
+    }
+    match endpoint_with_rights(lu_ret.cap, true, false, true) {
+        Ok(index) => {
+            let fault = current_fault;
+            thread.set_fault(fault);
+            if seL4_Fault_get_seL4_FaultType(&fault) == seL4_Fault_tag_t::seL4_Fault_CapFault as u64 {
+                thread.set_lookup_failure(original_lookup_fault);
+            }
+            sendIPC(
+                true,
+                true,
+                cap_endpoint_cap_get_capEPBadge(lu_ret.cap),
+                true,
+                thread,
+                index,
+            );
+            0u64
+        }
+        Err(_) => {
+            current_fault = seL4_Fault_CapFault_new(handlerCPtr, 0);
+            current_lookup_fault = lookup_fault_missing_capability_new(0);
+            exception::EXCEPTION_FAULT as u64
+        }
+    }
+}
+
+/* A fault that can't be sent to the fault handler leaves the task
+inactive. */
+fn handleDoubleFault(thread: &TaskHandle) {
+    warn!(
+        "The task {} has no valid fault handler, it is left inactive.",
+        thread.get_name()
+    );
+    park(thread);
+}
+
+/// # Description
+/// Send the fault set in current_fault to the fault endpoint of the running
+/// task, and wait for the reply.  The task is left inactive if its fault
+/// handler is invalid, or if the reply doesn't restart it.
+///
+/// * C implementation: syscall.c handleFault()
+///
+/// # Arguments:
+/// * `thread` - the running task, which has a fault handler.
+///
+/// # Return:
+/// Nothing, once the task is restarted or resumed.
+pub fn handleFault(thread: &TaskHandle) {
+    if unsafe { sendFaultIPC(thread) } != 0u64 {
+        handleDoubleFault(thread);
+    }
+}
+
+/// # Description
+/// Send the panic of the closure of the running task to its fault endpoint,
+/// as a user exception.  Called by the run wrapper of the task once the panic
+/// is caught.
+///
+/// # Arguments:
+/// * `payload` - the payload of the panic, resumed if the task has no fault handler.
+///
+/// # Return:
+/// The closure to run next, boxed as the parameter of the run wrapper,
+/// once it is given by TcbCap::set_entry() and the task is restarted.
+pub fn handlePanic(payload: Box<dyn Any + Send>) -> u64 {
+    let thread = get_current_task_handle!();
+    if thread.get_fault_handler() == 0 {
+        panic::resume_unwind(payload);
+    }
+    unsafe {
+        current_fault = seL4_Fault_UserException_new(0, 0);
+    }
+    handleFault(&thread);
+    loop {
+        let entry = thread.take_entry();
+        if entry != 0 {
+            return entry;
+        }
+        park(&thread);
+    }
+}
+
+/* Set the message registers of a fault message, see the layouts above. */
+unsafe fn setMRs_fault(sender: &TaskHandle, receiver: *mut tcb_t, receiveIPCBuffer: *mut u64) -> u32 {
+    let fault = sender.get_fault();
+    let faultType = seL4_Fault_get_seL4_FaultType(&fault);
+    if faultType == seL4_Fault_tag_t::seL4_Fault_CapFault as u64 {
+        setMR(receiver, receiveIPCBuffer, 0, 0);
+        setMR(receiver, receiveIPCBuffer, 1, seL4_Fault_CapFault_get_address(&fault));
+        setMR(receiver, receiveIPCBuffer, 2, seL4_Fault_CapFault_get_inReceivePhase(&fault));
+        return setMRs_lookup_failure(receiver, receiveIPCBuffer, sender.get_lookup_failure(), 3);
+    } else if faultType == seL4_Fault_tag_t::seL4_Fault_UnknownSyscall as u64 {
+        return setMR(
+            receiver,
+            receiveIPCBuffer,
+            0,
+            seL4_Fault_UnknownSyscall_get_syscallNumber(&fault),
+        );
+    } else if faultType == seL4_Fault_tag_t::seL4_Fault_UserException as u64 {
+        for i in 0..3 {
+            setMR(receiver, receiveIPCBuffer, i, 0);
+        }
+        setMR(receiver, receiveIPCBuffer, 3, seL4_Fault_UserException_get_number(&fault));
+        return setMR(receiver, receiveIPCBuffer, 4, seL4_Fault_UserException_get_code(&fault));
+    }
+    panic!("Invalid fault");
+}
+
+/// # Description
+/// Transfer the fault of `sender` to `receiver` as a message labelled with
+/// the type of the fault, instead of the message of `sender`.
+///
+/// * C implementation: ipc.c doFaultTransfer()
+///
+/// # Arguments:
+/// * `badge` - the badge of the fault endpoint cap.
+/// * `sender` - the faulting task.
+/// * `receiver` - the task receiving on the fault endpoint.
+///
+/// # Return:
+/// Nothing
+pub fn doFaultTransfer(badge: word_t, sender: &TaskHandle, receiver: &TaskHandle) {
+    let receive_buffer = receiver.get_ipc_buffer() as *mut u64;
+    let label = seL4_Fault_get_seL4_FaultType(&sender.get_fault());
+    unsafe {
+        let receiver_ptr = get_ptr_from_handle!(receiver);
+        let length = setMRs_fault(sender, receiver_ptr, receive_buffer);
+        let tag = seL4_MessageInfo_new(label, 0, 0, length as u64);
+        setRegister(receiver_ptr, msgInfoRegister, wordFromMessageInfo(tag));
+        setRegister(receiver_ptr, badgeRegister, badge);
+    }
+}
+
+/// # Description
+/// Take the reply to a fault.  The reply to an unknown system call is passed
+/// to the faulting task as the message of the system call.
+///
+/// * C implementation: faulthandler.c handleFaultReply()
+///
+/// # Arguments:
+/// * `receiver` - the faulting task.
+/// * `sender` - the task replying to the fault.
+///
+/// # Return:
+/// Whether the reply restarts the faulting task, if it is labelled 0.
+pub fn handleFaultReply(receiver: &TaskHandle, sender: &TaskHandle) -> bool {
+    let tag = messageInfoFromWord(get_tcb_from_handle!(sender).registers[msgInfoRegister]);
+    if seL4_Fault_get_seL4_FaultType(&receiver.get_fault())
+        == seL4_Fault_tag_t::seL4_Fault_UnknownSyscall as u64
+    {
+        doIPCTransfer(sender, 0, receiver);
+    }
+    seL4_MessageInfo_get_label(tag) == 0
+}
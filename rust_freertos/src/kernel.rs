@@ -2,7 +2,7 @@
 // This file is created by Fan Jinhao.
 // Functions defined in this file are explained in Chapter 9 and 10.
 use crate::list;
-use crate::port::UBaseType;
+use crate::port::{BaseType, UBaseType};
 use crate::projdefs::pdFALSE;
 #[cfg(not(feature = "configUSE_CAPS"))]
 use crate::task_control::{TaskHandle, TCB};
@@ -11,6 +11,8 @@ use crate::task_control_cap::{TaskHandle, TCB};
 use crate::task_global::*;
 use crate::*; // TODO: Is this line necessary?
               // use crate::task_control::TCB;
+use std::marker::PhantomData;
+use std::mem;
 
 /* Definitions returned by xTaskGetSchedulerState().
  * The originial definitons are C constants, we changed them to enums.
@@ -103,6 +105,119 @@ macro_rules! taskEXIT_CRITICAL_FROM_ISR {
     };
 }
 
+/// A critical region that ends when the guard is dropped.
+/// `CriticalSection::enter()` is taskENTER_CRITICAL!(), and dropping the guard
+/// is the matching taskEXIT_CRITICAL!(), so an early return (or `?`) can never
+/// leave the critical nesting count unbalanced.
+///
+/// Guards can be nested; the port only leaves the critical region when the
+/// outermost guard is dropped. The guard must be dropped by the task that
+/// created it, so it is neither `Send` nor `Sync`.
+///
+/// * C implementation: task.h taskENTER_CRITICAL() / taskEXIT_CRITICAL()
+#[must_use = "the critical section ends as soon as the guard is dropped"]
+pub struct CriticalSection {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl CriticalSection {
+    /// Enters a critical region, see taskENTER_CRITICAL!().
+    ///
+    /// # Return
+    ///
+    /// The guard; the critical region is exited when it is dropped.
+    pub fn enter() -> Self {
+        taskENTER_CRITICAL!();
+        CriticalSection {
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for CriticalSection {
+    fn drop(&mut self) {
+        taskEXIT_CRITICAL!();
+    }
+}
+
+/// The interrupt safe version of `CriticalSection`.
+/// `IsrCriticalSection::enter()` masks interrupts and remembers the previous
+/// mask, which is restored when the guard is dropped.
+///
+/// * C implementation: task.h taskENTER_CRITICAL_FROM_ISR() / taskEXIT_CRITICAL_FROM_ISR()
+#[must_use = "the interrupt mask is restored as soon as the guard is dropped"]
+pub struct IsrCriticalSection {
+    saved_interrupt_status: BaseType,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl IsrCriticalSection {
+    /// Masks interrupts, see taskENTER_CRITICAL_FROM_ISR!().
+    ///
+    /// # Return
+    ///
+    /// The guard; the saved interrupt mask is restored when it is dropped.
+    pub fn enter() -> Self {
+        let saved_interrupt_status = taskENTER_CRITICAL_FROM_ISR!();
+        IsrCriticalSection {
+            saved_interrupt_status,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The interrupt mask that was in effect before the guard was created.
+    pub fn saved_interrupt_status(&self) -> BaseType {
+        self.saved_interrupt_status
+    }
+}
+
+impl Drop for IsrCriticalSection {
+    fn drop(&mut self) {
+        taskEXIT_CRITICAL_FROM_ISR!(self.saved_interrupt_status);
+    }
+}
+
+/// Suspends the scheduler until the guard is dropped or `resume()`d.
+/// Creating the guard calls task_suspend_all(); dropping it calls
+/// task_resume_all() and discards the result. Use `resume()` when the caller
+/// needs to know whether resuming the scheduler already caused a yield.
+///
+/// * C implementation: task.h vTaskSuspendAll() / xTaskResumeAll()
+#[must_use = "the scheduler is resumed as soon as the guard is dropped"]
+pub struct SchedulerSuspendGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl SchedulerSuspendGuard {
+    /// Suspends the scheduler, see task_suspend_all().
+    ///
+    /// # Return
+    ///
+    /// The guard; the scheduler is resumed when it is dropped.
+    pub fn suspend() -> Self {
+        task_suspend_all();
+        SchedulerSuspendGuard {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Resumes the scheduler, see task_resume_all().
+    ///
+    /// # Return
+    ///
+    /// true if resuming the scheduler caused a context switch.
+    pub fn resume(self) -> bool {
+        mem::forget(self);
+        task_resume_all()
+    }
+}
+
+impl Drop for SchedulerSuspendGuard {
+    fn drop(&mut self) {
+        task_resume_all();
+    }
+}
+
 /// Macro to disable all maskable interrupts.
 /// * Implemented by: Fan Jinhao.
 /// * C implementation: task.h
@@ -180,6 +295,10 @@ pub fn create_idle_task() -> TaskHandle {
     let idle_task_fn = || {
         loop {
             trace!("Idle Task running");
+            /* The idle task never blocks, so on emulated/simulated ports it
+            waits here while another task is running. */
+            portSOFTWARE_BARRIER!();
+
             /* THIS IS THE RTOS IDLE TASK - WHICH IS CREATED AUTOMATICALLY WHEN THE
             SCHEDULER IS STARTED. */
 
@@ -362,6 +481,10 @@ pub fn task_suspend_all() {
     post in the FreeRTOS support forum before reporting this as a bug! -
     http://goo.gl/wu4acr */
 
+    /* portSOFTWARE_BARRIER() is only implemented for emulated/simulated ports that
+    do not otherwise exhibit real time behaviour. */
+    portSOFTWARE_BARRIER!();
+
     // Increment SCHEDULER_SUSPENDED.
     set_scheduler_suspended!(get_scheduler_suspended!() + 1);
 }
@@ -397,8 +520,8 @@ pub fn task_resume_all() -> bool {
     removed task will have been added to the xPendingReadyList.  Once the
     scheduler has been resumed it is safe to move all the pending ready
     tasks from this list into their appropriate ready list. */
-    taskENTER_CRITICAL!();
     {
+        let _critical = CriticalSection::enter();
         // Decrement SCHEDULER_SUSPENDED.
         set_scheduler_suspended!(get_scheduler_suspended!() - 1);
         println!(
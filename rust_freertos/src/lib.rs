@@ -11,7 +11,19 @@ extern crate log;
 extern crate simplelog;
 // extern crate test;
 
+#[cfg(all(feature = "rust_port", feature = "c_port"))]
+compile_error!("Features \"rust_port\" and \"c_port\" can't be enabled at the same time.");
+#[cfg(not(any(feature = "rust_port", feature = "c_port")))]
+compile_error!("Either feature \"rust_port\" or feature \"c_port\" must be enabled.");
+
+#[cfg(feature = "c_port")]
 mod bindings; // This file is generated by bindgen and doesn't show up in the git repo.
+// The pure Rust port provides the same functions as the C bindings,
+// so port.rs works with either of them.
+#[cfg(feature = "rust_port")]
+mod rust_port;
+#[cfg(feature = "rust_port")]
+use rust_port as bindings;
 #[macro_use]
 pub mod config;
 pub mod ffi;
@@ -28,11 +40,19 @@ pub mod trace;
 pub mod kernel;
 pub mod queue;
 pub mod queue_api;
+pub mod block_queue;
 mod queue_h;
 mod task_queue;
-//mod mutex;
+#[cfg(feature = "configUSE_MUTEXES")]
+pub mod mutex;
 pub mod semaphore;
 pub mod task_timemanager;
+#[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+pub mod task_notify;
+#[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+pub mod executor;
+#[cfg(feature = "configCHECK_FOR_DEADLOCKS")]
+pub mod deadlock;
 // #[cfg(feature = "configUSE_CAPS")]
 // pub mod seL4;
 #[cfg(feature = "configUSE_CAPS")]
@@ -43,6 +63,18 @@ pub mod CSpace;
 pub mod arch_structures_TCB;
 #[cfg(feature = "configUSE_CAPS")]
 pub mod task_ipc;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod endpoint;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod fault;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod notification;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod syscall;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod capability;
+#[cfg(feature = "configUSE_CAPS")]
+pub mod untyped;
 
 #[cfg(feature = "configUSE_CAPS")]
 #[macro_use]
@@ -55,6 +87,8 @@ pub mod task_control;
 pub mod types;
 #[cfg(feature = "configUSE_STREAMBUFFER")]
  pub mod stream_buffer;
+#[cfg(feature = "configUSE_STREAMBUFFER")]
+pub mod message_buffer;
 #[cfg(feature = "configUSE_CAPS")]
 pub mod regs;
 #[cfg(test)]
@@ -202,12 +236,12 @@ mod tests {
         let mutex_holder = move || {
             for i in 1..11 {
                 trace!("Call down_recursive");
-                recursive_mutex.down_recursive(pdMS_TO_TICKS!(0));
+                recursive_mutex.down_recursive(pdMS_TO_TICKS!(0)).unwrap();
                 assert!(recursive_mutex.get_recursive_count() == i);
             }
 
             for j in 1..11 {
-                recursive_mutex.up_recursive();
+                recursive_mutex.up_recursive().unwrap();
                 assert!(recursive_mutex.get_recursive_count() == 10-j);
             }
             kernel::task_end_scheduler();
@@ -485,4 +519,1941 @@ mod tests {
 
             kernel::task_start_scheduler();
         }
+
+        #[test]
+        fn test_critical_section_guards() {
+            use kernel::{CriticalSection, SchedulerSuspendGuard};
+            use simplelog::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+
+            // Leaves both guards early, the way `?` or `return` would.
+            fn leave_early(fail: bool) -> Result<(), ()> {
+                let _scheduler = SchedulerSuspendGuard::suspend();
+                let _critical = CriticalSection::enter();
+                let _nested = CriticalSection::enter();
+                if fail {
+                    return Err(());
+                }
+                Ok(())
+            }
+
+            let checker = move || {
+                assert!(leave_early(true).is_err());
+                assert!(leave_early(false).is_ok());
+                assert_eq!(get_scheduler_suspended!(), 0);
+
+                // The tick only advances outside of critical sections.
+                let before = get_tick_count!();
+                task_timemanager::task_delay(pdMS_TO_TICKS!(2));
+                assert!(get_tick_count!() > before);
+                kernel::task_end_scheduler();
+            };
+
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(3)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_priority_ordered_waiters() {
+            use queue_api::Queue;
+            use simplelog::*;
+            use std::sync::Mutex;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let queue = Arc::new(Queue::new(3));
+            let woken = Arc::new(Mutex::new(Vec::new()));
+
+            // The receivers block in the order of increasing priority,
+            // so a FIFO wakeup would be the reverse of the expected one.
+            for priority in 2..5 {
+                let queue = Arc::clone(&queue);
+                let woken = Arc::clone(&woken);
+                let receiver = move || {
+                    task_timemanager::task_delay(priority as port::TickType - 2);
+                    if queue.receive(pdMS_TO_TICKS!(100)).is_ok() {
+                        woken.lock().unwrap().push(priority);
+                    }
+                    loop {
+                        task_timemanager::task_delay(pdMS_TO_TICKS!(100));
+                    }
+                };
+                let _receiver_task = TCB::new()
+                                    .name("Receiver")
+                                    .priority(priority)
+                                    .initialise(receiver);
+            }
+
+            // Every send wakes a receiver, which preempts the sender.
+            let sender = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                for i in 0..3 {
+                    queue.send(i, 0).unwrap();
+                }
+                assert_eq!(*woken.lock().unwrap(), vec![4, 3, 2]);
+                kernel::task_end_scheduler();
+            };
+            let _sender_task = TCB::new()
+                                .name("Sender")
+                                .priority(1)
+                                .initialise(sender);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_waiter_resorted_on_priority_change() {
+            use queue_api::Queue;
+            use simplelog::*;
+            use std::sync::Mutex;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let queue = Arc::new(Queue::new(2));
+            let woken = Arc::new(Mutex::new(Vec::new()));
+
+            let mut receivers = Vec::new();
+            for &(name, priority) in [("Low", 2), ("Mid", 3)].iter() {
+                let queue = Arc::clone(&queue);
+                let woken = Arc::clone(&woken);
+                let receiver = move || {
+                    if queue.receive(pdMS_TO_TICKS!(100)).is_ok() {
+                        woken.lock().unwrap().push(name);
+                    }
+                    loop {
+                        task_timemanager::task_delay(pdMS_TO_TICKS!(100));
+                    }
+                };
+                receivers.push(TCB::new()
+                                .name(name)
+                                .priority(priority)
+                                .initialise(receiver)
+                                .unwrap());
+            }
+
+            // Raise the priority of the blocked "Low" above "Mid".
+            let low = receivers[0].clone();
+            let sender = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                low.set_priority(4);
+                for i in 0..2 {
+                    queue.send(i, 0).unwrap();
+                }
+                assert_eq!(*woken.lock().unwrap(), vec!["Low", "Mid"]);
+                kernel::task_end_scheduler();
+            };
+            let _sender_task = TCB::new()
+                                .name("Sender")
+                                .priority(1)
+                                .initialise(sender);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_transitive_priority_inheritance() {
+            use semaphore::Semaphore;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicBool, Ordering};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let outer = Arc::new(Semaphore::new_mutex());
+            let inner = Arc::new(Semaphore::new_mutex());
+            let high_got_mutex = Arc::new(AtomicBool::new(false));
+
+            // "Low" holds `inner`, "Mid" holds `outer` and waits for `inner`,
+            // "High" waits for `outer`.
+            let low_inner = Arc::clone(&inner);
+            let low = TCB::new()
+                        .name("Low")
+                        .priority(1)
+                        .initialise(move || {
+                            low_inner.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(200));
+                            low_inner.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        })
+                        .unwrap();
+
+            let mid_outer = Arc::clone(&outer);
+            let mid = TCB::new()
+                        .name("Mid")
+                        .priority(2)
+                        .initialise(move || {
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                            mid_outer.semaphore_down(0).unwrap();
+                            inner.semaphore_down(pdMS_TO_TICKS!(1000)).unwrap();
+                            inner.semaphore_up().unwrap();
+                            mid_outer.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        })
+                        .unwrap();
+
+            let high_done = Arc::clone(&high_got_mutex);
+            let _high = TCB::new()
+                        .name("High")
+                        .priority(3)
+                        .initialise(move || {
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                            outer.semaphore_down(pdMS_TO_TICKS!(1000)).unwrap();
+                            high_done.store(true, Ordering::SeqCst);
+                            outer.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(60));
+                // "High" passes its priority down the whole chain.
+                assert_eq!(mid.get_priority(), 3);
+                assert_eq!(low.get_priority(), 3);
+
+                task_timemanager::task_delay(pdMS_TO_TICKS!(300));
+                assert!(high_got_mutex.load(Ordering::SeqCst));
+                assert_eq!(mid.get_priority(), 2);
+                assert_eq!(low.get_priority(), 1);
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(4)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_priority_disinherit_after_timeout() {
+            use semaphore::Semaphore;
+            use simplelog::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let outer = Arc::new(Semaphore::create_recursive_mutex());
+            let inner = Arc::new(Semaphore::new_mutex());
+
+            let low_inner = Arc::clone(&inner);
+            let low = TCB::new()
+                        .name("Low")
+                        .priority(1)
+                        .initialise(move || {
+                            low_inner.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(300));
+                            low_inner.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        })
+                        .unwrap();
+
+            let mid_outer = Arc::clone(&outer);
+            let mid = TCB::new()
+                        .name("Mid")
+                        .priority(2)
+                        .initialise(move || {
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                            mid_outer.down_recursive(0).unwrap();
+                            mid_outer.down_recursive(0).unwrap();
+                            inner.semaphore_down(pdMS_TO_TICKS!(1000)).unwrap();
+                            inner.semaphore_up().unwrap();
+                            mid_outer.up_recursive().unwrap();
+                            mid_outer.up_recursive().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        })
+                        .unwrap();
+
+            // "High" gives up on the recursive mutex long before "Low" is done.
+            let _high = TCB::new()
+                        .name("High")
+                        .priority(3)
+                        .initialise(move || {
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                            assert!(outer.down_recursive(pdMS_TO_TICKS!(50)).is_err());
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(60));
+                assert_eq!(mid.get_priority(), 3);
+                assert_eq!(low.get_priority(), 3);
+
+                // Both drop back to the priority of "Mid", the highest
+                // priority task still waiting.
+                task_timemanager::task_delay(pdMS_TO_TICKS!(100));
+                assert_eq!(mid.get_priority(), 2);
+                assert_eq!(low.get_priority(), 2);
+
+                task_timemanager::task_delay(pdMS_TO_TICKS!(250));
+                assert_eq!(mid.get_priority(), 2);
+                assert_eq!(low.get_priority(), 1);
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(4)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_priority_set_while_inherited() {
+            use semaphore::Semaphore;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicBool, Ordering};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let mutex = Arc::new(Semaphore::new_mutex());
+            let high_got_mutex = Arc::new(AtomicBool::new(false));
+
+            let low_mutex = Arc::clone(&mutex);
+            let low = TCB::new()
+                        .name("Low")
+                        .priority(1)
+                        .initialise(move || {
+                            low_mutex.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(100));
+                            low_mutex.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        })
+                        .unwrap();
+
+            let high_done = Arc::clone(&high_got_mutex);
+            let _high = TCB::new()
+                        .name("High")
+                        .priority(3)
+                        .initialise(move || {
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                            mutex.semaphore_down(pdMS_TO_TICKS!(1000)).unwrap();
+                            high_done.store(true, Ordering::SeqCst);
+                            mutex.semaphore_up().unwrap();
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                assert_eq!(low.get_priority(), 3);
+                // Below the inherited priority only the base priority is set,
+                // above it the task runs at the new priority at once.
+                low.set_sched_priority(2);
+                assert_eq!(low.get_priority(), 3);
+                low.set_sched_priority(4);
+                assert_eq!(low.get_priority(), 4);
+
+                task_timemanager::task_delay(pdMS_TO_TICKS!(150));
+                assert!(high_got_mutex.load(Ordering::SeqCst));
+                assert_eq!(low.get_priority(), 4);
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(5)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_ceiling_mutex() {
+            use queue_h::QueueError;
+            use semaphore::Semaphore;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicBool, Ordering};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            assert_eq!(
+                Semaphore::new_ceiling_mutex(configMAX_PRIORITIES!()).err(),
+                Some(QueueError::MutexCeilingInvalid)
+            );
+            let mutex = Arc::new(Semaphore::new_ceiling_mutex(3).unwrap());
+            let holder_done = Arc::new(AtomicBool::new(false));
+
+            let holder_mutex = Arc::clone(&mutex);
+            let done = Arc::clone(&holder_done);
+            let holder = move || {
+                holder_mutex.semaphore_down(0).unwrap();
+                // Raised to the ceiling as soon as the mutex is taken.
+                assert_eq!(get_current_task_priority!(), 3);
+                holder_mutex.semaphore_up().unwrap();
+                assert_eq!(get_current_task_priority!(), 1);
+                done.store(true, Ordering::SeqCst);
+                loop {
+                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                }
+            };
+            let _holder_task = TCB::new()
+                                .name("Holder")
+                                .priority(1)
+                                .initialise(holder);
+
+            // A task above the ceiling is refused the mutex.
+            let checker = move || {
+                assert_eq!(mutex.semaphore_down(0), Err(QueueError::MutexCeilingViolated));
+                task_timemanager::task_delay(pdMS_TO_TICKS!(50));
+                assert!(holder_done.load(Ordering::SeqCst));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(4)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "INCLUDE_vTaskDelete")]
+        fn test_mutex_ownership() {
+            use queue_h::QueueError;
+            use semaphore::Semaphore;
+            use simplelog::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let mutex = Arc::new(Semaphore::new_mutex());
+            let recursive_mutex = Arc::new(Semaphore::create_recursive_mutex());
+
+            let holder_mutex = Arc::clone(&mutex);
+            let holder_recursive_mutex = Arc::clone(&recursive_mutex);
+            let holder = TCB::new()
+                            .name("Holder")
+                            .priority(1)
+                            .initialise(move || {
+                                holder_mutex.semaphore_down(0).unwrap();
+                                holder_recursive_mutex.down_recursive(0).unwrap();
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            })
+                            .unwrap();
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                assert_eq!(mutex.semaphore_up().err(), Some(QueueError::MutexNotOwned));
+                assert_eq!(recursive_mutex.up_recursive(), Err(QueueError::MutexNotOwned));
+                assert_eq!(recursive_mutex.get_recursive_count(), 1);
+                assert_eq!(task_delete(Some(holder)), Err(QueueError::MutexStillHeld));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_queue_queries() {
+            use queue_api::Queue;
+            use queue_h::QueueError;
+            use simplelog::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let queue = Arc::new(Queue::new(3));
+
+            // "Sender" blocks on the full queue until an item is received from an ISR.
+            let sender_queue = Arc::clone(&queue);
+            let _sender = TCB::new()
+                            .name("Sender")
+                            .priority(3)
+                            .initialise(move || {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                                sender_queue.send(4, pdMS_TO_TICKS!(1000)).unwrap();
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                assert_eq!((queue.messages_waiting(), queue.spaces_available()), (0, 3));
+                assert!(queue.is_empty_from_isr());
+                assert_eq!(queue.peek_from_isr(), Err(QueueError::QueueEmpty));
+                for i in 1..4 {
+                    queue.send(i, 0).unwrap();
+                }
+                assert!(queue.is_full_from_isr());
+                assert_eq!(queue.peek_from_isr(), Ok(1));
+                assert_eq!(queue.messages_waiting_from_isr(), 3);
+
+                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                // Receiving makes room for the blocked, higher priority "Sender".
+                assert_eq!(queue.receive_from_isr(), (Ok(1), true));
+                assert_eq!(queue.spaces_available(), 1);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                assert!(queue.is_full_from_isr());
+
+                queue.reset().unwrap();
+                assert_eq!((queue.messages_waiting(), queue.spaces_available()), (0, 3));
+                assert_eq!(queue.receive_from_isr(), (Err(QueueError::QueueEmpty), false));
+                queue.send(5, 0).unwrap();
+                assert_eq!(queue.receive(0), Ok(5));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_typed_mutex() {
+            use mutex::{Mutex, RecursiveMutex};
+            use simplelog::*;
+            use std::cell::Cell;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let counter = Arc::new(Mutex::new(0));
+            let recursive_counter = Arc::new(RecursiveMutex::new(Cell::new(0)));
+
+            // "Holder" keeps the lock while "Checker" tries to take it.
+            let holder_counter = Arc::clone(&counter);
+            let _holder = TCB::new()
+                            .name("Holder")
+                            .priority(1)
+                            .initialise(move || {
+                                let mut guard = holder_counter.lock(0).unwrap();
+                                *guard += 1;
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                                *guard += 1;
+                                drop(guard);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                assert!(counter.try_lock().is_err());
+                // Blocks until "Holder" drops its guard.
+                let mut guard = counter.lock(pdMS_TO_TICKS!(1000)).unwrap();
+                assert_eq!(*guard, 2);
+                *guard += 1;
+                drop(guard);
+                assert_eq!(*counter.try_lock().unwrap(), 3);
+
+                let outer = recursive_counter.lock(0).unwrap();
+                outer.set(outer.get() + 1);
+                {
+                    let inner = recursive_counter.try_lock().unwrap();
+                    inner.set(inner.get() + 1);
+                }
+                assert_eq!(outer.get(), 2);
+                drop(outer);
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+        fn test_async_executor() {
+            use executor::{block_on, Executor};
+            use queue_api::Queue;
+            use semaphore::Semaphore;
+            use simplelog::*;
+            use std::cell::Cell;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let queue = Arc::new(Queue::new(2));
+            let mutex = Arc::new(Semaphore::new_mutex());
+
+            // "Producer" holds the mutex and sends while "Consumer" awaits both.
+            let producer_queue = Arc::clone(&queue);
+            let producer_mutex = Arc::clone(&mutex);
+            let _producer = TCB::new()
+                            .name("Producer")
+                            .priority(1)
+                            .initialise(move || {
+                                producer_mutex.semaphore_down(0).unwrap();
+                                for i in 1..6 {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                                    producer_queue.send(i, pdMS_TO_TICKS!(100)).unwrap();
+                                }
+                                producer_mutex.semaphore_up().unwrap();
+                                // The waiting "Consumer" is notified from the ISR path as well.
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                                let (result, woken) = producer_queue.send_to_back_from_isr(7);
+                                result.unwrap();
+                                assert!(woken);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let consumer = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                let sum = Cell::new(0);
+                let acquired_after = Cell::new(0);
+                {
+                    let mut executor = Executor::new();
+                    executor.spawn(async {
+                        for _ in 1..6 {
+                            sum.set(sum.get() + queue.recv_async().await);
+                        }
+                    });
+                    executor.spawn(async {
+                        mutex.acquire_async().await.unwrap();
+                        acquired_after.set(sum.get());
+                        mutex.semaphore_up().unwrap();
+                    });
+                    executor.run();
+                }
+                assert_eq!(sum.get(), 15);
+                // The mutex is only given back after the last item was sent.
+                assert!(acquired_after.get() >= 10);
+
+                queue.send(42, 0).unwrap();
+                assert_eq!(block_on(queue.recv_async()), 42);
+                assert_eq!(block_on(queue.recv_async()), 7);
+                kernel::task_end_scheduler();
+            };
+            let _consumer_task = TCB::new()
+                                .name("Consumer")
+                                .priority(2)
+                                .initialise(consumer);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        fn test_block_queue() {
+            use block_queue::BlockQueue;
+            use queue_api::MoveQueue;
+            use simplelog::*;
+
+            // Neither Clone nor Default.
+            struct Frame(Vec<u8>);
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let frames = Arc::new(MoveQueue::new(2));
+            let blocks = Arc::new(BlockQueue::new(2, 3, 1024));
+
+            let producer_frames = Arc::clone(&frames);
+            let producer_blocks = Arc::clone(&blocks);
+            let _producer = TCB::new()
+                            .name("Producer")
+                            .priority(1)
+                            .initialise(move || {
+                                for i in 0..5u8 {
+                                    producer_frames.send(Frame(vec![i; 1024]), pdMS_TO_TICKS!(100)).ok().unwrap();
+                                    // Blocks once all three blocks are in use.
+                                    let mut block = producer_blocks.allocate(pdMS_TO_TICKS!(100)).unwrap();
+                                    block[0] = i;
+                                    block[1023] = i;
+                                    producer_blocks.send(block, pdMS_TO_TICKS!(100)).ok().unwrap();
+                                }
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let consumer = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                let mut kept = Vec::new();
+                for i in 0..5u8 {
+                    let frame = frames.receive(pdMS_TO_TICKS!(100)).unwrap();
+                    assert_eq!(frame.0, vec![i; 1024]);
+                    let block = blocks.receive(pdMS_TO_TICKS!(100)).unwrap();
+                    assert_eq!((block[0], block[1023], block.len()), (i, i, 1024));
+                    // Hold on to the first two blocks, so the producer runs short of blocks.
+                    if i < 2 {
+                        kept.push(block);
+                    }
+                }
+                assert_eq!(kept.len(), 2);
+                assert!(blocks.allocate(0).is_ok());
+                let _all_but_kept = blocks.allocate(0).unwrap();
+                assert!(blocks.allocate(0).is_err());
+                drop(kept);
+                assert!(blocks.allocate(0).is_ok());
+
+                // A full queue hands the item back.
+                frames.send(Frame(vec![1]), 0).ok().unwrap();
+                frames.send(Frame(vec![2]), 0).ok().unwrap();
+                let (error, frame) = frames.send(Frame(vec![3]), 0).err().unwrap();
+                assert_eq!((error, frame.0), (queue_h::QueueError::QueueFull, vec![3]));
+                kernel::task_end_scheduler();
+            };
+            let _consumer_task = TCB::new()
+                                .name("Consumer")
+                                .priority(2)
+                                .initialise(consumer);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(all(feature = "configCHECK_FOR_DEADLOCKS", feature = "configUSE_CAPS"))]
+        fn test_deadlock_detection() {
+            use capability::*;
+            use deadlock::WaitForEdge;
+            use semaphore::Semaphore;
+            use simplelog::*;
+            use std::sync::Mutex;
+            use types::*;
+
+            lazy_static! {
+                static ref DEADLOCKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+            }
+            fn record_deadlock(cycle: &[WaitForEdge]) {
+                DEADLOCKS.lock().unwrap().push(deadlock::describe_cycle(cycle));
+            }
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            deadlock::set_deadlock_hook(Some(record_deadlock));
+            let m1 = Arc::new(Semaphore::new_mutex());
+            let m2 = Arc::new(Semaphore::new_mutex());
+            let m3 = Arc::new(Semaphore::new_mutex());
+
+            // "A" and "B" take two mutexes in opposite order.
+            let (a_m1, a_m2) = (Arc::clone(&m1), Arc::clone(&m2));
+            let _a = TCB::new()
+                        .name("A")
+                        .priority(2)
+                        .initialise(move || {
+                            a_m1.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                            assert!(a_m2.semaphore_down(pdMS_TO_TICKS!(100)).is_err());
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+            let _b = TCB::new()
+                        .name("B")
+                        .priority(2)
+                        .initialise(move || {
+                            m2.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                            assert!(m1.semaphore_down(pdMS_TO_TICKS!(1000)).is_err());
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+
+            // "C" holds a mutex while calling "D" through an endpoint, and
+            // "D" blocks on that mutex before replying.
+            let root = CNode::cnode_cap_new(4, 0, 60).unwrap();
+            unsafe {
+                CNode::provide_cap(root, 1, endpoint::endpoint_cap_new(8, 0, true, true, true)).unwrap();
+            }
+            let d_m3 = Arc::clone(&m3);
+            let _d = TCB::new()
+                        .name("D")
+                        .priority(2)
+                        .cspace_root(root)
+                        .initialise(move || {
+                            EndpointCap::from_cptr(CPtr::new(1)).unwrap().recv().unwrap();
+                            assert!(d_m3.semaphore_down(pdMS_TO_TICKS!(100)).is_err());
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+            let _c = TCB::new()
+                        .name("C")
+                        .priority(2)
+                        .cspace_root(root)
+                        .initialise(move || {
+                            m3.semaphore_down(0).unwrap();
+                            task_timemanager::task_delay(pdMS_TO_TICKS!(30));
+                            // Never replied, "D" gives up on the mutex instead.
+                            let _ = EndpointCap::from_cptr(CPtr::new(1)).unwrap().call(seL4_MessageInfo_new(0, 0, 0, 0));
+                            loop {
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                            }
+                        });
+
+            let checker = move || {
+                task_timemanager::task_delay(pdMS_TO_TICKS!(60));
+                deadlock::set_deadlock_hook(None);
+                let deadlocks = DEADLOCKS.lock().unwrap().clone();
+                assert_eq!(deadlocks.len(), 2);
+                assert!(deadlocks[0].starts_with("A is blocked on mutex"));
+                assert!(deadlocks[0].contains("held by B, B is blocked on mutex"));
+                assert!(deadlocks[0].ends_with("held by A"));
+                assert!(deadlocks[1].starts_with("D is blocked on mutex"));
+                assert!(deadlocks[1].ends_with("held by C, C is blocked on endpoint 8 served by D"));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(3)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_STREAMBUFFER")]
+        fn test_message_buffer() {
+            use message_buffer::MessageBuffer;
+            use simplelog::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let buffer = MessageBuffer::new(20);
+
+            // "Receiver" waits for the first message, then drains a full buffer later.
+            let receiver_buffer = buffer.clone();
+            let _receiver = TCB::new()
+                            .name("Receiver")
+                            .priority(3)
+                            .initialise(move || {
+                                let mut message = [0u8; 16];
+                                assert_eq!(receiver_buffer.receive(&mut message, port::portMAX_DELAY), 5);
+                                assert_eq!(&message[..5], b"hello");
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                                assert_eq!(receiver_buffer.receive(&mut message, 0), 12);
+                                assert_eq!(&message[..12], b"0123456789ab");
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                assert!(buffer.is_empty());
+                assert_eq!((buffer.spaces_available(), buffer.next_length_bytes()), (20, 0));
+                assert_eq!(buffer.send(b"hello", 0), 5);
+                assert!(buffer.is_empty());
+
+                // Each message takes 4 more bytes than its length.
+                assert_eq!(buffer.send(b"abc", 0), 3);
+                assert_eq!(buffer.send(b"de", 0), 2);
+                assert_eq!(buffer.spaces_available(), 7);
+                assert_eq!(buffer.send(b"fghij", 0), 0);
+                // A message that can never fit fails without blocking.
+                assert_eq!(buffer.send(&[0; 20], pdMS_TO_TICKS!(1000)), 0);
+
+                // A message is left in the buffer if it doesn't fit.
+                let mut message = [0u8; 8];
+                assert_eq!(buffer.next_length_bytes(), 3);
+                assert_eq!(buffer.receive(&mut message[..2], 0), 0);
+                assert_eq!(buffer.receive_from_isr(&mut message), (3, false));
+                assert_eq!(&message[..3], b"abc");
+                assert_eq!(buffer.receive(&mut message, 0), 2);
+                assert_eq!(&message[..2], b"de");
+                assert_eq!(buffer.receive(&mut message, pdMS_TO_TICKS!(5)), 0);
+
+                // Messages wrap around the end of the storage.
+                for i in 0..10u8 {
+                    assert_eq!(buffer.send(&[i; 5], 0), 5);
+                    assert_eq!(buffer.receive(&mut message, 0), 5);
+                    assert_eq!(&message[..5], &[i; 5]);
+                }
+
+                assert_eq!(buffer.send_from_isr(b"xy"), (2, false));
+                assert_eq!(buffer.spaces_available(), 14);
+                buffer.reset().unwrap();
+                assert!(buffer.is_empty());
+
+                // Blocks until "Receiver" makes room.
+                assert_eq!(buffer.send(b"0123456789ab", 0), 12);
+                assert!(buffer.is_full());
+                assert_eq!(buffer.send(b"zz", pdMS_TO_TICKS!(1000)), 2);
+                assert_eq!(buffer.receive(&mut message, 0), 2);
+                assert_eq!(&message[..2], b"zz");
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_STREAMBUFFER")]
+        fn test_stream_buffer() {
+            use simplelog::*;
+            use stream_buffer::{StreamBufferError, StreamBufferHandle};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            // Caller-provided storage of 9 bytes holds 8 bytes of data.
+            let storage = Box::leak(vec![0u8; 9].into_boxed_slice());
+            let buffer = StreamBufferHandle::StreamBufferGenericCreateStatic(storage, 4, false);
+
+            // "Receiver" is only woken once the trigger level is reached, or
+            // gets the bytes there are when its block time expires.
+            let receiver_buffer = buffer.clone();
+            let _receiver = TCB::new()
+                            .name("Receiver")
+                            .priority(3)
+                            .initialise(move || {
+                                let mut bytes = [0u8; 8];
+                                assert_eq!(receiver_buffer.StreamBufferReceive(&mut bytes, port::portMAX_DELAY), 4);
+                                assert_eq!(&bytes[..4], &[1, 2, 3, 4]);
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                                assert_eq!(receiver_buffer.StreamBufferReceive(&mut bytes, pdMS_TO_TICKS!(30)), 1);
+                                assert_eq!(bytes[0], 5);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                assert_eq!(buffer.StreamBufferSpacesAvailable(), 8);
+                assert_eq!(buffer.StreamBufferSend(&[1, 2], 0), 2);
+                assert_eq!(buffer.StreamBufferBytesAvailable(), 2);
+                assert_eq!(buffer.StreamBufferSend(&[3, 4], 0), 2);
+                assert!(buffer.StreamBufferIsEmpty());
+
+                // As many bytes are written as fit.
+                let bytes: Vec<u8> = (0..10).collect();
+                assert_eq!(buffer.StreamBufferSend(&bytes, 0), 8);
+                assert!(buffer.StreamBufferIsFull());
+                let mut received = [0u8; 16];
+                assert_eq!(buffer.StreamBufferReceive(&mut received[..3], 0), 3);
+                assert_eq!(&received[..3], &[0, 1, 2]);
+                // The bytes wrap around the end of the storage.
+                assert_eq!(buffer.StreamBufferSend(&[10, 11, 12], 0), 3);
+                assert_eq!(buffer.StreamBufferReceive(&mut received, 0), 8);
+                assert_eq!(&received[..8], &[3, 4, 5, 6, 7, 10, 11, 12]);
+
+                assert_eq!(
+                    buffer.StreamBufferSetTriggerLevel(9),
+                    Err(StreamBufferError::StreamBufferTriggerLevelOverflow)
+                );
+                buffer.StreamBufferSetTriggerLevel(0).unwrap();
+                assert_eq!(buffer.get_triggerlevelbytes(), 1);
+                buffer.StreamBufferSetTriggerLevel(4).unwrap();
+
+                task_timemanager::task_delay(pdMS_TO_TICKS!(30));
+                assert_eq!(buffer.StreamBufferSend(&[5], 0), 1);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                assert!(buffer.StreamBufferIsEmpty());
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_STREAMBUFFER")]
+        fn test_stream_buffer_callbacks() {
+            use message_buffer::MessageBuffer;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            use stream_buffer::StreamBufferHandle;
+
+            static SENT: AtomicUsize = AtomicUsize::new(0);
+            static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+            // Counts the sends, and still wakes the receiver.
+            fn on_send(handle: &StreamBufferHandle, inside_isr: bool) -> bool {
+                SENT.fetch_add(1, Ordering::SeqCst);
+                if inside_isr {
+                    stream_buffer::send_completed_from_isr(handle)
+                } else {
+                    stream_buffer::send_completed(handle);
+                    false
+                }
+            }
+            // Counts the receives instead of waking the sender.
+            fn on_receive(_handle: &StreamBufferHandle, _inside_isr: bool) -> bool {
+                RECEIVED.fetch_add(1, Ordering::SeqCst);
+                false
+            }
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let buffer = StreamBufferHandle::StreamBufferGenericCreateWithCallback(
+                8,
+                1,
+                false,
+                Some(on_send),
+                Some(on_receive),
+            );
+
+            let receiver_buffer = buffer.clone();
+            let _receiver = TCB::new()
+                            .name("Receiver")
+                            .priority(3)
+                            .initialise(move || {
+                                let mut bytes = [0u8; 8];
+                                assert_eq!(receiver_buffer.StreamBufferReceive(&mut bytes, port::portMAX_DELAY), 2);
+                                assert_eq!(&bytes[..2], &[1, 2]);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                assert_eq!(buffer.StreamBufferSend(&[1, 2], 0), 2);
+                assert_eq!((SENT.load(Ordering::SeqCst), RECEIVED.load(Ordering::SeqCst)), (1, 1));
+                assert_eq!(buffer.StreamBufferSendFromISR(&[3]), (1, false));
+                let mut bytes = [0u8; 8];
+                assert_eq!(buffer.StreamBufferReceiveFromISR(&mut bytes), (1, false));
+                assert_eq!((SENT.load(Ordering::SeqCst), RECEIVED.load(Ordering::SeqCst)), (2, 2));
+
+                let messages = MessageBuffer::with_callback(16, None, Some(on_receive));
+                assert_eq!(messages.send(b"ab", 0), 2);
+                assert_eq!(messages.receive(&mut bytes, 0), 2);
+                assert_eq!((SENT.load(Ordering::SeqCst), RECEIVED.load(Ordering::SeqCst)), (2, 3));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_STREAMBUFFER")]
+        fn test_batching_stream_buffer() {
+            use simplelog::*;
+            use stream_buffer::StreamBufferHandle;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let buffer = StreamBufferHandle::StreamBatchingBufferCreate(16, 4);
+
+            // "Receiver" waits for a whole batch although a byte is already
+            // there, and gets a partial batch when its block time expires.
+            let receiver_buffer = buffer.clone();
+            let _receiver = TCB::new()
+                            .name("Receiver")
+                            .priority(3)
+                            .initialise(move || {
+                                let mut bytes = [0u8; 16];
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                                assert_eq!(receiver_buffer.StreamBufferReceive(&mut bytes, pdMS_TO_TICKS!(1000)), 5);
+                                assert_eq!(&bytes[..5], &[1, 2, 3, 4, 5]);
+                                let before = get_tick_count!();
+                                assert_eq!(receiver_buffer.StreamBufferReceive(&mut bytes, pdMS_TO_TICKS!(20)), 1);
+                                assert_eq!(bytes[0], 6);
+                                assert!(get_tick_count!() - before >= pdMS_TO_TICKS!(20));
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                assert_eq!(buffer.StreamBufferSend(&[1], 0), 1);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                assert_eq!(buffer.StreamBufferSend(&[2, 3], 0), 2);
+                assert_eq!(buffer.StreamBufferBytesAvailable(), 3);
+                assert_eq!(buffer.StreamBufferSend(&[4, 5], 0), 2);
+                assert!(buffer.StreamBufferIsEmpty());
+
+                assert_eq!(buffer.StreamBufferSend(&[6], 0), 1);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                assert_eq!(buffer.StreamBufferBytesAvailable(), 1);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(30));
+                assert!(buffer.StreamBufferIsEmpty());
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_endpoint_ipc() {
+            use endpoint::*;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicU64, Ordering};
+            use types::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            static SENT: AtomicU64 = AtomicU64::new(0);
+            static CALLS: AtomicU64 = AtomicU64::new(0);
+            fn ipc_buffer() -> u64 {
+                Box::leak(Box::new([0u64; seL4_MsgMaxLength as usize + 1])).as_mut_ptr() as u64
+            }
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            // The caps of the tasks, in a CNode with a guard of 60 bits so
+            // that the cptrs are the indexes of the slots.
+            let root = cnode_cap_new(4, 0, 60).unwrap();
+            unsafe {
+                provide_cap(root, 1, endpoint_cap_new(1, 0, false, true, false)).unwrap();
+                provide_cap(root, 2, endpoint_cap_new(1, 7, true, false, true)).unwrap();
+                provide_cap(root, 3, endpoint_cap_new(1, 9, true, false, false)).unwrap();
+                provide_cap(root, 4, endpoint_cap_new(2, 3, true, true, false)).unwrap();
+            }
+            let (server_cap, client_cap, sender_cap, idle_cap) = (1, 2, 3, 4);
+
+            // "Server" sums the words of each message and replies with the
+            // sum, labelled with the badge of the sender.  It starts late, so
+            // that the first call and send wait on the endpoint for it.
+            let _server = TCB::new()
+                            .name("Server")
+                            .priority(3)
+                            .cspace_root(root)
+                            .initialise(move || {
+                                get_current_task_handle!().set_ipc_buffer(ipc_buffer());
+                                task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                                let (mut info, mut badge) = seL4_Recv(server_cap).unwrap();
+                                loop {
+                                    let length = seL4_MessageInfo_get_length(info) as usize;
+                                    let sum = (0..length).map(seL4_GetMR).sum::<u64>();
+                                    if badge == 9 {
+                                        SENT.store(sum, Ordering::SeqCst);
+                                    }
+                                    seL4_SetMR(0, sum);
+                                    let reply = seL4_MessageInfo_new(badge, 0, 0, 1);
+                                    let next = seL4_ReplyRecv(server_cap, reply).unwrap();
+                                    info = next.0;
+                                    badge = next.1;
+                                }
+                            });
+
+            // "Client" calls with a message longer than the message registers.
+            let _client = TCB::new()
+                            .name("Client")
+                            .priority(2)
+                            .cspace_root(root)
+                            .initialise(move || {
+                                get_current_task_handle!().set_ipc_buffer(ipc_buffer());
+                                for _ in 0..2 {
+                                    for i in 0..6 {
+                                        seL4_SetMR(i, i as u64 + 1);
+                                    }
+                                    let reply = seL4_Call(client_cap, seL4_MessageInfo_new(0, 0, 0, 6)).unwrap();
+                                    assert_eq!(seL4_MessageInfo_get_label(reply), 7);
+                                    assert_eq!(seL4_MessageInfo_get_length(reply), 1);
+                                    assert_eq!(seL4_GetMR(0), 21);
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(20));
+                                }
+                                CALLS.store(2, Ordering::SeqCst);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            let checker = move || {
+                seL4_SetMR(0, 5);
+                seL4_Send(sender_cap, seL4_MessageInfo_new(0, 0, 0, 1)).unwrap();
+                assert_eq!(SENT.load(Ordering::SeqCst), 5);
+                seL4_SetMR(0, 6);
+                seL4_Send(sender_cap, seL4_MessageInfo_new(0, 0, 0, 1)).unwrap();
+                assert_eq!(SENT.load(Ordering::SeqCst), 6);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(40));
+                assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+                // The caps are looked up in the CSpace, and their rights are checked.
+                let info = seL4_MessageInfo_new(0, 0, 0, 0);
+                assert_eq!(seL4_Send(1 << 40, info).err(), Some(seL4_Error::seL4_FailedLookup));
+                assert_eq!(seL4_Send(5, info).err(), Some(seL4_Error::seL4_InvalidCapability));
+                assert_eq!(seL4_Send(server_cap, info).err(), Some(seL4_Error::seL4_InvalidCapability));
+                assert_eq!(seL4_Call(server_cap, info).err(), Some(seL4_Error::seL4_InvalidCapability));
+                assert_eq!(seL4_Recv(sender_cap).err(), Some(seL4_Error::seL4_InvalidCapability));
+
+                // A call through a cap without the grant right is received,
+                // but can't be replied to and leaves the caller inactive.
+                let caller = TCB::new()
+                                .name("Caller")
+                                .priority(1)
+                                .cspace_root(root)
+                                .initialise(move || {
+                                    seL4_SetMR(0, 4);
+                                    let _ = seL4_Call(sender_cap, seL4_MessageInfo_new(0, 0, 0, 1));
+                                    unreachable!("The call is never replied to.");
+                                })
+                                .unwrap();
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                assert_eq!(SENT.load(Ordering::SeqCst), 4);
+                assert!(matches!(caller.get_state(), task_control_cap::TaskState::InActive));
+
+                // Nothing waits on endpoint 2, so nothing is sent or received.
+                seL4_NBSend(idle_cap, seL4_MessageInfo_new(0, 0, 0, 1)).unwrap();
+                let (info, badge) = seL4_NBRecv(idle_cap).unwrap();
+                assert_eq!((seL4_MessageInfo_get_length(info), badge), (0, 0));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(1)
+                                .cspace_root(root)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_notification() {
+            use endpoint::*;
+            use notification::*;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicU64, Ordering};
+            use types::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            static WAITED: AtomicU64 = AtomicU64::new(0);
+            static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let root = cnode_cap_new(4, 0, 60).unwrap();
+            unsafe {
+                provide_cap(root, 1, endpoint_cap_new(3, 0, false, true, false)).unwrap();
+            }
+            let ntfn_cap = notification_cap_new(1, 0, false, true);
+            let bound_cap = notification_cap_new(2, 0, false, true);
+
+            let _waiter = TCB::new()
+                            .name("Waiter")
+                            .priority(3)
+                            .initialise(move || {
+                                WAITED.store(seL4_Wait(ntfn_cap).unwrap(), Ordering::SeqCst);
+                                loop {
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(1000));
+                                }
+                            });
+
+            // "Server" receives on an endpoint, and is woken by its bound
+            // notification as well.
+            let server = TCB::new()
+                            .name("Server")
+                            .priority(3)
+                            .cspace_root(root)
+                            .initialise(move || {
+                                let current = get_current_task_handle!();
+                                seL4_TCB_BindNotification(&current, bound_cap).unwrap();
+                                loop {
+                                    let (info, badge) = seL4_Recv(1).unwrap();
+                                    assert_eq!(seL4_MessageInfo_get_length(info), 0);
+                                    RECEIVED.store(badge, Ordering::SeqCst);
+                                    task_timemanager::task_delay(pdMS_TO_TICKS!(10));
+                                }
+                            })
+                            .unwrap();
+
+            let checker = move || {
+                // Signals are ORed together until they are taken.
+                seL4_Signal(notification_cap_new(1, 0b1, true, false)).unwrap();
+                assert_eq!(WAITED.load(Ordering::SeqCst), 0b1);
+                seL4_Signal(notification_cap_new(1, 0b10, true, false)).unwrap();
+                seL4_Signal(notification_cap_new(1, 0b100, true, false)).unwrap();
+                assert_eq!(seL4_Poll(ntfn_cap), Ok(0b110));
+                assert_eq!(seL4_Poll(ntfn_cap), Ok(0));
+                assert_eq!(seL4_Signal(ntfn_cap).err(), Some(seL4_Error::seL4_InvalidCapability));
+
+                // The bound notification wakes "Server" from its receive, or
+                // is taken by its next receive.
+                seL4_Signal(notification_cap_new(2, 8, true, false)).unwrap();
+                assert_eq!(RECEIVED.load(Ordering::SeqCst), 8);
+                seL4_Signal(notification_cap_new(2, 16, true, false)).unwrap();
+                assert_eq!(RECEIVED.load(Ordering::SeqCst), 8);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(15));
+                assert_eq!(RECEIVED.load(Ordering::SeqCst), 16);
+
+                // A bound notification is only for the task it is bound to.
+                assert_eq!(seL4_Poll(bound_cap), Err(seL4_Error::seL4_IllegalOperation));
+                assert_eq!(
+                    seL4_TCB_BindNotification(&get_current_task_handle!(), bound_cap),
+                    Err(seL4_Error::seL4_IllegalOperation)
+                );
+                assert_eq!(seL4_TCB_UnbindNotification(&server), Ok(()));
+                assert_eq!(seL4_TCB_UnbindNotification(&server), Err(seL4_Error::seL4_IllegalOperation));
+                assert_eq!(seL4_Poll(bound_cap), Ok(0));
+                kernel::task_end_scheduler();
+            };
+            let _checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .initialise(checker);
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_syscall() {
+            use arch_structures_TCB::*;
+            use crate::endpoint::*;
+            use notification::*;
+            use regs::*;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicU64, Ordering};
+            use syscall::*;
+            use types::*;
+
+            static BADGE: AtomicU64 = AtomicU64::new(0);
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            // A CNode of 16 slots, with a guard of 60 bits so that the cptrs
+            // are the indexes of the slots.
+            let slots = Box::leak(Box::new(
+                [cte_t {
+                    cap: cap_null_cap_new(),
+                    cteMDBNode: mdb_node_new(0, 0, 0, 0),
+                }; 16],
+            ));
+            let root = cap_cnode_cap_new(0, 60, 4, slots.as_mut_ptr() as u64);
+            slots[1].cap = endpoint_cap_new(4, 7, true, false, true);
+            slots[2].cap = endpoint_cap_new(4, 0, false, true, false);
+            slots[3].cap = notification_cap_new(3, 0b1, true, false);
+            slots[4].cap = notification_cap_new(3, 0, false, true);
+
+            // "Server" adds 1 to the first word of the messages it receives.
+            let server = TCB::new()
+                            .name("Server")
+                            .priority(3)
+                            .cspace_root(root)
+                            .initialise(move || unsafe {
+                                let mut status = handleSyscall(syscall_t::SysRecv, 2, seL4_MessageInfo_new(0, 0, 0, 0), std::ptr::null_mut());
+                                loop {
+                                    assert_eq!(status, 0);
+                                    let current = get_current_task_handle!();
+                                    BADGE.store(get_tcb_from_handle!(current).registers[badgeRegister], Ordering::SeqCst);
+                                    seL4_SetMR(0, seL4_GetMR(0) + 1);
+                                    status = handleSyscall(syscall_t::SysReplyRecv, 2, seL4_MessageInfo_new(0, 0, 0, 1), std::ptr::null_mut());
+                                }
+                            })
+                            .unwrap();
+            slots[5].cap = server.thread_cap();
+            let bound = server.clone();
+
+            let checker = move || unsafe {
+                let buffer = Box::leak(Box::new([0u64; seL4_MsgMaxLength as usize + 2 + seL4_MsgMaxExtraCaps])).as_mut_ptr();
+                let reply_label = || {
+                    let current = get_current_task_handle!();
+                    let info = get_tcb_from_handle!(current).registers[msgInfoRegister];
+                    seL4_MessageInfo_get_label(messageInfoFromWord(info))
+                };
+
+                // A call through an endpoint cap is replied by "Server".
+                seL4_SetMR(0, 41);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 1, seL4_MessageInfo_new(0, 0, 0, 1), buffer), 0);
+                assert_eq!(seL4_GetMR(0), 42);
+                assert_eq!(BADGE.load(Ordering::SeqCst), 7);
+
+                // Errors are replied to the caller.
+                let error = exception::EXCEPTION_SYSCALL_ERROR as u64;
+                assert_eq!(handleSyscall(syscall_t::SysCall, 1 << 40, seL4_MessageInfo_new(0, 0, 0, 0), buffer), error);
+                assert_eq!(reply_label(), seL4_Error::seL4_FailedLookup as u64);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 6, seL4_MessageInfo_new(0, 0, 0, 0), buffer), error);
+                assert_eq!(reply_label(), seL4_Error::seL4_InvalidCapability as u64);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 2, seL4_MessageInfo_new(0, 0, 0, 0), buffer), error);
+                assert_eq!(reply_label(), seL4_Error::seL4_InvalidCapability as u64);
+                assert_eq!(handleSyscall(syscall_t::SysRecv, 3, seL4_MessageInfo_new(0, 0, 0, 0), buffer), error);
+
+                // Notifications are signalled and polled through their caps.
+                assert_eq!(handleSyscall(syscall_t::SysSend, 3, seL4_MessageInfo_new(0, 0, 0, 0), buffer), 0);
+                assert_eq!(handleSyscall(syscall_t::SysNBRecv, 4, seL4_MessageInfo_new(0, 0, 0, 0), buffer), 0);
+                let current = get_current_task_handle!();
+                assert_eq!(get_tcb_from_handle!(current).registers[badgeRegister], 0b1);
+
+                // Thread caps bind notifications, passed as extra caps.
+                *buffer.offset(seL4_MsgMaxLength as isize + 2) = 4;
+                let bind = seL4_MessageInfo_new(invocation_label::TCBBindNotification as u64, 0, 1, 0);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 5, bind, buffer), 0);
+                assert_eq!(reply_label(), 0);
+                assert_eq!(bound.get_bound_notification(), Some(3));
+                assert_eq!(handleSyscall(syscall_t::SysCall, 5, bind, buffer), error);
+                assert_eq!(reply_label(), seL4_Error::seL4_IllegalOperation as u64);
+                let unbind = seL4_MessageInfo_new(invocation_label::TCBUnbindNotification as u64, 0, 0, 0);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 5, unbind, buffer), 0);
+                assert_eq!(bound.get_bound_notification(), None);
+                let read = seL4_MessageInfo_new(invocation_label::TCBReadRegisters as u64, 0, 0, 0);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 5, read, buffer), error);
+                assert_eq!(reply_label(), seL4_Error::seL4_IllegalOperation as u64);
+                kernel::task_end_scheduler();
+            };
+            let checker_task = TCB::new()
+                                .name("Checker")
+                                .priority(2)
+                                .cspace_root(root)
+                                .initialise(checker)
+                                .unwrap();
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_capability() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::*;
+            use notification::*;
+            use simplelog::*;
+            use types::*;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let slots = Box::leak(Box::new(
+                [cte_t {
+                    cap: cap_null_cap_new(),
+                    cteMDBNode: mdb_node_new(0, 0, 0, 0),
+                }; 16],
+            ));
+            let root = cap_cnode_cap_new(0, 60, 4, slots.as_mut_ptr() as u64);
+            unsafe {
+                CNode::provide_cap(root, 1, endpoint_cap_new(5, 0, true, true, true)).unwrap();
+                CNode::provide_cap(root, 2, notification_cap_new(4, 0, true, true)).unwrap();
+            }
+            let slots = slots.as_mut_ptr() as usize;
+
+            let owner = move || {
+                let cap = |i: usize| unsafe { (*(slots as *mut cte_t).add(i)).cap };
+                let is_null = |i: usize| cap_get_capType(cap(i)) == cap_tag_t::cap_null_cap as u64;
+
+                let ep = EndpointCap::from_cptr(CPtr::new(1)).unwrap();
+                assert_eq!(NotificationCap::from_cptr(CPtr::new(1)), Err(seL4_Error::seL4_InvalidCapability));
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(1 << 40)), Err(seL4_Error::seL4_FailedLookup));
+
+                // Copies keep some of the rights, mints add a badge.
+                let sender = ep.copy(CPtr::new(3), CapRights::WRITE).unwrap();
+                assert_eq!(cap_endpoint_cap_get_capCanSend(cap(3)), 1);
+                assert_eq!(cap_endpoint_cap_get_capCanReceive(cap(3)), 0);
+                assert_eq!(ep.copy(CPtr::new(3), CapRights::ALL), Err(seL4_Error::seL4_DeleteFirst));
+                let minted = ep.mint(CPtr::new(4), CapRights::ALL, 5).unwrap();
+                assert_eq!(minted.badge(), Ok(5));
+                assert_eq!(minted.mint(CPtr::new(5), CapRights::ALL, 6), Err(seL4_Error::seL4_IllegalOperation));
+                assert!(is_null(5));
+
+                let moved = minted.move_to(CPtr::new(6)).unwrap();
+                assert!(is_null(4));
+                assert_eq!(moved.badge(), Ok(5));
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(4)), Err(seL4_Error::seL4_InvalidCapability));
+
+                // Revoking deletes the copies, wherever they were moved.
+                assert_eq!(ep.revoke(), Ok(()));
+                assert!(is_null(3) && is_null(6));
+                assert_eq!(sender.delete(), Err(seL4_Error::seL4_InvalidCapability));
+                assert_eq!(ep.badge(), Ok(0));
+                assert_eq!(ep.delete(), Ok(()));
+                assert!(is_null(1));
+
+                let ntfn = NotificationCap::from_cptr(CPtr::new(2)).unwrap();
+                let signaller = ntfn.mint(CPtr::new(7), CapRights::WRITE, 0b10).unwrap();
+                assert_eq!(signaller.badge(), Ok(0b10));
+                assert_eq!(cap_notification_cap_get_capNtfnCanReceive(cap(7)), 0);
+                assert_eq!(signaller.delete(), Ok(()));
+                assert!(is_null(7) && !is_null(2));
+
+                // A thread cap holds a reference to its task until it is deleted.
+                let target = TCB::new().name("Target").priority(1).initialise(|| {}).unwrap();
+                let refs = Arc::strong_count(&target.0);
+                unsafe {
+                    CNode::provide_cap(root, 8, target.thread_cap()).unwrap();
+                }
+                assert_eq!(Arc::strong_count(&target.0), refs + 1);
+                assert_eq!(TcbCap::from_cptr(CPtr::new(8)).unwrap().delete(), Ok(()));
+                assert_eq!(Arc::strong_count(&target.0), refs);
+                kernel::task_end_scheduler();
+            };
+            let owner_task = TCB::new()
+                                .name("Owner")
+                                .priority(2)
+                                .cspace_root(root)
+                                .initialise(owner)
+                                .unwrap();
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_cspace() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::*;
+            use simplelog::*;
+            use types::*;
+            use CNode::cnode_cap_new;
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            // A root CNode of 256 slots resolves the first 8 bits of the
+            // cptrs, and a CNode of 16 slots in its last slot the rest.
+            let root = cnode_cap_new(8, 0, 0).unwrap();
+            let leaf = cnode_cap_new(4, 0, 0).unwrap();
+            assert_eq!(cnode_cap_new(8, 0, 57), Err(seL4_Error::seL4_RangeError));
+            assert_eq!(cnode_cap_new(64, 0, 0), Err(seL4_Error::seL4_RangeError));
+            let root_slots = cap_cnode_cap_get_capCNodePtr(root) as *mut cte_t;
+            unsafe {
+                (*root_slots.add(1)).cap = leaf;
+                (*root_slots.add(2)).cap = endpoint_cap_new(6, 0, true, true, true);
+            }
+            // "Peer" only sees the CNode of 16 slots, whose cptrs are the
+            // indexes of the slots.
+            let peer_root = cap_cnode_cap_new(0, 60, 4, cap_cnode_cap_get_capCNodePtr(leaf));
+
+            let owner = move || {
+                let slot = |i: u64| CPtr::new(i << 56);
+                // The slots of the caps to CNodes are named with the depth
+                // of their CNode.
+                assert_eq!(CNodeCap::from_cptr(slot(1)), Err(seL4_Error::seL4_InvalidCapability));
+                let leaf = CNodeCap::from_cptr(CPtr::with_depth(1, 8)).unwrap();
+                assert_eq!(leaf.mint(CPtr::with_depth(3, 8), 0, 61), Err(seL4_Error::seL4_IllegalOperation));
+                leaf.mint(CPtr::with_depth(255, 8), 0, 52).unwrap();
+
+                let ep = EndpointCap::from_cptr(slot(2)).unwrap();
+                ep.mint(CPtr::new(255 << 56 | 3), CapRights::ALL, 9).unwrap();
+                ep.mint(slot(200), CapRights::ALL, 10).unwrap();
+                assert_eq!(EndpointCap::from_cptr(slot(200)).unwrap().badge(), Ok(10));
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(255 << 56 | 3)).unwrap().badge(), Ok(9));
+                // The guard of the cap in slot 255 must match.
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(255 << 56 | 1 << 10 | 3)), Err(seL4_Error::seL4_FailedLookup));
+                task_timemanager::task_delay(pdMS_TO_TICKS!(1));
+            };
+            let peer = move || {
+                let ep = EndpointCap::from_cptr(CPtr::new(3)).unwrap();
+                assert_eq!(ep.badge(), Ok(9));
+                ep.move_to(CPtr::new(4)).unwrap();
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(3)), Err(seL4_Error::seL4_InvalidCapability));
+                unsafe {
+                    let leaf_slots = cap_cnode_cap_get_capCNodePtr(peer_root) as *mut cte_t;
+                    assert_eq!(cap_endpoint_cap_get_capEPBadge((*leaf_slots.add(4)).cap), 9);
+                }
+                kernel::task_end_scheduler();
+            };
+            let _owner_task = TCB::new()
+                                .name("Owner")
+                                .priority(3)
+                                .cspace_root(root)
+                                .initialise(owner)
+                                .unwrap();
+            let _peer_task = TCB::new()
+                                .name("Peer")
+                                .priority(2)
+                                .cspace_root(peer_root)
+                                .initialise(peer)
+                                .unwrap();
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_mdb() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::*;
+            use regs::*;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicBool, Ordering};
+            use syscall::*;
+            use types::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            static WOKEN: AtomicBool = AtomicBool::new(false);
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            // "Owner" reaches the slots of "Peer" through the cap in its slot 15.
+            let owner_root = cnode_cap_new(4, 0, 0).unwrap();
+            let peer_root = cnode_cap_new(4, 0, 60).unwrap();
+            let owner_slots = cap_cnode_cap_get_capCNodePtr(owner_root) as usize;
+            let peer_slots = cap_cnode_cap_get_capCNodePtr(peer_root) as usize;
+            unsafe {
+                provide_cap(owner_root, 1, endpoint_cap_new(7, 0, true, true, true)).unwrap();
+                provide_cap(owner_root, 15, cap_cnode_cap_new(0, 56, 4, peer_slots as u64)).unwrap();
+                assert_eq!(provide_cap(owner_root, 1, endpoint_cap_new(7, 0, true, true, true)), Err(seL4_Error::seL4_DeleteFirst));
+                assert_eq!(provide_cap(owner_root, 16, endpoint_cap_new(7, 0, true, true, true)), Err(seL4_Error::seL4_RangeError));
+            }
+
+            let owner = move || {
+                let slot = |i: u64| CPtr::new(i << 60);
+                let peer = |i: u64| CPtr::new(15 << 60 | i);
+                let is_null = |slots: usize, i: usize| unsafe {
+                    cap_get_capType((*(slots as *mut cte_t).add(i)).cap) == cap_tag_t::cap_null_cap as u64
+                };
+
+                let ep = EndpointCap::from_cptr(slot(1)).unwrap();
+                ep.copy(peer(2), CapRights::READ).unwrap();
+                // "Peer" waits on its copy.
+                task_timemanager::task_delay(pdMS_TO_TICKS!(1));
+
+                // A badge tree: two badges, copied in both CSpaces.
+                let badge1 = ep.mint(slot(2), CapRights::ALL, 1).unwrap();
+                let badge2 = ep.mint(slot(3), CapRights::ALL, 2).unwrap();
+                let copy1 = badge1.copy(slot(4), CapRights::ALL).unwrap();
+                copy1.copy(slot(5), CapRights::WRITE).unwrap();
+                badge1.copy(peer(1), CapRights::WRITE).unwrap();
+                let copy2 = badge2.copy(slot(6), CapRights::ALL).unwrap();
+
+                // Revoking a badge deletes the caps with the badge only.
+                assert_eq!(badge1.revoke(), Ok(()));
+                assert!(is_null(owner_slots, 4) && is_null(owner_slots, 5) && is_null(peer_slots, 1));
+                assert!(!is_null(owner_slots, 2) && !is_null(owner_slots, 3) && !is_null(owner_slots, 6));
+                // The copies of a badged cap aren't the parents of each other.
+                assert_eq!(copy2.revoke(), Ok(()));
+                assert!(!is_null(owner_slots, 3));
+
+                // The receiver isn't woken until the last cap to the endpoint
+                // is deleted.
+                assert_eq!(ep.revoke(), Ok(()));
+                assert!(is_null(owner_slots, 2) && is_null(owner_slots, 3) && is_null(owner_slots, 6));
+                assert!(is_null(peer_slots, 2));
+                assert!(!WOKEN.load(Ordering::SeqCst));
+                assert_eq!(ep.delete(), Ok(()));
+                assert!(is_null(owner_slots, 1));
+                task_timemanager::task_delay(pdMS_TO_TICKS!(1));
+            };
+            let peer = move || {
+                let (_, badge) = EndpointCap::from_cptr(CPtr::new(2)).unwrap().recv().unwrap();
+                WOKEN.store(true, Ordering::SeqCst);
+                assert_eq!(badge, 0);
+                assert_eq!(EndpointCap::from_cptr(CPtr::new(2)), Err(seL4_Error::seL4_InvalidCapability));
+                kernel::task_end_scheduler();
+            };
+            let _owner_task = TCB::new()
+                                .name("Owner")
+                                .priority(3)
+                                .cspace_root(owner_root)
+                                .initialise(owner)
+                                .unwrap();
+            let _peer_task = TCB::new()
+                                .name("Peer")
+                                .priority(2)
+                                .cspace_root(peer_root)
+                                .initialise(peer)
+                                .unwrap();
+
+            kernel::task_start_scheduler();
+        }
+
+        /* The CSpace of the owners of the untyped memory tests: 4 KiB of
+        untyped memory in slot 1 and the root itself in slot 15, to be passed
+        as the extra cap of seL4_Untyped_Retype.  start_owner() adds the
+        thread cap of the owner in slot 2. */
+        #[cfg(feature = "configUSE_CAPS")]
+        fn owner_cspace() -> types::cap_t {
+            use CNode::{cnode_cap_new, provide_cap};
+            let root = cnode_cap_new(4, 0, 60).unwrap();
+            unsafe {
+                provide_cap(root, 1, untyped::untyped_cap_new(12).unwrap()).unwrap();
+                provide_cap(root, 15, root).unwrap();
+            }
+            root
+        }
+
+        /* Run `owner` in the CSpace `root` until it ends the scheduler. */
+        #[cfg(feature = "configUSE_CAPS")]
+        fn start_owner<F>(root: types::cap_t, owner: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            let owner_task = TCB::new()
+                                .name("Owner")
+                                .priority(3)
+                                .cspace_root(root)
+                                .initialise(owner)
+                                .unwrap();
+            unsafe {
+                CNode::provide_cap(root, 2, owner_task.thread_cap()).unwrap();
+            }
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_untyped() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::*;
+            use regs::*;
+            use simplelog::*;
+            use syscall::*;
+            use types::*;
+            use untyped::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let root = owner_cspace();
+            let root_slots = cap_cnode_cap_get_capCNodePtr(root) as usize;
+            assert_eq!(untyped_cap_new(3), Err(seL4_Error::seL4_RangeError));
+
+            let owner = move || unsafe {
+                let is_null = |slots: usize, i: usize| {
+                    cap_get_capType((*(slots as *mut cte_t).add(i)).cap) == cap_tag_t::cap_null_cap as u64
+                };
+                let ut = UntypedCap::from_cptr(CPtr::new(1)).unwrap();
+                let here = CPtr::with_depth(0, 0);
+
+                // The objects are retyped from the free index up.
+                ut.retype(seL4_ObjectType::seL4_EndpointObject, 0, here, 2, 2).unwrap();
+                ut.retype(seL4_ObjectType::seL4_NotificationObject, 0, here, 4, 1).unwrap();
+                assert_eq!(ut.watermark(), Ok(64));
+                let ntfn = NotificationCap::from_cptr(CPtr::new(4)).unwrap();
+                ntfn.mint(CPtr::new(5), CapRights::ALL, 0b10).unwrap().signal().unwrap();
+                assert_eq!(ntfn.poll(), Ok(0b10));
+
+                // A CNode and a TCB, retyped through the system call.
+                let buffer = Box::leak(Box::new([0u64; seL4_MsgMaxLength as usize + 2 + seL4_MsgMaxExtraCaps])).as_mut_ptr();
+                let retype = |object_type: seL4_ObjectType, size_bits: u64, offset: u64| {
+                    seL4_SetMR(0, object_type as u64);
+                    seL4_SetMR(1, size_bits);
+                    seL4_SetMR(2, 0);
+                    seL4_SetMR(3, 0);
+                    *buffer.add(5) = offset;
+                    *buffer.add(6) = 1;
+                    *buffer.add(seL4_MsgMaxLength as usize + 2) = 15;
+                    let info = seL4_MessageInfo_new(invocation_label::UntypedRetype as u64, 0, 1, 6);
+                    handleSyscall(syscall_t::SysCall, 1, info, buffer)
+                };
+                assert_eq!(retype(seL4_ObjectType::seL4_CapTableObject, 4, 6), 0);
+                assert_eq!(ut.watermark(), Ok(1024));
+                assert_eq!(retype(seL4_ObjectType::seL4_TCBObject, 0, 7), 0);
+                assert_eq!(ut.watermark(), Ok(4096));
+                assert!(TcbCap::from_cptr(CPtr::new(7)).is_ok());
+                let error = exception::EXCEPTION_SYSCALL_ERROR as u64;
+                assert_eq!(retype(seL4_ObjectType::seL4_EndpointObject, 0, 7), error);
+                assert_eq!(ut.retype(seL4_ObjectType::seL4_EndpointObject, 0, here, 8, 1), Err(seL4_Error::seL4_NotEnoughMemory));
+
+                // The slots of the CNode are emptied with it.
+                let sub_slots = cap_cnode_cap_get_capCNodePtr((*(root_slots as *mut cte_t).add(6)).cap) as usize;
+                provide_cap(cap_cnode_cap_new(0, 0, 4, sub_slots as u64), 3, untyped_cap_new(4).unwrap()).unwrap();
+                assert_eq!(ut.revoke(), Ok(()));
+                assert!((2..8).all(|i| is_null(root_slots, i)));
+                assert!(is_null(sub_slots, 3));
+
+                // Once the children are revoked, the memory is retyped from
+                // the start.
+                ut.retype(seL4_ObjectType::seL4_EndpointObject, 0, here, 2, 1).unwrap();
+                assert_eq!(ut.watermark(), Ok(16));
+                kernel::task_end_scheduler();
+            };
+            let _owner_task = TCB::new()
+                                .name("Owner")
+                                .priority(3)
+                                .cspace_root(root)
+                                .initialise(owner)
+                                .unwrap();
+
+            kernel::task_start_scheduler();
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_tcb_cap() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use simplelog::*;
+            use std::sync::atomic::{AtomicUsize, Ordering};
+            use std::sync::Arc;
+            use syscall::*;
+            use types::*;
+            use untyped::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let root = owner_cspace();
+            let root_slots = cap_cnode_cap_get_capCNodePtr(root) as usize;
+            let runs = Arc::new(AtomicUsize::new(0));
+            let runs_owner = Arc::clone(&runs);
+
+            let owner = move || unsafe {
+                let ut = UntypedCap::from_cptr(CPtr::new(1)).unwrap();
+                let here = CPtr::with_depth(0, 0);
+                ut.retype(seL4_ObjectType::seL4_TCBObject, 0, here, 3, 1).unwrap();
+                ut.retype(seL4_ObjectType::seL4_NotificationObject, 0, here, 4, 1).unwrap();
+                let tcb = TcbCap::from_cptr(CPtr::new(3)).unwrap();
+                let auth = TcbCap::from_cptr(CPtr::new(2)).unwrap();
+                let child = TaskHandle::from_thread_cap((*(root_slots as *mut cte_t).add(3)).cap);
+                assert!(!child.is_started());
+
+                // A retyped task has nothing to run until it is given a closure.
+                assert_eq!(tcb.resume(), Err(seL4_Error::seL4_IllegalOperation));
+                assert_eq!(tcb.configure(0, CPtr::new(2), 0), Err(seL4_Error::seL4_IllegalOperation));
+                tcb.configure(0, CPtr::new(15), 0x1000).unwrap();
+                assert_eq!(cap_get_capType(child.get_cspace_root()), cap_tag_t::cap_cnode_cap as u64);
+                assert_eq!(child.get_ipc_buffer(), 0x1000);
+                assert_eq!(tcb.set_priority(&auth, configMAX_PRIORITIES!()), Err(seL4_Error::seL4_RangeError));
+                let max = std::ptr::addr_of!(task_global::current_syscall_error.rangeErrorMax).read();
+                assert_eq!(max, configMAX_PRIORITIES!() - 1);
+                tcb.set_priority(&auth, 2).unwrap();
+                tcb.set_mc_priority(&auth, 2).unwrap();
+                assert_eq!(child.get_priority(), 2);
+
+                // The task runs in the CSpace it was configured with.
+                let runs_child = Arc::clone(&runs_owner);
+                tcb.set_entry(move || loop {
+                    if runs_child.fetch_add(1, Ordering::SeqCst) == 0 {
+                        NotificationCap::from_cptr(CPtr::new(4)).unwrap().signal().unwrap();
+                    }
+                    task_timemanager::task_delay(pdMS_TO_TICKS!(1));
+                })
+                .unwrap();
+                tcb.resume().unwrap();
+                assert!(child.is_started());
+                assert_eq!(tcb.set_entry(|| {}), Err(seL4_Error::seL4_IllegalOperation));
+                NotificationCap::from_cptr(CPtr::new(4)).unwrap().wait().unwrap();
+                assert!(runs_owner.load(Ordering::SeqCst) > 0);
+
+                // Revoking the untyped memory deletes the task.
+                ut.revoke().unwrap();
+                let stopped = runs_owner.load(Ordering::SeqCst);
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                assert_eq!(runs_owner.load(Ordering::SeqCst), stopped);
+                kernel::task_end_scheduler();
+            };
+            start_owner(root, owner);
+            assert!(runs.load(Ordering::SeqCst) > 0);
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_mcp() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::seL4_SetMR;
+            use simplelog::*;
+            use syscall::*;
+            use task_global::current_syscall_error;
+            use types::*;
+            use untyped::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let root = owner_cspace();
+            let root_slots = cap_cnode_cap_get_capCNodePtr(root) as usize;
+
+            let owner = move || unsafe {
+                let ut = UntypedCap::from_cptr(CPtr::new(1)).unwrap();
+                ut.retype(seL4_ObjectType::seL4_TCBObject, 0, CPtr::with_depth(0, 0), 3, 1).unwrap();
+                let tcb = TcbCap::from_cptr(CPtr::new(3)).unwrap();
+                let auth = TcbCap::from_cptr(CPtr::new(2)).unwrap();
+                let range = || {
+                    let min = std::ptr::addr_of!(current_syscall_error.rangeErrorMin).read();
+                    let max = std::ptr::addr_of!(current_syscall_error.rangeErrorMax).read();
+                    (min, max)
+                };
+
+                // A retyped task has no authority over priorities.
+                assert_eq!(tcb.set_priority(&tcb, 1), Err(seL4_Error::seL4_RangeError));
+                assert_eq!(range(), (0, 0));
+                tcb.set_priority(&tcb, 0).unwrap();
+
+                // The owner bounds its own maximum controlled priority, and
+                // can't raise it again.
+                auth.set_mc_priority(&auth, 2).unwrap();
+                assert_eq!(auth.set_mc_priority(&auth, 3), Err(seL4_Error::seL4_RangeError));
+                assert_eq!(range(), (0, 2));
+                assert_eq!(tcb.set_priority(&auth, 3), Err(seL4_Error::seL4_RangeError));
+                tcb.set_priority(&auth, 2).unwrap();
+                tcb.set_mc_priority(&auth, 1).unwrap();
+                assert_eq!(TaskHandle::from_thread_cap((*(root_slots as *mut cte_t).add(3)).cap).get_priority(), 2);
+
+                // The same check through the system call.
+                let buffer = Box::leak(Box::new([0u64; seL4_MsgMaxLength as usize + 2 + seL4_MsgMaxExtraCaps])).as_mut_ptr();
+                seL4_SetMR(0, 2);
+                *buffer.add(seL4_MsgMaxLength as usize + 2) = 3;
+                let info = seL4_MessageInfo_new(invocation_label::TCBSetPriority as u64, 0, 1, 1);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 3, info, buffer), exception::EXCEPTION_SYSCALL_ERROR as u64);
+                assert_eq!(range(), (0, 1));
+                seL4_SetMR(0, 1);
+                assert_eq!(handleSyscall(syscall_t::SysCall, 3, info, buffer), 0);
+                kernel::task_end_scheduler();
+            };
+            start_owner(root, owner);
+        }
+
+        #[test]
+        #[cfg(feature = "configUSE_CAPS")]
+        fn test_fault_endpoint() {
+            use arch_structures_TCB::*;
+            use capability::*;
+            use crate::endpoint::{seL4_GetMR, seL4_Reply, seL4_SetMR};
+            use simplelog::*;
+            use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+            use std::sync::Arc;
+            use syscall::*;
+            use types::*;
+            use untyped::*;
+            use CNode::{cnode_cap_new, provide_cap};
+
+            let _ = TermLogger::init(LevelFilter::Trace, Config::default());
+            let root = owner_cspace();
+            let sent = Arc::new(AtomicU64::new(0));
+            let emulated = Arc::new(AtomicU64::new(0));
+            let restarted = Arc::new(AtomicBool::new(false));
+            let restarted_owner = Arc::clone(&restarted);
+
+            let owner = move || unsafe {
+                let ut = UntypedCap::from_cptr(CPtr::new(1)).unwrap();
+                let here = CPtr::with_depth(0, 0);
+                ut.retype(seL4_ObjectType::seL4_TCBObject, 0, here, 3, 1).unwrap();
+                ut.retype(seL4_ObjectType::seL4_EndpointObject, 0, here, 4, 1).unwrap();
+                let tcb = TcbCap::from_cptr(CPtr::new(3)).unwrap();
+                let auth = TcbCap::from_cptr(CPtr::new(2)).unwrap();
+                let fault_ep = EndpointCap::from_cptr(CPtr::new(4)).unwrap();
+                tcb.configure(4, CPtr::new(15), 0).unwrap();
+                tcb.set_priority(&auth, 2).unwrap();
+
+                // The cptr 0x19 only designates the slot 9 behind a guard of 1.
+                let (sent_child, emulated_child) = (Arc::clone(&sent), Arc::clone(&emulated));
+                tcb.set_entry(move || {
+                    let empty = seL4_MessageInfo_new(0, 0, 0, 0);
+                    let status = handleSyscall(syscall_t::SysSend, 0x19, empty, std::ptr::null_mut());
+                    sent_child.store(status + 1, Ordering::SeqCst);
+                    assert_eq!(slowpath(-42, 0, empty, std::ptr::null_mut()), 0);
+                    emulated_child.store(seL4_GetMR(0), Ordering::SeqCst);
+                    panic!("The task faults.");
+                })
+                .unwrap();
+                tcb.resume().unwrap();
+
+                // A cap fault is restarted once the CSpace of the task is fixed.
+                let (info, _) = fault_ep.recv().unwrap();
+                assert_eq!(seL4_MessageInfo_get_label(info), seL4_Fault_tag_t::seL4_Fault_CapFault as u64);
+                assert_eq!(seL4_GetMR(1), 0x19);
+                assert_eq!(seL4_GetMR(2), 0);
+                assert_eq!(seL4_GetMR(3), lookup_fault_tag_t::lookup_fault_guard_mismatch as u64 + 1);
+                CNodeCap::from_cptr(CPtr::new(15)).unwrap().mint(CPtr::new(5), 1, 60).unwrap();
+                ut.retype(seL4_ObjectType::seL4_NotificationObject, 0, here, 9, 1).unwrap();
+                tcb.configure(0x14, CPtr::new(5), 0).unwrap();
+                seL4_Reply(seL4_MessageInfo_new(0, 0, 0, 0));
+
+                // An unknown system call is emulated by the reply.
+                let (info, _) = fault_ep.recv().unwrap();
+                assert_eq!(sent.load(Ordering::SeqCst), 1);
+                assert_eq!(seL4_MessageInfo_get_label(info), seL4_Fault_tag_t::seL4_Fault_UnknownSyscall as u64);
+                assert_eq!(seL4_GetMR(0), -42i64 as u64);
+                seL4_SetMR(0, 7);
+                seL4_Reply(seL4_MessageInfo_new(0, 0, 0, 1));
+
+                // A panic kills the task, which is given another closure.
+                let (info, _) = fault_ep.recv().unwrap();
+                assert_eq!(emulated.load(Ordering::SeqCst), 7);
+                assert_eq!(seL4_MessageInfo_get_label(info), seL4_Fault_tag_t::seL4_Fault_UserException as u64);
+                assert_eq!(seL4_GetMR(3), 0);
+                assert_eq!(tcb.set_entry(|| {}), Err(seL4_Error::seL4_IllegalOperation));
+                seL4_Reply(seL4_MessageInfo_new(1, 0, 0, 0));
+                let restarted_child = Arc::clone(&restarted_owner);
+                tcb.set_entry(move || loop {
+                    restarted_child.store(true, Ordering::SeqCst);
+                    task_timemanager::task_delay(pdMS_TO_TICKS!(1));
+                })
+                .unwrap();
+                tcb.resume().unwrap();
+                task_timemanager::task_delay(pdMS_TO_TICKS!(5));
+                assert!(restarted_owner.load(Ordering::SeqCst));
+
+                ut.revoke().unwrap();
+                kernel::task_end_scheduler();
+            };
+            start_owner(root, owner);
+            assert!(restarted.load(Ordering::SeqCst));
+        }
 }
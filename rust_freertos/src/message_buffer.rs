@@ -0,0 +1,200 @@
+// message_buffer.rs, message buffers
+// A message buffer is a stream buffer that stores discrete messages of
+// variable length.  Each message is stored behind its length, and is always
+// written and read as a whole.
+
+use crate::port::*;
+use crate::stream_buffer::{StreamBufferCallbackFunction, StreamBufferError, StreamBufferHandle};
+
+/// * Description:
+///
+/// A buffer of variable-length messages, passed from a single sender to a
+/// single receiver.  Messages are copied in and out of the buffer, and each
+/// one takes sbBYTES_TO_STORE_MESSAGE_LENGTH bytes more than its length.
+/// Clones of the buffer share the same messages.
+#[derive(Clone)]
+pub struct MessageBuffer(StreamBufferHandle);
+
+impl MessageBuffer {
+    /// # Description
+    /// Create a message buffer.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferCreate()
+    ///
+    /// # Arguments:
+    /// * `buffer_size_bytes` - the total number of bytes the buffer can hold,
+    ///   including the lengths stored with the messages.
+    ///
+    /// # Return:
+    /// The created message buffer.
+    pub fn new(buffer_size_bytes: UBaseType) -> Self {
+        MessageBuffer(StreamBufferHandle::StreamBufferGenericCreate(
+            buffer_size_bytes,
+            0,
+            true,
+        ))
+    }
+
+    /// # Description
+    /// Create a message buffer whose send-completed and receive-completed
+    /// behaviour is replaced by callbacks.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferCreateWithCallback()
+    ///
+    /// # Arguments:
+    /// * `buffer_size_bytes` - as for new().
+    /// * `send_completed` - called when a message was sent, or None to notify the waiting receiver.
+    /// * `receive_completed` - called when a message was received, or None to notify the waiting
+    ///   sender.
+    ///
+    /// # Return:
+    /// The created message buffer.
+    pub fn with_callback(
+        buffer_size_bytes: UBaseType,
+        send_completed: Option<StreamBufferCallbackFunction>,
+        receive_completed: Option<StreamBufferCallbackFunction>,
+    ) -> Self {
+        MessageBuffer(StreamBufferHandle::StreamBufferGenericCreateWithCallback(
+            buffer_size_bytes,
+            0,
+            true,
+            send_completed,
+            receive_completed,
+        ))
+    }
+
+    /// # Description
+    /// Send a message to the buffer.  The message is written completely or
+    /// not at all.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferSend()
+    ///
+    /// # Arguments:
+    /// * `message` - the message to send.
+    /// * `ticks_to_wait` - The maximum amount of time the task should block waiting for enough
+    ///   space, should the buffer be too full to hold the message.  A message that can never fit
+    ///   fails at once.
+    ///
+    /// # Return:
+    /// `UBaseType` - the length of the message if it was sent, otherwise 0.
+    pub fn send(&self, message: &[u8], ticks_to_wait: TickType) -> UBaseType {
+        self.0.StreamBufferSend(message, ticks_to_wait)
+    }
+
+    /// # Description
+    /// A version of send() that can be used in an interrupt service routine
+    /// (ISR).  It never blocks.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferSendFromISR()
+    ///
+    /// # Arguments:
+    /// * `message` - the message to send.
+    ///
+    /// # Return:
+    /// The length of the message if it was sent, otherwise 0, and whether a
+    /// task of a higher priority than the running task was unblocked.
+    pub fn send_from_isr(&self, message: &[u8]) -> (UBaseType, bool) {
+        self.0.StreamBufferSendFromISR(message)
+    }
+
+    /// # Description
+    /// Receive the next message from the buffer.  If the message doesn't fit
+    /// into `buffer`, it is left in the message buffer and 0 is returned; use
+    /// next_length_bytes() to size the buffer.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferReceive()
+    ///
+    /// # Arguments:
+    /// * `buffer` - the buffer the message is copied into.
+    /// * `ticks_to_wait` - The maximum amount of time the task should block waiting for a message.
+    ///
+    /// # Return:
+    /// `UBaseType` - the length of the received message, or 0 if none was received.
+    pub fn receive(&self, buffer: &mut [u8], ticks_to_wait: TickType) -> UBaseType {
+        self.0.StreamBufferReceive(buffer, ticks_to_wait)
+    }
+
+    /// # Description
+    /// A version of receive() that can be used in an interrupt service
+    /// routine (ISR).  It never blocks.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferReceiveFromISR()
+    ///
+    /// # Arguments:
+    /// * `buffer` - the buffer the message is copied into.
+    ///
+    /// # Return:
+    /// The length of the received message, or 0 if none was received, and
+    /// whether a task of a higher priority than the running task was unblocked.
+    pub fn receive_from_isr(&self, buffer: &mut [u8]) -> (UBaseType, bool) {
+        self.0.StreamBufferReceiveFromISR(buffer)
+    }
+
+    /// # Description
+    /// Get the length of the next message, without receiving it.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferNextLengthBytes()
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// `UBaseType` - the length of the next message, or 0 if the buffer is empty.
+    pub fn next_length_bytes(&self) -> UBaseType {
+        self.0.StreamBufferNextMessageLengthBytes()
+    }
+
+    /// # Description
+    /// Get the free space of the buffer.  A message fits if this is at least
+    /// its length plus sbBYTES_TO_STORE_MESSAGE_LENGTH.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferSpacesAvailable()
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// `UBaseType` - the number of free bytes.
+    pub fn spaces_available(&self) -> UBaseType {
+        self.0.StreamBufferSpacesAvailable()
+    }
+
+    /// # Description
+    /// Query whether the buffer holds no message.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// `bool` - true if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.StreamBufferIsEmpty()
+    }
+
+    /// # Description
+    /// Query whether not even an empty message fits into the buffer.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// `bool` - true if the buffer is full.
+    pub fn is_full(&self) -> bool {
+        self.0.StreamBufferIsFull()
+    }
+
+    /// # Description
+    /// Discard all messages.  Only possible while no task is blocked on the
+    /// buffer.
+    ///
+    /// * C implementation: message_buffer.h xMessageBufferReset()
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// Ok() if the buffer was reset, otherwise StreamBufferTaskWaiting.
+    pub fn reset(&self) -> Result<(), StreamBufferError> {
+        self.0.StreamBufferReset()
+    }
+}
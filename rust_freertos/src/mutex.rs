@@ -0,0 +1,244 @@
+// mutex.rs, typed mutexes
+// Mutexes that own the data they protect, built on mutex type semaphores.
+
+use crate::port::*;
+use crate::queue_h::QueueError;
+use crate::semaphore::Semaphore;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+/// * Description:
+///
+/// A mutex that owns the data it protects.  The data can only be reached
+/// through the `MutexGuard` returned by `lock()`, and the mutex is given back
+/// when the guard is dropped.  As the mutex is a mutex type semaphore, the
+/// task holding it inherits the priority of the tasks waiting for it.
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// The data of a locked `Mutex`.  Only the task that locked the mutex can
+/// give it back, so the guard can't be sent to another task.
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for MutexGuard<'a, T> {}
+
+impl<T> Mutex<T> {
+    /// # Description
+    /// Create a new mutex protecting `data`.
+    ///
+    /// # Arguments:
+    /// `data` - the data to protect.
+    ///
+    /// # Return:
+    /// The created mutex.
+    pub fn new(data: T) -> Self {
+        Mutex {
+            semaphore: Semaphore::new_mutex(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// # Description
+    /// Consume the mutex and return the data it protects.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// The protected data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// # Description
+    /// Lock the mutex, blocking until it is available.
+    ///
+    /// # Arguments:
+    /// `ticks_to_wait` - The time in ticks to wait for the mutex to become available.
+    /// A block time of portMAX_DELAY can be used to block indefinitely.
+    ///
+    /// # Return:
+    /// Ok(MutexGuard) if the mutex was locked, otherwise the error of the
+    /// underlying semaphore.
+    pub fn lock(&self, ticks_to_wait: TickType) -> Result<MutexGuard<'_, T>, QueueError> {
+        self.semaphore.semaphore_down(ticks_to_wait)?;
+        Ok(MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// # Description
+    /// Lock the mutex if it is available, without blocking.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// Ok(MutexGuard) if the mutex was locked, otherwise the error of the
+    /// underlying semaphore.
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, QueueError> {
+        self.lock(0)
+    }
+
+    /// # Description
+    /// Get the data without locking, as the mutable borrow guarantees that
+    /// no guard exists.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// A mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        /* The guard never leaves the task that locked the mutex, so the
+        mutex is always given back by its holder. */
+        if let Err(error) = self.mutex.semaphore.semaphore_up() {
+            error!("Failed to give back a mutex: {}", error);
+        }
+    }
+}
+
+/// * Description:
+///
+/// A mutex that the task holding it can lock again.  As several guards of
+/// the same task can be alive at once, they only give shared access to the
+/// data; use a `Cell` or `RefCell` inside to change it.  The mutex is given
+/// back when the last guard is dropped.
+pub struct RecursiveMutex<T: ?Sized> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RecursiveMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for RecursiveMutex<T> {}
+
+/// The data of a locked `RecursiveMutex`.
+pub struct RecursiveMutexGuard<'a, T: ?Sized> {
+    mutex: &'a RecursiveMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> RecursiveMutex<T> {
+    /// # Description
+    /// Create a new recursive mutex protecting `data`.
+    ///
+    /// # Arguments:
+    /// `data` - the data to protect.
+    ///
+    /// # Return:
+    /// The created recursive mutex.
+    pub fn new(data: T) -> Self {
+        RecursiveMutex {
+            semaphore: Semaphore::create_recursive_mutex(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// # Description
+    /// Consume the mutex and return the data it protects.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// The protected data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RecursiveMutex<T> {
+    /// # Description
+    /// Lock the mutex, blocking until it is available.  Returns at once if
+    /// the calling task already holds it.
+    ///
+    /// # Arguments:
+    /// `ticks_to_wait` - The time in ticks to wait for the mutex to become available.
+    /// A block time of portMAX_DELAY can be used to block indefinitely.
+    ///
+    /// # Return:
+    /// Ok(RecursiveMutexGuard) if the mutex was locked, otherwise the error of
+    /// the underlying semaphore.
+    pub fn lock(&self, ticks_to_wait: TickType) -> Result<RecursiveMutexGuard<'_, T>, QueueError> {
+        self.semaphore.down_recursive(ticks_to_wait)?;
+        Ok(RecursiveMutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// # Description
+    /// Lock the mutex if it is available or already held by the calling
+    /// task, without blocking.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// Ok(RecursiveMutexGuard) if the mutex was locked, otherwise the error of
+    /// the underlying semaphore.
+    pub fn try_lock(&self) -> Result<RecursiveMutexGuard<'_, T>, QueueError> {
+        self.lock(0)
+    }
+
+    /// # Description
+    /// Get the data without locking, as the mutable borrow guarantees that
+    /// no guard exists.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// A mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RecursiveMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RecursiveMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        if let Err(error) = self.mutex.semaphore.up_recursive() {
+            error!("Failed to give back a recursive mutex: {}", error);
+        }
+    }
+}
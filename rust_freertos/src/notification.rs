@@ -0,0 +1,414 @@
+// notification.rs, notification objects
+// A notification is a word of binary semaphores.  Signalling it ORs the badge
+// of the cap into the word, and waiting on it takes the whole word.  The
+// notifications are indexed like the endpoints, the NtfnPtr of a notification
+// cap being the index of its queue in NOTIFICATION_LIST, or the object holding
+// the index.  A notification can be bound to a task, so that the task is also
+// woken by it while it waits on an endpoint.
+
+#![allow(non_snake_case)]
+
+use crate::arch_structures_TCB::*;
+use crate::endpoint::{cancelIPC, wake, yield_to};
+use crate::kernel::CriticalSection;
+use crate::list;
+use crate::port::*;
+use crate::regs::*;
+use crate::task_control_cap::*;
+use crate::task_global;
+use crate::types::*;
+use crate::*;
+use std::sync::RwLock;
+
+/* The state and badge word of a notification, and the task bound to it. */
+struct Notification {
+    ntfn: notification_t,
+    bound_tcb: Option<TaskHandle>,
+}
+
+lazy_static! {
+    static ref NOTIFICATIONS: RwLock<Vec<Notification>> = RwLock::new(
+        (0..configMAX_NOTIFICATIONS!())
+            .map(|_| Notification {
+                ntfn: notification_t { words: [0; 4] },
+                bound_tcb: None,
+            })
+            .collect()
+    );
+}
+
+/// # Description
+/// Create a cap to a notification.
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+/// * `badge` - the bits the cap sets in the notification word when signalling.
+/// * `can_send` - whether the cap can signal.
+/// * `can_receive` - whether the cap can wait and poll.
+///
+/// # Return:
+/// The notification cap.
+pub fn notification_cap_new(index: u64, badge: word_t, can_send: bool, can_receive: bool) -> cap_t {
+    cap_notification_cap_new(badge, can_receive as u64, can_send as u64, index)
+}
+
+/* Check the rights of a notification cap and get the index of its
+notification. */
+fn notification_with_rights(cap: cap_t, send: bool, receive: bool) -> Result<u64, seL4_Error> {
+    if cap_get_capType(cap) != cap_tag_t::cap_notification_cap as u64 {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    let index = unsafe { crate::untyped::object_of(cap_notification_cap_get_capNtfnPtr(cap)) };
+    if index >= configMAX_NOTIFICATIONS!()
+        || (send && cap_notification_cap_get_capNtfnCanSend(cap) == 0)
+        || (receive && cap_notification_cap_get_capNtfnCanReceive(cap) == 0)
+    {
+        return Err(seL4_Error::seL4_InvalidCapability);
+    }
+    Ok(index)
+}
+
+/* A notification wakes the task with an empty message, and the badge. */
+fn set_badge(thread: &TaskHandle, badge: word_t) {
+    let mut tcb = get_tcb_from_handle_mut!(thread);
+    tcb.registers[badgeRegister] = badge;
+    tcb.registers[msgInfoRegister] = 0;
+}
+
+/// # Description
+/// Signal a notification.  The badge is passed to the first task waiting
+/// on the notification, or to the bound task if it waits on an endpoint;
+/// otherwise it is ORed into the notification word until a task waits.
+///
+/// * C implementation: notification.c sendSignal()
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+/// * `badge` - the badge of the cap the notification is signalled through.
+///
+/// # Return:
+/// Nothing
+pub fn sendSignal(index: u64, badge: word_t) {
+    let _critical = CriticalSection::enter();
+    let dest = {
+        let mut notifications = NOTIFICATIONS.write().unwrap();
+        let notification = &mut notifications[index as usize];
+        let ntfn = &mut notification.ntfn;
+        let state = notification_ptr_get_state(ntfn);
+        if state == notification_state::NtfnState_Idle as u64 {
+            match notification.bound_tcb.clone() {
+                Some(tcb) if matches!(tcb.get_state(), TaskState::BlockedOnReceive) => {
+                    cancelIPC(&tcb);
+                    Some(tcb)
+                }
+                _ => {
+                    notification_ptr_set_state(ntfn, notification_state::NtfnState_Active as u64);
+                    notification_ptr_set_ntfnMsgIdentifier(ntfn, badge);
+                    None
+                }
+            }
+        } else if state == notification_state::NtfnState_Waiting as u64 {
+            let queue = &task_global::NOTIFICATION_LIST[index as usize];
+            let dest = list::get_owner_of_head_entry(queue);
+            list::list_remove(dest.get_state_list_item());
+            if list::list_is_empty(queue) {
+                notification_ptr_set_state(ntfn, notification_state::NtfnState_Idle as u64);
+            }
+            Some(dest)
+        } else {
+            let badge2 = notification_ptr_get_ntfnMsgIdentifier(ntfn) | badge;
+            notification_ptr_set_ntfnMsgIdentifier(ntfn, badge2);
+            None
+        }
+    };
+    if let Some(dest) = dest {
+        set_badge(&dest, badge);
+        wake(&dest);
+        yield_to(&dest);
+    }
+}
+
+/// # Description
+/// Wait on a notification.  If it was signalled, the notification word is
+/// received and cleared; otherwise `thread` blocks until it is signalled.
+///
+/// * C implementation: notification.c receiveSignal()
+///
+/// # Arguments:
+/// * `thread` - the running task.
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+/// * `blocking` - whether to block if the notification wasn't signalled, else badge 0 is
+///   received.
+///
+/// # Return:
+/// Nothing
+pub fn receiveSignal(thread: &TaskHandle, index: u64, blocking: bool) {
+    let _critical = CriticalSection::enter();
+    let mut notifications = NOTIFICATIONS.write().unwrap();
+    let ntfn = &mut notifications[index as usize].ntfn;
+    if notification_ptr_get_state(ntfn) == notification_state::NtfnState_Active as u64 {
+        set_badge(thread, notification_ptr_get_ntfnMsgIdentifier(ntfn));
+        notification_ptr_set_state(ntfn, notification_state::NtfnState_Idle as u64);
+    } else if blocking {
+        thread.set_state(TaskState::BlockedOnNotificn);
+        if list::list_remove(thread.get_state_list_item()) == 0 {
+            taskRESET_READY_PRIORITY!(thread.get_priority());
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+        list::list_insert_end(
+            &task_global::NOTIFICATION_LIST[index as usize],
+            thread.get_state_list_item(),
+        );
+        notification_ptr_set_state(ntfn, notification_state::NtfnState_Waiting as u64);
+        /* The notification must not stay locked while other tasks run. */
+        drop(notifications);
+        portYIELD_WITHIN_API!();
+    } else {
+        /* doNBRecvFailedTransfer() */
+        set_badge(thread, 0);
+    }
+}
+
+/// # Description
+/// Wake all the tasks waiting on a notification, with badge 0, as when the
+/// last cap to it is deleted.
+///
+/// * C implementation: notification.c cancelAllSignals()
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+///
+/// # Return:
+/// Nothing
+pub fn cancelAllSignals(index: u64) {
+    let _critical = CriticalSection::enter();
+    let mut notifications = NOTIFICATIONS.write().unwrap();
+    let ntfn = &mut notifications[index as usize].ntfn;
+    if notification_ptr_get_state(ntfn) == notification_state::NtfnState_Waiting as u64 {
+        let queue = &task_global::NOTIFICATION_LIST[index as usize];
+        while !list::list_is_empty(queue) {
+            let thread = list::get_owner_of_head_entry(queue);
+            set_badge(&thread, 0);
+            wake(&thread);
+        }
+        notification_ptr_set_state(ntfn, notification_state::NtfnState_Idle as u64);
+        drop(notifications);
+        taskYIELD_IF_USING_PREEMPTION!();
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
+
+/// # Description
+/// Clear the word of a notification, which is taken again by an object
+/// retyped from untyped memory, as seL4 clears the memory of the objects.
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+///
+/// # Return:
+/// Nothing
+pub(crate) fn clearNotification(index: u64) {
+    let _critical = CriticalSection::enter();
+    let mut notifications = NOTIFICATIONS.write().unwrap();
+    notifications[index as usize].ntfn = notification_t { words: [0; 4] };
+    notifications[index as usize].bound_tcb = None;
+}
+
+/// # Description
+/// Receive the signal pending on a notification, if any.  Used for the
+/// notification bound to a task that receives on an endpoint.
+///
+/// * C implementation: notification.c completeSignal()
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+/// * `thread` - the running task.
+///
+/// # Return:
+/// `bool` - true if a signal was received.
+pub fn completeSignal(index: u64, thread: &TaskHandle) -> bool {
+    let _critical = CriticalSection::enter();
+    let mut notifications = NOTIFICATIONS.write().unwrap();
+    let ntfn = &mut notifications[index as usize].ntfn;
+    if notification_ptr_get_state(ntfn) == notification_state::NtfnState_Active as u64 {
+        set_badge(thread, notification_ptr_get_ntfnMsgIdentifier(ntfn));
+        notification_ptr_set_state(ntfn, notification_state::NtfnState_Idle as u64);
+        true
+    } else {
+        false
+    }
+}
+
+/// # Description
+/// Bind a notification to a task.  Check with decodeBindNotification()
+/// first.
+///
+/// * C implementation: notification.c bindNotification()
+///
+/// # Arguments:
+/// * `tcb` - the task.
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+///
+/// # Return:
+/// Nothing
+pub fn bindNotification(tcb: &TaskHandle, index: u64) {
+    let _critical = CriticalSection::enter();
+    NOTIFICATIONS.write().unwrap()[index as usize].bound_tcb = Some(tcb.clone());
+    tcb.set_bound_notification(Some(index));
+}
+
+/// # Description
+/// Unbind the notification bound to a task, if any.
+///
+/// * C implementation: notification.c unbindNotification()
+///
+/// # Arguments:
+/// * `tcb` - the task.
+///
+/// # Return:
+/// Nothing
+pub fn unbindNotification(tcb: &TaskHandle) {
+    let _critical = CriticalSection::enter();
+    if let Some(index) = tcb.get_bound_notification() {
+        NOTIFICATIONS.write().unwrap()[index as usize].bound_tcb = None;
+        tcb.set_bound_notification(None);
+    }
+}
+
+/// # Description
+/// Unbind a notification from the task it is bound to, if any.
+///
+/// * C implementation: notification.c unbindMaybeNotification()
+///
+/// # Arguments:
+/// * `index` - the index of the notification in NOTIFICATION_LIST.
+///
+/// # Return:
+/// Nothing
+pub fn unbindMaybeNotification(index: u64) {
+    let _critical = CriticalSection::enter();
+    let bound_tcb = NOTIFICATIONS.write().unwrap()[index as usize].bound_tcb.take();
+    if let Some(tcb) = bound_tcb {
+        tcb.set_bound_notification(None);
+    }
+}
+
+/// # Description
+/// Check that a notification can be bound to a task: the task has no bound
+/// notification, and the notification is neither bound nor waited on.
+///
+/// * C implementation: tcb.c decodeBindNotification()
+///
+/// # Arguments:
+/// * `tcb` - the task.
+/// * `ntfn` - a notification cap with the receive right.
+///
+/// # Return:
+/// The index of the notification, seL4_InvalidCapability for a bad cap,
+/// otherwise seL4_IllegalOperation.
+pub fn decodeBindNotification(tcb: &TaskHandle, ntfn: cap_t) -> Result<u64, seL4_Error> {
+    let index = notification_with_rights(ntfn, false, true)?;
+    let _critical = CriticalSection::enter();
+    let notifications = NOTIFICATIONS.read().unwrap();
+    let notification = &notifications[index as usize];
+    if tcb.get_bound_notification().is_some()
+        || notification.bound_tcb.is_some()
+        || notification_ptr_get_state(&notification.ntfn) == notification_state::NtfnState_Waiting as u64
+    {
+        return Err(seL4_Error::seL4_IllegalOperation);
+    }
+    Ok(index)
+}
+
+/// # Description
+/// Signal a notification.  Never blocks.
+///
+/// # Arguments:
+/// * `ntfn` - a notification cap with the send right.
+///
+/// # Return:
+/// Ok(()), otherwise seL4_InvalidCapability.
+pub fn seL4_Signal(ntfn: cap_t) -> Result<(), seL4_Error> {
+    let index = notification_with_rights(ntfn, true, false)?;
+    sendSignal(index, cap_notification_cap_get_capNtfnBadge(ntfn));
+    Ok(())
+}
+
+/* Waiting on a notification bound to another task would steal its signals. */
+pub(crate) fn notification_to_wait_on(ntfn: cap_t) -> Result<u64, seL4_Error> {
+    let index = notification_with_rights(ntfn, false, true)?;
+    let current = get_current_task_handle!();
+    let _critical = CriticalSection::enter();
+    match NOTIFICATIONS.read().unwrap()[index as usize].bound_tcb {
+        Some(ref tcb) if *tcb != current => Err(seL4_Error::seL4_IllegalOperation),
+        _ => Ok(index),
+    }
+}
+
+/// # Description
+/// Block until a notification is signalled.
+///
+/// # Arguments:
+/// * `ntfn` - a notification cap with the receive right.
+///
+/// # Return:
+/// The notification word, that is the OR of the badges it was signalled
+/// with, seL4_InvalidCapability for a bad cap, or seL4_IllegalOperation if
+/// the notification is bound to another task.
+pub fn seL4_Wait(ntfn: cap_t) -> Result<word_t, seL4_Error> {
+    let index = notification_to_wait_on(ntfn)?;
+    let current = get_current_task_handle!();
+    receiveSignal(&current, index, true);
+    let badge = get_tcb_from_handle!(current).registers[badgeRegister];
+    Ok(badge)
+}
+
+/// # Description
+/// Take the word of a notification without blocking.
+///
+/// # Arguments:
+/// * `ntfn` - a notification cap with the receive right.
+///
+/// # Return:
+/// As seL4_Wait(); 0 if the notification wasn't signalled.
+pub fn seL4_Poll(ntfn: cap_t) -> Result<word_t, seL4_Error> {
+    let index = notification_to_wait_on(ntfn)?;
+    let current = get_current_task_handle!();
+    receiveSignal(&current, index, false);
+    let badge = get_tcb_from_handle!(current).registers[badgeRegister];
+    Ok(badge)
+}
+
+/// # Description
+/// Bind a notification to a task, see decodeBindNotification().
+///
+/// # Arguments:
+/// * `tcb` - the task.
+/// * `ntfn` - a notification cap with the receive right.
+///
+/// # Return:
+/// Ok(()) if the notification was bound, otherwise the error of
+/// decodeBindNotification().
+pub fn seL4_TCB_BindNotification(tcb: &TaskHandle, ntfn: cap_t) -> Result<(), seL4_Error> {
+    let index = decodeBindNotification(tcb, ntfn)?;
+    tcb.invokeTCB_NotificationControl(Some(index));
+    Ok(())
+}
+
+/// # Description
+/// Unbind the notification bound to a task.
+///
+/// # Arguments:
+/// * `tcb` - the task.
+///
+/// # Return:
+/// Ok(()), or seL4_IllegalOperation if no notification is bound to the task.
+pub fn seL4_TCB_UnbindNotification(tcb: &TaskHandle) -> Result<(), seL4_Error> {
+    if tcb.get_bound_notification().is_none() {
+        return Err(seL4_Error::seL4_IllegalOperation);
+    }
+    tcb.invokeTCB_NotificationControl(None);
+    Ok(())
+}
@@ -111,6 +111,22 @@ macro_rules! portEXIT_CRITICAL {
     };
 }
 
+// portSOFTWARE_BARRIER() is only implemented for emulated/simulated ports that
+// do not otherwise exhibit real time behaviour.
+#[cfg(feature = "rust_port")]
+#[macro_export]
+macro_rules! portSOFTWARE_BARRIER {
+    () => {
+        unsafe { crate::bindings::vPortSoftwareBarrier() }
+    };
+}
+
+#[cfg(not(feature = "rust_port"))]
+#[macro_export]
+macro_rules! portSOFTWARE_BARRIER {
+    () => {};
+}
+
 // TODO: TASK_FUNCTION and TASK_FUNCTION_PROTO may be defined as a macro.
 // They were not defined because we haven't decided the prototype of a task function.
 
@@ -1,9 +1,12 @@
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::Waker;
 use crate::port::*;
 use crate::list::*;
 use crate::queue_h::*;
 use crate::*;
 use crate::task_queue::*;
+use crate::kernel::{CriticalSection, IsrCriticalSection, SchedulerSuspendGuard};
 #[cfg(feature = "configUSE_CAPS")]
 use crate::task_control_cap::*;
 #[cfg(not(feature = "configUSE_CAPS"))]
@@ -29,6 +32,16 @@ pub struct QueueDefinition<T> where T: Default + Clone, {
     xTasksWaitingToSend: ListLink,
     xTasksWaitingToReceive: ListLink,
 
+    /* The holder of a mutex, shared with the tasks blocked on it. */
+    #[cfg(feature = "configUSE_MUTEXES")]
+    xMutexHolder: MutexHolderLink,
+    /* The priority a priority ceiling mutex raises its holder to. */
+    #[cfg(feature = "configUSE_MUTEXES")]
+    uxCeilingPriority: UBaseType,
+
+    /* The wakers of futures waiting for the queue to change. */
+    xAsyncWaiters: Vec<Waker>,
+
     uxMessagesWaiting: UBaseType,
     uxLength: UBaseType,
     cRxLock: i8,
@@ -107,8 +120,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
     pub fn queue_generic_reset(&mut self, xNewQueue: bool) -> Result<(), QueueError> {
         // xNewQueue 源码中为 BaseType，改为bool
         // 返回值原为 BaseType, 改为 result
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             //初始化队列相关成员变量
             self.pcTail = self.pcHead + self.uxLength;
             self.uxMessagesWaiting = 0 as UBaseType;
@@ -117,7 +130,12 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
             self.cRxLock = queueUNLOCKED; // what are these two locks?
             self.cTxLock = queueUNLOCKED;
             self.pcQueue.clear(); //初始化空队列
+            #[cfg(feature = "configUSE_MUTEXES")]
+            {
+                *self.xMutexHolder.write().unwrap() = None;
+            }
             if xNewQueue == false {
+                self.wake_async_waiters();
                 if list::list_is_empty(&self.xTasksWaitingToSend) == false {
                     if task_queue::task_remove_from_event_list(&self.xTasksWaitingToSend) != false {
                         queueYIELD_IF_USING_PREEMPTION!();
@@ -132,7 +150,6 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 self.xTasksWaitingToReceive = Default::default();
             }
         }
-        taskEXIT_CRITICAL!();
         Ok(())
     }
 
@@ -161,9 +178,23 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         let mut xEntryTimeSet: bool = false;
         let mut xTimeOut: time_out = Default::default();
         let mut xTicksToWait = xTicksToWait;
+        #[cfg(feature = "configUSE_MUTEXES")]
+        let mut xInheritanceOccurred: bool = false;
 
         assert!(!(xCopyPosition == queueOVERWRITE && self.uxLength == 1));
 
+        /* Under the priority ceiling protocol no task may take a mutex whose
+        ceiling is below its own priority. */
+        #[cfg(feature = "configUSE_MUTEXES")]
+        {
+            if self.ucQueueType == QueueType::CeilingMutex
+                && get_current_task_handle!().get_base_priority() > self.uxCeilingPriority
+            {
+                traceQUEUE_SEND_FAILED!(&self);
+                return Err(QueueError::MutexCeilingViolated);
+            }
+        }
+
         #[cfg(all(feature = "xTaskGetSchedulerState", feature = "configUSE_TIMERS"))]
         assert!(
             !(kernel::task_get_scheduler_state() == SchedulerState::Suspended
@@ -174,8 +205,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         statements within the function itself.  This is done in the interest
         of execution time efficiency. */
         loop {
-            taskENTER_CRITICAL!();
             {
+                let _critical = CriticalSection::enter();
                 /* Is there room on the queue now?  The running task must be the
                 highest priority task wanting to access the queue.  If the head item
                 in the queue is to be overwritten then it does not matter if the
@@ -183,6 +214,7 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 if self.uxMessagesWaiting < self.uxLength || xCopyPosition == queueOVERWRITE {
                     traceQUEUE_SEND!(&self);
                     self.copy_data_to_queue(pvItemToQueue, xCopyPosition);
+                    self.wake_async_waiters();
                     trace!("Queue can be sent");
 
                     /* The queue is a member of a queue set, and posting
@@ -230,27 +262,11 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                             mtCOVERAGE_TEST_MARKER!();
                         }
                     }
-                    taskEXIT_CRITICAL!();
                     return Ok(()); //return pdPASS
                 } else {
-                    {
-                        #![cfg(feature = "configUSE_MUTEXES")]
-                        if self.ucQueueType == QueueType::Mutex || self.ucQueueType == QueueType::RecursiveMutex {
-                            taskENTER_CRITICAL!();
-                            {
-                                let task_handle = self.transed_task_handle_for_mutex();
-                                task_queue::task_priority_inherit(task_handle);
-                            }
-                            taskEXIT_CRITICAL!();
-                        }
-                        else {
-                            mtCOVERAGE_TEST_MARKER!();
-                        }
-                    }
                     if xTicksToWait == 0 as TickType {
                         /* The queue was full and no block time is specified (or
                         the block time has expired) so leave now. */
-                        taskEXIT_CRITICAL!();
                         /* Return to the original privilege level before exiting
                         the function. */
                         traceQUEUE_SEND_FAILED!(&self);
@@ -267,17 +283,38 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                     }
                 }
             }
-            taskEXIT_CRITICAL!();
 
             /* Interrupts and other tasks can send to and receive from the queue
             now the critical section has been exited. */
-            kernel::task_suspend_all();
+            let scheduler = SchedulerSuspendGuard::suspend();
             self.lock_queue();
 
             /* Update the timeout state to see if it has expired yet. */
             if !task_queue::task_check_for_timeout(&mut xTimeOut, &mut xTicksToWait) {
                 if self.is_queue_full() {
                     traceBLOCKING_ON_QUEUE_SEND!(&self);
+                    {
+                        #![cfg(feature = "configUSE_MUTEXES")]
+                        if self.is_mutex() {
+                            let _critical = CriticalSection::enter();
+                            let task_handle = self.transed_task_handle_for_mutex();
+                            if task_queue::task_priority_inherit(task_handle) {
+                                xInheritanceOccurred = true;
+                            }
+
+                            /* Remember the mutex, so that a task blocking on a
+                            mutex this task holds can pass its priority on to
+                            the holder of this one. */
+                            get_current_task_handle!().set_mutex_blocked_on(Some(BlockedOnMutex {
+                                holder: Arc::clone(&self.xMutexHolder),
+                                waiters: Arc::clone(&self.xTasksWaitingToSend),
+                            }));
+                            #[cfg(feature = "configCHECK_FOR_DEADLOCKS")]
+                            deadlock::check_for_deadlock(&get_current_task_handle!());
+                        } else {
+                            mtCOVERAGE_TEST_MARKER!();
+                        }
+                    }
                     trace!("queue_generic_send place on event list");
                     task_queue::task_place_on_event_list(&self.xTasksWaitingToSend, xTicksToWait);
 
@@ -293,18 +330,40 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                     task is already in a ready list before it yields - in which
                     case the yield will not cause a context switch unless there
                     is also a higher priority task in the pending ready list. */
-                    if !kernel::task_resume_all() {
+                    if !scheduler.resume() {
                         portYIELD_WITHIN_API!();
                     }
+
+                    /* The task is running again, so it no longer waits for
+                    the mutex. */
+                    #[cfg(feature = "configUSE_MUTEXES")]
+                    get_current_task_handle!().set_mutex_blocked_on(None);
                 } else {
                     /* Try again. */
                     self.unlock_queue();
-                    kernel::task_resume_all();
+                    scheduler.resume();
                 }
             } else {
                 /* The timeout has expired. */
                 self.unlock_queue();
-                kernel::task_resume_all();
+                scheduler.resume();
+
+                {
+                    #![cfg(feature = "configUSE_MUTEXES")]
+                    /* xInheritanceOccurred could only have been set if the
+                    queue is a mutex.  The task that held the mutex, and the
+                    tasks it waits for in turn, raised their priority for this
+                    task, which is no longer waiting. */
+                    if xInheritanceOccurred {
+                        let _critical = CriticalSection::enter();
+                        let uxHighestWaitingPriority =
+                            task_queue::task_get_highest_waiting_priority(&self.xTasksWaitingToSend);
+                        task_queue::task_priority_disinherit_after_timeout(
+                            self.transed_task_handle_for_mutex(),
+                            uxHighestWaitingPriority,
+                        );
+                    }
+                }
 
                 traceQUEUE_SEND_FAILED!(self);
                 return Err(QueueError::QueueFull);
@@ -338,12 +397,15 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         let mut pxHigherPriorityTaskWoken: bool = false; //默认为false,下面一些情况改为true
 
         portASSERT_IF_INTERRUPT_PRIORITY_INVALID!();
-        let uxSavedInterruptStatus: UBaseType = portSET_INTERRUPT_MASK_FROM_ISR!() as UBaseType;
         {
+            let _isr_critical = IsrCriticalSection::enter();
             if self.uxMessagesWaiting < self.uxLength || xCopyPosition == queueOVERWRITE {
                 let cTxLock: i8 = self.cTxLock;
                 traceQUEUE_SEND_FROM_ISR!(&self);
                 self.copy_data_to_queue(pvItemToQueue, xCopyPosition);
+                if self.wake_async_waiters_from_isr() {
+                    pxHigherPriorityTaskWoken = true;
+                }
 
                 if cTxLock == queueUNLOCKED {
                     #[cfg(feature = "configUSE_QUEUE_SETS")]
@@ -394,7 +456,6 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 xReturn = Err(QueueError::QueueFull);
             }
         }
-        portCLEAR_INTERRUPT_MASK_FROM_ISR!(uxSavedInterruptStatus);
         (xReturn, pxHigherPriorityTaskWoken)
     }
 
@@ -411,8 +472,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
     /// Nothing
     pub fn lock_queue(&mut self) {
         //源码中为宏，改为Queue的方法
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             if self.cRxLock == queueUNLOCKED {
                 self.cRxLock = queueLOCKED_UNMODIFIED;
             }
@@ -420,7 +481,6 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 self.cTxLock = queueLOCKED_UNMODIFIED;
             }
         }
-        taskEXIT_CRITICAL!();
     }
 
     /// # Description
@@ -435,8 +495,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
     /// # Return
     /// Nothing
     fn unlock_queue(&mut self) {
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             let mut cTxLock: i8 = self.cTxLock;
             while cTxLock > queueLOCKED_UNMODIFIED {
                 #[cfg(feature = "configUSE_QUEUE_SETS")] // ignore this
@@ -481,10 +541,9 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
             }
             self.cTxLock = queueUNLOCKED;
         }
-        taskEXIT_CRITICAL!();
 
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             let mut cRxLock: i8 = self.cRxLock;
             while cRxLock > queueLOCKED_UNMODIFIED {
                 if list::list_is_empty(&self.xTasksWaitingToReceive) == false {
@@ -503,7 +562,6 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
             }
             self.cRxLock = queueUNLOCKED;
         }
-        taskEXIT_CRITICAL!();
     }
 
     /// # Description
@@ -545,8 +603,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 xTicksToWait,
                 xJustPeeking
             );
-            taskENTER_CRITICAL!();
             {
+                let _critical = CriticalSection::enter();
                 let uxMessagesWaiting: UBaseType = self.uxMessagesWaiting;
                 trace!(
                     "queue_generic_receive: uxMessageWaiting: {}",
@@ -567,16 +625,18 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                         {
                             #![cfg(feature = "configUSE_MUTEXES")]
                             /*if uxQueueType == queueQUEUE_IS_MUTEX*/
-                            if self.ucQueueType == QueueType::Mutex
-                                || self.ucQueueType == QueueType::RecursiveMutex
-                            {
+                            if self.is_mutex() {
                                 let task_handle = self.transed_task_handle_for_mutex();
                                 xYieldRequired = task_queue::task_priority_disinherit(task_handle);
                                 self.pcQueue.pop_front();
+                                *self.xMutexHolder.write().unwrap() = None;
                             } else {
                                 mtCOVERAGE_TEST_MARKER!();
                             }
                         }
+                        /* Only wake the futures once the queue is consistent
+                        again, waking one may switch to its task. */
+                        self.wake_async_waiters();
                         trace!("queue_generic_receive -- line 498");
                         if list::list_is_empty(&self.xTasksWaitingToSend) == false { // 这基本上是一个routine，以后用的话看看就好吧
                             if task_queue::task_remove_from_event_list(&self.xTasksWaitingToSend)
@@ -616,14 +676,12 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                             mtCOVERAGE_TEST_MARKER!();
                         }
                     }
-                    taskEXIT_CRITICAL!();
                     trace!("queue_generic_receive -- line 529");
                     return Ok(buffer.unwrap_or_else(|| panic!("buffer is empty!")));
                 } else {
                     if xTicksToWait == 0 as TickType {
                         /* The queue was empty and no block time is specified (or
                         the block time has expired) so leave now. */
-                        taskEXIT_CRITICAL!();
                         traceQUEUE_RECEIVE_FAILED!(&self);
                         return Err(QueueError::QueueEmpty);
                     } else if xEntryTimeSet == false {
@@ -637,9 +695,8 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                     }
                 }
             }
-            taskEXIT_CRITICAL!();
             trace!("queue_generic_receive -- line 553");
-            kernel::task_suspend_all();
+            let scheduler = SchedulerSuspendGuard::suspend();
             self.lock_queue();
             trace!("queue_generic_receive -- line 556");
             /* Update the timeout state to see if it has expired yet. */
@@ -651,19 +708,19 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                         xTicksToWait,
                     );
                     self.unlock_queue();
-                    if kernel::task_resume_all() == false {
+                    if scheduler.resume() == false {
                         portYIELD_WITHIN_API!();
                     } else {
                         mtCOVERAGE_TEST_MARKER!();
                     }
                 } else {
                     self.unlock_queue();
-                    kernel::task_resume_all();
+                    scheduler.resume();
                 }
                 trace!("queue_generic_receive -- line 589");
             } else {
                 self.unlock_queue();
-                kernel::task_resume_all();
+                scheduler.resume();
                 if self.is_queue_empty() != false {
                     traceQUEUE_RECEIVE_FAILED!(&self);
                     return Err(QueueError::QueueEmpty);
@@ -674,6 +731,99 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         }
     }
 
+    /// # Description
+    /// Receive an item from a queue. It is safe to use this function from within an interrupt
+    /// service routine.
+    ///
+    /// * C implementation:queue.c xQueueReceiveFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// * `Result` - Ok(T) if an item was successfully received from the queue, otherwise
+    ///   QueueError::QueueEmpty.
+    /// * `bool` - pxHigherPriorityTaskWoken is changed to be a return value. it is true if
+    ///   receiving from the queue caused a task to unblock,otherwise it is false.
+    pub fn queue_receive_from_isr(&mut self) -> (Result<T, QueueError>, bool) {
+        let mut xReturn: Result<T, QueueError> = Err(QueueError::QueueEmpty);
+        let mut pxHigherPriorityTaskWoken: bool = false;
+
+        portASSERT_IF_INTERRUPT_PRIORITY_INVALID!();
+        {
+            let _isr_critical = IsrCriticalSection::enter();
+            let uxMessagesWaiting: UBaseType = self.uxMessagesWaiting;
+            /* Cannot block in an ISR, so check there is data available. */
+            if uxMessagesWaiting > 0 as UBaseType {
+                let cRxLock: i8 = self.cRxLock;
+                traceQUEUE_RECEIVE_FROM_ISR!(&self);
+                let buffer = self.copy_data_from_queue();
+                self.uxMessagesWaiting = uxMessagesWaiting - 1;
+                if self.wake_async_waiters_from_isr() {
+                    pxHigherPriorityTaskWoken = true;
+                }
+
+                /* If the queue is locked the event list will not be modified.
+                Instead update the lock count so the task that unlocks the queue
+                will know that an ISR has removed data while the queue was
+                locked. */
+                if cRxLock == queueUNLOCKED {
+                    if !list::list_is_empty(&self.xTasksWaitingToSend) {
+                        if task_queue::task_remove_from_event_list(&self.xTasksWaitingToSend) {
+                            /* The task waiting has a higher priority than us so
+                            force a context switch. */
+                            pxHigherPriorityTaskWoken = true;
+                        } else {
+                            mtCOVERAGE_TEST_MARKER!();
+                        }
+                    } else {
+                        mtCOVERAGE_TEST_MARKER!();
+                    }
+                } else {
+                    /* Increment the lock count so the task that unlocks the queue
+                    knows that data was removed while it was locked. */
+                    self.cRxLock = cRxLock + 1;
+                }
+                xReturn = Ok(buffer.unwrap_or_else(|| panic!("buffer is empty!")));
+            } else {
+                traceQUEUE_RECEIVE_FROM_ISR_FAILED!(&self);
+            }
+        }
+        (xReturn, pxHigherPriorityTaskWoken)
+    }
+
+    /// # Description
+    /// Receive an item from a queue without removing it. It is safe to use this function from
+    /// within an interrupt service routine.
+    ///
+    /// * C implementation:queue.c xQueuePeekFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// Ok(T) if an item was successfully received from the queue, otherwise QueueError::QueueEmpty.
+    pub fn queue_peek_from_isr(&mut self) -> Result<T, QueueError> {
+        /* Cannot peek a semaphore, which has no items. */
+        assert!(!self.is_mutex());
+
+        portASSERT_IF_INTERRUPT_PRIORITY_INVALID!();
+        let _isr_critical = IsrCriticalSection::enter();
+        /* Cannot block in an ISR, so check there is data available. */
+        if self.uxMessagesWaiting > 0 as UBaseType {
+            traceQUEUE_PEEK_FROM_ISR!(&self);
+            /* Remember the read position so it can be reset as nothing is
+            actually being removed from the queue. */
+            let pcOriginalReadPosition: UBaseType = self.QueueUnion; //QueueUnion represents pcReadFrom
+            let buffer = self.copy_data_from_queue();
+            self.QueueUnion = pcOriginalReadPosition;
+            Ok(buffer.unwrap_or_else(|| panic!("buffer is empty!")))
+        } else {
+            traceQUEUE_PEEK_FROM_ISR_FAILED!(&self);
+            Err(QueueError::QueueEmpty)
+        }
+    }
+
     pub fn copy_data_from_queue(&mut self) -> Option<T> {
         self.QueueUnion += 1; //QueueUnion represents pcReadFrom in the original code
         if self.QueueUnion >= self.pcTail {
@@ -692,9 +842,18 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
 
         {
             #![cfg(feature = "configUSE_MUTEXES")]
-            if self.ucQueueType == QueueType::Mutex || self.ucQueueType == QueueType::RecursiveMutex
-            {
-                let mutex_holder = transed_task_handle_to_T(task_increment_mutex_held_count());
+            if self.is_mutex() {
+                let task_handle = task_increment_mutex_held_count();
+                *self.xMutexHolder.write().unwrap() = task_handle.clone();
+
+                /* A priority ceiling mutex raises its holder to the ceiling
+                as soon as it is taken. */
+                if self.ucQueueType == QueueType::CeilingMutex {
+                    if let Some(ref holder) = task_handle {
+                        task_queue::task_priority_raise_to_ceiling(holder, self.uxCeilingPriority);
+                    }
+                }
+                let mutex_holder = transed_task_handle_to_T(task_handle);
                 self.pcQueue.insert(0, mutex_holder);
             } else {
                 mtCOVERAGE_TEST_MARKER!();
@@ -702,7 +861,7 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         }
 
         if xPosition == queueSEND_TO_BACK {
-            if self.ucQueueType != QueueType::Mutex && self.ucQueueType != QueueType::RecursiveMutex {
+            if !self.is_mutex() {
                 self.pcQueue.insert(self.pcWriteTo as usize, pvItemToQueue);
             }
             else {
@@ -715,7 +874,7 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
                 mtCOVERAGE_TEST_MARKER!();
             }
         } else {
-            if self.ucQueueType != QueueType::Mutex && self.ucQueueType != QueueType::RecursiveMutex {
+            if !self.is_mutex() {
                 self.pcQueue.insert(self.QueueUnion as usize, pvItemToQueue); //QueueUnion represents pcReadFrom
             }
             else {
@@ -744,6 +903,43 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
         self.uxMessagesWaiting = uxMessagesWaiting + 1;
     }
 
+    /// # Description
+    /// Remember the waker of a future that is waiting for the queue to change.
+    /// It is woken the next time an item is posted to or removed from the
+    /// queue.  Must be called from the critical section in which the future
+    /// found the queue full or empty, so that no change can be missed.
+    ///
+    /// # Argument
+    /// `waker` - the waker of the waiting future.
+    ///
+    /// # Return
+    /// Nothing
+    pub fn register_async_waiter(&mut self, waker: &Waker) {
+        if !self.xAsyncWaiters.iter().any(|w| w.will_wake(waker)) {
+            self.xAsyncWaiters.push(waker.clone());
+        }
+    }
+
+    /* Wake all futures waiting for the queue to change.  They check the
+    queue again when they are polled. */
+    fn wake_async_waiters(&mut self) {
+        for waker in self.xAsyncWaiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /* The interrupt safe version of wake_async_waiters().  Returns true if
+    a woken task has a priority above the running task. */
+    fn wake_async_waiters_from_isr(&mut self) -> bool {
+        let mut xHigherPriorityTaskWoken = false;
+        for waker in self.xAsyncWaiters.drain(..) {
+            if executor::wake_from_isr(waker) {
+                xHigherPriorityTaskWoken = true;
+            }
+        }
+        xHigherPriorityTaskWoken
+    }
+
     /// # Description
     /// To know whether the queue is empty.
     ///
@@ -757,13 +953,12 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
     /// `bool` - true if the queue was empty.
     pub fn is_queue_empty(&self) -> bool {
         let mut xReturn: bool = false;
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             if self.uxMessagesWaiting == 0 as UBaseType {
                 xReturn = true;
             }
         }
-        taskEXIT_CRITICAL!();
         xReturn
     }
 
@@ -779,20 +974,119 @@ impl<T> QueueDefinition<T> where T: Default + Clone, {
     /// `bool` - true if the queue if full.
     pub fn is_queue_full(&self) -> bool {
         let mut xReturn: bool = false;
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             if self.uxMessagesWaiting == self.uxLength {
                 xReturn = true;
             }
         }
-        taskEXIT_CRITICAL!();
         xReturn
     }
 
+    /// # Description
+    /// To know whether the queue is empty. It is safe to use this function from within an
+    /// interrupt service routine.
+    ///
+    /// * C implementation: queue.c xQueueIsQueueEmptyFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `bool` - true if the queue was empty.
+    pub fn is_queue_empty_from_isr(&self) -> bool {
+        self.uxMessagesWaiting == 0 as UBaseType
+    }
+
+    /// # Description
+    /// To know whether the queue is full. It is safe to use this function from within an
+    /// interrupt service routine.
+    ///
+    /// * C implementation: queue.c xQueueIsQueueFullFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `bool` - true if the queue is full.
+    pub fn is_queue_full_from_isr(&self) -> bool {
+        self.uxMessagesWaiting == self.uxLength
+    }
+
+    /// # Description
+    /// Get the number of items stored in the queue.
+    ///
+    /// * C implementation: queue.c uxQueueMessagesWaiting()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of items in the queue.
+    pub fn get_messages_waiting(&self) -> UBaseType {
+        let _critical = CriticalSection::enter();
+        self.uxMessagesWaiting
+    }
+
+    /// # Description
+    /// Get the number of items stored in the queue. It is safe to use this function from within
+    /// an interrupt service routine.
+    ///
+    /// * C implementation: queue.c uxQueueMessagesWaitingFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of items in the queue.
+    pub fn get_messages_waiting_from_isr(&self) -> UBaseType {
+        self.uxMessagesWaiting
+    }
+
+    /// # Description
+    /// Get the number of free spaces in the queue.
+    ///
+    /// * C implementation: queue.c uxQueueSpacesAvailable()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of items that can still be posted to the queue.
+    pub fn get_spaces_available(&self) -> UBaseType {
+        let _critical = CriticalSection::enter();
+        self.uxLength - self.uxMessagesWaiting
+    }
+
     pub fn initialise_count(&mut self, initial_count: UBaseType) {
         self.uxMessagesWaiting = initial_count;
     }
 
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn initialise_ceiling_priority(&mut self, ceiling_priority: UBaseType) {
+        self.uxCeilingPriority = ceiling_priority;
+    }
+
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn get_ceiling_priority(&self) -> UBaseType {
+        self.uxCeilingPriority
+    }
+
+    /// # Description
+    /// Tell whether the queue is one of the mutex types, which store their
+    /// holder instead of items.
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `bool` - true if the queue is a mutex.
+    pub fn is_mutex(&self) -> bool {
+        self.ucQueueType == QueueType::Mutex
+            || self.ucQueueType == QueueType::RecursiveMutex
+            || self.ucQueueType == QueueType::CeilingMutex
+    }
+
     pub fn QueueUnion_decrease(&mut self) {
         self.QueueUnion = self.QueueUnion - 1;
     }
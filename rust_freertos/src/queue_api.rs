@@ -2,10 +2,14 @@
 // This file is created by Ning Yuting.
 // To solve the issue of mutability of queue.
 
+use crate::kernel::{CriticalSection, IsrCriticalSection};
 use crate::port::*;
 use crate::queue::*;
 use crate::queue_h::*;
 use std::cell::UnsafeCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 /// * Description:
 ///
@@ -232,4 +236,325 @@ where
             (*inner).queue_generic_receive(xTicksToWait, true)
         }
     }
+
+    /// # Description
+    /// Receive an item from a queue. It is safe to use this function from within an interrupt
+    /// service routine.
+    ///
+    /// * C implementation:queue.h xQueueReceiveFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// * `Result` - Ok(T) if an item was successfully received from the queue, otherwise
+    ///   QueueError::QueueEmpty.
+    /// * `bool` - pxHigherPriorityTaskWoken is changed to be a return value. it is true if
+    ///   receiving from the queue caused a task to unblock,otherwise it is false.
+    pub fn receive_from_isr(&self) -> (Result<T, QueueError>, bool) {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).queue_receive_from_isr()
+        }
+    }
+
+    /// # Description
+    /// Receive an item from a queue without removing the item from the queue. It is safe to use
+    /// this function from within an interrupt service routine.
+    ///
+    /// * C implementation:queue.h xQueuePeekFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// Ok(T) if an item was successfully received from the queue, otherwise
+    /// QueueError::QueueEmpty.
+    pub fn peek_from_isr(&self) -> Result<T, QueueError> {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).queue_peek_from_isr()
+        }
+    }
+
+    /// # Description
+    /// Get the number of messages stored in a queue.
+    ///
+    /// * C implementation:queue.h uxQueueMessagesWaiting()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of messages available in the queue.
+    pub fn messages_waiting(&self) -> UBaseType {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).get_messages_waiting()
+        }
+    }
+
+    /// # Description
+    /// A version of messages_waiting() that can be used in an interrupt service routine (ISR).
+    ///
+    /// * C implementation:queue.h uxQueueMessagesWaitingFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of messages available in the queue.
+    pub fn messages_waiting_from_isr(&self) -> UBaseType {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).get_messages_waiting_from_isr()
+        }
+    }
+
+    /// # Description
+    /// Get the number of free spaces available in a queue.  This is equal to the
+    /// number of items that can be sent to the queue before the queue becomes
+    /// full if no items are removed.
+    ///
+    /// * C implementation:queue.h uxQueueSpacesAvailable()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `UBaseType` - the number of spaces available in the queue.
+    pub fn spaces_available(&self) -> UBaseType {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).get_spaces_available()
+        }
+    }
+
+    /// # Description
+    /// Reset a queue back to its original empty state.  A task blocked waiting to
+    /// send to the queue is unblocked.
+    ///
+    /// * C implementation:queue.h xQueueReset()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// Ok() is the only value that can be returned.
+    pub fn reset(&self) -> Result<(), QueueError> {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).queue_generic_reset(false)
+        }
+    }
+
+    /// # Description
+    /// Query a queue to determine if the queue is empty.  This function should
+    /// only be used in an ISR.
+    ///
+    /// * C implementation:queue.h xQueueIsQueueEmptyFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `bool` - true if the queue is empty.
+    pub fn is_empty_from_isr(&self) -> bool {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).is_queue_empty_from_isr()
+        }
+    }
+
+    /// # Description
+    /// Query a queue to determine if the queue is full.  This function should
+    /// only be used in an ISR.
+    ///
+    /// * C implementation:queue.h xQueueIsQueueFullFromISR()
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// `bool` - true if the queue is full.
+    pub fn is_full_from_isr(&self) -> bool {
+        unsafe {
+            let inner = self.0.get();
+            (*inner).is_queue_full_from_isr()
+        }
+    }
+
+    /// # Description
+    /// Receive an item from a queue without blocking the calling task.
+    /// The returned future completes with the item once one is available,
+    /// so it can be awaited among other futures run by an executor.
+    ///
+    /// # Argument
+    /// Nothing
+    ///
+    /// # Return
+    /// A future that resolves to the received item.
+    pub fn recv_async(&self) -> Receive<'_, T> {
+        Receive { queue: self }
+    }
+}
+
+/// The future returned by `Queue::recv_async()`.
+pub struct Receive<'a, T>
+where
+    T: Default + Clone,
+{
+    queue: &'a Queue<T>,
+}
+
+impl<'a, T> Future for Receive<'a, T>
+where
+    T: Default + Clone,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        /* The waker is registered in the critical section in which the queue
+        was found empty, so an item can't be posted in between unnoticed. */
+        let _critical = CriticalSection::enter();
+        unsafe {
+            let inner = self.queue.0.get();
+            match (*inner).queue_generic_receive(0, false) {
+                Ok(item) => Poll::Ready(item),
+                Err(_) => {
+                    (*inner).register_async_waiter(cx.waker());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+/// * Description:
+///
+/// A queue that moves its items instead of copying them, so the items need
+/// neither `Clone` nor `Default`.  An item is moved into a slot of the queue
+/// when it is sent, and out of it when it is received; only the index of the
+/// slot passes through the underlying queue.  Items that own a buffer, such as
+/// `Box<[u8]>` or a pool `Block`, pass between tasks without being copied.
+pub struct MoveQueue<T> {
+    queue: UnsafeCell<QueueDefinition<usize>>,
+    /* The items that were sent but not received yet, and the indexes of the
+    empty slots. */
+    slots: UnsafeCell<Vec<Option<T>>>,
+    free_slots: UnsafeCell<Vec<usize>>,
+}
+
+unsafe impl<T: Send> Send for MoveQueue<T> {}
+unsafe impl<T: Send> Sync for MoveQueue<T> {}
+
+impl<T> MoveQueue<T> {
+    /// # Description:
+    /// Create a new queue.
+    ///
+    /// # Arguments:
+    /// * `length` - The maximum number of items that the queue can contain.
+    ///
+    /// # Return:
+    /// The created queue.
+    pub fn new(length: UBaseType) -> Self {
+        MoveQueue {
+            queue: UnsafeCell::new(QueueDefinition::new(length, QueueType::Base)),
+            slots: UnsafeCell::new(Vec::with_capacity(length as usize)),
+            free_slots: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    /* Move an item into an empty slot.  Called from a critical section. */
+    unsafe fn store(&self, item: T) -> usize {
+        let slots = &mut *self.slots.get();
+        match (*self.free_slots.get()).pop() {
+            Some(index) => {
+                slots[index] = Some(item);
+                index
+            }
+            None => {
+                slots.push(Some(item));
+                slots.len() - 1
+            }
+        }
+    }
+
+    /* Move an item out of its slot.  Called from a critical section. */
+    unsafe fn take(&self, index: usize) -> T {
+        let slots = &mut *self.slots.get();
+        (*self.free_slots.get()).push(index);
+        slots[index].take().unwrap()
+    }
+
+    /// # Description
+    /// Post an item to the back of the queue.
+    ///
+    /// # Argument
+    /// * `item` - the item that is to be placed on the queue.
+    /// * `xTicksToWait` - The maximum amount of time the task should block waiting for space to
+    ///   become available on the queue, should it already be full.
+    ///
+    /// # Return
+    /// Ok() if the item was successfully posted, otherwise the error and the
+    /// item, which is given back to the caller.
+    pub fn send(&self, item: T, xTicksToWait: TickType) -> Result<(), (QueueError, T)> {
+        unsafe {
+            let index = {
+                let _critical = CriticalSection::enter();
+                self.store(item)
+            };
+            let inner = self.queue.get();
+            (*inner)
+                .queue_generic_send(index, xTicksToWait, queueSEND_TO_BACK)
+                .map_err(|error| {
+                    let _critical = CriticalSection::enter();
+                    (error, self.take(index))
+                })
+        }
+    }
+
+    /// # Description
+    /// Post an item to the back of the queue. It is safe to use this function from within an
+    /// interrupt service routine.
+    ///
+    /// # Argument
+    /// * `item` - the item that is to be placed on the queue.
+    ///
+    /// # Return
+    /// * `Result` - Ok() if the item was successfully posted, otherwise the error and the item.
+    /// * `bool` - pxHigherPriorityTaskWoken is changed to be a return value. it is true if sending to the
+    ///   queue caused a task to unblock,otherwise it is false.
+    pub fn send_from_isr(&self, item: T) -> (Result<(), (QueueError, T)>, bool) {
+        unsafe {
+            let _isr_critical = IsrCriticalSection::enter();
+            let index = self.store(item);
+            let inner = self.queue.get();
+            let (result, xHigherPriorityTaskWoken) =
+                (*inner).queue_generic_send_from_isr(index, queueSEND_TO_BACK);
+            (
+                result.map_err(|error| (error, self.take(index))),
+                xHigherPriorityTaskWoken,
+            )
+        }
+    }
+
+    /// # Description
+    /// Receive an item from the queue.  The item is moved out of the queue.
+    ///
+    /// # Argument
+    /// * `xTicksToWait` - The maximum amount of time the task should block
+    ///   waiting for an item to receive should the queue be empty at the time
+    ///   of the call.It will return immediately if xTicksToWait is zero and the queue is empty.
+    ///
+    /// # Return
+    /// Ok(T) if an item was successfully received from the queue, otherwise QueueError::QueueEmpty.
+    pub fn receive(&self, xTicksToWait: TickType) -> Result<T, QueueError> {
+        unsafe {
+            let inner = self.queue.get();
+            let index = (*inner).queue_generic_receive(xTicksToWait, false)?;
+            let _critical = CriticalSection::enter();
+            Ok(self.take(index))
+        }
+    }
 }
@@ -8,6 +8,10 @@ pub enum QueueError {
     MutexTimeout,
     QueueFull,
     QueueEmpty,
+    MutexCeilingViolated,
+    MutexCeilingInvalid,
+    MutexNotOwned,
+    MutexStillHeld,
 }
 
 impl fmt::Display for QueueError {
@@ -18,6 +22,10 @@ impl fmt::Display for QueueError {
             QueueError::MutexTimeout => write!(f, "MutexSendTimeOut"),
             QueueError::QueueFull => write!(f, "QueueFull"),
             QueueError::QueueEmpty => write!(f, "QueueEmpty"),
+            QueueError::MutexCeilingViolated => write!(f, "MutexCeilingViolated"),
+            QueueError::MutexCeilingInvalid => write!(f, "MutexCeilingInvalid"),
+            QueueError::MutexNotOwned => write!(f, "MutexNotOwned"),
+            QueueError::MutexStillHeld => write!(f, "MutexStillHeld"),
         }
     }
 }
@@ -36,6 +44,7 @@ pub enum QueueType {
     CountingSemaphore,
     BinarySemaphore,
     RecursiveMutex,
+    CeilingMutex,
 }
 impl Default for QueueType {
     fn default() -> Self {
@@ -0,0 +1,630 @@
+// rust_port.rs - The portable layer written in pure Rust.
+// It provides the same functions as the bindings of port.c (see bindings.rs),
+// so it is used in place of them when the "rust_port" feature is enabled.
+//
+// Like port.c, every task runs in its own thread. port.c suspends and resumes
+// the threads with signals, which can't be done with std. Instead, a thread
+// only executes kernel code (critical sections, yields and the scheduler
+// suspension) while its task is the one that was scheduled. When a task is
+// switched out by the tick it keeps running until its next kernel call,
+// where it waits on a condition variable until it is scheduled again.
+//
+// Only one scheduler can exist in a process. The thread that first creates a
+// kernel object (a task, a queue...) owns the kernel until its scheduler has
+// ended or the thread has exited, other threads wait for it before entering
+// the kernel. The kernel is reset afterwards, so every #[test] can start its
+// own scheduler.
+use crate::kernel;
+use crate::port::{BaseType, CVoidPointer, StackType, UBaseType};
+use crate::projdefs::{pdFALSE, pdTRUE};
+use crate::task_global;
+use crate::*;
+use std::alloc::{alloc, dealloc, Layout};
+use std::cell::{Cell, RefCell};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle, ThreadId};
+use std::time::{Duration, Instant};
+
+/* Same as the definition generated by bindgen. */
+pub type TaskFunction_t = ::std::option::Option<unsafe extern "C" fn(arg1: CVoidPointer)>;
+
+/* Resolution of the run time stats counter, see ulPortGetTimerValue(). */
+const RUN_TIME_COUNTER_HZ: u64 = 1_000_000;
+
+/* Every block returned by pvPortMalloc() is preceded by a header holding its size. */
+const HEAP_HEADER_SIZE: usize = 16;
+
+/* What a thread is to the port. */
+#[derive(Clone, Copy, PartialEq)]
+enum ThreadRole {
+    /* A thread that isn't created by the port, e.g. main(). */
+    Foreign,
+    /* The thread generating the tick interrupt. */
+    Tick,
+    /* The thread running a task. `session` tells which scheduler the task belongs to,
+     * `index` is the index of the thread in PortState::threads. */
+    Task { session: u64, index: usize },
+}
+
+struct ThreadState {
+    /* Handle of the task run by the thread, null until vPortAddTaskHandle() is called. */
+    task: usize,
+    /* Cleared by vPortForciblyEndThread(). */
+    alive: bool,
+    /* Set while the thread waits in wait_until_scheduled(). */
+    waiting: bool,
+}
+
+struct PortState {
+    threads: Vec<ThreadState>,
+    index_of_last_added_task: usize,
+    /* The thread owning the kernel and the scheduler it starts. */
+    session_owner: Option<ThreadId>,
+    session: u64,
+    /* The task which is allowed to execute kernel code. */
+    running_task: usize,
+    /* The thread of running_task has not resumed since it was switched in. */
+    switching_in: bool,
+    /* The thread that is inside a critical section, if any. */
+    critical_holder: Option<ThreadId>,
+    interrupts_enabled: bool,
+    pend_yield: bool,
+    scheduler_started: bool,
+    scheduler_end: bool,
+}
+
+lazy_static! {
+    static ref PORT_STATE: Mutex<PortState> = Mutex::new(PortState {
+        threads: Vec::new(),
+        index_of_last_added_task: 0,
+        session_owner: None,
+        session: 0,
+        running_task: 0,
+        switching_in: false,
+        critical_holder: None,
+        interrupts_enabled: true,
+        pend_yield: false,
+        scheduler_started: false,
+        scheduler_end: false,
+    });
+    /* Notified whenever PORT_STATE changes. */
+    static ref PORT_CONDVAR: Condvar = Condvar::new();
+    static ref RUN_TIME_EPOCH: Instant = Instant::now();
+}
+
+/* Releases the kernel if the thread owning it exits without ending its scheduler. */
+struct SessionGuard {
+    owner: ThreadId,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let state = lock_state();
+        if state.session_owner == Some(self.owner) {
+            end_session(state);
+        }
+    }
+}
+
+thread_local! {
+    static THREAD_ROLE: Cell<ThreadRole> = const { Cell::new(ThreadRole::Foreign) };
+    /* Each task maintains its own interrupt status in the critical nesting variable. */
+    static CRITICAL_NESTING: Cell<UBaseType> = const { Cell::new(0) };
+    static SESSION_GUARD: RefCell<Option<SessionGuard>> = const { RefCell::new(None) };
+}
+
+fn lock_state() -> MutexGuard<'static, PortState> {
+    PORT_STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn wait(state: MutexGuard<'static, PortState>) -> MutexGuard<'static, PortState> {
+    PORT_CONDVAR
+        .wait(state)
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn thread_role() -> ThreadRole {
+    THREAD_ROLE.with(|role| role.get())
+}
+
+fn critical_nesting() -> UBaseType {
+    CRITICAL_NESTING.with(|nesting| nesting.get())
+}
+
+fn set_critical_nesting(nesting: UBaseType) {
+    CRITICAL_NESTING.with(|cell| cell.set(nesting));
+}
+
+fn current_task() -> usize {
+    match task_global::CURRENT_TCB.read().unwrap().as_ref() {
+        Some(handle) => handle.as_ptr() as usize,
+        None => 0,
+    }
+}
+
+/* Threads not created by the port wait until no other thread owns the kernel, then take it. */
+fn attach_to_session(mut state: MutexGuard<'static, PortState>) -> MutexGuard<'static, PortState> {
+    if thread_role() != ThreadRole::Foreign {
+        return state;
+    }
+    let me = thread::current().id();
+    loop {
+        match state.session_owner {
+            Some(owner) if owner == me => return state,
+            Some(_) => state = wait(state),
+            None => {
+                state.session_owner = Some(me);
+                SESSION_GUARD.with(|guard| {
+                    if guard.borrow().is_none() {
+                        *guard.borrow_mut() = Some(SessionGuard { owner: me });
+                    }
+                });
+                return state;
+            }
+        }
+    }
+}
+
+fn lock_attached_state() -> MutexGuard<'static, PortState> {
+    attach_to_session(lock_state())
+}
+
+/* Give the kernel back after its scheduler has ended (or was never started).
+ * Threads of the session are left waiting forever on their next kernel call. */
+fn end_session(mut state: MutexGuard<'static, PortState>) {
+    task_global::reset_task_globals();
+
+    state.threads.clear();
+    state.index_of_last_added_task = 0;
+    state.session_owner = None;
+    state.session += 1;
+    state.running_task = 0;
+    state.switching_in = false;
+    state.critical_holder = None;
+    state.interrupts_enabled = true;
+    state.pend_yield = false;
+    state.scheduler_started = false;
+    state.scheduler_end = false;
+    PORT_CONDVAR.notify_all();
+}
+
+/* The thread of a deleted task, or of a scheduler that has ended, never runs kernel code again. */
+fn retire(mut state: MutexGuard<'static, PortState>) -> ! {
+    loop {
+        state = wait(state);
+    }
+}
+
+/* Wait until the task of the calling thread is scheduled and no critical section is held. */
+fn wait_until_scheduled(
+    mut state: MutexGuard<'static, PortState>,
+    session: u64,
+    index: usize,
+) -> MutexGuard<'static, PortState> {
+    loop {
+        if state.session != session || state.scheduler_end || !state.threads[index].alive {
+            retire(state);
+        }
+        if state.scheduler_started
+            && state.critical_holder.is_none()
+            && state.running_task == state.threads[index].task
+        {
+            state.threads[index].waiting = false;
+            state.switching_in = false;
+            PORT_CONDVAR.notify_all();
+            return state;
+        }
+        state.threads[index].waiting = true;
+        state = wait(state);
+    }
+}
+
+/*
+ * Select the task which is allowed to execute kernel code. If its thread is
+ * waiting to be scheduled, the tick is held pending until the thread has
+ * resumed. Otherwise a busy host may never give the thread the chance to run
+ * before the tick switches it out again.
+ */
+fn set_running_task(state: &mut PortState) {
+    let running_task = current_task();
+    state.running_task = running_task;
+    state.switching_in = state
+        .threads
+        .iter()
+        .any(|thread_state| thread_state.task == running_task && thread_state.alive && thread_state.waiting);
+}
+
+/* Wait until the calling thread is allowed to enter the kernel. */
+fn acquire_kernel(state: MutexGuard<'static, PortState>) -> MutexGuard<'static, PortState> {
+    match thread_role() {
+        ThreadRole::Task { session, index } => wait_until_scheduled(state, session, index),
+        ThreadRole::Tick => {
+            let mut state = state;
+            while state.critical_holder.is_some() || !state.interrupts_enabled {
+                state = wait(state);
+            }
+            state
+        }
+        ThreadRole::Foreign => {
+            let mut state = attach_to_session(state);
+            while state.critical_holder.is_some() {
+                state = wait(state);
+            }
+            state
+        }
+    }
+}
+
+/* Let the task selected by the kernel run, and wait until the caller's task is selected again. */
+fn switch_to_current_task(mut state: MutexGuard<'static, PortState>) -> MutexGuard<'static, PortState> {
+    set_running_task(&mut state);
+    state.critical_holder = None;
+    /* The interrupt status belongs to the task that was switched out. A task
+     * switched out inside a critical section disables the interrupts again
+     * when it is switched back in, see vPortYield(). */
+    state.interrupts_enabled = true;
+    PORT_CONDVAR.notify_all();
+
+    match thread_role() {
+        ThreadRole::Task { session, index } => wait_until_scheduled(state, session, index),
+        _ => state,
+    }
+}
+
+/* Entry of every task thread. */
+fn run_task_thread(session: u64, index: usize, code: unsafe extern "C" fn(CVoidPointer), parameters: usize) {
+    THREAD_ROLE.with(|role| role.set(ThreadRole::Task { session, index }));
+
+    let mut state = wait_until_scheduled(lock_state(), session, index);
+    state.interrupts_enabled = true;
+    drop(state);
+
+    unsafe { code(parameters as CVoidPointer) };
+
+    let state = lock_state();
+    if state.session == session && !state.scheduler_end {
+        /* Task functions must never return, FreeRTOS would trap here as well. */
+        error!("A task function returned. Tasks must delete themselves or loop forever.");
+        drop(state);
+        loop {
+            vPortYield();
+        }
+    }
+}
+
+/* Entry of the thread generating the tick interrupt. */
+fn run_tick_thread(session: u64) {
+    THREAD_ROLE.with(|role| role.set(ThreadRole::Tick));
+
+    let period = Duration::from_micros(1_000_000 / configTICK_RATE_HZ!() as u64);
+    let mut next_tick = Instant::now() + period;
+    let mut state = lock_state();
+    loop {
+        /* Sleep until the next tick, unless the scheduler is ended in the meantime. */
+        loop {
+            if state.session != session || state.scheduler_end {
+                return;
+            }
+            let now = Instant::now();
+            if now >= next_tick {
+                break;
+            }
+            state = PORT_CONDVAR
+                .wait_timeout(state, next_tick - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+
+        /* The tick is held pending while interrupts are disabled, or a task is
+         * being switched in. */
+        while state.critical_holder.is_some() || !state.interrupts_enabled || state.switching_in {
+            if state.session != session || state.scheduler_end {
+                return;
+            }
+            state = wait(state);
+        }
+
+        /* Like a pending interrupt, ticks missed while the tick was held are
+         * delivered once. Making up for them in a burst would expire the
+         * timeouts of tasks that had no chance to run in between. */
+        next_tick += period;
+        let now = Instant::now();
+        if next_tick <= now {
+            next_tick = now + period;
+        }
+        state.critical_holder = Some(thread::current().id());
+        let yield_pended = state.pend_yield;
+        state.pend_yield = false;
+        drop(state);
+
+        let switch_required = kernel::task_increment_tick();
+        #[cfg(feature = "configUSE_PREEMPTION")]
+        {
+            if switch_required || yield_pended {
+                kernel::task_switch_context();
+            }
+        }
+
+        state = switch_to_current_task(lock_state());
+    }
+}
+
+/*
+ * Request a context switch from an ISR. It is held pending until the next tick,
+ * or until the interrupted task leaves its critical section.
+ */
+pub fn vPortYieldFromISR() {
+    lock_attached_state().pend_yield = true;
+}
+
+pub fn vPortYield() {
+    let nesting = critical_nesting();
+    let mut state = lock_attached_state();
+    match thread_role() {
+        ThreadRole::Task { .. } => {}
+        _ => {
+            /* Only tasks can yield, the request is held pending for them. */
+            state.pend_yield = true;
+            return;
+        }
+    }
+
+    if nesting == 0 {
+        state = acquire_kernel(state);
+    }
+    state.critical_holder = Some(thread::current().id());
+    state.pend_yield = false;
+    drop(state);
+
+    kernel::task_switch_context();
+
+    let mut state = switch_to_current_task(lock_state());
+    if nesting > 0 {
+        /* The critical section is taken back when the task is switched in. */
+        state.critical_holder = Some(thread::current().id());
+        state.interrupts_enabled = false;
+    } else {
+        state.interrupts_enabled = true;
+    }
+}
+
+pub fn xPortSetInterruptMask() -> BaseType {
+    let interrupts_enabled = lock_state().interrupts_enabled;
+    vPortEnterCritical();
+    if interrupts_enabled {
+        pdTRUE
+    } else {
+        pdFALSE
+    }
+}
+
+pub fn vPortClearInterruptMask(xMask: BaseType) {
+    let nesting = critical_nesting();
+    if nesting > 0 {
+        set_critical_nesting(nesting - 1);
+    }
+    if nesting <= 1 {
+        let mut state = lock_state();
+        state.critical_holder = None;
+        state.interrupts_enabled = xMask != pdFALSE;
+        PORT_CONDVAR.notify_all();
+    }
+}
+
+pub fn vPortDisableInterrupts() {
+    lock_attached_state().interrupts_enabled = false;
+}
+
+pub fn vPortEnableInterrupts() {
+    lock_attached_state().interrupts_enabled = true;
+    PORT_CONDVAR.notify_all();
+}
+
+pub fn vPortEnterCritical() {
+    let nesting = critical_nesting();
+    if nesting == 0 {
+        let mut state = acquire_kernel(lock_state());
+        state.critical_holder = Some(thread::current().id());
+        state.interrupts_enabled = false;
+    }
+    set_critical_nesting(nesting + 1);
+}
+
+pub fn vPortExitCritical() {
+    /* Check for unmatched exits. */
+    let nesting = critical_nesting();
+    if nesting == 0 {
+        return;
+    }
+    set_critical_nesting(nesting - 1);
+
+    /* If we have reached 0 then re-enable the interrupts. */
+    if nesting == 1 {
+        let mut state = lock_state();
+        state.critical_holder = None;
+        state.interrupts_enabled = true;
+        PORT_CONDVAR.notify_all();
+
+        /* Have we missed ticks? This is the equivalent of pending an interrupt. */
+        let yield_pended = state.pend_yield && state.scheduler_started;
+        drop(state);
+        if yield_pended {
+            if let ThreadRole::Task { .. } = thread_role() {
+                vPortYield();
+            }
+        }
+    }
+}
+
+/*
+ * Called before the scheduler is suspended, and by the idle task on every
+ * iteration. A task that has been switched out waits here until it is
+ * switched in again, so that it can't suspend the scheduler on behalf of the
+ * running task, and the idle task doesn't spin while other tasks run.
+ */
+pub fn vPortSoftwareBarrier() {
+    if let ThreadRole::Task { session, index } = thread_role() {
+        if critical_nesting() == 0 {
+            wait_until_scheduled(lock_state(), session, index);
+        }
+    }
+}
+
+pub fn vPortForciblyEndThread(pxTaskToDelete: CVoidPointer) {
+    let task_to_delete = pxTaskToDelete as usize;
+    let mut state = lock_state();
+    for thread_state in state.threads.iter_mut() {
+        if thread_state.task == task_to_delete {
+            thread_state.alive = false;
+        }
+    }
+    PORT_CONDVAR.notify_all();
+
+    let suicidal = match thread_role() {
+        ThreadRole::Task { index, .. } => state.threads[index].task == task_to_delete,
+        _ => false,
+    };
+    if suicidal {
+        /* This is a suicidal thread, need to select a different task to run. */
+        state.critical_holder = Some(thread::current().id());
+        drop(state);
+        kernel::task_switch_context();
+
+        let mut state = lock_state();
+        set_running_task(&mut state);
+        state.critical_holder = None;
+        state.interrupts_enabled = true;
+        set_critical_nesting(0);
+        PORT_CONDVAR.notify_all();
+        retire(state);
+    }
+}
+
+pub fn vPortAddTaskHandle(pxTaskHandle: CVoidPointer) {
+    let mut state = lock_attached_state();
+    let index = state.index_of_last_added_task;
+    if let Some(thread_state) = state.threads.get_mut(index) {
+        thread_state.task = pxTaskHandle as usize;
+    }
+    PORT_CONDVAR.notify_all();
+}
+
+pub fn vPortFindTicksPerSecond() {
+    lazy_static::initialize(&RUN_TIME_EPOCH);
+    trace!(
+        "Timer Resolution for Run TimeStats is {} ticks per second.",
+        RUN_TIME_COUNTER_HZ
+    );
+}
+
+pub fn ulPortGetTimerValue() -> u64 {
+    let elapsed = RUN_TIME_EPOCH.elapsed();
+    elapsed.as_secs() * RUN_TIME_COUNTER_HZ
+        + elapsed.subsec_nanos() as u64 * RUN_TIME_COUNTER_HZ / 1_000_000_000
+}
+
+/*
+ * The stack isn't used by this port, each task gets the stack of its thread.
+ * The thread is created here and waits until the task is scheduled.
+ */
+pub fn pxPortInitialiseStack(
+    pxTopOfStack: *mut StackType,
+    pxCode: TaskFunction_t,
+    pvParameters: CVoidPointer,
+) -> *mut StackType {
+    let code = match pxCode {
+        Some(code) => code,
+        None => return std::ptr::null_mut(),
+    };
+    /* Raw pointers can't be sent to another thread. */
+    let parameters = pvParameters as usize;
+
+    let mut state = lock_attached_state();
+    let session = state.session;
+    let index = state.threads.len();
+    state.threads.push(ThreadState {
+        task: 0,
+        alive: true,
+        waiting: false,
+    });
+    state.index_of_last_added_task = index;
+    drop(state);
+
+    let spawned = thread::Builder::new()
+        .name(format!("task-{}", index))
+        .spawn(move || run_task_thread(session, index, code, parameters));
+    match spawned {
+        Ok(_) => pxTopOfStack,
+        Err(err) => {
+            error!("Failed to create the thread of a task: {}", err);
+            lock_state().threads[index].alive = false;
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/* Map to the system allocator, like heap_3.c maps to malloc(). */
+pub unsafe fn pvPortMalloc(xSize: usize) -> CVoidPointer {
+    let layout = match Layout::from_size_align(xSize + HEAP_HEADER_SIZE, HEAP_HEADER_SIZE) {
+        Ok(layout) => layout,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let block = alloc(layout);
+    if block.is_null() {
+        return std::ptr::null_mut();
+    }
+    *(block as *mut usize) = layout.size();
+    block.add(HEAP_HEADER_SIZE) as CVoidPointer
+}
+
+pub unsafe fn vPortFree(pv: CVoidPointer) {
+    if pv.is_null() {
+        return;
+    }
+    let block = (pv as *mut u8).sub(HEAP_HEADER_SIZE);
+    let size = *(block as *mut usize);
+    dealloc(block, Layout::from_size_align_unchecked(size, HEAP_HEADER_SIZE));
+}
+
+/*
+ * Start the tick and the first task, then wait until vPortEndScheduler() is called.
+ */
+pub fn xPortStartScheduler() -> BaseType {
+    let mut state = lock_attached_state();
+    let session = state.session;
+    state.scheduler_started = true;
+    state.scheduler_end = false;
+    set_running_task(&mut state);
+    state.critical_holder = None;
+    set_critical_nesting(0);
+
+    let tick_thread: Option<JoinHandle<()>> = thread::Builder::new()
+        .name("tick".to_string())
+        .spawn(move || run_tick_thread(session))
+        .ok();
+    if tick_thread.is_none() {
+        error!("Failed to create the tick thread.");
+    }
+
+    /* Start the first task. */
+    state.interrupts_enabled = true;
+    PORT_CONDVAR.notify_all();
+
+    while !state.scheduler_end {
+        state = wait(state);
+    }
+    drop(state);
+
+    trace!("Cleaning Up, Exiting.");
+    if let Some(tick_thread) = tick_thread {
+        let _ = tick_thread.join();
+    }
+    end_session(lock_state());
+    pdFALSE
+}
+
+pub fn vPortEndScheduler() {
+    let mut state = lock_attached_state();
+    state.scheduler_end = true;
+    PORT_CONDVAR.notify_all();
+}
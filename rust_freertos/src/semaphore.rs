@@ -1,3 +1,4 @@
+use crate::kernel::CriticalSection;
 use crate::port::*;
 use crate::queue::*;
 use crate::queue_h::*;
@@ -7,6 +8,9 @@ use crate::task_control::*;
 use crate::task_control_cap::*;
 use crate::*;
 use std::cell::UnsafeCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 pub struct Semaphore(UnsafeCell<QueueDefinition<Option<TaskHandle>>>);
 unsafe impl Send for Semaphore {}
@@ -28,6 +32,28 @@ impl Semaphore {
         Semaphore(UnsafeCell::new(QueueDefinition::new(1, QueueType::Mutex)))
     }
 
+    /// # Descrpition
+    /// Create a new mutex type semaphore that follows the immediate priority
+    /// ceiling protocol.  A task taking the mutex runs at `ceiling` until it
+    /// gives the mutex back, and a task whose priority is above `ceiling`
+    /// can't take it.
+    ///
+    /// # Arguments:
+    /// `ceiling` - the priority of the highest priority task using the mutex.
+    ///
+    /// # Return:
+    /// `Ok(Semaphore)` - the created mutex.
+    /// `Err(QueueError::MutexCeilingInvalid)` - `ceiling` is not below configMAX_PRIORITIES.
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn new_ceiling_mutex(ceiling: UBaseType) -> Result<Self, QueueError> {
+        if ceiling >= configMAX_PRIORITIES!() {
+            return Err(QueueError::MutexCeilingInvalid);
+        }
+        let mut queue = QueueDefinition::new(1, QueueType::CeilingMutex);
+        queue.initialise_ceiling_priority(ceiling);
+        Ok(Semaphore(UnsafeCell::new(queue)))
+    }
+
     /// # Description
     /// Get the mutex holder.
     ///
@@ -44,14 +70,13 @@ impl Semaphore {
     ))]
     pub fn get_mutex_holder(&self) -> Option<TaskHandle> {
         let mut mutex_holder: Option<TaskHandle>;
-        taskENTER_CRITICAL!();
         {
+            let _critical = CriticalSection::enter();
             unsafe {
                 let inner = self.0.get();
                 mutex_holder = (*inner).queue_generic_receive(0, true).unwrap();
             }
         }
-        taskEXIT_CRITICAL!();
         mutex_holder
     }
 
@@ -65,12 +90,20 @@ impl Semaphore {
     /// Nothing
     ///
     /// # Return:
-    /// Ok(T) if the semaphore was released, otherwise QueueError::QueueEmpty.
+    /// Ok(T) if the semaphore was released, QueueError::MutexNotOwned if the
+    /// semaphore is a mutex the calling task doesn't hold, otherwise
+    /// QueueError::QueueEmpty.
     pub fn semaphore_up(&self) -> Result<Option<TaskHandle>, QueueError> {
         unsafe {
             trace!("Semaphore up runs!");
             let inner = self.0.get();
             trace!("Semaphore up get finished!");
+            /* Only the holder of a mutex may give it back. */
+            if (*inner).is_mutex()
+                && (*inner).transed_task_handle_for_mutex() != Some(get_current_task_handle!())
+            {
+                return Err(QueueError::MutexNotOwned);
+            }
             (*inner).queue_generic_receive(semGIVE_BLOCK_TIME, false)
         }
     }
@@ -95,6 +128,22 @@ impl Semaphore {
         }
     }
 
+    /// # Description
+    /// Obtain a semaphore without blocking the calling task.  The returned
+    /// future completes once the semaphore was obtained, so it can be awaited
+    /// among other futures run by an executor.  A mutex obtained this way is
+    /// held by the task running the executor.
+    ///
+    /// # Arguments:
+    /// Nothing
+    ///
+    /// # Return:
+    /// A future that resolves to Ok() once the semaphore was obtained, or to
+    /// the error if it can never be obtained, e.g. QueueError::MutexCeilingViolated.
+    pub fn acquire_async(&self) -> Acquire<'_> {
+        Acquire { semaphore: self }
+    }
+
     /// # Description
     /// Create a binary semaphore.
     ///
@@ -166,24 +215,23 @@ impl Semaphore {
     /// Nothing
     ///
     /// # Return
-    /// `bool` - true if the recursive mutex was released.
-    pub fn up_recursive(&self) -> bool {
+    /// Ok(()) if the recursive mutex was released, QueueError::MutexNotOwned
+    /// if the calling task doesn't hold it.
+    pub fn up_recursive(&self) -> Result<(), QueueError> {
         unsafe {
             let inner = self.0.get();
-            if (*inner).transed_task_handle_for_mutex().unwrap().clone()
-                == get_current_task_handle!()
-            {
+            if (*inner).transed_task_handle_for_mutex() == Some(get_current_task_handle!()) {
                 traceGIVE_MUTEX_RECURSIVE!(*inner);
                 (*inner).QueueUnion_decrease();
                 if (*inner).is_QueueUnion_zero() {
-                    (*inner).queue_generic_receive(semGIVE_BLOCK_TIME, false);
+                    (*inner).queue_generic_receive(semGIVE_BLOCK_TIME, false)?;
                 } else {
                     mtCOVERAGE_TEST_MARKER!();
                 }
-                return true;
+                Ok(())
             } else {
                 traceGIVE_MUTEX_RECURSIVE_FAILED!(*inner);
-                return false;
+                Err(QueueError::MutexNotOwned)
             }
         }
     }
@@ -199,38 +247,35 @@ impl Semaphore {
     /// A block time of zero can be used to poll the semaphore.
     ///
     /// # Return:
-    /// `bool` - true if the recursive mutex was obtained.
-    pub fn down_recursive(&self, ticks_to_wait: TickType) -> bool {
-        let mut xReturn: bool = false;
+    /// Ok(()) if the recursive mutex was obtained, otherwise the error of the
+    /// underlying take, e.g. QueueError::MutexTimeout.
+    pub fn down_recursive(&self, ticks_to_wait: TickType) -> Result<(), QueueError> {
         unsafe {
             let inner = self.0.get();
             traceTAKE_MUTEX_RECURSIVE!(*inner);
             trace!("Ready to get recursive mutex holder");
             let mutex_holder = (*inner).transed_task_handle_for_mutex();
             trace!("Get recursive mutex holder successfully");
-            if mutex_holder.is_some()
-            {
-                if mutex_holder.unwrap().clone() == get_current_task_handle!() {
-                    trace!("Not First Time get this mutex");
-                    (*inner).QueueUnion_increase();
-                    xReturn = false;
-                }
-            }
-            // else {
+            if mutex_holder == Some(get_current_task_handle!()) {
+                /* The running task already holds the mutex, so taking it
+                again must not block on the mutex itself. */
+                trace!("Not First Time get this mutex");
+                (*inner).QueueUnion_increase();
+                Ok(())
+            } else {
                 trace!("First Time get this mutex");
                 match (*inner).queue_generic_send(None, ticks_to_wait, queueSEND_TO_BACK) {
-                    Ok(x) => {
+                    Ok(()) => {
                         (*inner).QueueUnion_increase();
-                        xReturn = true;
+                        Ok(())
                     }
-                    Err(x) => {
+                    Err(error) => {
                         traceTAKE_MUTEX_RECURSIVE_FAILED!(*inner);
-                        xReturn = false;
+                        Err(error)
                     }
                 }
-            // }
+            }
         }
-        return xReturn;
     }
 
     /// # Description
@@ -250,3 +295,28 @@ impl Semaphore {
         }
     }
 }
+
+/// The future returned by `Semaphore::acquire_async()`.
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<(), QueueError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        /* The waker is registered in the critical section in which the
+        semaphore was found unavailable, so a give can't be missed. */
+        let _critical = CriticalSection::enter();
+        unsafe {
+            let inner = self.semaphore.0.get();
+            match (*inner).queue_generic_send(None, 0, queueSEND_TO_BACK) {
+                Err(QueueError::QueueFull) => {
+                    (*inner).register_async_waiter(cx.waker());
+                    Poll::Pending
+                }
+                result => Poll::Ready(result),
+            }
+        }
+    }
+}
@@ -1,21 +1,24 @@
-use lazy_static::__Deref;
-
 use crate::config::*;
 use crate::kernel::*;
-use crate::list::list_remove;
 use crate::port::*;
+#[cfg(not(feature = "configUSE_CAPS"))]
 use crate::task_control::*;
+#[cfg(feature = "configUSE_CAPS")]
+use crate::task_control_cap::*;
+use crate::task_notify::{task_notify_give, task_notify_give_from_isr, task_notify_take};
+use crate::task_queue::{task_check_for_timeout, task_set_time_out_state, time_out};
 use crate::trace::*;
 use crate::*;
 use std::fmt;
-use std::ops::DerefMut;
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, RwLock, Weak};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum StreamBufferError {
     StreamBufferTriggerLevelOverflow,
     StreamBufferFull,
     StreamBufferEmpty,
+    StreamBufferTaskWaiting,
 }
 impl fmt::Display for StreamBufferError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -25,19 +28,56 @@ impl fmt::Display for StreamBufferError {
             }
             StreamBufferError::StreamBufferFull => write!(f, "StreamBufferFull"),
             StreamBufferError::StreamBufferEmpty => write!(f, "StreamBufferEmpty"),
+            StreamBufferError::StreamBufferTaskWaiting => write!(f, "StreamBufferTaskWaiting"),
         }
     }
 }
 
 pub const sbFLAGS_IS_MESSAGE_BUFFER: u8 = 1;
 pub const sbFLAGS_IS_STATICALLY_ALLOCATED: u8 = 2;
+pub const sbFLAGS_IS_BATCHING_BUFFER: u8 = 4;
+
+/// Called instead of notifying the waiting task when data was written to or
+/// read from a stream buffer, with the buffer and whether it was done from an
+/// interrupt service routine (ISR).  Returns whether a task of a higher
+/// priority than the running task was unblocked, which is only used from an
+/// ISR.  The default behaviour is send_completed() and receive_completed(), or
+/// their _from_isr() versions, which the callback can still call.
+pub type StreamBufferCallbackFunction = fn(&StreamBufferHandle, bool) -> bool;
+
+/// The storage of a stream buffer, either allocated together with the buffer
+/// or provided by the caller.
+pub enum StreamBufferStorage {
+    Dynamic(Vec<u8>),
+    Static(&'static mut [u8]),
+}
+
+impl Deref for StreamBufferStorage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            StreamBufferStorage::Dynamic(storage) => storage,
+            StreamBufferStorage::Static(storage) => storage,
+        }
+    }
+}
+
+impl DerefMut for StreamBufferStorage {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            StreamBufferStorage::Dynamic(storage) => storage,
+            StreamBufferStorage::Static(storage) => storage,
+        }
+    }
+}
+
 /// *Descrpition:
 /// Definition of  Stream Buffer
 ///
-/// The source code : stream_buffer.c  
+/// The source code : stream_buffer.c
 ///
 /// *Implemented by Chen Wenjie
-#[derive(Clone)]
 pub struct StreamBufferDef {
     xTail: UBaseType,
     xHead: UBaseType,
@@ -45,10 +85,19 @@ pub struct StreamBufferDef {
     xTriggerLevelBytes: UBaseType,
     xTaskWaitingToReceive: Option<TaskHandle>,
     xTaskWaitingToSend: Option<TaskHandle>,
-    pucBuffer: u8,
+    pucBuffer: StreamBufferStorage,
     ucFlag: u8,
 
     uxStreamBufferNumber: UBaseType,
+
+    pxSendCompletedCallback: Option<StreamBufferCallbackFunction>,
+    pxReceiveCompletedCallback: Option<StreamBufferCallbackFunction>,
+}
+
+impl Default for StreamBufferDef {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StreamBufferDef {
@@ -60,20 +109,22 @@ impl StreamBufferDef {
             xTriggerLevelBytes: 1,
             xTaskWaitingToReceive: None,
             xTaskWaitingToSend: None,
-            pucBuffer: 0,
+            pucBuffer: StreamBufferStorage::Dynamic(Vec::new()),
             ucFlag: 0,
             uxStreamBufferNumber: 0,
+            pxSendCompletedCallback: None,
+            pxReceiveCompletedCallback: None,
         }
     }
 
     /// get the number of bytes in buffer
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer to be read.
+    ///   self             : The handle of the stream buffer to be read.
     /// * Source code : stream_buffer.c 1229-1247
     ///
     /// * Return
-    ///     the number
+    ///   the number
     pub fn BytesInBuffer(&self) -> UBaseType {
         let mut Count: UBaseType;
 
@@ -88,35 +139,199 @@ impl StreamBufferDef {
         Count
     }
 
+    /// Get the number of bytes that can be written before the buffer is full.
+    /// One byte of the storage is always left free, so that a full buffer can
+    /// be told apart from an empty one.
+    /// * C implementation: stream_buffer.c xStreamBufferSpacesAvailable()
+    ///
+    /// * Return
+    ///   the number
+    pub fn SpacesAvailable(&self) -> UBaseType {
+        let mut xSpace: UBaseType;
+
+        xSpace = self.xLength + self.xTail;
+        xSpace -= self.xHead;
+        xSpace -= 1;
+
+        if xSpace >= self.xLength {
+            xSpace -= self.xLength;
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+        xSpace
+    }
 
     /// Initialise the buffer
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self                : The handle of the stream buffer to be initialised.
-    ///     pucBuffer           : The initial data in buffer
-    ///     BufferSizeBytes    : The size, in bytes, of the buffer pointed to by the pucStreamBufferStorageArea parameter.
-    ///     TriggerLevelBytes  : The number of bytes that must be in the stream buffer before a task that is blocked on the stream buffer to wait for data is
-    ///                           moved out of the blocked state.  For example, if a task is blocked on a read of an empty stream buffer that has a trigger level of 1 then the task will be
-    ///                           unblocked when a single byte is written to the buffer or the task's block time expires.  As another example, if a task is blocked on a read of an empty
-    ///                           stream buffer that has a trigger level of 10 then the task will not be unblocked until the stream buffer contains at least 10 bytes or the task's
-    ///                           block time expires.  If a reading task's block time expires before the trigger level is reached then the task will still receive however many bytes
-    ///                           are actually available.  Setting a trigger level of 0 will result in a trigger level of 1 being used.  It is not valid to specify a trigger level
-    ///                           that is greater than the buffer size.     
+    ///   self                : The handle of the stream buffer to be initialised.
+    ///   pucBuffer           : The storage of the buffer, which becomes empty.
+    ///   TriggerLevelBytes  : The number of bytes that must be in the stream buffer before a task that is blocked on the stream buffer to wait for data is
+    ///   moved out of the blocked state.  For example, if a task is blocked on a read of an empty stream buffer that has a trigger level of 1 then the task will be
+    ///   unblocked when a single byte is written to the buffer or the task's block time expires.  As another example, if a task is blocked on a read of an empty
+    ///   stream buffer that has a trigger level of 10 then the task will not be unblocked until the stream buffer contains at least 10 bytes or the task's
+    ///   block time expires.  If a reading task's block time expires before the trigger level is reached then the task will still receive however many bytes
+    ///   are actually available.  Setting a trigger level of 0 will result in a trigger level of 1 being used.  It is not valid to specify a trigger level
+    ///   that is greater than the buffer size.
     /// * Source code : stream_buffer.c 1250-1274
     ///
     /// * Return
-    ///     
+    ///
     pub fn InitialiseNewStreamBuffer(
         &mut self,
-        pucBuffer: u8,
-        BufferSizeBytes: UBaseType,
+        pucBuffer: StreamBufferStorage,
         TriggerLevelBytes: UBaseType,
         ucFlag: u8,
     ) {
+        self.xLength = pucBuffer.len() as UBaseType;
         self.pucBuffer = pucBuffer;
+        self.xHead = 0;
+        self.xTail = 0;
         self.ucFlag = ucFlag;
         self.xTriggerLevelBytes = TriggerLevelBytes;
-        self.xLength = BufferSizeBytes;
+    }
+
+    /* Copy `pucData` into the storage from xHead, wrapping around the end of
+    the storage, and move xHead past it.  The caller has checked that there
+    is space for it.
+    C implementation: stream_buffer.c prvWriteBytesToBuffer() */
+    fn WriteBytesToBuffer(&mut self, pucData: &[u8]) {
+        let xHead = self.xHead as usize;
+        let xLength = self.xLength as usize;
+        assert!(pucData.len() < xLength);
+
+        /* Write as many bytes as can be written in the first write. */
+        let xFirstLength = pucData.len().min(xLength - xHead);
+        self.pucBuffer[xHead..xHead + xFirstLength].copy_from_slice(&pucData[..xFirstLength]);
+
+        /* Any remaining bytes wrap around to the start of the storage. */
+        let xRemaining = pucData.len() - xFirstLength;
+        self.pucBuffer[..xRemaining].copy_from_slice(&pucData[xFirstLength..]);
+
+        self.xHead = ((xHead + pucData.len()) % xLength) as UBaseType;
+    }
+
+    /* Copy bytes from the storage, starting at xTail, into `pucData`.  The
+    caller has checked that the bytes are in the buffer.  Returns the index
+    that follows the bytes, which becomes the new xTail if they are consumed.
+    C implementation: stream_buffer.c prvReadBytesFromBuffer() */
+    fn ReadBytesFromBuffer(&self, pucData: &mut [u8]) -> UBaseType {
+        let xTail = self.xTail as usize;
+        let xLength = self.xLength as usize;
+        assert!(pucData.len() < xLength);
+
+        let xFirstLength = pucData.len().min(xLength - xTail);
+        pucData[..xFirstLength].copy_from_slice(&self.pucBuffer[xTail..xTail + xFirstLength]);
+
+        let xRemaining = pucData.len() - xFirstLength;
+        pucData[xFirstLength..].copy_from_slice(&self.pucBuffer[..xRemaining]);
+
+        ((xTail + pucData.len()) % xLength) as UBaseType
+    }
+
+    /// Get the length of the message at the front of a message buffer.
+    /// * C implementation: stream_buffer.c xStreamBufferNextMessageLengthBytes()
+    ///
+    /// * Return
+    ///   The length of the next message, or 0 if the buffer is empty or is
+    ///   not a message buffer.
+    pub fn NextMessageLength(&self) -> UBaseType {
+        if (self.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) == 0
+            || self.BytesInBuffer() <= sbBYTES_TO_STORE_MESSAGE_LENGTH!()
+        {
+            return 0;
+        }
+
+        let mut xLengthBytes = [0u8; sbBYTES_TO_STORE_MESSAGE_LENGTH!()];
+        self.ReadBytesFromBuffer(&mut xLengthBytes);
+        u32::from_le_bytes(xLengthBytes) as UBaseType
+    }
+
+    /// A function to help write a stream buffer to a task.
+    ///
+    /// * Implemented by Chen Wenjie
+    /// * Arguments
+    ///   self             : The stream buffer to which a stream is being sent.
+    ///   TxData           : The bytes to be copied into the stream buffer.
+    ///   Space            : The available space in the stream buffer
+    ///   RequireSpace     : The space needed to write all data
+    ///
+    /// * The Resource Code : stream_buffer.c 721-760
+    /// * Return
+    ///   The number of bytes written to the stream buffer.
+    fn WriteMessageToBuffer(
+        &mut self,
+        TxData: &[u8],
+        Space: UBaseType,
+        RequiredSpace: UBaseType,
+    ) -> UBaseType {
+        let mut DataLengthBytes = TxData.len() as UBaseType;
+
+        if (self.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+            /* This is a message buffer, as opposed to a stream buffer.  A
+            message is either written completely, together with its length,
+            or not at all. */
+            if Space >= RequiredSpace {
+                self.WriteBytesToBuffer(&(DataLengthBytes as u32).to_le_bytes());
+            } else {
+                DataLengthBytes = 0;
+            }
+        } else {
+            /* This is a stream buffer, so write as many bytes as fit. */
+            if DataLengthBytes > Space {
+                DataLengthBytes = Space;
+            }
+        }
+
+        if DataLengthBytes != 0 {
+            self.WriteBytesToBuffer(&TxData[..DataLengthBytes as usize]);
+        }
+
+        DataLengthBytes
+    }
+
+    /// A function to help read a stream buffer to a task.
+    ///
+    /// * Implemented by Chen Wenjie
+    /// * Arguments
+    ///   self             : The stream buffer from which bytes are being read.
+    ///   RxData           : The buffer into which the bytes are copied.
+    ///   BytesAvailable   : The number of bytes in the stream buffer
+    ///
+    /// * The Resource Code : stream_buffer.c 965-1014
+    /// * Return
+    ///   The number of bytes read from the stream buffer.
+    fn ReadMessageFromBuffer(&mut self, RxData: &mut [u8], mut BytesAvailable: UBaseType) -> UBaseType {
+        let mut NextMessageLength: UBaseType;
+
+        if (self.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+            NextMessageLength = self.NextMessageLength();
+
+            if NextMessageLength > RxData.len() as UBaseType {
+                /* The user has provided insufficient space to read the
+                message, so leave it in the buffer. */
+                NextMessageLength = 0;
+            } else {
+                /* Consume the length together with the message. */
+                self.xTail = (self.xTail + sbBYTES_TO_STORE_MESSAGE_LENGTH!()) % self.xLength;
+                BytesAvailable -= sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+            }
+        } else {
+            /* A stream of bytes is being read, so read as many as fit. */
+            NextMessageLength = RxData.len() as UBaseType;
+        }
+
+        let Count = if NextMessageLength > BytesAvailable {
+            BytesAvailable
+        } else {
+            NextMessageLength
+        };
+
+        if Count != 0 {
+            self.xTail = self.ReadBytesFromBuffer(&mut RxData[..Count as usize]);
+        }
+
+        Count
     }
 }
 #[derive(Clone)]
@@ -158,10 +373,6 @@ impl StreamBufferHandle {
         StreamBufferHandle(arc)
     }
 
-    pub fn get_buffer(&self) -> u8 {
-        get_streambuffer_from_handle!(self).pucBuffer
-    }
-
     pub fn get_length(&self) -> UBaseType {
         get_streambuffer_from_handle!(self).xLength
     }
@@ -178,64 +389,55 @@ impl StreamBufferHandle {
         get_streambuffer_from_handle!(self).uxStreamBufferNumber
     }
 
-    // pub fn get_tasktoreceive_notifystate(&self)->Option<TaskHandle>{
-    //     get_streambuffer_from_handle!(self).xTaskWaitingToReceive
-    // }
-
-    // pub fn get_tasktosend(&self)-> TaskHandle{
-    //     get_streambuffer_from_handle!(self).xTaskWaitingToSend.unwrap()
-    // }
-
-    /// Reset a stream buffer
+    /// Reset a stream buffer to its empty state.  A stream buffer can only be
+    /// reset if no task is blocked on it.
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer to be reset.
+    ///   self             : The handle of the stream buffer to be reset.
     ///
     /// * The source code : stream_buffer.c  line416 - 462
     /// * Return
-    ///
-    pub fn StreamBufferReset(&mut self) -> Result<(), StreamBufferError> {
+    ///   Ok() if the buffer was reset, otherwise StreamBufferTaskWaiting.
+    pub fn StreamBufferReset(&self) -> Result<(), StreamBufferError> {
+        let _critical = CriticalSection::enter();
         let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
 
-        let Buffer = self.get_buffer();
-        let Length = self.get_length();
-        let TriggerLevelBytes = self.get_triggerlevelbytes();
-        let Flag = self.get_flag();
-        let mut uxStreamBufferNumber = self.get_streambuffernumber();
-
-        taskENTER_CRITICAL!();
-
-        if (!unwrap_streambuffer.xTaskWaitingToReceive.is_none())
-            && (!unwrap_streambuffer.xTaskWaitingToSend.is_none())
+        if unwrap_streambuffer.xTaskWaitingToReceive.is_none()
+            && unwrap_streambuffer.xTaskWaitingToSend.is_none()
         {
-            unwrap_streambuffer.InitialiseNewStreamBuffer(Buffer, Length, TriggerLevelBytes, Flag);
-
-            unwrap_streambuffer.uxStreamBufferNumber = uxStreamBufferNumber;
+            unwrap_streambuffer.xHead = 0;
+            unwrap_streambuffer.xTail = 0;
             traceSTREAM_BUFFER_RESET!();
+            Ok(())
+        } else {
+            Err(StreamBufferError::StreamBufferTaskWaiting)
         }
-        taskEXIT_CRITICAL!();
-        Ok(())
     }
 
     /// Set the trigger level of a stream buffer
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer being updated.
-    ///     xTriggerLevel    : The new trigger level for the stream buffer.
-    /// * The source code    : stream_buffer.c  line465 - 492    
+    ///   self             : The handle of the stream buffer being updated.
+    ///   xTriggerLevel    : The new trigger level for the stream buffer.
+    /// * The source code    : stream_buffer.c  line465 - 492
     /// * Return
-    ///     
+    ///
     pub fn StreamBufferSetTriggerLevel(
-        &mut self,
-        xTriggerLevel: UBaseType,
+        &self,
+        mut xTriggerLevel: UBaseType,
     ) -> Result<(), StreamBufferError> {
+        let _critical = CriticalSection::enter();
         let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
 
+        /* It is not valid for the trigger level to be 0. */
         if xTriggerLevel == 0 {
-            xTriggerLevel == 1;
+            xTriggerLevel = 1;
         }
 
-        if xTriggerLevel <= unwrap_streambuffer.xLength {
+        /* The trigger level is the number of bytes that must be in the stream
+        buffer before a task that is waiting for data is unblocked, so it
+        can't be more than the buffer can hold. */
+        if xTriggerLevel < unwrap_streambuffer.xLength {
             unwrap_streambuffer.xTriggerLevelBytes = xTriggerLevel;
             return Ok(());
         }
@@ -243,277 +445,476 @@ impl StreamBufferHandle {
         Err(StreamBufferError::StreamBufferTriggerLevelOverflow)
     }
 
-    /// Queries a stream buffer to see how much free space it contains, which is equal to the amount of data that
-    /// can be sent to the stream buffer before it is full.
+    /// Queries a stream buffer to see how much data it contains, which is equal to the number of bytes
+    /// that can be read from the stream buffer before it is empty.
     ///
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer being queried.
-    ///     xTriggerLevel    : The new trigger level for the stream buffer.
+    ///   self             : The handle of the stream buffer being queried.
     /// * Return
-    ///     The number of bytes that can be read from the stream buffer before the stream buffer would be full.
-    pub fn StreamBufferBytesAvailable(&mut self) -> UBaseType {
+    ///   The number of bytes that can be read from the stream buffer before the stream buffer would be empty.
+    pub fn StreamBufferBytesAvailable(&self) -> UBaseType {
         get_streambuffer_from_handle!(self).BytesInBuffer()
     }
 
+    /// Queries a stream buffer to see how much free space it contains, which is equal to the amount of data that
+    /// can be sent to the stream buffer before it is full.
+    ///
+    /// * Arguments
+    ///   self             : The handle of the stream buffer being queried.
+    /// * Return
+    ///   The number of bytes that can be written to the stream buffer before the stream buffer would be full.
     pub fn StreamBufferSpacesAvailable(&self) -> UBaseType {
-        let mut unwrap_streambuffer = get_streambuffer_from_handle!(self);
-
-        let mut xSpace: UBaseType;
+        get_streambuffer_from_handle!(self).SpacesAvailable()
+    }
 
-        xSpace = unwrap_streambuffer.xLength + unwrap_streambuffer.xLength;
-        xSpace -= (unwrap_streambuffer.xHead + 1 as UBaseType);
+    /// Queries a stream buffer to see if it is empty.
+    /// * C implementation: stream_buffer.c xStreamBufferIsEmpty()
+    ///
+    /// * Return
+    ///   true if the stream buffer is empty.
+    pub fn StreamBufferIsEmpty(&self) -> bool {
+        get_streambuffer_from_handle!(self).BytesInBuffer() == 0
+    }
 
-        if xSpace >= unwrap_streambuffer.xLength {
-            xSpace -= unwrap_streambuffer.xLength;
+    /// Queries a stream buffer to see if it is full.  A message buffer is
+    /// full when not even a message of length 0 fits.
+    /// * C implementation: stream_buffer.c xStreamBufferIsFull()
+    ///
+    /// * Return
+    ///   true if the stream buffer is full.
+    pub fn StreamBufferIsFull(&self) -> bool {
+        let unwrap_streambuffer = get_streambuffer_from_handle!(self);
+        let xBytesToStoreMessageLength = if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+            sbBYTES_TO_STORE_MESSAGE_LENGTH!()
         } else {
-            mtCOVERAGE_TEST_MARKER!();
-        }
-        xSpace
+            0
+        };
+        unwrap_streambuffer.SpacesAvailable() <= xBytesToStoreMessageLength
+    }
+
+    /// Get the length of the message at the front of a message buffer.
+    /// * C implementation: stream_buffer.c xStreamBufferNextMessageLengthBytes()
+    ///
+    /// * Return
+    ///   The length of the next message, or 0 if the buffer is empty or is
+    ///   not a message buffer.
+    pub fn StreamBufferNextMessageLengthBytes(&self) -> UBaseType {
+        let _critical = CriticalSection::enter();
+        get_streambuffer_from_handle!(self).NextMessageLength()
     }
 
-    /// Create a StreamBuffer in static allocation
+    /// Create a StreamBuffer, with its storage allocated on the heap.
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     xBufferSizeBytes    : The size, in bytes, of the buffer pointed to by the pucStreamBufferStorageArea parameter.
-    ///     xTriggerLevelBytes  : The number of bytes that must be in the stream buffer before a task that is blocked on the stream buffer to wait for data is
-    ///                           moved out of the blocked state.  For example, if a task is blocked on a read of an empty stream buffer that has a trigger level of 1 then the task will be
-    ///                           unblocked when a single byte is written to the buffer or the task's block time expires.  As another example, if a task is blocked on a read of an empty
-    ///                           stream buffer that has a trigger level of 10 then the task will not be unblocked until the stream buffer contains at least 10 bytes or the task's
-    ///                           block time expires.  If a reading task's block time expires before the trigger level is reached then the task will still receive however many bytes
-    ///                           are actually available.  Setting a trigger level of 0 will result in a trigger level of 1 being used.  It is not valid to specify a trigger level
-    ///                           that is greater than the buffer size.    
+    ///   xBufferSizeBytes    : The total number of bytes the stream buffer will be able to hold at any one time.
+    ///   xTriggerLevelBytes  : The number of bytes that must be in the stream buffer before a task that is blocked on the stream buffer to wait for data is
+    ///   moved out of the blocked state.  For example, if a task is blocked on a read of an empty stream buffer that has a trigger level of 1 then the task will be
+    ///   unblocked when a single byte is written to the buffer or the task's block time expires.  As another example, if a task is blocked on a read of an empty
+    ///   stream buffer that has a trigger level of 10 then the task will not be unblocked until the stream buffer contains at least 10 bytes or the task's
+    ///   block time expires.  If a reading task's block time expires before the trigger level is reached then the task will still receive however many bytes
+    ///   are actually available.  Setting a trigger level of 0 will result in a trigger level of 1 being used.  It is not valid to specify a trigger level
+    ///   that is greater than the buffer size.
+    ///   xIsMessageBuffer    : true to create a message buffer, which stores discrete messages.
     /// * Return
-    ///     The created stream buffer handle
+    ///   The created stream buffer handle
     pub fn StreamBufferGenericCreate(
         xBufferSizeBytes: UBaseType,
         xTriggerLevelBytes: UBaseType,
         xIsMessageBuffer: bool,
-        pucStreamBufferStorageArea: u8,
     ) -> Self {
-        let mut ucFlag: u8;
+        Self::StreamBufferGenericCreateWithCallback(
+            xBufferSizeBytes,
+            xTriggerLevelBytes,
+            xIsMessageBuffer,
+            None,
+            None,
+        )
+    }
+
+    /// Create a StreamBuffer whose send-completed and receive-completed
+    /// behaviour is replaced by callbacks, e.g. to forward its data to an
+    /// event loop instead of waking a task.
+    /// * C implementation: stream_buffer.h xStreamBufferCreateWithCallback()
+    /// * Arguments
+    ///   xBufferSizeBytes, xTriggerLevelBytes, xIsMessageBuffer : As for StreamBufferGenericCreate().
+    ///   pxSendCompletedCallback    : Called when data was written to the buffer, or None to notify the waiting receiver.
+    ///   pxReceiveCompletedCallback : Called when data was read from the buffer, or None to notify the waiting sender.
+    /// * Return
+    ///   The created stream buffer handle
+    pub fn StreamBufferGenericCreateWithCallback(
+        xBufferSizeBytes: UBaseType,
+        mut xTriggerLevelBytes: UBaseType,
+        xIsMessageBuffer: bool,
+        pxSendCompletedCallback: Option<StreamBufferCallbackFunction>,
+        pxReceiveCompletedCallback: Option<StreamBufferCallbackFunction>,
+    ) -> Self {
         let mut streambuffer = StreamBufferDef::new();
-        if xIsMessageBuffer == true {
-            ucFlag = sbFLAGS_IS_MESSAGE_BUFFER;
+        let ucFlag: u8 = if xIsMessageBuffer {
+            sbFLAGS_IS_MESSAGE_BUFFER
         } else {
-            ucFlag = 0;
-        }
+            0
+        };
 
+        /* The trigger level can't be greater than the size of the buffer,
+        and a trigger level of 0 would unblock a reader of an empty buffer. */
+        assert!(xTriggerLevelBytes <= xBufferSizeBytes);
         if xTriggerLevelBytes == 0 {
-            xTriggerLevelBytes == 1;
+            xTriggerLevelBytes = 1;
         }
 
+        /* The requested size is incremented so the free space is returned as
+        the user would expect - this is a quirk of the implementation that
+        means otherwise the free space would be reported as one byte smaller
+        than would be logically expected. */
         streambuffer.InitialiseNewStreamBuffer(
+            StreamBufferStorage::Dynamic(vec![0; xBufferSizeBytes as usize + 1]),
+            xTriggerLevelBytes,
+            ucFlag,
+        );
+        streambuffer.pxSendCompletedCallback = pxSendCompletedCallback;
+        streambuffer.pxReceiveCompletedCallback = pxReceiveCompletedCallback;
+
+        from(streambuffer)
+    }
+
+    /// Create a StreamBuffer that uses storage provided by the caller, e.g. a
+    /// `static` array or memory outside the heap.
+    /// * C implementation: stream_buffer.c xStreamBufferGenericCreateStatic()
+    /// * Arguments
+    ///   pucStreamBufferStorageArea : The storage of the stream buffer.  As one byte of the storage is always left free,
+    ///   the buffer can hold one byte less than the length of the storage.
+    ///   xTriggerLevelBytes         : As for StreamBufferGenericCreate().
+    ///   xIsMessageBuffer           : true to create a message buffer, which stores discrete messages.
+    /// * Return
+    ///   The created stream buffer handle
+    pub fn StreamBufferGenericCreateStatic(
+        pucStreamBufferStorageArea: &'static mut [u8],
+        xTriggerLevelBytes: UBaseType,
+        xIsMessageBuffer: bool,
+    ) -> Self {
+        Self::StreamBufferGenericCreateStaticWithCallback(
             pucStreamBufferStorageArea,
-            xBufferSizeBytes,
+            xTriggerLevelBytes,
+            xIsMessageBuffer,
+            None,
+            None,
+        )
+    }
+
+    /// A version of StreamBufferGenericCreateWithCallback() that uses storage
+    /// provided by the caller.
+    /// * C implementation: stream_buffer.h xStreamBufferCreateStaticWithCallback()
+    /// * Arguments
+    ///   pucStreamBufferStorageArea, xTriggerLevelBytes, xIsMessageBuffer : As for StreamBufferGenericCreateStatic().
+    ///   pxSendCompletedCallback, pxReceiveCompletedCallback : As for StreamBufferGenericCreateWithCallback().
+    /// * Return
+    ///   The created stream buffer handle
+    pub fn StreamBufferGenericCreateStaticWithCallback(
+        pucStreamBufferStorageArea: &'static mut [u8],
+        mut xTriggerLevelBytes: UBaseType,
+        xIsMessageBuffer: bool,
+        pxSendCompletedCallback: Option<StreamBufferCallbackFunction>,
+        pxReceiveCompletedCallback: Option<StreamBufferCallbackFunction>,
+    ) -> Self {
+        let mut streambuffer = StreamBufferDef::new();
+        let mut ucFlag: u8 = sbFLAGS_IS_STATICALLY_ALLOCATED;
+        if xIsMessageBuffer {
+            ucFlag |= sbFLAGS_IS_MESSAGE_BUFFER;
+        }
+
+        assert!(!pucStreamBufferStorageArea.is_empty());
+        assert!(xTriggerLevelBytes < pucStreamBufferStorageArea.len() as UBaseType);
+        if xTriggerLevelBytes == 0 {
+            xTriggerLevelBytes = 1;
+        }
+
+        streambuffer.InitialiseNewStreamBuffer(
+            StreamBufferStorage::Static(pucStreamBufferStorageArea),
             xTriggerLevelBytes,
             ucFlag,
         );
+        streambuffer.pxSendCompletedCallback = pxSendCompletedCallback;
+        streambuffer.pxReceiveCompletedCallback = pxReceiveCompletedCallback;
 
         from(streambuffer)
     }
 
+    /// Create a batching buffer: a stream buffer whose reader is only woken
+    /// once the trigger level is reached or its block time expires, whichever
+    /// comes first, even if some bytes are already in the buffer.  Useful to
+    /// drain the buffer in chunks, e.g. for logging.
+    /// * C implementation: stream_buffer.h xStreamBatchingBufferCreate()
+    /// * Arguments
+    ///   xBufferSizeBytes    : The total number of bytes the stream buffer will be able to hold at any one time.
+    ///   xTriggerLevelBytes  : The size of a batch.
+    /// * Return
+    ///   The created stream buffer handle
+    pub fn StreamBatchingBufferCreate(xBufferSizeBytes: UBaseType, xTriggerLevelBytes: UBaseType) -> Self {
+        let handle = Self::StreamBufferGenericCreate(xBufferSizeBytes, xTriggerLevelBytes, false);
+        get_streambuffer_from_handle_mut!(handle).ucFlag |= sbFLAGS_IS_BATCHING_BUFFER;
+        handle
+    }
+
     /// Write to a stream buffer from a task.
     ///
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer to which a stream is being sent.
-    ///     TxData           : A pointer to the buffer that holds the bytes to be copied into the stream buffer.
-    ///     DataLengthBytes  : The maximum number of bytes to copy from pvTxData into the stream buffer.
-    ///     TicksToWait      : The maximum amount of time the task should remain in the Blocked state to wait for enough space to become available in the stream
-    ///         buffer, should the stream buffer contain too little space to hold the another xDataLengthBytes bytes.  The block time is specified in tick periods,            
-    ///         so the absolute time it represents is dependent on the tick frequency.  The macro pdMS_TO_TICKS() can be used to convert a time specified in milliseconds
-    ///         into a time specified in ticks.  Setting xTicksToWait to portMAX_DELAY will cause the task to wait indefinitely (without timing out), provided
-    ///         INCLUDE_vTaskSuspend is set to 1 in FreeRTOSConfig.h.  If a task times out before it can write all xDataLengthBytes into the buffer it will still write
-    ///         as many bytes as possible.  A task does not use any CPU time when it is in the blocked state.
+    ///   self             : The handle of the stream buffer to which a stream is being sent.
+    ///   TxData           : The bytes to be copied into the stream buffer.
+    ///   TicksToWait      : The maximum amount of time the task should remain in the Blocked state to wait for enough space to become available in the stream
+    ///   buffer, should the stream buffer contain too little space to hold the another xDataLengthBytes bytes.  The block time is specified in tick periods,
+    ///   so the absolute time it represents is dependent on the tick frequency.  The macro pdMS_TO_TICKS() can be used to convert a time specified in milliseconds
+    ///   into a time specified in ticks.  Setting xTicksToWait to portMAX_DELAY will cause the task to wait indefinitely (without timing out), provided
+    ///   INCLUDE_vTaskSuspend is set to 1 in FreeRTOSConfig.h.  If a task times out before it can write all xDataLengthBytes into the buffer it will still write
+    ///   as many bytes as possible.  A task does not use any CPU time when it is in the blocked state.
     ///
     /// * The Resource Code : stream_buffer.c 539-668
     /// * Return
-    ///     The number of bytes written to the stream buffer.  If a task times out before it can write all xDataLengthBytes into the buffer it will still
-    ///     write as many bytes as possible.
-    pub fn StreamBufferSend(
-        &mut self,
-        TxData: u8,
-        DataLengthBytes: UBaseType,
-        mut TicksToWait: TickType,
-    ) -> UBaseType {
-        let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
-
-        let mut Return: UBaseType = 0;
-        let mut Space: UBaseType = 0;
+    ///   The number of bytes written to the stream buffer.  If a task times out before it can write all xDataLengthBytes into the buffer it will still
+    ///   write as many bytes as possible.
+    pub fn StreamBufferSend(&self, TxData: &[u8], mut TicksToWait: TickType) -> UBaseType {
+        let DataLengthBytes = TxData.len() as UBaseType;
         let mut RequiredSpace: UBaseType = DataLengthBytes;
-        let mut TimeOut: time_out = Default::default();
-        let mut MaxReportedSpace: UBaseType = 0;
-
-        MaxReportedSpace = unwrap_streambuffer.xLength - 1;
-
-        if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+        let (Length, Flag) = {
+            let _critical = CriticalSection::enter();
+            let unwrap_streambuffer = get_streambuffer_from_handle!(self);
+            (unwrap_streambuffer.xLength, unwrap_streambuffer.ucFlag)
+        };
+        let MaxReportedSpace: UBaseType = Length - 1;
+        /* Whether this task is still recorded as waiting to send. */
+        let mut Waiting = false;
+
+        if (Flag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
             RequiredSpace += sbBYTES_TO_STORE_MESSAGE_LENGTH!();
 
-            assert!(RequiredSpace > DataLengthBytes);
-
+            /* If the message will never fit then don't wait for space. */
             if RequiredSpace > MaxReportedSpace {
                 TicksToWait = 0;
             } else {
                 mtCOVERAGE_TEST_MARKER!();
             }
+        } else if RequiredSpace > MaxReportedSpace {
+            /* A stream buffer can't wait for more space than it has. */
+            RequiredSpace = MaxReportedSpace;
         } else {
-            if (RequiredSpace > MaxReportedSpace) {
-                RequiredSpace = MaxReportedSpace;
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
-            }
+            mtCOVERAGE_TEST_MARKER!();
         }
 
         if TicksToWait != 0 {
+            let mut TimeOut: time_out = Default::default();
             task_set_time_out_state(&mut TimeOut);
 
-            while (task_check_for_timeout(&mut TimeOut, &mut TicksToWait) == false) {
-                taskENTER_CRITICAL!();
-
-                Space = unwrap_streambuffer.BytesInBuffer();
+            loop {
+                {
+                    let _critical = CriticalSection::enter();
+                    let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+                    if Waiting {
+                        unwrap_streambuffer.xTaskWaitingToSend = None;
+                        Waiting = false;
+                    }
 
-                if Space < RequiredSpace {
-                    //TaskNotifyStateClear();
+                    if unwrap_streambuffer.SpacesAvailable() >= RequiredSpace {
+                        break;
+                    }
 
-                    //assert!(unwrap_streambuffer.xTaskWaitingToSend == NULL);
+                    /* Wait until the receiver notifies this task that it has
+                    read from the buffer. */
+                    assert!(unwrap_streambuffer.xTaskWaitingToSend.is_none());
                     unwrap_streambuffer.xTaskWaitingToSend = Some(get_current_task_handle!());
-                } else {
-                    taskEXIT_CRITICAL!();
+                    Waiting = true;
+                }
+
+                /* A notification given before this point is counted, so it
+                can't be lost. */
+                task_notify_take(true, TicksToWait);
+
+                if task_check_for_timeout(&mut TimeOut, &mut TicksToWait) {
                     break;
                 }
+            }
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+
+        let (Return, TriggerLevelReached) = {
+            let _critical = CriticalSection::enter();
+            let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+            if Waiting {
+                unwrap_streambuffer.xTaskWaitingToSend = None;
+            }
+            let Space = unwrap_streambuffer.SpacesAvailable();
+            let Return = unwrap_streambuffer.WriteMessageToBuffer(TxData, Space, RequiredSpace);
+            (Return, unwrap_streambuffer.BytesInBuffer() >= unwrap_streambuffer.xTriggerLevelBytes)
+        };
+
+        if Return > 0 {
+            traceSTREAM_BUFFER_SEND!();
 
-                taskEXIT_CRITICAL!();
+            /* Was a task waiting for the data? */
+            if TriggerLevelReached {
+                self.SendCompleted(false);
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
             }
         } else {
             mtCOVERAGE_TEST_MARKER!();
+            traceSTREAM_BUFFER_SEND_FAILED!(self);
         }
 
-        // Return = self.WriteMessageToBuffer(TxData, DataLengthBytes, Space, RequiredSpace);
+        Return
+    }
 
-        unwrap_streambuffer.pucBuffer = TxData;
-        unwrap_streambuffer.xTail +=1;
-        unwrap_streambuffer.xLength += 1;
+    /// A version of StreamBufferSend() that can be used in an interrupt
+    /// service routine (ISR).  It never blocks.
+    /// * C implementation: stream_buffer.c xStreamBufferSendFromISR()
+    ///
+    /// * Arguments
+    ///   self             : The handle of the stream buffer to which a stream is being sent.
+    ///   TxData           : The bytes to be copied into the stream buffer.
+    /// * Return
+    ///   The number of bytes written to the stream buffer, and whether a task
+    ///   of a higher priority than the running task was unblocked.
+    pub fn StreamBufferSendFromISR(&self, TxData: &[u8]) -> (UBaseType, bool) {
+        let mut HigherPriorityTaskWoken = false;
+        let mut RequiredSpace = TxData.len() as UBaseType;
+
+        let (Return, TriggerLevelReached) = {
+            let _isr_critical = IsrCriticalSection::enter();
+            let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+
+            if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+                RequiredSpace += sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
+            }
 
+            let Space = unwrap_streambuffer.SpacesAvailable();
+            let Return = unwrap_streambuffer.WriteMessageToBuffer(TxData, Space, RequiredSpace);
+            (Return, unwrap_streambuffer.BytesInBuffer() >= unwrap_streambuffer.xTriggerLevelBytes)
+        };
 
-        drop(unwrap_streambuffer);
-        
-        traceSTREAM_BUFFER_SEND!();
+        if Return > 0 {
+            traceSTREAM_BUFFER_SEND!();
 
-        if (self.BytesInBuffer() >= self.get_triggerlevelbytes()) {
-            trace!("Bytes in buffer is more than trigger level.");
-            send_completed(self);
+            if TriggerLevelReached {
+                HigherPriorityTaskWoken = self.SendCompleted(true);
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
+            }
         } else {
             mtCOVERAGE_TEST_MARKER!();
+            traceSTREAM_BUFFER_SEND_FAILED!(self);
         }
 
-
-        1   
-        
+        (Return, HigherPriorityTaskWoken)
     }
 
     /// Receive to a stream buffer from a task.
     ///
     /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer to which a stream is being sent.
-    ///     RxData           : A pointer to the buffer that holds the bytes to be read from the stream buffer.
-    ///     DataLengthBytes  : The maximum number of bytes to read from pvTxData into the stream buffer.
-    ///     TicksToWait      : The maximum amount of time the task should remain in the Blocked state to wait for enough space to become available in the stream
-    ///         buffer, should the stream buffer contain too little space to hold the another xDataLengthBytes bytes.  The block time is specified in tick periods,            
-    ///         so the absolute time it represents is dependent on the tick frequency.  The macro pdMS_TO_TICKS() can be used to convert a time specified in milliseconds
-    ///         into a time specified in ticks.  Setting xTicksToWait to portMAX_DELAY will cause the task to wait indefinitely (without timing out), provided
-    ///         INCLUDE_vTaskSuspend is set to 1 in FreeRTOSConfig.h.  If a task times out before it can write all xDataLengthBytes into the buffer it will still write
-    ///         as many bytes as possible.  A task does not use any CPU time when it is in the blocked state.
+    ///   self             : The handle of the stream buffer from which bytes are to be received.
+    ///   RxData           : The buffer into which the received bytes will be copied.  A message is only received if it fits.
+    ///   TicksToWait      : The maximum amount of time the task should remain in the Blocked state to wait for data to become available if the stream
+    ///   buffer is empty.  Setting xTicksToWait to portMAX_DELAY will cause the task to wait indefinitely (without timing out), provided
+    ///   INCLUDE_vTaskSuspend is set to 1 in FreeRTOSConfig.h.  A task does not use any CPU time when it is in the blocked state.
+    ///   The reader of a batching buffer waits until the trigger level is reached instead, and gets the bytes there are if it times out.
     ///
     /// * The Resource Code : stream_buffer.c line 764-865
     /// * Return
-    ///     The number of bytes written to the stream buffer.  If a task times out before it can write all xDataLengthBytes into the buffer it will still
-    ///     write as many bytes as possible.
-    pub fn StreamBufferReceive(
-        &mut self,
-        RxData: &mut u8,
-        BufferLengthBytes: UBaseType,
-        TicksToWait: TickType,
-    ) -> UBaseType {
-
+    ///   The number of bytes read from the stream buffer.  This will be the number of bytes available up to a maximum of the length of RxData,
+    ///   or the length of the message for a message buffer.
+    pub fn StreamBufferReceive(&self, RxData: &mut [u8], mut TicksToWait: TickType) -> UBaseType {
         let mut ReceiveLength: UBaseType = 0;
-        let mut BytesAvailable: UBaseType;
-        let mut BytesToStoreMessageLength: UBaseType;
-
-        if (self.get_flag() & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
-            BytesToStoreMessageLength = sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+        /* This receive function is used by both message buffers, which store
+        discrete messages, and stream buffers, which store a continuous stream
+        of bytes.  Discrete messages include an additional
+        sbBYTES_TO_STORE_MESSAGE_LENGTH bytes that hold the length of the
+        message. */
+        let Flag = {
+            let _critical = CriticalSection::enter();
+            get_streambuffer_from_handle!(self).ucFlag
+        };
+        let BytesToStoreMessageLength: UBaseType = if (Flag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+            sbBYTES_TO_STORE_MESSAGE_LENGTH!()
         } else {
-            BytesToStoreMessageLength = 0;
-        }
+            0
+        };
+        /* Whether this task is still recorded as waiting to receive. */
+        let mut Waiting = false;
 
         if TicksToWait != 0 {
-            taskENTER_CRITICAL!();
-            BytesAvailable = self.BytesInBuffer();
-
-            if BytesAvailable <= BytesToStoreMessageLength {
-                //( void ) xTaskNotifyStateClear( NULL );
-
-                // assert!(unwrap_streambuffer.xTaskWaitingToReceive == NULL);
-                get_streambuffer_from_handle_mut!(self).xTaskWaitingToReceive = Some(get_current_task_handle!());
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
-            }
-            taskEXIT_CRITICAL!();
-
-            if BytesAvailable <= BytesToStoreMessageLength {
-                traceBLOCKING_ON_STREAM_BUFFER_RECEIVE!(unwrap_streambuffer);
+            let mut TimeOut: time_out = Default::default();
+            task_set_time_out_state(&mut TimeOut);
 
-                //TaskNotifyWait
-                taskENTER_CRITICAL!();
+            loop {
                 {
-                    if get_current_task_handle!().get_notify_state() != taskNOTIFICATION_RECEIVED!()
-                    {
-                        get_current_task_handle!().set_notify_state(taskWAITING_NOTIFICATION!());
-
-                        if TicksToWait > 0 {
-                            add_current_task_to_delayed_list(TicksToWait, true);
-                            traceTASK_NOTIFY_WAIT_BLOCK!();
+                    let _critical = CriticalSection::enter();
+                    let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+                    if Waiting {
+                        unwrap_streambuffer.xTaskWaitingToReceive = None;
+                        Waiting = false;
+                    }
 
-                            portYIELD_WITHIN_API!();
-                        } else {
-                            mtCOVERAGE_TEST_MARKER!();
+                    /* If this function was invoked by a message buffer read
+                    then BytesToStoreMessageLength holds the number of bytes
+                    used to hold the length of the next discrete message.  If
+                    this function was invoked by a stream buffer read then
+                    BytesToStoreMessageLength will be 0. */
+                    let BytesAvailable = unwrap_streambuffer.BytesInBuffer();
+                    if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_BATCHING_BUFFER) != 0 {
+                        /* The reader of a batching buffer waits for a whole
+                        batch, even if some bytes are already there. */
+                        if BytesAvailable >= unwrap_streambuffer.xTriggerLevelBytes {
+                            break;
                         }
-                    } else {
-                        mtCOVERAGE_TEST_MARKER!();
+                    } else if BytesAvailable > BytesToStoreMessageLength {
+                        break;
                     }
+
+                    /* Wait until the sender notifies this task that it has
+                    written to the buffer. */
+                    assert!(unwrap_streambuffer.xTaskWaitingToReceive.is_none());
+                    unwrap_streambuffer.xTaskWaitingToReceive = Some(get_current_task_handle!());
+                    Waiting = true;
                 }
-                taskEXIT_CRITICAL!();
 
-                taskENTER_CRITICAL!();
-                {
-                    traceTASK_NOTIFY_WAIT!();
+                traceBLOCKING_ON_STREAM_BUFFER_RECEIVE!(self);
+                task_notify_take(true, TicksToWait);
 
-                    get_current_task_handle!().set_notify_state(taskNOT_WAITING_NOTIFICATION!());
+                if task_check_for_timeout(&mut TimeOut, &mut TicksToWait) {
+                    break;
                 }
-                taskEXIT_CRITICAL!();
-                // TaskNotifyWait
-
-                get_streambuffer_from_handle_mut!(self).xTaskWaitingToReceive = None;
-                BytesAvailable = self.BytesInBuffer();
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
             }
         } else {
-            BytesAvailable = self.BytesInBuffer();
+            mtCOVERAGE_TEST_MARKER!();
         }
 
-        // let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
-
-        if BytesAvailable > BytesToStoreMessageLength {
-            ReceiveLength =
-                self.ReadMessageFromBuffer(RxData, BufferLengthBytes, BytesAvailable);
-
-            if ReceiveLength != 0 {
-                // receive_completed!(unwrap_streambuffer);
+        {
+            let _critical = CriticalSection::enter();
+            let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+            if Waiting {
+                unwrap_streambuffer.xTaskWaitingToReceive = None;
+            }
+            let BytesAvailable = unwrap_streambuffer.BytesInBuffer();
+
+            /* Whether receiving a discrete message (where BytesToStoreMessageLength
+            holds the number of bytes used to store the message length) or a
+            stream of bytes (where BytesToStoreMessageLength is zero), the
+            number of bytes available must be greater than
+            BytesToStoreMessageLength to be able to read bytes from the
+            buffer. */
+            if BytesAvailable > BytesToStoreMessageLength {
+                ReceiveLength = unwrap_streambuffer.ReadMessageFromBuffer(RxData, BytesAvailable);
             } else {
                 mtCOVERAGE_TEST_MARKER!();
             }
+        }
+
+        if ReceiveLength != 0 {
+            traceSTREAM_BUFFER_RECEIVE!(ReceiveLength);
+            self.ReceiveCompleted(false);
         } else {
             traceSTREAM_BUFFER_RECEIVE_FAILED!();
             mtCOVERAGE_TEST_MARKER!();
@@ -522,108 +923,94 @@ impl StreamBufferHandle {
         ReceiveLength
     }
 
-    /// A function to help write a stream buffer to a task.
+    /// A version of StreamBufferReceive() that can be used in an interrupt
+    /// service routine (ISR).  It never blocks.
+    /// * C implementation: stream_buffer.c xStreamBufferReceiveFromISR()
     ///
-    /// * Implemented by Chen Wenjie
     /// * Arguments
-    ///     self             : The handle of the stream buffer to which a stream is being sent.
-    ///     TxData           : A pointer to the buffer that holds the bytes to be copied into the stream buffer.
-    ///     DataLengthBytes  : The maximum number of bytes to copy from pvTxData into the stream buffer.
-    ///     Space            : The available space in the stream buffer
-    ///     RequireSpace     : The space needed to write all data
-    ///
-    /// * The Resource Code : stream_buffer.c 721-760
+    ///   self             : The handle of the stream buffer from which bytes are to be received.
+    ///   RxData           : The buffer into which the received bytes will be copied.
     /// * Return
-    ///     The number of bytes written to the stream buffer.
-    // fn WriteMessageToBuffer(
-    //     &self,
-    //     TxData: u8,
-    //     mut DataLengthBytes: UBaseType,
-    //     Space: UBaseType,
-    //     RequiredSpace: UBaseType,
-    // ) -> UBaseType {
-    //     let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
-
-    //     let mut NextHead = unwrap_streambuffer.xHead;
-
-    //     let bytesinstore = sbBYTES_TO_STORE_MESSAGE_LENGTH!();
-
-    //     if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
-    //         if Space >= RequiredSpace {
-    //             unwrap_streambuffer.pucBuffer = TxData;
-    //         } else {
-    //             DataLengthBytes = 0;
-    //         }
-    //     } else {
-    //         if DataLengthBytes >= Space {
-    //             DataLengthBytes = Space;
-    //         }
-    //     }
-
-    //     if DataLengthBytes != 0 {
-    //         unwrap_streambuffer.pucBuffer = TxData;
-    //     }
-
-    //     DataLengthBytes
-    // }
-
-    /// A function to help read a stream buffer to a task.
-    ///
-    /// * Implemented by Chen Wenjie
-    /// * Arguments
-    ///     self             : The handle of the stream buffer to which a stream is being sent.
-    ///     RxData           : A pointer to the buffer that holds the bytes to be read from the stream buffer.
-    ///     BufferLengthBytes: The maximum number of bytes to copy from pvTxData into the stream buffer.
-    ///     BytesAvailable   : The available space in the stream buffer
-    ///
-    /// * The Resource Code : stream_buffer.c 965-1014
-    /// * Return
-    ///     The number of bytes read from the stream buffer.
-    fn ReadMessageFromBuffer(
-        &self,
-        RxData: &mut u8,
-        BufferLengthBytes: UBaseType,
-        mut BytesAvailable: UBaseType,
-    ) -> UBaseType {
-        let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
-
-        let mut Count: UBaseType;
-        let mut NextMessageLength: UBaseType = 1;
-        let mut TempNext: UBaseType;
-        let mut NextTail: UBaseType = unwrap_streambuffer.xTail;
-
-        if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
-            *RxData = unwrap_streambuffer.pucBuffer;
-            unwrap_streambuffer.xTail += sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+    ///   The number of bytes read from the stream buffer, and whether a task
+    ///   of a higher priority than the running task was unblocked.
+    pub fn StreamBufferReceiveFromISR(&self, RxData: &mut [u8]) -> (UBaseType, bool) {
+        let mut HigherPriorityTaskWoken = false;
+        let mut ReceiveLength: UBaseType = 0;
 
-            BytesAvailable -= sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+        {
+            let _isr_critical = IsrCriticalSection::enter();
+            let mut unwrap_streambuffer = get_streambuffer_from_handle_mut!(self);
+            let BytesToStoreMessageLength = if (unwrap_streambuffer.ucFlag & sbFLAGS_IS_MESSAGE_BUFFER) != 0 {
+                sbBYTES_TO_STORE_MESSAGE_LENGTH!()
+            } else {
+                0
+            };
 
-            if NextMessageLength > BufferLengthBytes {
-                NextMessageLength = 0;
+            let BytesAvailable = unwrap_streambuffer.BytesInBuffer();
+            if BytesAvailable > BytesToStoreMessageLength {
+                ReceiveLength = unwrap_streambuffer.ReadMessageFromBuffer(RxData, BytesAvailable);
             } else {
                 mtCOVERAGE_TEST_MARKER!();
             }
-        } else {
-            NextMessageLength = BufferLengthBytes;
         }
 
-        if NextMessageLength > BytesAvailable {
-            Count = BytesAvailable;
+        if ReceiveLength != 0 {
+            traceSTREAM_BUFFER_RECEIVE!(ReceiveLength);
+            HigherPriorityTaskWoken = self.ReceiveCompleted(true);
         } else {
-            Count = NextMessageLength;
-        }
-
-        if Count != 0 {
-            *RxData = unwrap_streambuffer.pucBuffer;
-            unwrap_streambuffer.xTail += sbBYTES_TO_STORE_MESSAGE_LENGTH!();
+            traceSTREAM_BUFFER_RECEIVE_FAILED!();
+            mtCOVERAGE_TEST_MARKER!();
         }
 
-        Count
+        (ReceiveLength, HigherPriorityTaskWoken)
     }
 
     pub fn BytesInBuffer(&self) -> UBaseType {
         get_streambuffer_from_handle!(self).BytesInBuffer()
     }
+
+    /* Call the send-completed callback of the buffer if it has one, otherwise
+    notify the task waiting to receive.  The lock of the buffer is not held
+    while the callback runs, so it can use the buffer.
+    C implementation: stream_buffer.c prvSEND_COMPLETED() */
+    fn SendCompleted(&self, xIsInsideISR: bool) -> bool {
+        let pxSendCompletedCallback = if xIsInsideISR {
+            let _isr_critical = IsrCriticalSection::enter();
+            get_streambuffer_from_handle!(self).pxSendCompletedCallback
+        } else {
+            let _critical = CriticalSection::enter();
+            get_streambuffer_from_handle!(self).pxSendCompletedCallback
+        };
+        match pxSendCompletedCallback {
+            Some(callback) => callback(self, xIsInsideISR),
+            None if xIsInsideISR => send_completed_from_isr(self),
+            None => {
+                send_completed(self);
+                false
+            }
+        }
+    }
+
+    /* Call the receive-completed callback of the buffer if it has one,
+    otherwise notify the task waiting to send.
+    C implementation: stream_buffer.c prvRECEIVE_COMPLETED() */
+    fn ReceiveCompleted(&self, xIsInsideISR: bool) -> bool {
+        let pxReceiveCompletedCallback = if xIsInsideISR {
+            let _isr_critical = IsrCriticalSection::enter();
+            get_streambuffer_from_handle!(self).pxReceiveCompletedCallback
+        } else {
+            let _critical = CriticalSection::enter();
+            get_streambuffer_from_handle!(self).pxReceiveCompletedCallback
+        };
+        match pxReceiveCompletedCallback {
+            Some(callback) => callback(self, xIsInsideISR),
+            None if xIsInsideISR => receive_completed_from_isr(self),
+            None => {
+                receive_completed(self);
+                false
+            }
+        }
+    }
 }
 
 #[macro_export]
@@ -662,172 +1049,70 @@ macro_rules! get_streambuffer_handle_from_option {
     };
 }
 
-pub fn send_completed(handle: &mut StreamBufferHandle) {
-    
-    let mut stream_buffer = get_streambuffer_from_handle_mut!(handle);
-
-    task_suspend_all();
-
-    if stream_buffer.xTaskWaitingToReceive.is_some() {
-        taskENTER_CRITICAL!();
-
-        // let task_receive = stream_buffer.xTaskWaitingToReceive;
-
-        let  OriginalNotifyState = stream_buffer.clone().xTaskWaitingToReceive.unwrap().get_notify_state();
-
-
-        traceTASK_NOTIFY!();
-
-        if OriginalNotifyState == taskWAITING_NOTIFICATION!() {
-            // list_remove(stream_buffer.clone().xTaskWaitingToReceive.unwrap().get_event_list_item());
-
-            stream_buffer.clone()
-                .xTaskWaitingToReceive
-                .unwrap()
-                .append_task_to_ready_list();
-
-            if get_current_task_handle!().get_priority()
-                < stream_buffer.clone().xTaskWaitingToReceive.unwrap().get_priority()
-            {
-                taskYIELD_IF_USING_PREEMPTION!();
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
-            }
-        } else {
-            mtCOVERAGE_TEST_MARKER!();
-        }
-
-        taskEXIT_CRITICAL!();
-
-        stream_buffer.xTaskWaitingToReceive = None;
-
-        drop(stream_buffer);
-        trace!("The send is over.");
+/// Unblock the task waiting to receive from a stream buffer, if any, after
+/// data was written to it.
+/// * C implementation: stream_buffer.c sbSEND_COMPLETED()
+pub fn send_completed(handle: &StreamBufferHandle) {
+    let _critical = CriticalSection::enter();
+
+    /* The lock of the buffer is released before notifying, as notifying may
+    switch to the receiver. */
+    let task_to_notify = get_streambuffer_from_handle_mut!(handle).xTaskWaitingToReceive.take();
+    if let Some(task) = task_to_notify {
+        task_notify_give(&task);
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
     }
-
-    task_resume_all();
 }
 
-#[macro_export]
-macro_rules! receive_completed {
-    ($stream_buffer:expr) => {
-        task_suspend_all();
-
-        if $stream_buffer.xTaskWaitingToSend.is_some() {
-            taskENTER_CRITICAL!();
-
-            let OriginalNotifyState = $stream_buffer.clone()
-                .xTaskWaitingToSend
-                .unwrap()
-                .get_notify_value();
-
-            $stream_buffer.clone()
-                .xTaskWaitingToSend
-                .unwrap()
-                .set_notify_value(taskNOTIFICATION_RECEIVED!());
-
-            traceTASK_NOTIFY!();
-
-            if OriginalNotifyState == taskWAITING_NOTIFICATION!() {
-                list_remove(($stream_buffer.clone().xTaskWaitingToSend.unwrap()).get_event_list_item());
-
-                $stream_buffer.clone()
-                    .xTaskWaitingToSend
-                    .unwrap()
-                    .append_task_to_ready_list();
-
-                if get_current_task_handle!().get_priority()
-                    < $stream_buffer.clone().xTaskWaitingToSend.unwrap().get_priority()
-                {
-                    taskYIELD_IF_USING_PREEMPTION!();
-                } else {
-                    mtCOVERAGE_TEST_MARKER!();
-                }
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
-            }
-
-            taskEXIT_CRITICAL!();
+/// A version of send_completed() for interrupt service routines.
+/// * C implementation: stream_buffer.c sbSEND_COMPLETE_FROM_ISR()
+///
+/// * Return
+///   true if a task of a higher priority than the running task was unblocked.
+pub fn send_completed_from_isr(handle: &StreamBufferHandle) -> bool {
+    let _isr_critical = IsrCriticalSection::enter();
+
+    let task_to_notify = get_streambuffer_from_handle_mut!(handle).xTaskWaitingToReceive.take();
+    match task_to_notify {
+        Some(task) => task_notify_give_from_isr(&task),
+        None => false,
+    }
+}
 
-            $stream_buffer.xTaskWaitingToSend = None;
-        }
+/// Unblock the task waiting to send to a stream buffer, if any, after data
+/// was read from it.
+/// * C implementation: stream_buffer.c sbRECEIVE_COMPLETED()
+pub fn receive_completed(handle: &StreamBufferHandle) {
+    let _critical = CriticalSection::enter();
+
+    let task_to_notify = get_streambuffer_from_handle_mut!(handle).xTaskWaitingToSend.take();
+    if let Some(task) = task_to_notify {
+        task_notify_give(&task);
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
 
-        task_resume_all();
-    };
+/// A version of receive_completed() for interrupt service routines.
+/// * C implementation: stream_buffer.c sbRECEIVE_COMPLETED_FROM_ISR()
+///
+/// * Return
+///   true if a task of a higher priority than the running task was unblocked.
+pub fn receive_completed_from_isr(handle: &StreamBufferHandle) -> bool {
+    let _isr_critical = IsrCriticalSection::enter();
+
+    let task_to_notify = get_streambuffer_from_handle_mut!(handle).xTaskWaitingToSend.take();
+    match task_to_notify {
+        Some(task) => task_notify_give_from_isr(&task),
+        None => false,
+    }
 }
 
+/* The length of a message is stored in front of it as a little-endian u32. */
 #[macro_export]
 macro_rules! sbBYTES_TO_STORE_MESSAGE_LENGTH {
     () => {
-        1
+        4
     };
 }
-
-#[derive(Debug, Default)]
-pub struct time_out {
-    overflow_count: BaseType,
-    time_on_entering: TickType,
-}
-
-
-
-pub fn task_set_time_out_state(pxtimeout: &mut time_out) {
-    // assert! ( pxtimeout );
-    pxtimeout.overflow_count = get_num_of_overflows!();
-    pxtimeout.time_on_entering = get_tick_count!();
-}
-
-pub fn task_check_for_timeout(pxtimeout: &mut time_out, ticks_to_wait: &mut TickType) -> bool {
-    trace!("time_out is {:?}", pxtimeout);
-    trace!("ticks_to_wait is {}", ticks_to_wait);
-    let mut xreturn: bool = false;
-    // assert! (pxtimeout);
-    // assert! (ticks_to_wait);
-
-    taskENTER_CRITICAL!();
-    {
-        let const_tick_count: TickType = get_tick_count!();
-        set_tick_count!(const_tick_count + 1);
-        trace!("Tick_count is {}", const_tick_count);
-        let unwrapped_cur = get_current_task_handle!();
-        let mut cfglock1 = false;
-        let mut cfglock2 = false;
-
-        {
-            #![cfg(feature = "INCLUDE_xTaskAbortDelay")]
-            cfglock1 = true;
-        }
-
-        {
-            #![cfg(feature = "INCLUDE_vTaskSuspend")]
-            cfglock2 = true;
-        }
-
-        if cfglock1 && unwrapped_cur.get_delay_aborted() {
-            unwrapped_cur.set_delay_aborted(false);
-            xreturn = true;
-        }
-
-        if cfglock2 && *ticks_to_wait == portMAX_DELAY {
-            xreturn = false;
-        }
-
-        if get_num_of_overflows!() != pxtimeout.overflow_count
-            && const_tick_count >= pxtimeout.time_on_entering
-        {
-            trace!("IF");
-            xreturn = true;
-        } else if const_tick_count - pxtimeout.time_on_entering < *ticks_to_wait {
-            trace!("ELSE IF");
-            *ticks_to_wait -= const_tick_count - pxtimeout.time_on_entering;
-            // task_set_time_out_state(pxtimeout);
-            xreturn = false;
-        } else {
-            trace!("ELSE");
-            xreturn = true;
-        }
-    }
-    taskEXIT_CRITICAL!();
-
-    xreturn
-}
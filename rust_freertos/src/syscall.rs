@@ -0,0 +1,479 @@
+// syscall.rs, capability-checked system calls
+// A system call names a cap of the running task by its cptr, which is looked
+// up in the CSpace of the task, and what the call does depends on the type of
+// the cap and on the label of the message, as in seL4.  The message is in the
+// message registers and the IPC buffer of the task.  The errors of the
+// invocations are set in current_syscall_error, and replied to the caller by
+// setMRs_syscall_error() if it waits for a reply.  The cap faults and unknown
+// system calls of a task with a fault handler are sent to it, see fault.rs.
+
+#![allow(non_snake_case)]
+#![allow(non_camel_case_types)]
+
+use crate::arch_structures_TCB::*;
+use crate::endpoint::{doReplyTransfer, endpoint_with_rights, receiveIPC, sendIPC};
+use crate::fault::handleFault;
+use crate::kernel::CriticalSection;
+use crate::notification::{notification_to_wait_on, receiveSignal, sendSignal};
+use crate::regs::*;
+use crate::task_control_cap::*;
+use crate::task_global::*;
+use crate::types::*;
+use crate::CNode::decodeCNodeInvocation;
+use crate::CSpace::{lookupCap, lookupCapAndSlot};
+use crate::untyped::decodeUntypedInvocation;
+use crate::*;
+
+/* The system calls, numbered as in seL4. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum syscall_t {
+    SysCall = -1,
+    SysReplyRecv = -2,
+    SysSend = -3,
+    SysNBSend = -4,
+    SysRecv = -5,
+    SysReply = -6,
+    SysYield = -7,
+    SysNBRecv = -8,
+}
+
+/* Set an error of a system call, to be returned as EXCEPTION_SYSCALL_ERROR. */
+unsafe fn syscall_error(error: seL4_Error) -> u64 {
+    current_syscall_error.type_ = error as u64;
+    exception::EXCEPTION_SYSCALL_ERROR as u64
+}
+
+/* Send the fault set in current_fault to the fault handler of `thread`, and
+return EXCEPTION_FAULT for the system call to be made again once the task is
+restarted.  A task without a fault handler gets `error` instead. */
+unsafe fn fault_or_error(thread: &TaskHandle, error: seL4_Error) -> u64 {
+    if thread.get_fault_handler() == 0 {
+        return syscall_error(error);
+    }
+    handleFault(thread);
+    exception::EXCEPTION_FAULT as u64
+}
+
+/* A cptr that doesn't designate a cap is a cap fault, or a failed lookup of
+the cap invoked for a task without a fault handler. */
+unsafe fn cap_fault(thread: &TaskHandle, cptr: u64, isReceive: bool) -> u64 {
+    current_fault = seL4_Fault_CapFault_new(cptr, isReceive as u64);
+    current_syscall_error.failedLookupWasSource = 0;
+    fault_or_error(thread, seL4_Error::seL4_FailedLookup)
+}
+
+/// # Description
+/// Reply the error of an invocation, set in current_syscall_error, to the
+/// task that made it.
+///
+/// * C implementation: thread.c replyFromKernel_error()
+///
+/// # Arguments:
+/// * `thread` - the task that made the invocation.
+///
+/// # Return:
+/// Nothing
+pub fn replyFromKernel_error(thread: &TaskHandle) {
+    let buffer = thread.get_ipc_buffer() as *mut u64;
+    unsafe {
+        let thread_ptr = get_ptr_from_handle!(thread);
+        setRegister(thread_ptr, badgeRegister, 0);
+        let length = setMRs_syscall_error(thread_ptr, buffer);
+        setRegister(
+            thread_ptr,
+            msgInfoRegister,
+            wordFromMessageInfo(seL4_MessageInfo_new(current_syscall_error.type_, 0, 0, length)),
+        );
+    }
+}
+
+/// # Description
+/// Reply an empty message to the task that made an invocation.
+///
+/// * C implementation: thread.c replyFromKernel_success_empty()
+///
+/// # Arguments:
+/// * `thread` - the task that made the invocation.
+///
+/// # Return:
+/// Nothing
+pub fn replyFromKernel_success_empty(thread: &TaskHandle) {
+    let mut tcb = get_tcb_from_handle_mut!(thread);
+    tcb.registers[badgeRegister] = 0;
+    tcb.registers[msgInfoRegister] = wordFromMessageInfo(seL4_MessageInfo_new(0, 0, 0, 0));
+}
+
+/// # Description
+/// Decode and perform the invocation of a cap by the running task.  The
+/// operations of endpoints and notifications are performed here; the others
+/// are passed to the decoder of the object.
+///
+/// * C implementation: objecttype.c decodeInvocation()
+///
+/// # Arguments:
+/// * `invLabel` - the label of the message.
+/// * `length` - the length of the message.
+/// * `slot` - the slot of the cap invoked.
+/// * `cap` - the cap invoked.
+/// * `excaps` - the caps looked up from the extra cptrs of the message.
+/// * `block` - whether to block until the operation can be done.
+/// * `call` - whether the running task waits for a reply.
+/// * `buffer` - the IPC buffer of the running task.
+///
+/// # Return:
+/// 0, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// `buffer` must be null or the IPC buffer of the running task, and `slot`
+/// and the caps of `excaps` must point to slots of its CSpace.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn decodeInvocation(
+    invLabel: u64,
+    length: u64,
+    slot: *mut cte_t,
+    cap: cap_t,
+    excaps: extra_caps_t,
+    block: bool,
+    call: bool,
+    buffer: *mut u64,
+) -> u64 {
+    let mut thread = get_current_task_handle!();
+    let cap_type = cap_get_capType(cap);
+    if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+        /* A call through a cap without the grant right can't be replied
+        to, sendIPC() leaves the caller inactive instead. */
+        let index = match endpoint_with_rights(cap, true, false, false) {
+            Ok(index) => index,
+            Err(error) => {
+                userError!("Attempted to invoke a read-only endpoint cap.");
+                current_syscall_error.invalidCapNumber = 0;
+                return syscall_error(error);
+            }
+        };
+        thread.set_state(_thread_state::Restart);
+        sendIPC(
+            block,
+            call,
+            cap_endpoint_cap_get_capEPBadge(cap),
+            cap_endpoint_cap_get_capCanGrant(cap) != 0,
+            &thread,
+            index,
+        );
+        0u64
+    } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+        let index = crate::untyped::object_of(cap_notification_cap_get_capNtfnPtr(cap));
+        if index >= configMAX_NOTIFICATIONS!() || cap_notification_cap_get_capNtfnCanSend(cap) == 0 {
+            userError!("Attempted to invoke a read-only notification cap.");
+            current_syscall_error.invalidCapNumber = 0;
+            return syscall_error(seL4_Error::seL4_InvalidCapability);
+        }
+        thread.set_state(_thread_state::Restart);
+        sendSignal(index, cap_notification_cap_get_capNtfnBadge(cap));
+        0u64
+    } else if cap_type == cap_tag_t::cap_thread_cap as u64 {
+        decodeTCBInvocation(invLabel, length, slot, cap, excaps, buffer)
+    } else if cap_type == cap_tag_t::cap_cnode_cap as u64 {
+        decodeCNodeInvocation(&mut thread, invLabel, length, cap, excaps, buffer)
+    } else if cap_type == cap_tag_t::cap_untyped_cap as u64 {
+        decodeUntypedInvocation(&mut thread, invLabel, length, slot, cap, excaps, buffer)
+    } else {
+        /* Reply caps aren't kept in the CSpace, seL4_Reply() replies to the
+        caller instead. */
+        userError!("Attempted to invoke a cap that can't be invoked.");
+        current_syscall_error.invalidCapNumber = 0;
+        syscall_error(seL4_Error::seL4_InvalidCapability)
+    }
+}
+
+/// # Description
+/// Invoke a cap of the running task with the message of the task.  If the
+/// invocation fails and the task waits for a reply, the error is replied to
+/// it, see replyFromKernel_error().  The invocations that the kernel performs
+/// at once reply an empty message.
+///
+/// * C implementation: syscall.c handleInvocation()
+///
+/// # Arguments:
+/// * `isCall` - whether the running task waits for a reply.
+/// * `isBlocking` - whether to block until the operation can be done.
+/// * `cptr` - the cptr of the cap in the CSpace of the running task.
+/// * `info` - the label, length and number of extra caps of the message.
+/// * `buffer` - the IPC buffer of the running task, or null.
+///
+/// # Return:
+/// 0, EXCEPTION_FAULT if a cap fault was sent to the fault handler of the
+/// task, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// `buffer` must be null or the IPC buffer of the running task, with the
+/// extra cptrs of the message after the message.
+pub unsafe fn handleInvocation(
+    isCall: bool,
+    isBlocking: bool,
+    cptr: u64,
+    info: seL4_MessageInfo_t,
+    buffer: *mut u64,
+) -> u64 {
+    let mut thread = get_current_task_handle!();
+    get_tcb_from_handle_mut!(thread).registers[msgInfoRegister] = wordFromMessageInfo(info);
+    /* The caps are looked up, and the invocations of the caps that aren't
+    endpoint or notification caps decoded, in a critical section, so that no
+    other task changes the slots meanwhile.  Faults and sends may block, so
+    they are made after it. */
+    let critical = CriticalSection::enter();
+    let lu_ret = lookupCapAndSlot(&mut thread, cptr);
+    if lu_ret.status != 0u64 {
+        drop(critical);
+        userError!("Invocation of invalid cap.");
+        let status = cap_fault(&thread, cptr, false);
+        if status == exception::EXCEPTION_SYSCALL_ERROR as u64 && isCall {
+            replyFromKernel_error(&thread);
+        }
+        return status;
+    }
+    let status = TaskHandle::lookupExtraCaps(&mut thread, buffer, info);
+    if status != 0u64 {
+        drop(critical);
+        userError!("Lookup of extra caps failed.");
+        current_syscall_error.failedLookupWasSource = 1;
+        let status = fault_or_error(&thread, seL4_Error::seL4_FailedLookup);
+        if status == exception::EXCEPTION_SYSCALL_ERROR as u64 && isCall {
+            replyFromKernel_error(&thread);
+        }
+        return status;
+    }
+    let mut length = seL4_MessageInfo_get_length(info);
+    if length > n_msgRegisters as u64 && buffer.is_null() {
+        length = n_msgRegisters as u64;
+    }
+    let cap_type = cap_get_capType(lu_ret.cap);
+    let status = if cap_type == cap_tag_t::cap_endpoint_cap as u64
+        || cap_type == cap_tag_t::cap_notification_cap as u64
+    {
+        drop(critical);
+        decodeInvocation(
+            seL4_MessageInfo_get_label(info),
+            length,
+            lu_ret.slot,
+            lu_ret.cap,
+            current_extra_caps,
+            isBlocking,
+            isCall,
+            buffer,
+        )
+    } else {
+        let status = decodeInvocation(
+            seL4_MessageInfo_get_label(info),
+            length,
+            lu_ret.slot,
+            lu_ret.cap,
+            current_extra_caps,
+            isBlocking,
+            isCall,
+            buffer,
+        );
+        drop(critical);
+        status
+    };
+    if status == exception::EXCEPTION_SYSCALL_ERROR as u64 {
+        if isCall {
+            replyFromKernel_error(&thread);
+        }
+        return status;
+    }
+    if let _thread_state::Restart = thread.get_state() {
+        if isCall {
+            replyFromKernel_success_empty(&thread);
+        }
+        thread.set_state(_thread_state::running);
+    }
+    0u64
+}
+
+/// # Description
+/// Receive on a cap of the running task: a message if it is an endpoint cap,
+/// the notification word if it is a notification cap.
+///
+/// * C implementation: syscall.c handleRecv()
+///
+/// # Arguments:
+/// * `isBlocking` - whether to block until there is something to receive.
+/// * `cptr` - the cptr of the cap in the CSpace of the running task.
+///
+/// # Return:
+/// 0, EXCEPTION_FAULT if a cap fault was sent to the fault handler of the
+/// task, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// The CSpace of the running task must be made of valid CNodes.
+pub unsafe fn handleRecv(isBlocking: bool, cptr: u64) -> u64 {
+    let mut thread = get_current_task_handle!();
+    let lu_ret = {
+        let _critical = CriticalSection::enter();
+        lookupCap(&mut thread, cptr)
+    };
+    if lu_ret.status != 0u64 {
+        userError!("Receive on invalid cap.");
+        return cap_fault(&thread, cptr, true);
+    }
+    let cap = lu_ret.cap;
+    let cap_type = cap_get_capType(cap);
+    if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+        match endpoint_with_rights(cap, false, true, false) {
+            Ok(index) => {
+                thread.set_caller(None);
+                receiveIPC(&thread, index, isBlocking);
+                0u64
+            }
+            Err(error) => syscall_error(error),
+        }
+    } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+        match notification_to_wait_on(cap) {
+            Ok(index) => {
+                receiveSignal(&thread, index, isBlocking);
+                0u64
+            }
+            Err(error) => syscall_error(error),
+        }
+    } else {
+        userError!("Receive on a cap that is neither an endpoint nor a notification.");
+        current_syscall_error.invalidCapNumber = 0;
+        syscall_error(seL4_Error::seL4_InvalidCapability)
+    }
+}
+
+/// # Description
+/// Reply the message of the running task to the last call it received.
+/// Nothing happens if there is no call to reply to.
+///
+/// * C implementation: syscall.c handleReply()
+///
+/// # Arguments:
+/// * `info` - the label and length of the reply.
+///
+/// # Return:
+/// Nothing
+pub fn handleReply(info: seL4_MessageInfo_t) {
+    let thread = get_current_task_handle!();
+    get_tcb_from_handle_mut!(thread).registers[msgInfoRegister] = wordFromMessageInfo(info);
+    if let Some(caller) = thread.get_caller() {
+        thread.set_caller(None);
+        doReplyTransfer(&thread, &caller);
+    }
+}
+
+/// # Description
+/// The entry of the system calls of the running task.  The result of the
+/// call is in the message registers and the IPC buffer of the task: the
+/// reply of a SysCall, the message received and its badge, or the error of
+/// a SysCall as replied by replyFromKernel_error().  A system call that
+/// faulted is made again once the fault handler restarts the task.
+///
+/// * C implementation: syscall.c handleSyscall()
+///
+/// # Arguments:
+/// * `syscall` - the system call.
+/// * `cptr` - the cptr of the cap invoked or received on, in the CSpace of the running task.
+/// * `info` - the label, length and number of extra caps of the message sent.
+/// * `buffer` - the IPC buffer of the running task, or null.
+///
+/// # Return:
+/// 0, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// See handleInvocation().
+pub unsafe fn handleSyscall(
+    syscall: syscall_t,
+    cptr: u64,
+    info: seL4_MessageInfo_t,
+    buffer: *mut u64,
+) -> u64 {
+    loop {
+        let status = match syscall {
+            syscall_t::SysSend => handleInvocation(false, true, cptr, info, buffer),
+            syscall_t::SysNBSend => handleInvocation(false, false, cptr, info, buffer),
+            syscall_t::SysCall => handleInvocation(true, true, cptr, info, buffer),
+            syscall_t::SysRecv => handleRecv(true, cptr),
+            syscall_t::SysNBRecv => handleRecv(false, cptr),
+            syscall_t::SysReply => {
+                handleReply(info);
+                0u64
+            }
+            syscall_t::SysReplyRecv => {
+                handleReply(info);
+                handleRecv(true, cptr)
+            }
+            syscall_t::SysYield => {
+                taskYIELD!();
+                0u64
+            }
+        };
+        if status != exception::EXCEPTION_FAULT as u64 {
+            return status;
+        }
+    }
+}
+
+/// # Description
+/// Send a system call unknown to the kernel to the fault handler of the
+/// running task, which may emulate it by its reply.
+///
+/// * C implementation: syscall.c handleUnknownSyscall()
+///
+/// # Arguments:
+/// * `w` - the number of the system call.
+///
+/// # Return:
+/// 0 once the fault handler replied, the reply being the message of the
+/// task, or EXCEPTION_SYSCALL_ERROR with seL4_IllegalOperation if the task
+/// has no fault handler.
+///
+/// # Safety
+///
+/// Must be called by the running task.
+pub unsafe fn handleUnknownSyscall(w: word_t) -> u64 {
+    let thread = get_current_task_handle!();
+    current_fault = seL4_Fault_UnknownSyscall_new(w);
+    if fault_or_error(&thread, seL4_Error::seL4_IllegalOperation)
+        == exception::EXCEPTION_SYSCALL_ERROR as u64
+    {
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
+    0u64
+}
+
+/// # Description
+/// The entry of the system calls of the running task by number, as made by
+/// a trap: the numbers of syscall_t are passed to handleSyscall(), the
+/// others to handleUnknownSyscall().
+///
+/// * C implementation: c_traps.c slowpath()
+///
+/// # Arguments:
+/// * `syscall` - the number of the system call.
+/// * `cptr`, `info`, `buffer` - see handleSyscall().
+///
+/// # Return:
+/// See handleSyscall() and handleUnknownSyscall().
+///
+/// # Safety
+///
+/// See handleInvocation().
+pub unsafe fn slowpath(syscall: i64, cptr: u64, info: seL4_MessageInfo_t, buffer: *mut u64) -> u64 {
+    let syscall = match syscall {
+        -1 => syscall_t::SysCall,
+        -2 => syscall_t::SysReplyRecv,
+        -3 => syscall_t::SysSend,
+        -4 => syscall_t::SysNBSend,
+        -5 => syscall_t::SysRecv,
+        -6 => syscall_t::SysReply,
+        -7 => syscall_t::SysYield,
+        -8 => syscall_t::SysNBRecv,
+        _ => return handleUnknownSyscall(syscall as word_t),
+    };
+    handleSyscall(syscall, cptr, info, buffer)
+}
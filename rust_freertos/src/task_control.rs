@@ -2,7 +2,10 @@ use crate::kernel::*;
 use crate::list::*;
 use crate::port::*;
 use crate::projdefs::FreeRtosError;
+use crate::queue_h::QueueError;
 use crate::task_global::*;
+#[cfg(feature = "configUSE_MUTEXES")]
+use crate::task_queue::BlockedOnMutex;
 use crate::*;
 use std::ops::FnOnce;
 use std::mem;
@@ -48,6 +51,8 @@ pub struct task_control_block {
     base_priority: UBaseType,
     #[cfg(feature = "configUSE_MUTEXES")]
     mutexes_held: UBaseType,
+    #[cfg(feature = "configUSE_MUTEXES")]
+    mutex_blocked_on: Option<BlockedOnMutex>,
 
     #[cfg(feature = "configGENERATE_RUN_TIME_STATS")]
     runtime_counter: TickType,
@@ -96,6 +101,8 @@ impl task_control_block {
             base_priority: 0,
             #[cfg(feature = "configUSE_MUTEXES")]
             mutexes_held: 0,
+            #[cfg(feature = "configUSE_MUTEXES")]
+            mutex_blocked_on: None,
 
             #[cfg(feature = "configGENERATE_RUN_TIME_STATS")]
             runtime_counter: 0,
@@ -285,9 +292,9 @@ impl task_control_block {
             self.runtime_counter = 0;
         }
 
-        #[cfg(feature = "config_USE_TASK_NOTIFICATIONS")]
+        #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
         {
-            self.notify_state = taskNOT_WAITING_NOTIFICATION;
+            self.notify_state = taskNOT_WAITING_NOTIFICATION!();
             self.notified_value = 0;
         }
 
@@ -299,8 +306,9 @@ impl task_control_block {
         let event_list_item = handle.get_event_list_item();
         list::set_list_item_owner(&state_list_item, handle.clone());
         list::set_list_item_owner(&event_list_item, handle.clone());
+        /* Event lists are always in priority order. */
         let item_value = (configMAX_PRIORITIES!() - handle.get_priority()) as TickType;
-        list::set_list_item_value(&state_list_item, item_value);
+        list::set_list_item_value(&event_list_item, item_value);
 
         handle.add_new_task_to_ready_list()?;
 
@@ -361,6 +369,16 @@ impl task_control_block {
         self.mutexes_held = new_count;
     }
 
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn get_mutex_blocked_on(&self) -> Option<BlockedOnMutex> {
+        self.mutex_blocked_on.clone()
+    }
+
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn set_mutex_blocked_on(&mut self, blocked_on: Option<BlockedOnMutex>) {
+        self.mutex_blocked_on = blocked_on;
+    }
+
     #[cfg(feature = "configUSE_MUTEXES")]
     pub fn get_base_priority(&self) -> UBaseType {
         self.base_priority
@@ -466,10 +484,10 @@ impl From<TaskHandle> for Weak<RwLock<TCB>> {
 
 impl TaskHandle {
 
-    pub fn set_notify_state(&mut self, notify_state:u8){
+    pub fn set_notify_state(&self, notify_state:u8){
         get_tcb_from_handle_mut!(self).set_notify_state(notify_state);
     }
-    pub fn set_notify_value(&mut self, notified_value:u32){
+    pub fn set_notify_value(&self, notified_value:u32){
         get_tcb_from_handle_mut!(self).set_notify_value(notified_value);
     }
     pub fn get_notify_state(&self) -> u8{
@@ -506,6 +524,12 @@ impl TaskHandle {
         Arc::into_raw(self.0) as *mut _
     }
 
+    /* Same as as_raw(), but the handle is neither consumed nor leaked.
+     * The port uses it to tell which task a thread belongs to. */
+    pub fn as_ptr(&self) -> ffi::xTaskHandle {
+        Arc::as_ptr(&self.0) as *mut _
+    }
+
     pub fn get_priority(&self) -> UBaseType {
         /* Get the priority of a task.
          * Since this method is so frequently used, I used a funtion to do it.
@@ -514,7 +538,11 @@ impl TaskHandle {
     }
 
     pub fn set_priority(&self, new_priority: UBaseType) {
+        let _critical = CriticalSection::enter();
         get_tcb_from_handle_mut!(self).set_priority(new_priority);
+        /* A task blocked on a queue or a semaphore is moved within the event
+         * list, so that the waiters are still woken in priority order. */
+        task_queue::task_reset_event_list_item_value(self);
     }
 
     /// Place the task represented by pxTCB into the appropriate ready list for
@@ -655,6 +683,16 @@ impl TaskHandle {
         get_tcb_from_handle_mut!(self).set_mutex_held_count(new_count)
     }
 
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn get_mutex_blocked_on(&self) -> Option<BlockedOnMutex> {
+        get_tcb_from_handle!(self).get_mutex_blocked_on()
+    }
+
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn set_mutex_blocked_on(&self, blocked_on: Option<BlockedOnMutex>) {
+        get_tcb_from_handle_mut!(self).set_mutex_blocked_on(blocked_on)
+    }
+
     pub fn get_base_priority(&self) -> UBaseType {
         get_tcb_from_handle!(self).get_base_priority()
     }
@@ -851,13 +889,23 @@ macro_rules! get_handle_from_option {
 ///  cause the calling task to be deleted.
 ///
 /// # Return:
-///
+///  Ok(()) if the task was deleted, QueueError::MutexStillHeld if it still
+///  holds mutexes, in which case it is left alone.
 #[cfg(feature = "INCLUDE_vTaskDelete")]
-pub fn task_delete(task_to_delete: Option<TaskHandle>) {
+pub fn task_delete(task_to_delete: Option<TaskHandle>) -> Result<(), QueueError> {
     /* If null is passed in here then it is the calling task that is
     being deleted. */
     let pxtcb = get_handle_from_option!(task_to_delete);
 
+    /* The mutexes of a deleted task could never be given back, so the tasks
+    waiting for them would block forever. */
+    #[cfg(feature = "configUSE_MUTEXES")]
+    {
+        if pxtcb.get_mutex_held_count() > 0 {
+            return Err(QueueError::MutexStillHeld);
+        }
+    }
+
     taskENTER_CRITICAL!();
     {
         /* Remove task from the ready list. */
@@ -927,6 +975,7 @@ pub fn task_delete(task_to_delete: Option<TaskHandle>) {
             mtCOVERAGE_TEST_MARKER!();
         }
     }
+    Ok(())
 }
 
 ///  INCLUDE_vTaskSuspend must be defined as 1 for this function to be available.
@@ -5,11 +5,16 @@ use crate::list::*;
 use crate::port;
 use crate::port::*;
 use crate::projdefs::FreeRtosError;
+use crate::queue_h::QueueError;
 use crate::task_global;
+#[cfg(feature = "configUSE_MUTEXES")]
+use crate::task_queue::BlockedOnMutex;
 use crate::*;
 use crate::regs::*;
 use std::ops::FnOnce;
+use std::fmt;
 use std::mem;
+use std::panic;
 use std::sync::{Arc, RwLock, Weak};
 use crate::arch_structures_TCB::*;
 use crate::CNode::*;
@@ -18,17 +23,12 @@ use crate::task_global::*;
 use crate::task_ipc::*;
 
 
-pub const MAX_CSlots: usize = wordBits as usize;
 
 pub const L2_BITMAP_SIZE: usize = (256 + (1 << 6) - 1) / (1 << 6);
 extern "C" {
     static mut ksReadyQueues: [tcb_queue_t; 256];   // TODO -> READY_TASK_LISTS
     static mut ksReadyQueuesL1Bitmap: [u64; 1];
     static mut ksReadyQueuesL2Bitmap: [[u64; L2_BITMAP_SIZE]; 1];
-    pub static mut current_extra_caps: extra_caps_t;
-    pub static mut current_syscall_error: syscall_error_t;
-    pub static mut current_fault: seL4_Fault_t;
-    pub static mut current_lookup_fault: lookup_fault_t;
     pub fn getRestartPC(thread: *mut tcb_t) -> u64;
     pub fn setNextPC(thread: *mut tcb_t, v: u64);
     pub fn lookupIPCBuffer(isReceiver: bool, thread: *mut tcb_t) -> *mut u64;
@@ -36,7 +36,6 @@ extern "C" {
     pub fn Arch_setTCBIPCBuffer(thread: *mut tcb_t, bufferAddr: u64);
     pub fn Arch_postModifyRegisters(tptr: *mut tcb_t);
     pub fn Arch_performTransfer(arch: u64, tcb_src: *mut tcb_t, dest: *mut tcb_t) -> u64;
-    pub fn sameObjectAs(cap_a: cap_t, cap_b: cap_t) -> bool_t;
     // fn kprintf(format: *const u8, ...) -> u64;
 }
 // TODO how to convert??
@@ -88,6 +87,9 @@ pub struct task_control_block {
     task_name: String,
     // `stack_pos` is StackType because raw pointer can't be sent between threads safely.
     stack_pos: StackType,
+    /* The closure of a task retyped from untyped memory, boxed by box_entry()
+     * until the task is started, see TaskHandle::set_entry(). */
+    entry: u64,
 
     //* end of stack
     // #[cfg(portStack_GROWTH)]{}
@@ -102,6 +104,9 @@ pub struct task_control_block {
     base_priority: UBaseType,
     #[cfg(feature = "configUSE_MUTEXES")]
     mutexes_held: UBaseType,
+    #[cfg(feature = "configUSE_MUTEXES")]
+    mutex_blocked_on: Option<BlockedOnMutex>,
+    endpoint_blocked_on: Option<EndpointWait>,
 
     #[cfg(feature = "configGENERATE_RUN_TIME_STATS")]
     runtime_counter: TickType,
@@ -117,16 +122,22 @@ pub struct task_control_block {
     // #[cfg(feature = "configUSE_CAPS")]
     // arch : ???,  //  暂时先不考虑? arch里面有很多register,单独拿出来了
     //  TODO
-    // bound_notification : Option<Notification> 
-    // task_fault : FaultType,
-    // lookup_failure : LookupFault,
     // domain : Domain,
+    /* The fault the task waits to be replied to on its fault endpoint, and
+     * the lookup failure of a cap fault, see fault::sendFaultIPC(). */
+    fault: seL4_Fault_t,
+    lookup_failure: lookup_fault_t,
     task_state : TaskState, 
     max_ctrl_prio : UBaseType, 
     fault_handler : UBaseType,
     ipc_buffer : UBaseType,
     pub registers : [word_t; n_contextRegisters],
-    pub ctable: CTable,
+    /* The slots of the task, indexed by tcb_cnode_index; tcbCTable holds the
+     * root CNode of its CSpace. */
+    pub(crate) cnode: [cte_t; tcb_cnode_index::tcbCNodeEntries as usize],
+    blocking_ipc: BlockingIPC,
+    caller: Option<Weak<RwLock<TCB>>>,
+    bound_notification: Option<u64>,
 }
 
 // pub unsafe fn suspend(target: *mut tcb_t) {
@@ -153,6 +164,29 @@ pub struct task_control_block {
 // }
 
 pub type TCB = task_control_block;
+
+/// The endpoint a task is blocked on, and the task expected to serve it, if
+/// it is known.
+#[derive(Clone)]
+pub struct EndpointWait {
+    pub index: u64,
+    pub partner: Option<TaskHandle>,
+}
+
+impl fmt::Debug for EndpointWait {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EndpointWait on endpoint {}", self.index)
+    }
+}
+
+/// How a task blocked on an endpoint takes part in the IPC, like the
+/// blockingIPC fields of the seL4 thread state.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BlockingIPC {
+    pub badge: word_t,
+    pub can_grant: bool,
+    pub is_call: bool,
+}
 pub type Task = task_control_block;
 impl task_control_block {
     pub fn new() -> Self {
@@ -163,6 +197,7 @@ impl task_control_block {
             task_stacksize: configMINIMAL_STACK_SIZE!(),
             task_name: String::from("Unnamed"),
             stack_pos: 0,
+            entry: 0,
 
             //* nesting
             #[cfg(feature = "portCRITICAL_NESTING_IN_TCB")]
@@ -173,6 +208,9 @@ impl task_control_block {
             base_priority: 0,
             #[cfg(feature = "configUSE_MUTEXES")]
             mutexes_held: 0,
+            #[cfg(feature = "configUSE_MUTEXES")]
+            mutex_blocked_on: None,
+            endpoint_blocked_on: None,
 
             #[cfg(feature = "configGENERATE_RUN_TIME_STATS")]
             runtime_counter: 0,
@@ -187,22 +225,24 @@ impl task_control_block {
 
             // TODO
             task_state: TaskState::Idle,
-            // lookup_failure: 0,
+            fault: seL4_Fault_NullFault_new(),
+            lookup_failure: lookup_fault_t { words: [0, 0] },
             // domain: 0,
             fault_handler: 0,
-            max_ctrl_prio: configMAX_PRIORITIES!(),
+            max_ctrl_prio: configMAX_PRIORITIES!() - 1,
             ipc_buffer: 0,
             registers : [0; n_contextRegisters],
-            ctable: CTable {
-                caps: [cte_t {
-                    cap: cap_t {
-                        words: [0, 0]
-                    },
-                    cteMDBNode: mdb_node_t {
-                        words: [0, 0]
-                    }
-                }; MAX_CSlots]
-            },
+            cnode: [cte_t {
+                cap: cap_t {
+                    words: [0, 0]
+                },
+                cteMDBNode: mdb_node_t {
+                    words: [0, 0]
+                }
+            }; tcb_cnode_index::tcbCNodeEntries as usize],
+            blocking_ipc: Default::default(),
+            caller: None,
+            bound_notification: None,
         }
     }
 
@@ -273,6 +313,19 @@ impl task_control_block {
         self
     }
 
+    /// Set the root CNode of the CSpace of a TCB.
+    ///
+    /// # Arguments:
+    ///  `root` A CNode cap, see CNode::cnode_cap_new().  The cptrs of the
+    ///  task are resolved from it, through the CNodes it holds caps to.
+    ///
+    /// # Return:
+    /// Return a TCB with new CSpace.
+    pub fn cspace_root(mut self, root: cap_t) -> Self {
+        self.cnode[tcb_cnode_index::tcbCTable as usize].cap = root;
+        self
+    }
+
     /// * Descrpition:
     ///
     ///  Internally, within the FreeRTOS implementation, tasks use two blocks of
@@ -308,6 +361,19 @@ impl task_control_block {
     where
         F: FnOnce() -> () + Send + 'static,
     {
+        self.initialise_stack(box_entry(func))?;
+
+        // Create task handle.
+        let handle = TaskHandle(Arc::new(RwLock::new(self)));
+        handle.add_new_task_to_ready_list()?;
+
+        Ok(handle)
+    }
+
+    /* Allocate the stack of the task and create its thread, which calls
+     * `entry`, made by box_entry(), when the task is first switched in.
+     * `entry` is freed if this fails. */
+    fn initialise_stack(&mut self, entry: u64) -> Result<(), FreeRtosError> {
         let size_of_stacktype = std::mem::size_of::<StackType>();
         let stacksize_as_bytes = size_of_stacktype * self.task_stacksize as usize;
         trace!(
@@ -317,7 +383,13 @@ impl task_control_block {
         );
 
         // Return `Err` if malloc fails.
-        let px_stack = port::port_malloc(stacksize_as_bytes)?;
+        let px_stack = match port::port_malloc(stacksize_as_bytes) {
+            Ok(px_stack) => px_stack,
+            Err(e) => {
+                unsafe { drop_entry(entry) };
+                return Err(e);
+            }
+        };
 
         // A trick here. By changing raw pointer `px_stack` to StackType,
         // avoid using unsafe `*mut` as a struct field.
@@ -333,12 +405,10 @@ impl task_control_block {
         let mut top_of_stack = self.stack_pos + self.task_stacksize as StackType - 1;
         top_of_stack = top_of_stack & portBYTE_ALIGNMENT_MASK as StackType;
 
-        let f = Box::new(Box::new(func) as Box<dyn FnOnce()>); // Pass task function as a parameter.
-        let param_ptr = &*f as *const _ as *mut _; // Convert to raw pointer.
         trace!(
             "Function ptr of {} is at {:X}",
             self.get_name(),
-            param_ptr as u64
+            entry
         );
 
         /* We use a wrapper function to call the task closure,
@@ -346,17 +416,15 @@ impl task_control_block {
          * https://stackoverflow.com/questions/32270030/how-do-i-convert-a-rust-closure-to-a-c-style-callback
          */
         let result =
-            port::port_initialise_stack(top_of_stack as *mut _, Some(run_wrapper), param_ptr);
+            port::port_initialise_stack(top_of_stack as *mut _, Some(run_wrapper), entry as *mut _);
         match result {
             Ok(_) => {
                 trace!("Stack initialisation succeeded");
-                /* We MUST forget `f`, otherwise it will be freed at the end of this function.
-                 * But we need to call `f` later in `run_wrapper`, which will lead to
-                 * some unexpected behavior.
-                 */
-                mem::forget(f);
             }
-            Err(e) => return Err(e),
+            Err(e) => {
+                unsafe { drop_entry(entry) };
+                return Err(e);
+            }
         }
 
         /* Do a bunch of conditional initialisations. */
@@ -381,26 +449,12 @@ impl task_control_block {
             self.runtime_counter = 0;
         }
 
-        #[cfg(feature = "config_USE_TASK_NOTIFICATIONS")]
+        #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
         {
-            self.notify_state = taskNOT_WAITING_NOTIFICATION;
+            self.notify_state = taskNOT_WAITING_NOTIFICATION!();
             self.notified_value = 0;
         }
-
-        // Create task handle.
-        let sp = self.stack_pos;
-        let handle = TaskHandle(Arc::new(RwLock::new(self)));
-        // TODO: Change type of list_items.
-        let state_list_item = handle.get_state_list_item();
-        let event_list_item = handle.get_event_list_item();
-        list::set_list_item_owner(&state_list_item, handle.clone());
-        list::set_list_item_owner(&event_list_item, handle.clone());
-        let item_value = (configMAX_PRIORITIES!() - handle.get_priority()) as TickType;
-        list::set_list_item_value(&state_list_item, item_value);
-
-        handle.add_new_task_to_ready_list()?;
-
-        Ok(handle)
+        Ok(())
     }
 
     pub fn get_state_list_item(&self) -> ItemLink {
@@ -447,6 +501,26 @@ impl task_control_block {
         prev_val
     }
 
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn get_notify_state(&self) -> u8 {
+        self.notify_state
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn set_notify_state(&mut self, notify_state: u8) {
+        self.notify_state = notify_state;
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn get_notify_value(&self) -> u32 {
+        self.notified_value
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn set_notify_value(&mut self, notified_value: u32) {
+        self.notified_value = notified_value;
+    }
+
     #[cfg(feature = "configUSE_MUTEXES")]
     pub fn get_mutex_held_count(&self) -> UBaseType {
         self.mutexes_held
@@ -457,6 +531,24 @@ impl task_control_block {
         self.mutexes_held = new_count;
     }
 
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn get_mutex_blocked_on(&self) -> Option<BlockedOnMutex> {
+        self.mutex_blocked_on.clone()
+    }
+
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn set_mutex_blocked_on(&mut self, blocked_on: Option<BlockedOnMutex>) {
+        self.mutex_blocked_on = blocked_on;
+    }
+
+    pub fn get_endpoint_blocked_on(&self) -> Option<EndpointWait> {
+        self.endpoint_blocked_on.clone()
+    }
+
+    pub fn set_endpoint_blocked_on(&mut self, blocked_on: Option<EndpointWait>) {
+        self.endpoint_blocked_on = blocked_on;
+    }
+
     #[cfg(feature = "configUSE_MUTEXES")]
     pub fn get_base_priority(&self) -> UBaseType {
         self.base_priority
@@ -472,10 +564,34 @@ impl task_control_block {
         self.fault_handler = faulteq;
     }
 
+    pub fn get_fault_handler(&self) -> u64 {
+        self.fault_handler
+    }
+
+    pub fn set_fault(&mut self, fault: seL4_Fault_t) {
+        self.fault = fault;
+    }
+
+    pub fn get_fault(&self) -> seL4_Fault_t {
+        self.fault
+    }
+
+    pub fn set_lookup_failure(&mut self, lookup_failure: lookup_fault_t) {
+        self.lookup_failure = lookup_failure;
+    }
+
+    pub fn get_lookup_failure(&self) -> lookup_fault_t {
+        self.lookup_failure
+    }
+
     pub fn set_MCPriority(&mut self, mcp: prio_t) {
         self.max_ctrl_prio = mcp;
     }
 
+    pub fn get_MCPriority(&self) -> prio_t {
+        self.max_ctrl_prio
+    }
+
     pub fn set_ipc_buffer(&mut self, bufferAddr: u64) {
         self.ipc_buffer = bufferAddr;
     }
@@ -483,6 +599,34 @@ impl task_control_block {
     pub fn set_state(&mut self, state: TaskState) {
         self.task_state = state;
     }
+
+    pub fn get_state(&self) -> TaskState {
+        self.task_state
+    }
+
+    pub fn get_ipc_buffer(&self) -> u64 {
+        self.ipc_buffer
+    }
+
+    pub fn get_blocking_ipc(&self) -> BlockingIPC {
+        self.blocking_ipc
+    }
+
+    pub fn set_blocking_ipc(&mut self, blocking_ipc: BlockingIPC) {
+        self.blocking_ipc = blocking_ipc;
+    }
+
+    pub fn get_bound_notification(&self) -> Option<u64> {
+        self.bound_notification
+    }
+
+    pub fn get_cspace_root(&self) -> cap_t {
+        self.cnode[tcb_cnode_index::tcbCTable as usize].cap
+    }
+
+    pub fn set_bound_notification(&mut self, bound_notification: Option<u64>) {
+        self.bound_notification = bound_notification;
+    }
 }
 
 impl PartialEq for TCB {
@@ -491,16 +635,35 @@ impl PartialEq for TCB {
     }
 }
 
+/* Box a task closure to be passed to run_wrapper() as its parameter.  The
+closure is leaked here, and taken back by run_wrapper() or drop_entry(). */
+fn box_entry<F>(func: F) -> u64
+where
+    F: FnOnce() + Send + 'static,
+{
+    Box::into_raw(Box::new(Box::new(func) as Box<dyn FnOnce()>)) as u64
+}
+
+/* Free a closure boxed by box_entry() that won't be run. */
+unsafe fn drop_entry(entry: u64) {
+    drop(Box::from_raw(entry as *mut Box<dyn FnOnce()>));
+}
+
 /* Task call wrapper function. */
 extern "C" fn run_wrapper(func_to_run: CVoidPointer) {
     info!(
         "Run_wrapper: The function is at position: {:X}",
         func_to_run as u64
     );
-    unsafe {
-        let func_to_run = Box::from_raw(func_to_run as *mut Box<dyn FnOnce() + 'static>);
-        func_to_run();
-        // TODO: Delete this wrapper task.
+    let mut entry = func_to_run as u64;
+    loop {
+        let func_to_run = unsafe { Box::from_raw(entry as *mut Box<dyn FnOnce() + 'static>) };
+        match panic::catch_unwind(panic::AssertUnwindSafe(func_to_run)) {
+            // TODO: Delete this wrapper task.
+            Ok(()) => break,
+            /* The fault handler of the task may give it another closure. */
+            Err(payload) => entry = crate::fault::handlePanic(payload),
+        }
     }
 }
 
@@ -603,6 +766,33 @@ impl TaskHandle {
         Arc::into_raw(self.0) as *mut _
     }
 
+    /* Same as as_raw(), but the handle is neither consumed nor leaked.
+     * The port uses it to tell which task a thread belongs to. */
+    pub fn as_ptr(&self) -> ffi::xTaskHandle {
+        Arc::as_ptr(&self.0) as *mut _
+    }
+
+    /* The thread cap of a task holds the pointer returned by as_ptr(), so
+     * that the task can be found again from the cap.  The cap holds a
+     * reference to the task as well, which is dropped when the cap is
+     * deleted (see untyped::releaseObject()), so it must be put in a slot,
+     * and only in one: copies are made by deriving it. */
+    pub fn thread_cap(&self) -> cap_t {
+        cap_thread_cap_new(Arc::into_raw(self.0.clone()) as u64)
+    }
+
+    /// Get the task designated by a thread cap.
+    ///
+    /// # Safety
+    ///
+    /// `cap` must be a thread cap made by thread_cap(), or retyped from
+    /// untyped memory, that is still in its slot.
+    pub unsafe fn from_thread_cap(cap: cap_t) -> Self {
+        let ptr = crate::untyped::object_of(cap_thread_cap_get_capTCBPtr(cap)) as *const RwLock<TCB>;
+        Arc::increment_strong_count(ptr);
+        TaskHandle(Arc::from_raw(ptr))
+    }
+
     pub fn get_priority(&self) -> UBaseType {
         /* Get the priority of a task.
          * Since this method is so frequently used, I used a funtion to do it.
@@ -611,7 +801,54 @@ impl TaskHandle {
     }
 
     pub fn set_priority(&self, new_priority: UBaseType) {
+        let _critical = CriticalSection::enter();
         get_tcb_from_handle_mut!(self).set_priority(new_priority);
+        /* A task blocked on a queue or a semaphore is moved within the event
+         * list, so that the waiters are still woken in priority order. */
+        task_queue::task_reset_event_list_item_value(self);
+    }
+
+    /// # Description:
+    ///    set the priority of the task and move it to the ready list of the priority if it
+    ///    is ready.  While the task has inherited a priority through a mutex, its base
+    ///    priority is set, and the inherited priority is only raised to a higher new one;
+    ///    the rest is given back with the mutex.
+    /// * C implementation: tasks.c vTaskPrioritySet()
+    /// # Arguments:
+    ///    new_priority  the priority, lower than configMAX_PRIORITIES
+    /// # Return:
+    ///    Nothing
+    pub fn set_sched_priority(&self, new_priority: UBaseType) {
+        if !self.is_started() {
+            /* The base priority is set when the task is started. */
+            self.set_priority(new_priority);
+            return;
+        }
+        {
+            let _critical = CriticalSection::enter();
+            let old_priority = self.get_priority();
+            #[cfg(feature = "configUSE_MUTEXES")]
+            let inherited = self.get_base_priority() != old_priority;
+            #[cfg(not(feature = "configUSE_MUTEXES"))]
+            let inherited = false;
+            #[cfg(feature = "configUSE_MUTEXES")]
+            self.set_base_priority(new_priority);
+
+            if !inherited || new_priority > old_priority {
+                let state_list_item = self.get_state_list_item();
+                let ready = is_contained_within(&task_global::READY_TASK_LISTS[old_priority as usize], &state_list_item);
+                if ready && list_remove(state_list_item) == 0 {
+                    taskRESET_READY_PRIORITY!(old_priority);
+                }
+                self.set_priority(new_priority);
+                if ready {
+                    let _ = self.append_task_to_ready_list();
+                }
+            }
+        }
+        if get_scheduler_running!() {
+            taskYIELD_IF_USING_PREEMPTION!();
+        }
     }
 
     /// Place the task represented by pxTCB into the appropriate ready list for
@@ -690,6 +927,15 @@ impl TaskHandle {
     ///
     /// TODO
     fn add_new_task_to_ready_list(&self) -> Result<(), FreeRtosError> {
+        // TODO: Change type of list_items.
+        let state_list_item = self.get_state_list_item();
+        let event_list_item = self.get_event_list_item();
+        list::set_list_item_owner(&state_list_item, self.clone());
+        list::set_list_item_owner(&event_list_item, self.clone());
+        /* Event lists are always in priority order. */
+        let item_value = (configMAX_PRIORITIES!() - self.get_priority()) as TickType;
+        list::set_list_item_value(&event_list_item, item_value);
+
         let unwrapped_tcb = get_tcb_from_handle!(self);
 
         taskENTER_CRITICAL!();
@@ -746,8 +992,18 @@ impl TaskHandle {
     ///     Err(FreeRtosError)
     /// TODO return what?
     pub fn append_task_to_endpoint_list(&self, index_queue: u64) -> Result<(), FreeRtosError> {
-        let unwrapped_tcb = get_tcb_from_handle!(self);
-        list::list_insert_end(&task_global::ENDPOINT_LIST[index_queue as usize], Arc::clone(&unwrapped_tcb.state_list_item));
+        /* A task blocked sending or receiving has no partner in the wait-for
+        graph, any task with a cap to the endpoint may serve it. */
+        self.set_endpoint_blocked_on(Some(EndpointWait {
+            index: index_queue,
+            partner: None,
+        }));
+        {
+            let unwrapped_tcb = get_tcb_from_handle!(self);
+            list::list_insert_end(&task_global::ENDPOINT_LIST[index_queue as usize], Arc::clone(&unwrapped_tcb.state_list_item));
+        }
+        #[cfg(feature = "configCHECK_FOR_DEADLOCKS")]
+        deadlock::check_for_deadlock(self);
         Ok(())
     }
 
@@ -760,8 +1016,11 @@ impl TaskHandle {
     /// # Return:
     ///
     pub fn delete_task_from_endpoint_list(&self, index_queue: u64) -> Result<(), FreeRtosError> {
-        let unwrapped_tcb = get_tcb_from_handle!(self);
-        list::list_remove(Arc::clone(&unwrapped_tcb.state_list_item));
+        {
+            let unwrapped_tcb = get_tcb_from_handle!(self);
+            list::list_remove(Arc::clone(&unwrapped_tcb.state_list_item));
+        }
+        self.set_endpoint_blocked_on(None);
         Ok(())
     }
 
@@ -797,6 +1056,26 @@ impl TaskHandle {
         get_tcb_from_handle_mut!(self).set_delay_aborted(next_val)
     }
 
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn get_notify_state(&self) -> u8 {
+        get_tcb_from_handle!(self).get_notify_state()
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn set_notify_state(&self, notify_state: u8) {
+        get_tcb_from_handle_mut!(self).set_notify_state(notify_state)
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn get_notify_value(&self) -> u32 {
+        get_tcb_from_handle!(self).get_notify_value()
+    }
+
+    #[cfg(feature = "configUSE_TASK_NOTIFICATIONS")]
+    pub fn set_notify_value(&self, notified_value: u32) {
+        get_tcb_from_handle_mut!(self).set_notify_value(notified_value)
+    }
+
     #[cfg(feature = "configUSE_MUTEXES")]
     pub fn get_mutex_held_count(&self) -> UBaseType {
         get_tcb_from_handle!(self).get_mutex_held_count()
@@ -807,6 +1086,24 @@ impl TaskHandle {
         get_tcb_from_handle_mut!(self).set_mutex_held_count(new_count)
     }
 
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn get_mutex_blocked_on(&self) -> Option<BlockedOnMutex> {
+        get_tcb_from_handle!(self).get_mutex_blocked_on()
+    }
+
+    #[cfg(feature = "configUSE_MUTEXES")]
+    pub fn set_mutex_blocked_on(&self, blocked_on: Option<BlockedOnMutex>) {
+        get_tcb_from_handle_mut!(self).set_mutex_blocked_on(blocked_on)
+    }
+
+    pub fn get_endpoint_blocked_on(&self) -> Option<EndpointWait> {
+        get_tcb_from_handle!(self).get_endpoint_blocked_on()
+    }
+
+    pub fn set_endpoint_blocked_on(&self, blocked_on: Option<EndpointWait>) {
+        get_tcb_from_handle_mut!(self).set_endpoint_blocked_on(blocked_on)
+    }
+
     pub fn get_base_priority(&self) -> UBaseType {
         get_tcb_from_handle!(self).get_base_priority()
     }
@@ -819,10 +1116,39 @@ impl TaskHandle {
         get_tcb_from_handle_mut!(self).set_fault_handler(faulteq);
     }
 
+    /// The cptr of the fault endpoint of the task in its CSpace, or 0 if the
+    /// task has no fault handler, see fault::handleFault().
+    pub fn get_fault_handler(&self) -> u64 {
+        get_tcb_from_handle!(self).get_fault_handler()
+    }
+
+    pub fn set_fault(&self, fault: seL4_Fault_t) {
+        get_tcb_from_handle_mut!(self).set_fault(fault);
+    }
+
+    /// The fault the task waits to be replied to, a NullFault if there is none.
+    pub fn get_fault(&self) -> seL4_Fault_t {
+        get_tcb_from_handle!(self).get_fault()
+    }
+
+    pub fn set_lookup_failure(&self, lookup_failure: lookup_fault_t) {
+        get_tcb_from_handle_mut!(self).set_lookup_failure(lookup_failure);
+    }
+
+    pub fn get_lookup_failure(&self) -> lookup_fault_t {
+        get_tcb_from_handle!(self).get_lookup_failure()
+    }
+
     pub fn set_MCPriority(&self, mcp: prio_t) {
         get_tcb_from_handle_mut!(self).set_MCPriority(mcp);
     }
 
+    /// The highest priority the task can give to a task through a thread
+    /// cap, see decodeSetPriority().
+    pub fn get_MCPriority(&self) -> prio_t {
+        get_tcb_from_handle!(self).get_MCPriority()
+    }
+
     pub fn set_ipc_buffer(&self, bufferAddr: u64) {
         get_tcb_from_handle_mut!(self).set_ipc_buffer(bufferAddr);
     }
@@ -831,6 +1157,100 @@ impl TaskHandle {
         get_tcb_from_handle_mut!(self).set_state(state);
     }
 
+    pub fn get_state(&self) -> TaskState {
+        get_tcb_from_handle!(self).get_state()
+    }
+
+    pub fn get_ipc_buffer(&self) -> u64 {
+        get_tcb_from_handle!(self).get_ipc_buffer()
+    }
+
+    pub fn get_blocking_ipc(&self) -> BlockingIPC {
+        get_tcb_from_handle!(self).get_blocking_ipc()
+    }
+
+    pub fn set_blocking_ipc(&self, blocking_ipc: BlockingIPC) {
+        get_tcb_from_handle_mut!(self).set_blocking_ipc(blocking_ipc)
+    }
+
+    /// The index of the notification bound to this task, see
+    /// notification::bindNotification().
+    pub fn get_bound_notification(&self) -> Option<u64> {
+        get_tcb_from_handle!(self).get_bound_notification()
+    }
+
+    /// The root CNode of the CSpace of this task, see TCB::cspace_root().
+    pub fn get_cspace_root(&self) -> cap_t {
+        get_tcb_from_handle!(self).get_cspace_root()
+    }
+
+    pub fn set_bound_notification(&self, bound_notification: Option<u64>) {
+        get_tcb_from_handle_mut!(self).set_bound_notification(bound_notification)
+    }
+
+    /// The task waiting for this task to reply, which seL4 keeps as a reply
+    /// cap in the tcbCaller slot.
+    pub fn get_caller(&self) -> Option<TaskHandle> {
+        get_tcb_from_handle!(self).caller.as_ref().and_then(Weak::upgrade).map(TaskHandle)
+    }
+
+    pub fn set_caller(&self, caller: Option<TaskHandle>) {
+        get_tcb_from_handle_mut!(self).caller = caller.map(Weak::from);
+    }
+
+    /// Whether the task has a thread and has been added to the scheduler,
+    /// by TCB::initialise() or start().  A task retyped from untyped memory
+    /// is only started when it is first resumed.
+    pub fn is_started(&self) -> bool {
+        get_tcb_from_handle!(self).stack_pos != 0
+    }
+
+    /// Whether a closure has been set for the task to run once started.
+    pub fn has_entry(&self) -> bool {
+        get_tcb_from_handle!(self).entry != 0
+    }
+
+    /// Set the closure a task retyped from untyped memory runs once it is
+    /// started, replacing the one set before.  The task must not have been
+    /// started, see is_started(), or its closure must have panicked, see
+    /// fault::handlePanic().
+    pub fn set_entry<F>(&self, func: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.clear_entry();
+        get_tcb_from_handle_mut!(self).entry = box_entry(func);
+    }
+
+    /// Take the closure set by set_entry(), boxed by box_entry(), or 0 if
+    /// there is none.
+    pub(crate) fn take_entry(&self) -> u64 {
+        mem::replace(&mut get_tcb_from_handle_mut!(self).entry, 0)
+    }
+
+    /// Free the closure set by set_entry(), if the task hasn't run it.
+    pub fn clear_entry(&self) {
+        let entry = self.take_entry();
+        if entry != 0 {
+            unsafe { drop_entry(entry) };
+        }
+    }
+
+    /// # Description:
+    ///    start a task retyped from untyped memory: create its thread, which runs the
+    ///    closure set by set_entry(), and add the task to the ready list, as
+    ///    TCB::initialise() does.
+    /// # Arguments:
+    ///    self  a task that isn't started and has an entry, see has_entry()
+    /// # Return:
+    ///    Ok(()), or the error of the port if the thread can't be created
+    pub fn start(&self) -> Result<(), FreeRtosError> {
+        let entry = self.take_entry();
+        assert_ne!(entry, 0, "A task is started without an entry.");
+        get_tcb_from_handle_mut!(self).initialise_stack(entry)?;
+        self.add_new_task_to_ready_list()
+    }
+
     /// # Description:
     ///    set TaskHandle's slot, fault handler, mcp, priority, CTable, VTable, IPC buffer
     /// * Implemented by:
@@ -841,22 +1261,25 @@ impl TaskHandle {
     ///
     pub unsafe fn invokeTCB_ThreadControl(
         &self,
-        slot: Arc<cte_t>,
+        slot: *mut cte_t,
         faultep: u64,
         mcp: prio_t,
         priority: prio_t,
         cRoot_newCap: cap_t,
-        cRoot_srcSlot: Arc<cte_t>,
+        cRoot_srcSlot: *mut cte_t,
         vRoot_newCap: cap_t,
-        vRoot_srcSlot: Arc<cte_t>,
+        vRoot_srcSlot: *mut cte_t,
         bufferAddr: u64,
         bufferCap: cap_t,
-        bufferSrcSlot: Arc<cte_t>,
+        bufferSrcSlot: *mut cte_t,
         updateFlags: u64,
     ) -> u64 {
         let target_ptr = get_ptr_from_handle!(self);
         // let tCap = cap_thread_cap_new(target_ptr as u64);  //  originally
-        let tCap = cap_thread_cap_new(target_ptr as u64);  //  use *mut tcb_t and modify the function, in the function, use "as u64"
+        /* The cap invoked rather than self.thread_cap(), as the cap of a task
+        retyped from untyped memory holds the address of its object, see
+        untyped::object_of(). */
+        let tCap = (*slot).cap;
         //  Fault Handler
         if updateFlags & thread_control_flag::thread_control_update_space as u64 != 0u64 {
             get_tcb_from_handle_mut!(self).set_fault_handler(faultep);
@@ -867,48 +1290,49 @@ impl TaskHandle {
         }
         //  Priority
         if updateFlags & thread_control_flag::thread_control_update_priority as u64 != 0u64 {
-            get_tcb_from_handle_mut!(self).set_priority(priority);
+            self.set_sched_priority(priority);
         }
         //  CTable
         if updateFlags & thread_control_flag::thread_control_update_space as u64 != 0u64 {
-            let rootSlot = Arc::from_raw(tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbCTable as u64));
-            let e = cteDelete(Arc::new(RwLock::new(*rootSlot.clone())), 1u64);
+            let rootSlot = tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbCTable as u64);
+            let e = cteDelete(rootSlot, 1u64);
             if e != 0u64 {
                 return e;
             }
             if sameObjectAs(cRoot_newCap, (*cRoot_srcSlot).cap) != 0u64
                 && sameObjectAs(tCap, (*slot).cap) != 0u64
             {
-                cteInsert(cRoot_newCap, Arc::new(RwLock::new(*cRoot_srcSlot.clone())), Arc::new(RwLock::new(*rootSlot.clone())));
+                cteInsert(cRoot_newCap, cRoot_srcSlot, rootSlot);
             }
         }
         //  VTable
         if updateFlags & thread_control_flag::thread_control_update_space as u64 != 0u64 {
-            let rootSlot = Arc::from_raw(tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbVTable as u64));
-            let e = cteDelete(Arc::new(RwLock::new(*rootSlot.clone())), 1u64);
+            let rootSlot = tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbVTable as u64);
+            let e = cteDelete(rootSlot, 1u64);
             if e != 0u64 {
                 return e;
             }
-            if sameObjectAs(vRoot_newCap, (*vRoot_srcSlot).cap) != 0u64
+            if vRoot_srcSlot as u64 != 0u64
+                && sameObjectAs(vRoot_newCap, (*vRoot_srcSlot).cap) != 0u64
                 && sameObjectAs(tCap, (*slot).cap) != 0u64
             {
-                cteInsert(vRoot_newCap, Arc::new(RwLock::new(*vRoot_srcSlot.clone())), Arc::new(RwLock::new(*rootSlot.clone())));
+                cteInsert(vRoot_newCap, vRoot_srcSlot, rootSlot);
             }
         }
         //  IPC Buffer
         if updateFlags & thread_control_flag::thread_control_update_ipc_buffer as u64 != 0u64 {
-            let bufferSlot = Arc::from_raw(tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbBuffer as u64));
-            let e = cteDelete(Arc::new(RwLock::new(*bufferSlot.clone())), 1u64);
+            let bufferSlot = tcb_ptr_cte_ptr(target_ptr, tcb_cnode_index::tcbBuffer as u64);
+            let e = cteDelete(bufferSlot, 1u64);
             if e != 0u64 {
                 return e;
             }
             get_tcb_from_handle_mut!(self).set_ipc_buffer(bufferAddr);
             // Arch_setTCBIPCBuffer(target_ptr, bufferAddr);    //  not appear in source code?  TODO
-            if Arc::as_ptr(&bufferSrcSlot) as u64 != 0u64
+            if bufferSrcSlot as u64 != 0u64
                 && sameObjectAs(bufferCap, (*bufferSrcSlot).cap) != 0u64
                 && sameObjectAs(tCap, (*slot).cap) != 0u64
             {
-                cteInsert(bufferCap, Arc::new(RwLock::new(*bufferSrcSlot.clone())), Arc::new(RwLock::new(*bufferSlot.clone())));
+                cteInsert(bufferCap, bufferSrcSlot, bufferSlot);
             }
             if self == &get_current_task_handle!() {
                 // rescheduleRequired();
@@ -1095,19 +1519,261 @@ impl TaskHandle {
         0u64
     }
 
-    //  ???notification TODO
-    pub unsafe fn invokeTCB_NotificationControl(
-        &mut self,// tcb: *mut tcb_t,
-        ntfnPtr: *mut notification_t
+    /// # Description:
+    ///    bind a notification to the task, or unbind the bound one
+    /// # Arguments:
+    ///    ntfnPtr  the index of the notification, as in its cap, or None to unbind
+    /// # Return:
+    ///    0
+    pub fn invokeTCB_NotificationControl(
+        &self,
+        ntfnPtr: Option<u64>
     ) -> u64 {
-        if ntfnPtr as u64 != 0u64 {
-            // bindNotification(tcb, ntfnPtr);
+        if let Some(ntfnPtr) = ntfnPtr {
+            notification::bindNotification(self, ntfnPtr);
         } else {
-            // unbindNotification(tcb);
+            notification::unbindNotification(self);
         }
         0u64
     }
 }
+
+/// # Description:
+///    decode and perform an invocation of a thread cap.  Only the invocations that don't need
+///    the registers of the thread are supported, the others are illegal operations.
+///    Resuming a task retyped from untyped memory starts it, see TaskHandle::start().
+/// * C implementation: tcb.c decodeTCBInvocation()
+/// # Arguments:
+///    invLabel  the label of the message
+///    length  the length of the message
+///    slot  the slot of the thread cap
+///    cap  the thread cap
+///    excaps  the caps looked up from the extra cptrs of the message
+///    buffer  the IPC buffer of the running task
+/// # Return:
+///    0, or EXCEPTION_SYSCALL_ERROR with current_syscall_error set
+/// # Safety
+///    `cap` must be a thread cap, see TaskHandle::from_thread_cap(), and `slot` its slot
+pub unsafe fn decodeTCBInvocation(
+    invLabel: u64,
+    length: u64,
+    slot: *mut cte_t,
+    cap: cap_t,
+    excaps: extra_caps_t,
+    buffer: *mut u64,
+) -> u64 {
+    let mut target = TaskHandle::from_thread_cap(cap);
+    if invLabel == invocation_label::TCBSuspend as u64 {
+        get_current_task_handle!().set_state(_thread_state::Restart);
+        suspend_task(&mut target);
+        0u64
+    } else if invLabel == invocation_label::TCBResume as u64 {
+        if !target.is_started() {
+            return decodeTCBStart(&target);
+        }
+        get_current_task_handle!().set_state(_thread_state::Restart);
+        resume_task(&mut target);
+        0u64
+    } else if invLabel == invocation_label::TCBConfigure as u64 {
+        if length < 4 || excaps.excaprefs[0].is_null() {
+            userError!("TCB Configure: Truncated message.");
+            current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
+            return exception::EXCEPTION_SYSCALL_ERROR as u64;
+        }
+        let faultEP = getSyscallArg(0, buffer);
+        let cRootData = getSyscallArg(1, buffer);
+        let bufferAddr = getSyscallArg(3, buffer);
+        decodeTCBConfigure(&target, slot, faultEP, cRootData, bufferAddr, excaps.excaprefs[0])
+    } else if invLabel == invocation_label::TCBSetPriority as u64
+        || invLabel == invocation_label::TCBSetMCPriority as u64
+    {
+        if length < 1 || excaps.excaprefs[0].is_null() {
+            userError!("TCB SetPriority: Truncated message.");
+            current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
+            return exception::EXCEPTION_SYSCALL_ERROR as u64;
+        }
+        let newPrio = getSyscallArg(0, buffer);
+        let authCap = (*excaps.excaprefs[0]).cap;
+        let updateFlag = if invLabel == invocation_label::TCBSetPriority as u64 {
+            thread_control_flag::thread_control_update_priority
+        } else {
+            thread_control_flag::thread_control_update_mcp
+        };
+        decodeSetPriority(&target, slot, newPrio, authCap, updateFlag)
+    } else if invLabel == invocation_label::TCBBindNotification as u64 {
+        if excaps.excaprefs[0].is_null() {
+            userError!("TCB BindNotification: Truncated message.");
+            current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
+            return exception::EXCEPTION_SYSCALL_ERROR as u64;
+        }
+        match notification::decodeBindNotification(&target, (*excaps.excaprefs[0]).cap) {
+            Ok(index) => {
+                get_current_task_handle!().set_state(_thread_state::Restart);
+                target.invokeTCB_NotificationControl(Some(index))
+            }
+            Err(error) => {
+                current_syscall_error.type_ = error as u64;
+                current_syscall_error.invalidCapNumber = 1;
+                exception::EXCEPTION_SYSCALL_ERROR as u64
+            }
+        }
+    } else if invLabel == invocation_label::TCBUnbindNotification as u64 {
+        if target.get_bound_notification().is_none() {
+            userError!("TCB UnbindNotification: TCB already has no bound Notification.");
+            current_syscall_error.type_ = seL4_Error::seL4_IllegalOperation as u64;
+            return exception::EXCEPTION_SYSCALL_ERROR as u64;
+        }
+        get_current_task_handle!().set_state(_thread_state::Restart);
+        target.invokeTCB_NotificationControl(None)
+    } else {
+        userError!("TCB: Illegal operation.");
+        current_syscall_error.type_ = seL4_Error::seL4_IllegalOperation as u64;
+        exception::EXCEPTION_SYSCALL_ERROR as u64
+    }
+}
+
+/* Start a task retyped from untyped memory, on its first resume. */
+unsafe fn decodeTCBStart(target: &TaskHandle) -> u64 {
+    if !target.has_entry() {
+        userError!("TCB Resume: The task has no entry.");
+        current_syscall_error.type_ = seL4_Error::seL4_IllegalOperation as u64;
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
+    get_current_task_handle!().set_state(_thread_state::Restart);
+    match target.start() {
+        Ok(()) => 0u64,
+        Err(_) => {
+            userError!("TCB Resume: The thread of the task can't be created.");
+            current_syscall_error.type_ = seL4_Error::seL4_NotEnoughMemory as u64;
+            current_syscall_error.memoryLeft = 0;
+            exception::EXCEPTION_SYSCALL_ERROR as u64
+        }
+    }
+}
+
+/// # Description:
+///    check the arguments of TCBConfigure, once they are read, and set the fault endpoint,
+///    the CSpace root and the IPC buffer of the task.  Tasks have no VSpace and their IPC
+///    buffer isn't mapped from a frame, so only the CSpace root is passed as a cap.
+///    Shared by decodeTCBInvocation() and capability::TcbCap::configure().
+/// * C implementation: tcb.c decodeTCBConfigure()
+/// # Arguments:
+///    target  the task
+///    slot  the slot of the thread cap
+///    faultEP  the cptr of the fault endpoint, in the CSpace of the task
+///    cRootData  the guard of the CSpace root if not 0, as in CNodeCap::mint()
+///    bufferAddr  the address of the IPC buffer
+///    cRootSlot  the slot of the CNode cap of the CSpace root
+/// # Return:
+///    0, or EXCEPTION_SYSCALL_ERROR with current_syscall_error set
+/// # Safety
+///    `slot` and `cRootSlot` must be slots of the CSpace of the running task
+pub unsafe fn decodeTCBConfigure(
+    target: &TaskHandle,
+    slot: *mut cte_t,
+    faultEP: u64,
+    cRootData: u64,
+    bufferAddr: u64,
+    cRootSlot: *mut cte_t,
+) -> u64 {
+    let mut cRootCap = (*cRootSlot).cap;
+    if cRootData != 0u64 {
+        cRootCap = updateCapData(0u64, cRootData, cRootCap);
+    }
+    let dc_ret = deriveCap(cRootSlot, cRootCap);
+    if dc_ret.status != 0u64 {
+        return dc_ret.status;
+    }
+    cRootCap = dc_ret.cap;
+    if cap_get_capType(cRootCap) != cap_tag_t::cap_cnode_cap as u64 {
+        userError!("TCB Configure: CSpace cap is invalid.");
+        current_syscall_error.type_ = seL4_Error::seL4_IllegalOperation as u64;
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
+    get_current_task_handle!().set_state(_thread_state::Restart);
+    target.invokeTCB_ThreadControl(
+        slot,
+        faultEP,
+        0,
+        0,
+        cRootCap,
+        cRootSlot,
+        cap_null_cap_new(),
+        std::ptr::null_mut(),
+        bufferAddr,
+        cap_null_cap_new(),
+        std::ptr::null_mut(),
+        thread_control_flag::thread_control_update_space as u64
+            | thread_control_flag::thread_control_update_ipc_buffer as u64,
+    )
+}
+
+/* Check a priority set with the authority of `auth`: it mustn't be higher
+than the maximum controlled priority of `auth`, nor than the highest priority
+of the system.
+* C implementation: tcb.c checkPrio() */
+unsafe fn checkPrio(prio: prio_t, auth: &TaskHandle) -> u64 {
+    let mcp = auth.get_MCPriority().min(configMAX_PRIORITIES!() - 1);
+    if prio > mcp {
+        userError!("TCB: Requested priority {} too high (max {}).", prio, mcp);
+        current_syscall_error.type_ = seL4_Error::seL4_RangeError as u64;
+        current_syscall_error.rangeErrorMin = 0;
+        current_syscall_error.rangeErrorMax = mcp;
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
+    0u64
+}
+
+/// # Description:
+///    check the arguments of TCBSetPriority or TCBSetMCPriority, once they are read, and
+///    set the priority or the maximum controlled priority of the task.  Either is bounded
+///    by the maximum controlled priority of the authority, see checkPrio().  Shared by
+///    decodeTCBInvocation() and capability::TcbCap::set_priority().
+/// * C implementation: tcb.c decodeSetPriority(), decodeSetMCPriority()
+/// # Arguments:
+///    target  the task
+///    slot  the slot of the thread cap
+///    newPrio  the priority
+///    authCap  the thread cap of the task whose authority the priority is set with
+///    updateFlag  thread_control_update_priority or thread_control_update_mcp
+/// # Return:
+///    0, or EXCEPTION_SYSCALL_ERROR with current_syscall_error set, seL4_RangeError
+///    with the range allowed by the authority if the priority is too high
+/// # Safety
+///    `slot` must be a slot of the CSpace of the running task
+pub unsafe fn decodeSetPriority(
+    target: &TaskHandle,
+    slot: *mut cte_t,
+    newPrio: prio_t,
+    authCap: cap_t,
+    updateFlag: thread_control_flag,
+) -> u64 {
+    if cap_get_capType(authCap) != cap_tag_t::cap_thread_cap as u64 {
+        userError!("Set priority: authority cap not a TCB.");
+        current_syscall_error.type_ = seL4_Error::seL4_InvalidCapability as u64;
+        current_syscall_error.invalidCapNumber = 1;
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
+    let status = checkPrio(newPrio, &TaskHandle::from_thread_cap(authCap));
+    if status != 0u64 {
+        return status;
+    }
+    get_current_task_handle!().set_state(_thread_state::Restart);
+    target.invokeTCB_ThreadControl(
+        slot,
+        0,
+        newPrio,
+        newPrio,
+        cap_null_cap_new(),
+        std::ptr::null_mut(),
+        cap_null_cap_new(),
+        std::ptr::null_mut(),
+        0,
+        cap_null_cap_new(),
+        std::ptr::null_mut(),
+        updateFlag as u64,
+    )
+}
     pub unsafe fn setMRs_lookup_failure(
         receiver: *mut tcb_t,
         receiveIPCBuffer: *mut u64,
@@ -1149,7 +1815,7 @@ impl TaskHandle {
                 receiver,
                 receiveIPCBuffer,
                 offset + 2,
-                lookup_fault_guard_mismatch_get_bitsFound(luf),
+                lookup_fault_guard_mismatch_get_guardFound(luf),
             );
             return setMR(
                 receiver,
@@ -1422,13 +2088,23 @@ macro_rules! get_handle_from_option {
 ///  cause the calling task to be deleted.
 ///
 /// # Return:
-///
+///  Ok(()) if the task was deleted, QueueError::MutexStillHeld if it still
+///  holds mutexes, in which case it is left alone.
 #[cfg(feature = "INCLUDE_vTaskDelete")]
-pub fn task_delete(task_to_delete: Option<TaskHandle>) {
+pub fn task_delete(task_to_delete: Option<TaskHandle>) -> Result<(), QueueError> {
     /* If null is passed in here then it is the calling task that is
     being deleted. */
     let pxtcb = get_handle_from_option!(task_to_delete);
 
+    /* The mutexes of a deleted task could never be given back, so the tasks
+    waiting for them would block forever. */
+    #[cfg(feature = "configUSE_MUTEXES")]
+    {
+        if pxtcb.get_mutex_held_count() > 0 {
+            return Err(QueueError::MutexStillHeld);
+        }
+    }
+
     taskENTER_CRITICAL!();
     {
         /* Remove task from the ready list. */
@@ -1445,6 +2121,12 @@ pub fn task_delete(task_to_delete: Option<TaskHandle>) {
             mtCOVERAGE_TEST_MARKER!();
         }
 
+        /* The slots of the task are taken out of the mapping database, which
+        would otherwise link to the freed TCB. */
+        for i in 0..tcb_cnode_index::tcbCNodeEntries as u64 {
+            unsafe { CNode::cteDeleteOne(tcb_ptr_cte_ptr(get_ptr_from_handle!(pxtcb), i)) };
+        }
+
         /* Increment the uxTaskNumber also so kernel aware debuggers can
         detect that the task lists need re-generating.  This is done before
         portPRE_TASK_DELETE_HOOK() as in the Windows port that macro will
@@ -1498,6 +2180,7 @@ pub fn task_delete(task_to_delete: Option<TaskHandle>) {
             mtCOVERAGE_TEST_MARKER!();
         }
     }
+    Ok(())
 }
 
 ///  INCLUDE_vTaskSuspend must be defined as 1 for this function to be available.
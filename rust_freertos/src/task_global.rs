@@ -1,4 +1,4 @@
-use crate::list::ListLink;
+use crate::list::{List, ListLink};
 use crate::port::{BaseType, TickType, UBaseType};
 #[cfg(feature = "configUSE_CAPS")]
 use crate::task_control_cap::TaskHandle;
@@ -7,6 +7,8 @@ use crate::task_control::TaskHandle;
 use crate::*;
 #[cfg(feature = "configUSE_CAPS")]
 use crate::types::*;
+#[cfg(feature = "configUSE_CAPS")]
+use crate::arch_structures_TCB::seL4_Fault_t;
 use std::sync::RwLock;
 
 /* Some global variables. */
@@ -33,6 +35,14 @@ pub static mut current_syscall_error: syscall_error_t = syscall_error_t {
 pub static mut current_lookup_fault: lookup_fault_t = lookup_fault_t {
     words: [0, 0]
 };
+#[cfg(feature = "configUSE_CAPS")]
+pub static mut current_fault: seL4_Fault_t = seL4_Fault_t {
+    words: [0, 0]
+};
+#[cfg(feature = "configUSE_CAPS")]
+pub static mut current_extra_caps: extra_caps_t = extra_caps_t {
+    excaprefs: [std::ptr::null_mut(); seL4_MsgMaxExtraCaps]
+};
 
 /* GLOBAL TASK LISTS ARE CHANGED TO INTEGERS, WHICH ARE THEIR IDS. */
 
@@ -81,6 +91,7 @@ lazy_static! {
 #[cfg(feature = "configUSE_CAPS")]
 lazy_static! {
     pub static ref ENDPOINT_LIST: [ListLink; configMAX_ENDPOINTS!()] = Default::default();
+    pub static ref NOTIFICATION_LIST: [ListLink; configMAX_NOTIFICATIONS!()] = Default::default();
 }
 /* ------------------ End global lists ------------------- */
 
@@ -101,6 +112,61 @@ pub static mut TOTAL_RUN_TIME: u32 = 0;
 #[cfg(feature = "INCLUDE_vTaskDelete")]
 pub static mut DELETED_TASKS_WAITING_CLEAN_UP: UBaseType = 0;
 
+/// Put the global variables and task lists back to their initial values,
+/// dropping every task that was created so far. This allows the scheduler
+/// to be started again after task_end_scheduler() has been called.
+///
+/// It is called by the port after the scheduler has ended, and MUST NOT
+/// be called while the scheduler is running.
+///
+/// # Arguments
+///
+/// # Return
+///
+/// Nothing
+pub fn reset_task_globals() {
+    unsafe {
+        TICK_COUNT = 0;
+        TOP_READY_PRIORITY = 0;
+        PENDED_TICKS = 0;
+        SCHEDULER_RUNNING = false;
+        YIELD_PENDING = false;
+        NUM_OF_OVERFLOWS = 0;
+        TASK_NUMBER = 0;
+        NEXT_TASK_UNBLOCK_TIME = 0;
+        CURRENT_NUMBER_OF_TASKS = 0;
+        SCHEDULER_SUSPENDED = 0;
+        #[cfg(feature = "configGENERATE_RUN_TIME_STATS")]
+        {
+            TASK_SWITCHED_IN_TIME = 0;
+            TOTAL_RUN_TIME = 0;
+        }
+        #[cfg(feature = "INCLUDE_vTaskDelete")]
+        {
+            DELETED_TASKS_WAITING_CLEAN_UP = 0;
+        }
+    }
+
+    *CURRENT_TCB.write().unwrap() = None;
+
+    let mut lists: Vec<&ListLink> = READY_TASK_LISTS.iter().collect();
+    lists.push(&DELAYED_TASK_LIST);
+    lists.push(&OVERFLOW_DELAYED_TASK_LIST);
+    lists.push(&PENDING_READY_LIST);
+    #[cfg(feature = "INCLUDE_vTaskDelete")]
+    lists.push(&TASKS_WAITING_TERMINATION);
+    #[cfg(feature = "INCLUDE_vTaskSuspend")]
+    lists.push(&SUSPENDED_TASK_LIST);
+    #[cfg(feature = "configUSE_CAPS")]
+    lists.extend(ENDPOINT_LIST.iter());
+    #[cfg(feature = "configUSE_CAPS")]
+    lists.extend(NOTIFICATION_LIST.iter());
+
+    for list in lists {
+        *list.write().unwrap() = List::default();
+    }
+}
+
 /* Setters and getters of the above global variables to avoid redundancy of unsafe blocks. */
 #[macro_export]
 macro_rules! set_scheduler_suspended {
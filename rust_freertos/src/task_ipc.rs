@@ -6,12 +6,9 @@ use crate::arch_structures_TCB::*;
 use crate::CNode::*;
 use crate::CSpace::*;
 use crate::regs::*;
+use crate::task_global::*;
 use crate::*;
 
-extern "C" {
-    static mut current_extra_caps: extra_caps_t;
-    static mut current_fault: seL4_Fault_t;
-}
 
 impl TaskHandle {
     pub unsafe fn setupCallerCap(sender: Self, receiver: Self) {
@@ -26,15 +23,15 @@ impl TaskHandle {
         let callerSlot = tcb_ptr_cte_ptr(receiver_ptr, tcb_cnode_index::tcbCaller as u64);
         cteInsert(
             cap_reply_cap_new(0u64, sender_ptr as u64),
-            Arc::new(RwLock::new(*replySlot)),
-            Arc::new(RwLock::new(*callerSlot)),
+            replySlot,
+            callerSlot,
         );
     }
 
 pub unsafe fn deleteCallerCap(receiver: Self) {
     let receiver_ptr = get_ptr_from_handle!(receiver);
     let callerSlot = tcb_ptr_cte_ptr(receiver_ptr, tcb_cnode_index::tcbCaller as u64);
-    cteDeleteOne(Arc::new(RwLock::new(*callerSlot)));
+    cteDeleteOne(callerSlot);
 }
 
 // pub unsafe fn lookupExtraCaps(
@@ -45,7 +42,7 @@ pub unsafe fn lookupExtraCaps(
 ) -> u64 {
     let thread_ptr = get_ptr_from_handle!(thread);
     if bufferPtr as u64 == 0u64 {
-        current_extra_caps.excaprefs[0] = Box::from_raw(null_mut());
+        current_extra_caps.excaprefs[0] = null_mut();
         return 0u64;
     }
     let length = seL4_MessageInfo_get_extraCaps(info);
@@ -61,7 +58,7 @@ pub unsafe fn lookupExtraCaps(
         i += 1;
     }
     if i < seL4_MsgMaxExtraCaps {
-        current_extra_caps.excaprefs[i] = Box::from_raw(null_mut());
+        current_extra_caps.excaprefs[i] = null_mut();
     }
     0u64
 }
@@ -0,0 +1,166 @@
+// task_notify.rs, task notifications
+// Each task has a notification value that other tasks and interrupts can
+// increment, and that the task can wait on, as a light-weight counting semaphore.
+
+use crate::kernel::{CriticalSection, IsrCriticalSection};
+use crate::list;
+use crate::port::*;
+use crate::projdefs::pdFALSE;
+#[cfg(not(feature = "configUSE_CAPS"))]
+use crate::task_control::*;
+#[cfg(feature = "configUSE_CAPS")]
+use crate::task_control_cap::*;
+use crate::task_global::*;
+use crate::*;
+
+/// # Description
+/// Increment the notification value of a task, unblocking the task if it
+/// was waiting for a notification.  Used like giving a counting semaphore.
+///
+/// * C implementation: task.h xTaskNotifyGive()
+///
+/// # Arguments:
+/// `task_to_notify` - the task to notify.
+///
+/// # Return:
+/// Nothing
+pub fn task_notify_give(task_to_notify: &TaskHandle) {
+    let _critical = CriticalSection::enter();
+    let original_notify_state = task_to_notify.get_notify_state();
+    task_to_notify.set_notify_state(taskNOTIFICATION_RECEIVED!());
+    task_to_notify.set_notify_value(task_to_notify.get_notify_value().wrapping_add(1));
+    traceTASK_NOTIFY!();
+
+    /* If the task was blocked waiting for a notification then unblock it
+    now. */
+    if original_notify_state == taskWAITING_NOTIFICATION!() {
+        list::list_remove(task_to_notify.get_state_list_item());
+        task_to_notify.append_task_to_ready_list().unwrap();
+
+        /* The task should not have been on an event list. */
+        assert!(list::get_list_item_container(&task_to_notify.get_event_list_item()).is_none());
+
+        {
+            #![cfg(feature = "configUSE_TICKLESS_IDLE")]
+            /* The task may have been blocked with a timeout, in which case
+            the next unblock time may have been set to its wake time. */
+            reset_next_task_unblock_time();
+        }
+
+        if task_to_notify.get_priority() > get_current_task_priority!() {
+            /* The notified task has a priority above the currently
+            executing task so a yield is required. */
+            taskYIELD_IF_USING_PREEMPTION!();
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+}
+
+/// # Description
+/// A version of task_notify_give() that can be used in an interrupt service
+/// routine (ISR).
+///
+/// * C implementation: tasks.c vTaskNotifyGiveFromISR()
+///
+/// # Arguments:
+/// `task_to_notify` - the task to notify.
+///
+/// # Return:
+/// `bool` - pxHigherPriorityTaskWoken is changed to be a return value. It is
+/// true if the notified task has a priority above the running task.
+pub fn task_notify_give_from_isr(task_to_notify: &TaskHandle) -> bool {
+    let mut higher_priority_task_woken = false;
+
+    portASSERT_IF_INTERRUPT_PRIORITY_INVALID!();
+    {
+        let _isr_critical = IsrCriticalSection::enter();
+        let original_notify_state = task_to_notify.get_notify_state();
+        task_to_notify.set_notify_state(taskNOTIFICATION_RECEIVED!());
+        task_to_notify.set_notify_value(task_to_notify.get_notify_value().wrapping_add(1));
+        traceTASK_NOTIFY_GIVE_FROM_ISR!();
+
+        if original_notify_state == taskWAITING_NOTIFICATION!() {
+            /* The task should not have been on an event list. */
+            assert!(list::get_list_item_container(&task_to_notify.get_event_list_item()).is_none());
+
+            if get_scheduler_suspended!() == pdFALSE as UBaseType {
+                list::list_remove(task_to_notify.get_state_list_item());
+                task_to_notify.append_task_to_ready_list().unwrap();
+            } else {
+                /* The delayed and ready lists cannot be accessed, so hold
+                this task pending until the scheduler is resumed. */
+                list::list_insert_end(&PENDING_READY_LIST, task_to_notify.get_event_list_item());
+            }
+
+            if task_to_notify.get_priority() > get_current_task_priority!() {
+                /* Mark that a yield is pending in case the user is not using
+                the return value. */
+                higher_priority_task_woken = true;
+                set_yield_pending!(true);
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
+            }
+        }
+    }
+    higher_priority_task_woken
+}
+
+/// # Description
+/// Wait for the notification value of the calling task to be non-zero, and
+/// decrement or clear it.  Used like taking a counting or binary semaphore.
+///
+/// * C implementation: tasks.c ulTaskNotifyTake()
+///
+/// # Arguments:
+/// `clear_count_on_exit` - if true, the notification value is cleared to
+/// zero, otherwise it is decremented.
+/// `ticks_to_wait` - The maximum amount of time the task should block waiting
+/// for a notification.  A block time of portMAX_DELAY can be used to block
+/// indefinitely.
+///
+/// # Return:
+/// `u32` - the notification value before it was decremented or cleared.
+pub fn task_notify_take(clear_count_on_exit: bool, ticks_to_wait: TickType) -> u32 {
+    let current_task = get_current_task_handle!();
+    {
+        let _critical = CriticalSection::enter();
+        /* Only block if the notification count is not already non-zero. */
+        if current_task.get_notify_value() == 0 {
+            /* Mark this task as waiting for a notification. */
+            current_task.set_notify_state(taskWAITING_NOTIFICATION!());
+
+            if ticks_to_wait > 0 {
+                add_current_task_to_delayed_list(ticks_to_wait, true);
+                traceTASK_NOTIFY_TAKE_BLOCK!();
+
+                /* All ports are written to allow a yield in a critical
+                section (some will yield immediately, others wait until the
+                critical section exits) - but it is not something that
+                application code should ever do. */
+                portYIELD_WITHIN_API!();
+            } else {
+                mtCOVERAGE_TEST_MARKER!();
+            }
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+    }
+
+    let _critical = CriticalSection::enter();
+    traceTASK_NOTIFY_TAKE!();
+    let notify_value = current_task.get_notify_value();
+    if notify_value != 0 {
+        if clear_count_on_exit {
+            current_task.set_notify_value(0);
+        } else {
+            current_task.set_notify_value(notify_value - 1);
+        }
+    } else {
+        mtCOVERAGE_TEST_MARKER!();
+    }
+    current_task.set_notify_state(taskNOT_WAITING_NOTIFICATION!());
+    notify_value
+}
@@ -1,8 +1,11 @@
+use crate::kernel::CriticalSection;
 use crate::list;
 use crate::list::ListLink;
 use crate::port::*;
 // use crate::kernel::*;
 use crate::projdefs::pdFALSE;
+use std::fmt;
+use std::sync::{Arc, RwLock};
 #[cfg(not(feature = "configUSE_CAPS"))]
 use crate::task_control::*;
 #[cfg(feature = "configUSE_CAPS")]
@@ -87,8 +90,8 @@ pub fn task_check_for_timeout(pxtimeout: &mut time_out, ticks_to_wait: &mut Tick
     // assert! (pxtimeout);
     // assert! (ticks_to_wait);
 
-    taskENTER_CRITICAL!();
     {
+        let _critical = CriticalSection::enter();
         let const_tick_count: TickType = get_tick_count!();
         trace!("Tick_count is {}", const_tick_count);
         let unwrapped_cur = get_current_task_handle!();
@@ -129,11 +132,43 @@ pub fn task_check_for_timeout(pxtimeout: &mut time_out, ticks_to_wait: &mut Tick
             xreturn = true;
         }
     }
-    taskEXIT_CRITICAL!();
 
     xreturn
 }
 
+/// # Description
+/// Reset the event list item value of a task after its priority has changed,
+/// so it encodes `configMAX_PRIORITIES - priority` again. If the task is
+/// blocked on a queue or a semaphore, its event list item is moved to its new
+/// position, so the highest priority waiter is still the first to be woken.
+///
+/// # Arguments
+/// * `task` - the task whose priority has changed.
+///
+/// # Return
+/// Nothing
+pub fn task_reset_event_list_item_value(task: &TaskHandle) {
+    let event_list_item = task.get_event_list_item();
+
+    /* Only reset the event list item value if the value is not being used
+    for anything else. */
+    if (list::get_list_item_value(&event_list_item) & taskEVENT_LIST_ITEM_VALUE_IN_USE) != 0 {
+        mtCOVERAGE_TEST_MARKER!();
+        return;
+    }
+    let new_item_val = (configMAX_PRIORITIES!() - task.get_priority()) as TickType;
+    list::set_list_item_value(&event_list_item, new_item_val);
+
+    /* The pending ready list is not sorted, tasks in it are already
+    unblocked. */
+    if let Some(event_list) = list::get_list_item_container(&event_list_item) {
+        if !Arc::ptr_eq(&event_list, &PENDING_READY_LIST) {
+            list::list_remove(Arc::clone(&event_list_item));
+            list::list_insert(&event_list, event_list_item);
+        }
+    }
+}
+
 pub fn task_place_on_event_list(event_list: &ListLink, ticks_to_wait: TickType) {
     // assert! ( event_list );
 
@@ -168,62 +203,215 @@ pub fn task_increment_mutex_held_count() -> Option<TaskHandle> {
     }
 }
 
+/// The holder of a mutex, shared by the mutex and the tasks blocked on it.
+#[cfg(feature = "configUSE_MUTEXES")]
+pub type MutexHolderLink = Arc<RwLock<Option<TaskHandle>>>;
+
+/// The mutex a task is blocked on.  It is kept in the TCB of the blocked
+/// task, so that priority inheritance can follow a chain of mutex holders.
+#[cfg(feature = "configUSE_MUTEXES")]
+#[derive(Clone)]
+pub struct BlockedOnMutex {
+    /* The task currently holding the mutex. */
+    pub holder: MutexHolderLink,
+    /* The tasks waiting to take the mutex, in priority order. */
+    pub waiters: ListLink,
+}
+
+#[cfg(feature = "configUSE_MUTEXES")]
+impl fmt::Debug for BlockedOnMutex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlockedOnMutex with {} waiters", list::current_list_length(&self.waiters))
+    }
+}
+
+/// # Description
+/// Get the priority of the highest priority task in an event list.
+///
+/// # Arguments:
+/// `event_list` - the event list, which is sorted in priority order.
+///
+/// # Return:
+/// `UBaseType` - the priority of the task at the head of the list, or the
+/// idle priority if the list is empty.
+#[cfg(feature = "configUSE_MUTEXES")]
+pub fn task_get_highest_waiting_priority(event_list: &ListLink) -> UBaseType {
+    if list::list_is_empty(event_list) {
+        /* The idle priority. */
+        0
+    } else {
+        list::get_owner_of_head_entry(event_list).get_priority()
+    }
+}
+
+/* Change the priority of a task that holds a mutex, moving it into the ready
+list of its new priority if it is ready. */
+#[cfg(feature = "configUSE_MUTEXES")]
+fn task_change_mutex_holder_priority(task: &TaskHandle, new_priority: UBaseType) {
+    let this_task_priority = task.get_priority();
+
+    /* If the task being modified is in the ready state it will need
+    to be moved into a new list. */
+    let state_list_item = task.get_state_list_item();
+    if list::is_contained_within(
+        &READY_TASK_LISTS[this_task_priority as usize],
+        &state_list_item,
+    ) {
+        if list::list_remove(state_list_item) == 0 {
+            taskRESET_READY_PRIORITY!(this_task_priority);
+        } else {
+            mtCOVERAGE_TEST_MARKER!();
+        }
+
+        /* Change the priority before being moved into the new list. */
+        task.set_priority(new_priority);
+        task.append_task_to_ready_list().unwrap();
+    } else {
+        /* Just change the priority.  set_priority() resets the event list
+        item value as well, so a holder that is itself blocked is re-sorted
+        in the list it waits on. */
+        task.set_priority(new_priority);
+    }
+}
+
+/* Get the holder of the mutex that `task` is blocked on, if any. */
+#[cfg(feature = "configUSE_MUTEXES")]
+fn task_get_next_mutex_holder(task: &TaskHandle) -> Option<(TaskHandle, BlockedOnMutex)> {
+    let blocked_on = task.get_mutex_blocked_on()?;
+    let holder = blocked_on.holder.read().unwrap().clone()?;
+    Some((holder, blocked_on))
+}
+
+/// # Description
+/// Raise the priority of a mutex holder to the priority of the running task,
+/// which is about to block on the mutex.  If the holder is itself blocked on
+/// another mutex, the holder of that mutex inherits the priority too, and so
+/// on down the chain.
+///
+/// # Arguments:
+/// `mutex_holder` - the holder of the mutex the running task is blocking on.
+///
+/// # Return:
+/// `bool` - true if the holder runs at a priority inherited from the running
+/// task, so it has to be disinherited if the running task times out.
 #[cfg(feature = "configUSE_MUTEXES")]
-pub fn task_priority_inherit(mutex_holder: Option<TaskHandle>) {
+pub fn task_priority_inherit(mutex_holder: Option<TaskHandle>) -> bool {
     /* NOTE by Fan Jinhao: Maybe mutex_holder should be `&Option<TaskHandle>`.
      * But I'll leave it for now.
      */
     trace!("Enter function 'task_priority_inherit'");
     /* If the mutex was given back by an interrupt while the queue was
     locked then the mutex holder might now be NULL. */
-    if mutex_holder.is_some() {
-        trace!("Mutex holder exists!");
-        let task = mutex_holder.unwrap();
-        /* If the holder of the mutex has a priority below the priority of
-        the task attempting to obtain the mutex then it will temporarily
-        inherit the priority of the task attempting to obtain the mutex. */
-        let current_task_priority = get_current_task_priority!();
-        let this_task_priority = task.get_priority();
-
-        if this_task_priority < current_task_priority {
-            /* Adjust the mutex holder state to account for its new
-            priority.  Only reset the event list item value if the value is
-            not being used for anything else. */
+    let current_task_priority = get_current_task_priority!();
+    let inheritance_occurred = match mutex_holder {
+        Some(ref task) => task.get_base_priority() < current_task_priority,
+        None => false,
+    };
+
+    /* If the holder of the mutex has a priority below the priority of
+    the task attempting to obtain the mutex then it will temporarily
+    inherit the priority of the task attempting to obtain the mutex.  The
+    walk stops at the first holder that already runs at that priority, which
+    also ends it if the holders wait for each other in a cycle. */
+    let mut next_holder = mutex_holder;
+    while let Some(task) = next_holder {
+        if task.get_priority() < current_task_priority {
             trace!("change priority!");
-            let event_list_item = task.get_event_list_item();
-            if (list::get_list_item_value(&event_list_item) & taskEVENT_LIST_ITEM_VALUE_IN_USE) == 0
-            {
-                let new_item_val = (configMAX_PRIORITIES!() - current_task_priority) as TickType;   //  ??? why MAX - priority
-                list::set_list_item_value(&event_list_item, new_item_val);
-            } else {
-                mtCOVERAGE_TEST_MARKER!();
-            }
-
-            /* If the task being modified is in the ready state it will need
-            to be moved into a new list. */
-            let state_list_item = task.get_state_list_item();
-            if list::is_contained_within(
-                &READY_TASK_LISTS[this_task_priority as usize],
-                &state_list_item,
-            ) {
-                if list::list_remove(state_list_item) == 0 {
-                    taskRESET_READY_PRIORITY!(this_task_priority);
-                } else {
-                    mtCOVERAGE_TEST_MARKER!();
-                }
-
-                /* Inherit the priority before being moved into the new list. */
-                task.set_priority(current_task_priority);
-                task.append_task_to_ready_list().unwrap();
-            }
+            traceTASK_PRIORITY_INHERIT!(&task, current_task_priority);
+            task_change_mutex_holder_priority(&task, current_task_priority);
         } else {
             mtCOVERAGE_TEST_MARKER!();
+            break;
         }
+        next_holder = task_get_next_mutex_holder(&task).map(|(holder, _)| holder);
+    }
+
+    inheritance_occurred
+}
+
+/// # Description
+/// Raise the priority of a task that has just taken a priority ceiling mutex
+/// to the ceiling of the mutex.  The priority is restored when the task gives
+/// back its last mutex, in the same way as an inherited priority.
+///
+/// # Arguments:
+/// `mutex_holder` - the task that took the mutex.
+///
+/// `ceiling_priority` - the ceiling of the mutex.
+///
+/// # Return:
+/// Nothing
+#[cfg(feature = "configUSE_MUTEXES")]
+pub fn task_priority_raise_to_ceiling(mutex_holder: &TaskHandle, ceiling_priority: UBaseType) {
+    if mutex_holder.get_priority() < ceiling_priority {
+        traceTASK_PRIORITY_INHERIT!(mutex_holder, ceiling_priority);
+        task_change_mutex_holder_priority(mutex_holder, ceiling_priority);
     } else {
         mtCOVERAGE_TEST_MARKER!();
     }
 }
 
+/// # Description
+/// Called when a task that raised the priority of a mutex holder gives up
+/// waiting for the mutex.  The holder drops back to the priority of the
+/// highest priority task still waiting for the mutex, but not below its base
+/// priority, and so do the holders of the mutexes it is blocked on in turn.
+///
+/// # Arguments:
+/// `mutex_holder` - the holder of the mutex the running task timed out on.
+///
+/// `highest_waiting_priority` - the priority of the highest priority task
+/// still waiting for the mutex.
+///
+/// # Return:
+/// Nothing
+#[cfg(feature = "configUSE_MUTEXES")]
+pub fn task_priority_disinherit_after_timeout(
+    mutex_holder: Option<TaskHandle>,
+    highest_waiting_priority: UBaseType,
+) {
+    trace!("Enter function 'task_priority_disinherit_after_timeout'");
+    let mut next_holder = mutex_holder.map(|task| (task, highest_waiting_priority));
+    while let Some((task, highest_waiting_priority)) = next_holder {
+        /* If a task has timed out because it already holds the mutex it
+        was trying to obtain then it cannot have inherited a priority. */
+        assert!(task.get_mutex_held_count() > 0);
+
+        /* Determine the priority to which the priority of the task that
+        holds the mutex should be set.  This will be the greater of the
+        holding task's base priority and the priority of the highest
+        priority task that is waiting to obtain the mutex. */
+        let priority_to_use = task.get_base_priority().max(highest_waiting_priority);
+        if task.get_priority() == priority_to_use {
+            mtCOVERAGE_TEST_MARKER!();
+            break;
+        }
+
+        /* Only disinherit if no other mutexes are held.  This is a
+        simplification in the priority inheritance implementation.  If
+        the task that holds the mutex is also holding other mutexes then
+        the other mutexes may have caused the priority inheritance. */
+        if task.get_mutex_held_count() != 1 {
+            mtCOVERAGE_TEST_MARKER!();
+            break;
+        }
+
+        /* If a task has timed out because it already holds the mutex it
+        was trying to obtain then it cannot have inherited a priority,
+        let alone be disinheriting it now. */
+        assert!(task != get_current_task_handle!());
+
+        traceTASK_PRIORITY_DISINHERIT!(&task, priority_to_use);
+        task_change_mutex_holder_priority(&task, priority_to_use);
+
+        /* The holder of the mutex this task is blocked on may have
+        inherited the priority through it as well. */
+        next_holder = task_get_next_mutex_holder(&task).map(|(holder, blocked_on)| {
+            (holder, task_get_highest_waiting_priority(&blocked_on.waiters))
+        });
+    }
+}
+
 #[cfg(feature = "configUSE_MUTEXES")]
 pub fn task_priority_disinherit(mutex_holder: Option<TaskHandle>) -> bool {
     /* NOTE by Fan Jinhao: Maybe mutex_holder should be `&Option<TaskHandle>`.
@@ -265,15 +453,12 @@ pub fn task_priority_disinherit(mutex_holder: Option<TaskHandle>) -> bool {
                 }
 
                 /* Disinherit the priority before adding the task into the
-                new	ready list. */
+                new	ready list.  set_priority() also resets the event list
+                item value.  It cannot be in use for any other purpose if this
+                task is running, and it must be running to give back the
+                mutex. */
                 traceTASK_PRIORITY_DISINHERIT!(&task, this_task_base_priority);
                 task.set_priority(this_task_base_priority);
-
-                /* Reset the event list item value.  It cannot be in use for
-                any other purpose if this task is running, and it must be
-                running to give back the mutex. */
-                let new_item_val = (configMAX_PRIORITIES!() - this_task_priority) as TickType;
-                list::set_list_item_value(&task.get_event_list_item(), new_item_val);
                 task.append_task_to_ready_list().unwrap();
 
                 /* Return true to indicate that a context switch is required.
@@ -44,13 +44,13 @@ pub fn task_delay(ticks_to_delay: TickType) {
     if ticks_to_delay > 0 {
         assert!(get_scheduler_suspended!() == 0);
 
-        task_suspend_all();
+        let scheduler = SchedulerSuspendGuard::suspend();
         {
             traceTASK_DELAY!();
             add_current_task_to_delayed_list(ticks_to_delay, false);
         }
 
-        already_yielded = task_resume_all();
+        already_yielded = scheduler.resume();
     } else {
         mtCOVERAGE_TEST_MARKER!();
     }
@@ -89,6 +89,13 @@ macro_rules! traceTASK_PRIORITY_DISINHERIT {
     ($pxTCBOfMutexHolder: expr, $uxOriginalPriority: expr) => {};
 }
 
+/* Called when a blocking task closes a cycle of the wait-for graph.  pxCycle
+is the slice of edges that make up the cycle. */
+#[macro_export]
+macro_rules! traceDEADLOCK_DETECTED {
+    ($pxCycle: expr) => {};
+}
+
 /* Task is about to block because it cannot read from a
 queue/mutex/semaphore.  pxQueue is a pointer to the queue/mutex/semaphore
 upon which the read was attempted.  pxCurrentTCB points to the TCB of the
@@ -22,9 +22,6 @@ use crate::arch_structures_TCB::*;
 pub type tcb_t = task_control_block;
 pub const seL4_TCBBits: u64 = 11;
 
-extern "C" {
-    fn Arch_finaliseCap(cap: cap_t, final_: bool_t) -> finaliseCap_ret_t;
-}
 
 //  capability
 #[repr(C)]
@@ -161,7 +158,7 @@ pub struct notification_t {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub struct lookup_fault_t {
     pub words: [u64; 2],
 }
@@ -199,7 +196,7 @@ pub fn cap_get_capSizeBits(cap: cap_t) -> word_t {
     let ctag = cap_get_capType(cap);
     //rust不允许整数直接转枚举体，所以只能用这种别扭的写法了
     match ctag {
-        ctag if ctag == (cap_tag_t::cap_null_cap as u64) => cap_untyped_cap_get_capBlockSize(cap),
+        ctag if ctag == (cap_tag_t::cap_untyped_cap as u64) => cap_untyped_cap_get_capBlockSize(cap),
         ctag if ctag == (cap_tag_t::cap_endpoint_cap as u64) => seL4_EndpointBits,
         ctag if ctag == (cap_tag_t::cap_notification_cap as u64) => seL4_NotificationBits,
         ctag if ctag == (cap_tag_t::cap_cnode_cap as u64) => {
@@ -300,7 +297,7 @@ pub fn isCapRevocable(derivedCap: cap_t, srcCap: cap_t) -> bool_t {
 
 #[inline]
 pub unsafe fn tcb_ptr_cte_ptr(p: *mut tcb_t, i: u64) -> *mut cte_t {
-    ((p as u64 & (!MASK!(seL4_TCBBits))) as *mut cte_t).offset(i as isize)
+    (*p).cnode.as_mut_ptr().offset(i as isize)
 }
 
 // include/object/tcb.h 因为不想翻译tcb.h整个文件所以就放这里了
@@ -356,12 +353,12 @@ pub struct pte_range {
     length: word_t,
 }
 pub type pte_range_t = pte_range;
-pub type cte_ptr_t = Box<cte_t>;
+pub type cte_ptr_t = *mut cte_t;
 
 const seL4_MsgExtraCapBits: usize = 2;
 pub const seL4_MsgMaxExtraCaps: usize = (1usize << seL4_MsgExtraCapBits) - 1;
 
-#[derive(Clone)]
+#[derive(Copy, Clone)]
 #[repr(C)]
 pub struct extra_caps {
     pub excaprefs: [cte_ptr_t; seL4_MsgMaxExtraCaps],
@@ -376,6 +373,11 @@ pub struct seL4_MessageInfo {
 }
 pub type seL4_MessageInfo_t = seL4_MessageInfo;
 
+#[inline]
+pub fn seL4_MessageInfo_get_label(seL4_MessageInfo: seL4_MessageInfo_t) -> u64 {
+    (seL4_MessageInfo.words[0] & 0xfffffffffffff000u64) >> 12
+}
+
 #[inline]
 pub fn seL4_MessageInfo_get_length(seL4_MessageInfo: seL4_MessageInfo_t) -> u64 {
     seL4_MessageInfo.words[0] & 0x7fu64
@@ -531,6 +533,7 @@ pub struct syscall_error_t {
 }
 
 // error
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum seL4_Error {
     seL4_NoError = 0,
     seL4_InvalidArgument = 1,
@@ -587,7 +590,7 @@ pub enum invocation_label {
 }
 
 //  cap
-pub unsafe fn deriveCap(slot: Box<cte_t>, cap: cap_t) -> deriveCap_ret_t {
+pub unsafe fn deriveCap(slot: *mut cte_t, cap: cap_t) -> deriveCap_ret_t {
     if isArchCap(cap) != 0u64 {
         // return Arch_deriveCap(slot, cap);    //  TODO extern "C"
     }
@@ -600,7 +603,7 @@ pub unsafe fn deriveCap(slot: Box<cte_t>, cap: cap_t) -> deriveCap_ret_t {
             cap: cap_null_cap_new(),
         };
     } else if cap_type == cap_tag_t::cap_untyped_cap as u64 {
-        let status = ensureNoChildren(Arc::new(RwLock::new(*slot)));
+        let status = ensureNoChildren(slot);
         if status != 0u64 {
             return deriveCap_ret_t {
                 status: status,
@@ -665,93 +668,158 @@ pub fn hasCancelSendRights(cap: cap_t) -> bool_t {
     0u64
 }
 
-// pub unsafe extern "C" fn finaliseCap(
-//     cap: cap_t,
-//     final_: bool_t,
-//     exposed: bool_t,
-// ) -> finaliseCap_ret_t {
-//     if isArchCap(cap) != 0u64 {
-//         return Arch_finaliseCap(cap, final_);
-//     }
-//     let cap_type = cap_get_capType(cap);
-//     // if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
-//     //     if final_ != 0u64 {
-//     //         cancelAllIPC(cap_endpoint_cap_get_capEPPtr(cap) as *mut endpoint_t)
-//     //     }
-//     //     return finaliseCap_ret_t {
-//     //         remainder: cap_null_cap_new(),
-//     //         cleanupInfo: cap_null_cap_new(),
-//     //     };
-//     // } else
-//      if cap_type == cap_tag_t::cap_notification_cap as u64 {
-//         if final_ != 0u64 {
-//             let ntfn = cap_notification_cap_get_capNtfnPtr(cap) as *mut notification_t;
-//             unbindMaybeNotification(ntfn);
-//             cancelAllSignals(ntfn);
-//         }
-//         return finaliseCap_ret_t {
-//             remainder: cap_null_cap_new(),
-//             cleanupInfo: cap_null_cap_new(),
-//         };
-//     } else if cap_type == cap_tag_t::cap_reply_cap as u64
-//         || cap_type == cap_tag_t::cap_null_cap as u64
-//         || cap_type == cap_tag_t::cap_domain_cap as u64
-//     {
-//         return finaliseCap_ret_t {
-//             remainder: cap_null_cap_new(),
-//             cleanupInfo: cap_null_cap_new(),
-//         };
-//     }
-//     if exposed != 0u64 {
-//         panic!("finaliseCap: failed to finalise immediately.");
-//     }
-//     if cap_type == cap_tag_t::cap_cnode_cap as u64 {
-//         if final_ != 0u64 {
-//             return finaliseCap_ret_t {
-//                 remainder: Zombie_new(
-//                     1u64 << cap_cnode_cap_get_capCNodeRadix(cap),
-//                     cap_cnode_cap_get_capCNodeRadix(cap),
-//                     cap_cnode_cap_get_capCNodePtr(cap),
-//                 ),
-//                 cleanupInfo: cap_null_cap_new(),
-//             };
-//         }
-//     } else if cap_type == cap_tag_t::cap_thread_cap as u64 {
-//         if final_ != 0u64 {
-//             let tcb = cap_thread_cap_get_capTCBPtr(cap) as *mut tcb_t;
-//             //ignore smp
-//             let cte_ptr = tcb_ptr_cte_ptr(tcb, tcb_cnode_index::tcbCTable as u64);
-//             unbindNotification(tcb);
-//             suspend(tcb);
-//             //debug
-//             tcbDebugRemove(tcb);
-//             Arch_prepareThreadDelete(tcb);
-//             return finaliseCap_ret_t {
-//                 remainder: Zombie_new(
-//                     tcb_arch_cnode_index::tcbArchCNodeEntries as u64,
-//                     ZombieType_ZombieTCB,
-//                     cte_ptr as u64,
-//                 ),
-//                 cleanupInfo: cap_null_cap_new(),
-//             };
-//         }
-//     } else if cap_type == cap_tag_t::cap_zombie_cap as u64 {
-//         return finaliseCap_ret_t {
-//             remainder: cap,
-//             cleanupInfo: cap_null_cap_new(),
-//         };
-//     } else if cap_type == cap_tag_t::cap_irq_handler_cap as u64 {
-//         if final_ != 0u64 {
-//             let irq = cap_irq_handler_cap_get_capIRQ(cap) as u8;
-//             deletedIRQHandler(irq);
-//             return finaliseCap_ret_t {
-//                 remainder: cap_null_cap_new(),
-//                 cleanupInfo: cap,
-//             };
-//         }
-//     }
-//     finaliseCap_ret_t {
-//         remainder: cap_null_cap_new(),
-//         cleanupInfo: cap_null_cap_new(),
-//     }
-// }
+/// # Description
+/// Clean up the object of a cap that is deleted.  If it is the last cap to
+/// the object, the tasks waiting on the object are woken, and the objects
+/// retyped from untyped memory are released, see untyped::releaseObject().
+/// No zombie is left, as the slots of a task or a CNode are emptied at once.
+///
+/// * C implementation: objecttype.c finaliseCap()
+///
+/// # Arguments:
+/// * `cap` - the cap deleted.
+/// * `final_` - whether it is the last cap to the object.
+/// * `exposed` - whether the slot of the cap is already visible as empty.
+///
+/// # Return:
+/// A null remainder and cleanupInfo, or the cap itself for a zombie.
+///
+/// # Safety
+///
+/// A thread cap must designate a task that hasn't been deleted.
+pub unsafe fn finaliseCap(cap: cap_t, final_: bool_t, exposed: bool_t) -> finaliseCap_ret_t {
+    let cap_type = cap_get_capType(cap);
+    if final_ != 0u64 {
+        if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+            crate::endpoint::cancelAllIPC(crate::untyped::object_of(cap_endpoint_cap_get_capEPPtr(cap)));
+        } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+            let index = crate::untyped::object_of(cap_notification_cap_get_capNtfnPtr(cap));
+            crate::notification::unbindMaybeNotification(index);
+            crate::notification::cancelAllSignals(index);
+        } else if cap_type == cap_tag_t::cap_thread_cap as u64 {
+            crate::notification::unbindNotification(&TaskHandle::from_thread_cap(cap));
+        } else if cap_type == cap_tag_t::cap_zombie_cap as u64 {
+            return finaliseCap_ret_t {
+                remainder: cap,
+                cleanupInfo: cap_null_cap_new(),
+            };
+        }
+        crate::untyped::releaseObject(cap);
+    }
+    let _ = exposed;
+    finaliseCap_ret_t {
+        remainder: cap_null_cap_new(),
+        cleanupInfo: cap_null_cap_new(),
+    }
+}
+
+/// # Description
+/// Check whether the object of `cap_b` is within the object of `cap_a`, so
+/// that `cap_b` can be derived from `cap_a`.
+///
+/// * C implementation: objecttype.c sameRegionAs()
+///
+/// # Safety
+///
+/// The caps must be well formed.
+pub unsafe fn sameRegionAs(cap_a: cap_t, cap_b: cap_t) -> bool_t {
+    let cap_type = cap_get_capType(cap_a);
+    let cap_type_b = cap_get_capType(cap_b);
+    if cap_type == cap_tag_t::cap_untyped_cap as u64 {
+        if cap_get_capIsPhysical(cap_b) != 0u64 {
+            let aBase = cap_untyped_cap_get_capPtr(cap_a);
+            let bBase = cap_get_capPtr(cap_b);
+            let aTop = aBase + MASK!(cap_untyped_cap_get_capBlockSize(cap_a));
+            let bTop = bBase + MASK!(cap_get_capSizeBits(cap_b));
+            return ((aBase <= bBase) && (bTop <= aTop) && (bBase <= bTop)) as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+        if cap_type_b == cap_tag_t::cap_endpoint_cap as u64 {
+            return (cap_endpoint_cap_get_capEPPtr(cap_a) == cap_endpoint_cap_get_capEPPtr(cap_b)) as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+        if cap_type_b == cap_tag_t::cap_notification_cap as u64 {
+            return (cap_notification_cap_get_capNtfnPtr(cap_a)
+                == cap_notification_cap_get_capNtfnPtr(cap_b)) as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_cnode_cap as u64 {
+        if cap_type_b == cap_tag_t::cap_cnode_cap as u64 {
+            return ((cap_cnode_cap_get_capCNodePtr(cap_a) == cap_cnode_cap_get_capCNodePtr(cap_b))
+                && (cap_cnode_cap_get_capCNodeRadix(cap_a) == cap_cnode_cap_get_capCNodeRadix(cap_b)))
+                as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_thread_cap as u64 {
+        if cap_type_b == cap_tag_t::cap_thread_cap as u64 {
+            return (cap_thread_cap_get_capTCBPtr(cap_a) == cap_thread_cap_get_capTCBPtr(cap_b)) as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_reply_cap as u64 {
+        if cap_type_b == cap_tag_t::cap_reply_cap as u64 {
+            return (cap_reply_cap_get_capTCBPtr(cap_a) == cap_reply_cap_get_capTCBPtr(cap_b)) as u64;
+        }
+    } else if cap_type == cap_tag_t::cap_domain_cap as u64 {
+        return (cap_type_b == cap_tag_t::cap_domain_cap as u64) as u64;
+    } else if cap_type == cap_tag_t::cap_irq_control_cap as u64 {
+        return (cap_type_b == cap_tag_t::cap_irq_control_cap as u64
+            || cap_type_b == cap_tag_t::cap_irq_handler_cap as u64) as u64;
+    } else if cap_type == cap_tag_t::cap_irq_handler_cap as u64
+        && cap_type_b == cap_tag_t::cap_irq_handler_cap as u64
+    {
+        return ((cap_irq_handler_cap_get_capIRQ(cap_a) as u8)
+            == (cap_irq_handler_cap_get_capIRQ(cap_b) as u8)) as u64;
+    }
+    0u64
+}
+
+/// # Description
+/// Check whether two caps designate the same object.
+///
+/// * C implementation: objecttype.c sameObjectAs()
+///
+/// # Safety
+///
+/// See sameRegionAs().
+pub unsafe fn sameObjectAs(cap_a: cap_t, cap_b: cap_t) -> bool_t {
+    if cap_get_capType(cap_a) == cap_tag_t::cap_untyped_cap as u64 {
+        return 0u64;
+    }
+    if cap_get_capType(cap_a) == cap_tag_t::cap_irq_control_cap as u64
+        && cap_get_capType(cap_b) == cap_tag_t::cap_irq_handler_cap as u64
+    {
+        return 0u64;
+    }
+    sameRegionAs(cap_a, cap_b)
+}
+
+/// # Description
+/// Remove the rights of a cap that are not in `cap_rights`.  Only endpoint
+/// and notification caps have rights.
+///
+/// * C implementation: objecttype.c maskCapRights()
+pub fn maskCapRights(cap_rights: seL4_CapRights_t, cap: cap_t) -> cap_t {
+    let cap_type = cap_get_capType(cap);
+    if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+        let mut new_cap = cap_endpoint_cap_set_capCanSend(
+            cap,
+            cap_endpoint_cap_get_capCanSend(cap) & seL4_CapRights_get_capAllowWrite(cap_rights),
+        );
+        new_cap = cap_endpoint_cap_set_capCanReceive(
+            new_cap,
+            cap_endpoint_cap_get_capCanReceive(cap) & seL4_CapRights_get_capAllowRead(cap_rights),
+        );
+        cap_endpoint_cap_set_capCanGrant(
+            new_cap,
+            cap_endpoint_cap_get_capCanGrant(cap) & seL4_CapRights_get_capAllowGrant(cap_rights),
+        )
+    } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+        let new_cap = cap_notification_cap_set_capNtfnCanSend(
+            cap,
+            cap_notification_cap_get_capNtfnCanSend(cap) & seL4_CapRights_get_capAllowWrite(cap_rights),
+        );
+        cap_notification_cap_set_capNtfnCanReceive(
+            new_cap,
+            cap_notification_cap_get_capNtfnCanReceive(cap) & seL4_CapRights_get_capAllowRead(cap_rights),
+        )
+    } else {
+        cap
+    }
+}
@@ -1,85 +1,219 @@
+// untyped.rs, untyped memory and the objects retyped from it
+// The untyped memory of the port is an arena of configTOTAL_HEAP_SIZE!()
+// bytes, out of which untyped caps are carved, as seL4 hands the free memory
+// to the root task at boot.  Retyping an untyped cap makes objects in its
+// memory from its free index up, and puts the caps to them in the slots of a
+// CNode, as children of the untyped cap in the mapping database.  Once the
+// children are all deleted, the memory is cleared and retyped from the start.
+// CNodes are made in the memory itself.  Tasks, endpoints and notifications
+// are kept by the port elsewhere, as a TaskHandle and as the entries of
+// ENDPOINT_LIST and NOTIFICATION_LIST, so their objects in the arena are a
+// word naming them, and their caps point to that word, see object_of().
+
 #![allow(non_snake_case)]
-#![allow(unused_assignments)]
-#![allow(unused_attributes)]
-#![allow(unused_variables)]
 #![allow(non_upper_case_globals)]
-#![allow(unused_imports)]
+#![allow(non_camel_case_types)]
 
-use crate::*;
+use crate::arch_structures_TCB::*;
+use crate::endpoint::endpoint_cap_new;
+use crate::notification::{clearNotification, notification_cap_new};
 use crate::task_control_cap::*;
+use crate::task_global::*;
+use crate::task_ipc::getSyscallArg;
 use crate::types::*;
+use crate::CNode::{cteDeleteOne, ensureEmptySlot, ensureNoChildren, insertNewCap, slot_range_t};
+use crate::CSpace::lookupTargetSlot;
+use crate::*;
+use std::sync::{Arc, Mutex, RwLock};
+
+/* The types of the objects, numbered as in seL4.  There are no frames or
+page tables in the port. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum seL4_ObjectType {
+    seL4_UntypedObject = 0,
+    seL4_TCBObject = 1,
+    seL4_EndpointObject = 2,
+    seL4_NotificationObject = 3,
+    seL4_CapTableObject = 4,
+    seL4_ObjectTypeCount = 5,
+}
+
+pub const seL4_MinUntypedBits: u64 = 4;
+pub const seL4_MaxUntypedBits: u64 = 47;
+
+//CONFIG_RETYPE_FAN_OUT_LIMIT
+const CONFIG_RETYPE_FAN_OUT_LIMIT: u64 = 256;
 
-extern "C" {
-    static mut current_syscall_error: syscall_error_t;
-    static mut current_lookup_fault: lookup_fault_t;
-    fn createNewObjects(
-        t: u64,
-        parent: Arc<cte_t>,
-        slots: slot_range_t,
-        regionBase: u64,
-        userSize: u64,
-        deviceMemory: u64,
-    );
-    fn Arch_isFrameType(type_: u64) -> bool_t;
-    fn memzero(s: u64, n: u64);
-    fn preemptionPoint() -> u64;
+lazy_static! {
+    /* The base of the arena, aligned to its size. */
+    static ref ARENA: u64 = {
+        let size = configTOTAL_HEAP_SIZE!();
+        let layout = std::alloc::Layout::from_size_align(size, size).unwrap();
+        let base = unsafe { std::alloc::alloc_zeroed(layout) };
+        if base.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        base as u64
+    };
+    /* The number of bytes of the arena already carved into untyped caps. */
+    static ref CARVED: Mutex<u64> = Mutex::new(0);
+    /* The entries of ENDPOINT_LIST and NOTIFICATION_LIST taken by the
+    objects of the arena, one bit each. */
+    static ref ENDPOINT_ENTRIES: Mutex<u64> = Mutex::new(0);
+    static ref NOTIFICATION_ENTRIES: Mutex<u64> = Mutex::new(0);
 }
 
 #[inline]
 fn get_free_ref(base: u64, freeIndex: u64) -> u64 {
-    base + (freeIndex << 4)
+    base + (freeIndex << seL4_MinUntypedBits)
 }
 
 #[inline]
 fn get_free_index(base: u64, free: u64) -> u64 {
-    (free - base) >> 4
+    (free - base) >> seL4_MinUntypedBits
 }
 
 #[inline]
 fn free_index_to_offset(freeIndex: u64) -> u64 {
-    freeIndex << 4
+    freeIndex << seL4_MinUntypedBits
 }
 
 #[inline]
-fn get_offset_free_ptr(base: u64, offset: u64) -> u64 {
-    base + offset
+fn alignUp(baseValue: u64, alignment: u64) -> u64 {
+    (baseValue + (1u64 << alignment) - 1) & !MASK!(alignment)
 }
 
-#[inline]
-fn offset_to_free_index(offset: u64) -> u64 {
-    offset >> 4
+fn in_arena(ptr: u64) -> bool {
+    ptr >= *ARENA && ptr < *ARENA + configTOTAL_HEAP_SIZE!() as u64
 }
 
-#[inline]
-unsafe fn clearMemory(ptr: u64, bits: u32) {
-    memzero(ptr, 1 << bits)
+/* The kernel-made caps name the entries of the tables from the bottom up,
+so the objects of the arena take them from the top down. */
+fn free_entries(entries: &Mutex<u64>, count: u64) -> u64 {
+    let taken = *entries.lock().unwrap();
+    (0..count).filter(|i| taken & 1 << i == 0).count() as u64
 }
 
-//CONFIG_RETYPE_FAN_OUT_LIMIT
-const CONFIG_RETYPE_FAN_OUT_LIMIT: u64 = 256;
+fn take_entry(entries: &Mutex<u64>, count: u64) -> u64 {
+    let mut taken = entries.lock().unwrap();
+    let index = (0..count)
+        .rev()
+        .find(|i| *taken & 1 << i == 0)
+        .unwrap_or_else(|| panic!("No free entry, it must be checked before retyping"));
+    *taken |= 1 << index;
+    index
+}
 
-macro_rules! MASK {
-    ($x:expr) => {
-        (1u64 << ($x)) - 1u64
-    };
+fn release_entry(entries: &Mutex<u64>, index: u64) {
+    *entries.lock().unwrap() &= !(1 << index);
 }
 
-#[inline]
-fn alignUp(baseValue: u64, alignment: u64) -> u64 {
-    (baseValue + (1u64 << alignment) - 1) & !MASK!(alignment)
+/// # Description
+/// Carve an untyped cap out of the arena, as seL4 hands the free memory to
+/// the root task at boot.  The memory of the cap is zeroed, and is never
+/// carved again.
+///
+/// # Arguments:
+/// * `size_bits` - the number of bits of the size of the memory.
+///
+/// # Return:
+/// The untyped cap, to be put in a CNode with CNode::provide_cap(),
+/// seL4_RangeError if the memory is smaller than seL4_MinUntypedBits or
+/// larger than the arena, and seL4_NotEnoughMemory if the arena is used up.
+pub fn untyped_cap_new(size_bits: u64) -> Result<cap_t, seL4_Error> {
+    if !(seL4_MinUntypedBits..=seL4_MaxUntypedBits).contains(&size_bits)
+        || 1u64 << size_bits > configTOTAL_HEAP_SIZE!() as u64
+    {
+        return Err(seL4_Error::seL4_RangeError);
+    }
+    let mut carved = CARVED.lock().unwrap();
+    let offset = alignUp(*carved, size_bits);
+    if offset + (1u64 << size_bits) > configTOTAL_HEAP_SIZE!() as u64 {
+        return Err(seL4_Error::seL4_NotEnoughMemory);
+    }
+    *carved = offset + (1u64 << size_bits);
+    Ok(cap_untyped_cap_new(0, 0, size_bits, *ARENA + offset))
 }
 
-pub const seL4_MinUntypedBits: u64 = 4;
-pub const seL4_MaxUntypedBits: u64 = 47;
+/// # Description
+/// Get what the pointer of a cap to a task, an endpoint or a notification
+/// names: the TCB, or the index of the entry of ENDPOINT_LIST or
+/// NOTIFICATION_LIST.  The caps to the objects retyped from the arena point
+/// to the word of the object naming it, the other caps name it themselves.
+///
+/// # Arguments:
+/// * `ptr` - the pointer of the cap.
+///
+/// # Return:
+/// The pointer of the TCB, or the index of the entry.
+///
+/// # Safety
+///
+/// The object of the cap mustn't have been deleted.
+pub unsafe fn object_of(ptr: u64) -> u64 {
+    if in_arena(ptr) {
+        *(ptr as *const u64)
+    } else {
+        ptr
+    }
+}
 
-#[no_mangle]
+/// # Description
+/// Get the number of bits of the size of an object.
+///
+/// * C implementation: objecttype.c getObjectSize()
+///
+/// # Arguments:
+/// * `t` - the type of the object, see seL4_ObjectType.
+/// * `userObjSize` - the size of the untyped objects, and the radix of the CNodes.
+///
+/// # Return:
+/// The number of bits of the size of the object.
+pub fn getObjectSize(t: u64, userObjSize: u64) -> u64 {
+    if t == seL4_ObjectType::seL4_TCBObject as u64 {
+        seL4_TCBBits
+    } else if t == seL4_ObjectType::seL4_EndpointObject as u64 {
+        seL4_EndpointBits
+    } else if t == seL4_ObjectType::seL4_NotificationObject as u64 {
+        seL4_NotificationBits
+    } else if t == seL4_ObjectType::seL4_CapTableObject as u64 {
+        seL4_SlotBits + userObjSize
+    } else {
+        userObjSize
+    }
+}
+
+/// # Description
+/// Decode and perform the retype of an untyped cap of the running task.  The
+/// arguments are the type and size of the objects, the CNode of the slots
+/// of their caps, as a cptr and a depth from the extra cap, and the offset
+/// and number of the slots.
+///
+/// * C implementation: untyped.c decodeUntypedInvocation()
+///
+/// # Arguments:
+/// * `thread` - the running task.
+/// * `invLabel` - the label of the message.
+/// * `length` - the length of the message.
+/// * `slot` - the slot of the untyped cap.
+/// * `cap` - the untyped cap.
+/// * `excaps` - the caps looked up from the extra cptrs of the message.
+/// * `buffer` - the IPC buffer of the running task.
+///
+/// # Return:
+/// 0, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// `slot` must be the slot of `cap`, and the caps of `excaps` must point to
+/// slots of the CSpace of the running task.
 pub unsafe fn decodeUntypedInvocation(
+    thread: &mut TaskHandle,
     invLabel: u64,
     length: u64,
-    slot: Arc<cte_t>,
+    slot: *mut cte_t,
     cap: cap_t,
     excaps: extra_caps_t,
-    call: bool_t,
     buffer: *mut u64,
 ) -> u64 {
     if invLabel != invocation_label::UntypedRetype as u64 {
@@ -87,8 +221,8 @@ pub unsafe fn decodeUntypedInvocation(
         current_syscall_error.type_ = seL4_Error::seL4_IllegalOperation as u64;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
-    if length < 6 || excaps.excaprefs[0] as u64 == 0u64 {
-        userError!("Untyped invocation: Truncated messaage.");
+    if length < 6 || excaps.excaprefs[0].is_null() {
+        userError!("Untyped invocation: Truncated message.");
         current_syscall_error.type_ = seL4_Error::seL4_TruncatedMessage as u64;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
@@ -98,8 +232,60 @@ pub unsafe fn decodeUntypedInvocation(
     let nodeDepth = getSyscallArg(3, buffer);
     let nodeOffset = getSyscallArg(4, buffer);
     let nodeWindow = getSyscallArg(5, buffer);
-    let rootSlot = excaps.excaprefs[0];
-    if newType >= seL4_ArchObjectType::seL4_ObjectTypeCount as u64 {
+    let rootCap = (*excaps.excaprefs[0]).cap;
+    let nodeCap = if nodeDepth == 0 {
+        rootCap
+    } else {
+        let lu_ret = lookupTargetSlot(thread, rootCap, nodeIndex, nodeDepth);
+        if lu_ret.status != 0u64 {
+            userError!("Untyped Retype: Invalid destination address.");
+            return lu_ret.status;
+        }
+        (*lu_ret.slot).cap
+    };
+    let status = decodeUntypedRetype(slot, cap, newType, userObjSize, nodeCap, nodeDepth, nodeOffset, nodeWindow);
+    /* The retype is done at once, see handleInvocation(). */
+    if status == 0u64 {
+        thread.set_state(_thread_state::Restart);
+    }
+    status
+}
+
+/// # Description
+/// Check the arguments of a retype, once they are read, and perform it.
+/// Shared by decodeUntypedInvocation() and capability::UntypedCap::retype().
+///
+/// * C implementation: untyped.c decodeUntypedInvocation()
+///
+/// # Arguments:
+/// * `slot` - the slot of the untyped cap.
+/// * `cap` - the untyped cap.
+/// * `newType` - the type of the objects, see seL4_ObjectType.
+/// * `userObjSize` - the size of the untyped objects, and the radix of the CNodes.
+/// * `nodeCap` - the CNode of the slots of the caps to the objects.
+/// * `nodeDepth` - the depth `nodeCap` was looked up at.
+/// * `nodeOffset` - the index of the first slot in the CNode.
+/// * `nodeWindow` - the number of objects.
+///
+/// # Return:
+/// 0, otherwise EXCEPTION_SYSCALL_ERROR with current_syscall_error set.
+///
+/// # Safety
+///
+/// `slot` must be the slot of `cap`, and `nodeCap` a cap of the CSpace of
+/// the running task.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn decodeUntypedRetype(
+    slot: *mut cte_t,
+    cap: cap_t,
+    newType: u64,
+    userObjSize: u64,
+    nodeCap: cap_t,
+    nodeDepth: u64,
+    nodeOffset: u64,
+    nodeWindow: u64,
+) -> u64 {
+    if newType >= seL4_ObjectType::seL4_ObjectTypeCount as u64 {
         userError!("Untyped Retype: Invalid object type.");
         current_syscall_error.type_ = seL4_Error::seL4_InvalidArgument as u64;
         current_syscall_error.invalidArgumentNumber = 0;
@@ -125,18 +311,6 @@ pub unsafe fn decodeUntypedInvocation(
         current_syscall_error.invalidArgumentNumber = 1;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
-    let nodeCap: cap_t;
-    if nodeDepth == 0 {
-        nodeCap = (*excaps.excaprefs[0]).cap;
-    } else {
-        let rootCap = (*excaps.excaprefs[0]).cap;
-        let lu_ret = lookupTargetSlot(rootCap, nodeIndex, nodeDepth);
-        if lu_ret.status != 0u64 {
-            userError!("Untyped Retype: Invalid destination address.");
-            return lu_ret.status;
-        }
-        nodeCap = (*lu_ret.slot).cap
-    }
     if cap_get_capType(nodeCap) != cap_tag_t::cap_cnode_cap as u64 {
         userError!("Untyped Retype: Destination cap invalid or read-only.");
         current_syscall_error.type_ = seL4_Error::seL4_FailedLookup as u64;
@@ -146,20 +320,14 @@ pub unsafe fn decodeUntypedInvocation(
     }
     let nodeSize = 1u64 << cap_cnode_cap_get_capCNodeRadix(nodeCap);
     if nodeOffset > nodeSize - 1 {
-        userError!(
-            "Untyped Retype: Destination node offset #%d too large.",
-            nodeOffset as i32
-        );
+        userError!("Untyped Retype: Destination node offset #{} too large.", nodeOffset);
         current_syscall_error.type_ = seL4_Error::seL4_RangeError as u64;
         current_syscall_error.rangeErrorMin = 0;
         current_syscall_error.rangeErrorMax = nodeSize - 1;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
-    if nodeWindow < 1 || nodeWindow > CONFIG_RETYPE_FAN_OUT_LIMIT {
-        userError!(
-            "Untyped Retype: Number of requested objects (%d) too small or large.",
-            nodeWindow as i32
-        );
+    if !(1..=CONFIG_RETYPE_FAN_OUT_LIMIT).contains(&nodeWindow) {
+        userError!("Untyped Retype: Number of requested objects ({}) too small or large.", nodeWindow);
         current_syscall_error.type_ = seL4_Error::seL4_RangeError as u64;
         current_syscall_error.rangeErrorMin = 1;
         current_syscall_error.rangeErrorMax = CONFIG_RETYPE_FAN_OUT_LIMIT;
@@ -173,102 +341,94 @@ pub unsafe fn decodeUntypedInvocation(
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
     let slots = slot_range_t {
-        cnode: cap_cnode_cap_get_capCNodePtr(nodeCap) as Arc<cte_t>,
+        cnode: cap_cnode_cap_get_capCNodePtr(nodeCap) as *mut cte_t,
         offset: nodeOffset,
         length: nodeWindow,
     };
-    let mut i: u64 = nodeOffset;
-    while i < nodeOffset + nodeWindow {
+    for i in nodeOffset..nodeOffset + nodeWindow {
         let status = ensureEmptySlot(slots.cnode.offset(i as isize));
         if status != 0u64 {
-            userError!(
-                "Untyped Retype: Slot #%d in destination window non-empty",
-                i as i32
-            );
+            userError!("Untyped Retype: Slot #{} in destination window non-empty", i);
             return status;
         }
-        i += 1;
-    }
-    let status = ensureNoChildren(slot);
-    let freeIndex: u64;
-    let reset: bool;
-    if status != 0u64 {
-        freeIndex = cap_untyped_cap_get_capFreeIndex(cap);
-        reset = false;
-    } else {
-        freeIndex = 0;
-        reset = true;
     }
+    /* The memory is retyped from the start once the children of the cap are
+    all deleted. */
+    let reset = ensureNoChildren(slot) == 0u64;
+    let freeIndex = if reset { 0 } else { cap_untyped_cap_get_capFreeIndex(cap) };
     let freeRef = get_free_ref(cap_untyped_cap_get_capPtr(cap), freeIndex);
-    let untypedFreeBytes: u64 =
-        (1u64 << cap_untyped_cap_get_capBlockSize(cap)) - free_index_to_offset(freeIndex);
+    let untypedFreeBytes = (1u64 << cap_untyped_cap_get_capBlockSize(cap)) - free_index_to_offset(freeIndex);
     if (untypedFreeBytes >> objectSize) < nodeWindow {
-        //ignore userError
+        userError!("Untyped Retype: Insufficient memory ({} bytes available).", untypedFreeBytes);
         current_syscall_error.type_ = seL4_Error::seL4_NotEnoughMemory as u64;
         current_syscall_error.memoryLeft = untypedFreeBytes;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
     let deviceMemory = cap_untyped_cap_get_capIsDevice(cap);
-    if deviceMemory != 0u64
-        && Arch_isFrameType(newType) == 0u64
-        && newType != seL4_ObjectType::seL4_UntypedObject as u64
-    {
+    if deviceMemory != 0u64 && newType != seL4_ObjectType::seL4_UntypedObject as u64 {
         userError!("Untyped Retype: Creating kernel objects with device untyped");
         current_syscall_error.type_ = seL4_Error::seL4_InvalidArgument as u64;
         current_syscall_error.invalidArgumentNumber = 1;
         return exception::EXCEPTION_SYSCALL_ERROR as u64;
     }
+    /* The endpoints and notifications also take an entry of their table. */
+    let entriesLeft = if newType == seL4_ObjectType::seL4_EndpointObject as u64 {
+        free_entries(&ENDPOINT_ENTRIES, configMAX_ENDPOINTS!())
+    } else if newType == seL4_ObjectType::seL4_NotificationObject as u64 {
+        free_entries(&NOTIFICATION_ENTRIES, configMAX_NOTIFICATIONS!())
+    } else {
+        nodeWindow
+    };
+    if entriesLeft < nodeWindow {
+        userError!("Untyped Retype: Only {} free entries in the table of the objects.", entriesLeft);
+        current_syscall_error.type_ = seL4_Error::seL4_NotEnoughMemory as u64;
+        current_syscall_error.memoryLeft = untypedFreeBytes;
+        return exception::EXCEPTION_SYSCALL_ERROR as u64;
+    }
     let alignedFreeRef = alignUp(freeRef, objectSize);
-    setThreadState(
-        node_state!(get_ptr_from_handle!(get_current_task_handle!())),
-        _thread_state::ThreadState_Restart as u64,
-    );
-    invokeUntyped_Retype(
-        slot,
-        reset as u64,
-        alignedFreeRef,
-        newType,
-        userObjSize,
-        slots,
-        deviceMemory,
-    )
+    invokeUntyped_Retype(slot, reset, alignedFreeRef, newType, userObjSize, slots, deviceMemory)
 }
 
-unsafe fn resetUntypedCap(srcSlot: Arc<cte_t>) -> u64 {
+/* Clear the memory retyped from an untyped cap.  The kernel operations of
+the port are never preempted, so the memory is cleared at once.
+* C implementation: untyped.c resetUntypedCap() */
+unsafe fn resetUntypedCap(srcSlot: *mut cte_t) {
     let prev_cap = (*srcSlot).cap;
-    let block_size = cap_untyped_cap_get_capBlockSize(prev_cap);
     let regionBase = cap_untyped_cap_get_capPtr(prev_cap);
-    let chunk = 8u64;
     let offset = free_index_to_offset(cap_untyped_cap_get_capFreeIndex(prev_cap));
-    let deviceMemory = cap_untyped_cap_get_capIsDevice(prev_cap);
     if offset == 0 {
-        return 0;
+        return;
     }
-    if deviceMemory == 1 || block_size < chunk {
-        if deviceMemory == 0 {
-            clearMemory(regionBase, block_size as u32);
-        }
-        (*srcSlot).cap = cap_untyped_cap_set_capFreeIndex(prev_cap, 0);
-    } else {
-        let mut x: i64 = (((offset - 1) >> chunk) << chunk) as i64;
-        while x != -(1 << chunk) {
-            clearMemory(get_offset_free_ptr(regionBase, x as u64), chunk as u32);
-            (*srcSlot).cap =
-                cap_untyped_cap_set_capFreeIndex(prev_cap, offset_to_free_index(x as u64));
-            let status = preemptionPoint();
-            if status != 0 {
-                return status;
-            }
-            x -= 1 << chunk;
-        }
+    if cap_untyped_cap_get_capIsDevice(prev_cap) == 0u64 {
+        std::ptr::write_bytes(regionBase as *mut u8, 0, offset as usize);
     }
-    0
+    (*srcSlot).cap = cap_untyped_cap_set_capFreeIndex(prev_cap, 0);
 }
 
-#[no_mangle]
+/// # Description
+/// Retype the memory of an untyped cap into objects, and put the caps to
+/// them in a range of empty slots.  Check with decodeUntypedRetype() first.
+///
+/// * C implementation: untyped.c invokeUntyped_Retype()
+///
+/// # Arguments:
+/// * `srcSlot` - the slot of the untyped cap.
+/// * `reset` - whether to clear the memory first, as the cap has no children.
+/// * `retypeBase` - the address of the first object.
+/// * `newType` - the type of the objects, see seL4_ObjectType.
+/// * `userSize` - the size of the untyped objects, and the radix of the CNodes.
+/// * `destSlots` - the slots of the caps to the objects.
+/// * `deviceMemory` - whether the memory is device memory.
+///
+/// # Return:
+/// 0
+///
+/// # Safety
+///
+/// The arguments must be checked by decodeUntypedRetype().
 pub unsafe fn invokeUntyped_Retype(
-    srcSlot: Arc<cte_t>,
-    reset: u64,
+    srcSlot: *mut cte_t,
+    reset: bool,
     retypeBase: u64,
     newType: u64,
     userSize: u64,
@@ -276,24 +436,108 @@ pub unsafe fn invokeUntyped_Retype(
     deviceMemory: u64,
 ) -> u64 {
     let regionBase = cap_untyped_cap_get_capPtr((*srcSlot).cap);
-    let mut freeRef = get_free_ref(regionBase, cap_untyped_cap_get_capFreeIndex((*srcSlot).cap));
-    if reset == 1 {
-        let status = resetUntypedCap(srcSlot);
-        if status != 0 {
-            return status;
-        }
+    if reset {
+        resetUntypedCap(srcSlot);
     }
     let totalObjectSize = destSlots.length << getObjectSize(newType, userSize);
-    freeRef = retypeBase + totalObjectSize;
-    (*srcSlot).cap =
-        cap_untyped_cap_set_capFreeIndex((*srcSlot).cap, get_free_index(regionBase, freeRef));
-    createNewObjects(
-        newType,
-        srcSlot,
-        destSlots,
-        retypeBase,
-        userSize,
-        deviceMemory,
-    );
-    0
+    let freeRef = retypeBase + totalObjectSize;
+    (*srcSlot).cap = cap_untyped_cap_set_capFreeIndex((*srcSlot).cap, get_free_index(regionBase, freeRef));
+    createNewObjects(newType, srcSlot, destSlots, retypeBase, userSize, deviceMemory);
+    0u64
+}
+
+/* Make an object in zeroed memory, and get the original cap to it.
+* C implementation: objecttype.c createObject() */
+unsafe fn createObject(t: u64, regionBase: u64, userSize: u64, deviceMemory: u64) -> cap_t {
+    let object = regionBase as *mut u64;
+    if t == seL4_ObjectType::seL4_TCBObject as u64 {
+        /* A new task can't give priorities until it is given a maximum
+        controlled priority, as in seL4. */
+        let tcb = TaskHandle::from(TCB::new());
+        tcb.set_MCPriority(0);
+        *object = Arc::into_raw(tcb.0) as u64;
+        cap_thread_cap_new(regionBase)
+    } else if t == seL4_ObjectType::seL4_EndpointObject as u64 {
+        *object = take_entry(&ENDPOINT_ENTRIES, configMAX_ENDPOINTS!());
+        endpoint_cap_new(regionBase, 0, true, true, true)
+    } else if t == seL4_ObjectType::seL4_NotificationObject as u64 {
+        let index = take_entry(&NOTIFICATION_ENTRIES, configMAX_NOTIFICATIONS!());
+        clearNotification(index);
+        *object = index;
+        notification_cap_new(regionBase, 0, true, true)
+    } else if t == seL4_ObjectType::seL4_CapTableObject as u64 {
+        cap_cnode_cap_new(0, 0, userSize, regionBase)
+    } else {
+        cap_untyped_cap_new(0, deviceMemory, userSize, regionBase)
+    }
+}
+
+/* Make the objects of a retype, with their caps as children of the untyped
+cap.
+* C implementation: objecttype.c createNewObjects() */
+unsafe fn createNewObjects(
+    t: u64,
+    parent: *mut cte_t,
+    slots: slot_range_t,
+    regionBase: u64,
+    userSize: u64,
+    deviceMemory: u64,
+) {
+    let objectSize = getObjectSize(t, userSize);
+    for i in 0..slots.length {
+        let cap = createObject(t, regionBase + (i << objectSize), userSize, deviceMemory);
+        insertNewCap(parent, slots.cnode.offset((slots.offset + i) as isize), cap);
+    }
+}
+
+/// # Description
+/// Release what an object of the arena holds once its last cap is deleted:
+/// the entry of ENDPOINT_LIST or NOTIFICATION_LIST is free to be taken
+/// again, and the caps in the slots of a TCB or a CNode are deleted, as
+/// their memory is cleared by the next retype.  A started task is deleted.  Of the objects
+/// outside the arena, only a task is released: its thread cap made by
+/// TaskHandle::thread_cap() drops the reference to the task it holds.
+///
+/// * C implementation: objecttype.c finaliseCap()
+///
+/// # Arguments:
+/// * `cap` - the last cap to the object, after its object is finalised.
+///
+/// # Return:
+/// Nothing
+///
+/// # Safety
+///
+/// The object of the cap mustn't have been released already.
+pub unsafe fn releaseObject(cap: cap_t) {
+    let ptr = cap_get_capPtr(cap);
+    if !in_arena(ptr) {
+        if cap_get_capType(cap) == cap_tag_t::cap_thread_cap as u64 {
+            Arc::decrement_strong_count(ptr as *const RwLock<TCB>);
+        }
+        return;
+    }
+    let cap_type = cap_get_capType(cap);
+    if cap_type == cap_tag_t::cap_endpoint_cap as u64 {
+        release_entry(&ENDPOINT_ENTRIES, object_of(ptr));
+    } else if cap_type == cap_tag_t::cap_notification_cap as u64 {
+        release_entry(&NOTIFICATION_ENTRIES, object_of(ptr));
+    } else if cap_type == cap_tag_t::cap_thread_cap as u64 {
+        let tcb = TaskHandle(Arc::from_raw(object_of(ptr) as *const RwLock<TCB>));
+        let tcb_ptr = get_ptr_from_handle!(tcb);
+        for i in 0..tcb_cnode_index::tcbCNodeEntries as u64 {
+            cteDeleteOne(tcb_ptr_cte_ptr(tcb_ptr, i));
+        }
+        if tcb.is_started() && task_delete(Some(tcb.clone())).is_err() {
+            warn!("The task {} holds mutexes, it is suspended instead of deleted.", tcb.get_name());
+            suspend_task(&mut tcb.clone());
+        }
+        tcb.clear_entry();
+        *(ptr as *mut u64) = 0;
+    } else if cap_type == cap_tag_t::cap_cnode_cap as u64 {
+        let slots = ptr as *mut cte_t;
+        for i in 0..1u64 << cap_cnode_cap_get_capCNodeRadix(cap) {
+            cteDeleteOne(slots.offset(i as isize));
+        }
+    }
 }